@@ -26,8 +26,8 @@ class runner:
         current_time_in_seconds = (t.hour * 60 + t.minute) * 60 + t.second
         state = None
 
-        all_signals = requests.get("http://localhost:5000/traffic-signals/get-all-current-state").json() # this is bad and will probably cause memory issues,
-        if all_signals['551']['current_stage_idx'] == 0:                                                 # but there's no other way to do it 
+        signal = requests.get("http://localhost:5000/traffic-signals/get-current-state", params={"id": 551}).json()
+        if signal['current_stage_idx'] == 0:
             state = traffic_signal.GREEN.value
         else:
             state = traffic_signal.RED.value
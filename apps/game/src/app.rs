@@ -109,6 +109,54 @@ impl App {
                     "- intersection_delays: {} bytes",
                     prettyprint_usize(serialized_size_bytes(&a.intersection_delays))
                 );
+                println!(
+                    "- crosswalk_waits: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.crosswalk_waits))
+                );
+                println!(
+                    "- signal_priority: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.signal_priority))
+                );
+                println!(
+                    "- lane_queues: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.lane_queues))
+                );
+                println!(
+                    "- trip_emissions: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.trip_emissions))
+                );
+                println!(
+                    "- road_emissions: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.road_emissions))
+                );
+                println!(
+                    "- intersection_emissions: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.intersection_emissions))
+                );
+                println!(
+                    "- hourly_emissions: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.hourly_emissions))
+                );
+                println!(
+                    "- overtakes: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.overtakes))
+                );
+                println!(
+                    "- incident_impacts: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.incident_impacts))
+                );
+                println!(
+                    "- reroutes: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.reroutes))
+                );
+                println!(
+                    "- diverted_from: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.diverted_from))
+                );
+                println!(
+                    "- diverted_to: {} bytes",
+                    prettyprint_usize(serialized_size_bytes(&a.diverted_to))
+                );
                 println!(
                     "- parking_lane_changes: {} bytes",
                     prettyprint_usize(serialized_size_bytes(&a.parking_lane_changes))
@@ -1,12 +1,13 @@
-use geom::Duration;
+use geom::{Duration, Time};
 use map_gui::tools::FilePicker;
 use map_model::{
-    ControlStopSign, ControlTrafficSignal, EditIntersectionControl, IntersectionID, StageType,
+    demand_per_hour, ControlStopSign, ControlTrafficSignal, EditIntersectionControl,
+    IntersectionID, PedestrianPhase, StageType,
 };
 use widgetry::tools::{ChooseSomething, PopupMsg};
 use widgetry::{
     Choice, DrawBaselayer, EventCtx, Key, Line, Panel, SimpleState, Spinner, State, Text, TextExt,
-    Widget,
+    Toggle, Widget,
 };
 
 use crate::app::{App, Transition};
@@ -14,8 +15,14 @@ use crate::edit::traffic_signals::{BundleEdits, TrafficSignalEditor};
 use crate::edit::{apply_map_edits, check_sidewalk_connectivity, StopSignEditor};
 use crate::sandbox::GameplayMode;
 
+// A typical minimum walk interval for push-button crosswalks
+const DEFAULT_MIN_WALK: Duration = Duration::const_seconds(7.0);
+
 pub struct ChangeDuration {
     idx: usize,
+    has_crosswalks: bool,
+    // How long pedestrians need to finish crossing after the walk interval
+    clearance: Duration,
 }
 
 impl ChangeDuration {
@@ -26,6 +33,40 @@ impl ChangeDuration {
         idx: usize,
     ) -> Box<dyn State<App>> {
         let i = app.primary.map.get_i(signal.id);
+        let stage = &signal.stages[idx];
+        let has_crosswalks = stage.protected_movements.iter().any(|m| m.crosswalk);
+        let push_button = if has_crosswalks {
+            let min_walk = match stage.pedestrian_phase {
+                PedestrianPhase::Recall => None,
+                PedestrianPhase::PushButton { min_walk } => Some(min_walk),
+            };
+            Widget::col(vec![
+                Toggle::checkbox(
+                    ctx,
+                    "pedestrians must push a button to cross",
+                    None,
+                    min_walk.is_some(),
+                ),
+                Widget::row(vec![
+                    "Minimum walk interval:".text_widget(ctx).centered_vert(),
+                    Spinner::widget(
+                        ctx,
+                        "min walk",
+                        (Duration::seconds(1.0), Duration::minutes(1)),
+                        min_walk.unwrap_or(DEFAULT_MIN_WALK),
+                        Duration::seconds(1.0),
+                    ),
+                ]),
+                Line("After the walk interval, pedestrians get enough time to finish crossing")
+                    .secondary()
+                    .into_widget(ctx),
+            ])
+            .padding(10)
+            .bg(app.cs.inner_panel_bg)
+            .outline(ctx.style().section_outline)
+        } else {
+            Widget::nothing()
+        };
         let panel = Panel::new_builder(Widget::col(vec![
             Widget::row(vec![
                 Line("How long should this stage last?")
@@ -93,6 +134,38 @@ impl ChangeDuration {
             .padding(10)
             .bg(app.cs.inner_panel_bg)
             .outline(ctx.style().section_outline),
+            Widget::col(vec![
+                Line("After the stage ends")
+                    .small_heading()
+                    .into_widget(ctx),
+                Widget::row(vec![
+                    "Amber:".text_widget(ctx).centered_vert(),
+                    Spinner::widget(
+                        ctx,
+                        "amber",
+                        (Duration::ZERO, Duration::seconds(10.0)),
+                        stage.amber,
+                        Duration::seconds(1.0),
+                    ),
+                ]),
+                Widget::row(vec![
+                    "All-red:".text_widget(ctx).centered_vert(),
+                    Spinner::widget(
+                        ctx,
+                        "all-red",
+                        (Duration::ZERO, Duration::seconds(10.0)),
+                        stage.all_red,
+                        Duration::seconds(1.0),
+                    ),
+                ]),
+                Line("Vehicles already in the intersection may finish, but nobody new may enter")
+                    .secondary()
+                    .into_widget(ctx),
+            ])
+            .padding(10)
+            .bg(app.cs.inner_panel_bg)
+            .outline(ctx.style().section_outline),
+            push_button,
             ctx.style()
                 .btn_solid_primary
                 .text("Apply")
@@ -100,7 +173,14 @@ impl ChangeDuration {
                 .build_def(ctx),
         ]))
         .build(ctx);
-        <dyn SimpleState<_>>::new_state(panel, Box::new(ChangeDuration { idx }))
+        <dyn SimpleState<_>>::new_state(
+            panel,
+            Box::new(ChangeDuration {
+                idx,
+                has_crosswalks,
+                clearance: stage.pedestrian_clearance(i),
+            }),
+        )
     }
 }
 
@@ -115,9 +195,21 @@ impl SimpleState<App> for ChangeDuration {
         match x {
             "close" => Transition::Pop,
             "Apply" => {
-                let dt = panel.spinner("duration");
+                let mut dt = panel.spinner("duration");
                 let delay = panel.spinner("delay");
                 let additional = panel.spinner("additional");
+                let amber = panel.spinner("amber");
+                let all_red = panel.spinner("all-red");
+                let pedestrian_phase = if self.has_crosswalks
+                    && panel.is_checked("pedestrians must push a button to cross")
+                {
+                    let min_walk = panel.spinner("min walk");
+                    // Make sure the stage is long enough for the walk and clearance intervals
+                    dt = dt.max(min_walk + self.clearance);
+                    PedestrianPhase::PushButton { min_walk }
+                } else {
+                    PedestrianPhase::Recall
+                };
                 let new_type = if delay == Duration::ZERO || additional == Duration::ZERO {
                     StageType::Fixed(dt)
                 } else {
@@ -130,6 +222,9 @@ impl SimpleState<App> for ChangeDuration {
                         let editor = state.downcast_mut::<TrafficSignalEditor>().unwrap();
                         editor.add_new_edit(ctx, app, idx, |ts| {
                             ts.stages[idx].stage_type = new_type.clone();
+                            ts.stages[idx].pedestrian_phase = pedestrian_phase.clone();
+                            ts.stages[idx].amber = amber;
+                            ts.stages[idx].all_red = all_red;
                         });
                     })),
                 ])
@@ -230,10 +325,7 @@ pub fn edit_entire_signal(
             x if x == use_template => Transition::Replace(ChooseSomething::new_state(
                 ctx,
                 "Use which preset for this intersection?",
-                Choice::from(ControlTrafficSignal::get_possible_policies(
-                    &app.primary.map,
-                    i,
-                )),
+                Choice::from(possible_policies(app, i)),
                 Box::new(move |new_signal, _, _| {
                     Transition::Multi(vec![
                         Transition::Pop,
@@ -417,3 +509,20 @@ pub fn edit_entire_signal(
         }),
     )
 }
+
+/// Once the simulation has run for a while, also offer a policy timed for the traffic seen in the
+/// last hour or two.
+fn possible_policies(app: &App, i: IntersectionID) -> Vec<(String, ControlTrafficSignal)> {
+    let map = &app.primary.map;
+    let now = app.primary.sim.time();
+    if now == Time::START_OF_DAY {
+        return ControlTrafficSignal::get_possible_policies(map, i);
+    }
+    let (counts, window) = app
+        .primary
+        .sim
+        .get_analytics()
+        .recent_vehicle_thruput(now, map);
+    let demand = demand_per_hour(&counts, window);
+    ControlTrafficSignal::get_possible_policies_with_demand(map, i, &demand)
+}
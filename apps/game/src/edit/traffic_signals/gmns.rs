@@ -9,7 +9,8 @@ use serde::{Deserialize, Deserializer};
 use geom::{Angle, Duration, LonLat, Pt2D};
 use map_model::{
     osm, ControlTrafficSignal, DirectedRoadID, DrivingSide, EditIntersectionControl,
-    IntersectionID, Map, Movement, MovementID, Stage, StageType, TurnPriority, TurnType,
+    IntersectionID, Map, Movement, MovementID, PedestrianPhase, Stage, StageType, TurnPriority,
+    TurnType,
 };
 use widgetry::tools::PopupMsg;
 use widgetry::{EventCtx, State};
@@ -54,6 +55,9 @@ pub fn import(map: &Map, i: IntersectionID, bytes: &Vec<u8>) -> Result<ControlTr
                     protected_movements: BTreeSet::new(),
                     yield_movements: BTreeSet::new(),
                     stage_type: StageType::Fixed(Duration::seconds(rec.green_time as f64)),
+                    pedestrian_phase: PedestrianPhase::Recall,
+                    amber: Duration::seconds(rec.yellow_time as f64),
+                    all_red: Duration::seconds(rec.red_time as f64),
                 });
             }
             std::cmp::Ordering::Less => {
@@ -174,6 +178,11 @@ struct Record {
     osm_ids: Vec<osm::NodeID>,
     timing_plan_id: String,
     green_time: usize,
+    // Clearance intervals after the green. Older files may not have these columns.
+    #[serde(default)]
+    yellow_time: usize,
+    #[serde(default)]
+    red_time: usize,
     #[serde(rename = "stage_no")]
     stage: usize,
     #[serde(deserialize_with = "parse_linestring")]
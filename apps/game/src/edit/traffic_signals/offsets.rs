@@ -3,7 +3,8 @@ use std::collections::BTreeSet;
 use maplit::btreeset;
 
 use geom::{Distance, Duration};
-use map_model::IntersectionID;
+use map_model::{IntersectionID, PathConstraints, SignalCorridor};
+use widgetry::tools::PopupMsg;
 use widgetry::{
     Color, Drawable, EventCtx, GfxCtx, HorizontalAlignment, Key, Line, Panel, RewriteColor,
     SimpleState, Spinner, State, Text, TextExt, VerticalAlignment, Widget,
@@ -50,6 +51,11 @@ impl ShowAbsolute {
                 ctx.style().btn_close_widget(ctx),
             ]),
             "Select an intersection as the base".text_widget(ctx),
+            ctx.style()
+                .btn_outline
+                .text("Create a green wave")
+                .hotkey(Key::G)
+                .build_def(ctx),
         ]))
         .aligned(HorizontalAlignment::Center, VerticalAlignment::Top)
         .build(ctx);
@@ -64,13 +70,25 @@ impl ShowAbsolute {
 }
 
 impl SimpleState<App> for ShowAbsolute {
-    fn on_click(&mut self, _: &mut EventCtx, _: &mut App, x: &str, _: &mut Panel) -> Transition {
+    fn on_click(
+        &mut self,
+        ctx: &mut EventCtx,
+        app: &mut App,
+        x: &str,
+        _: &mut Panel,
+    ) -> Transition {
         match x {
             "close" => {
                 // TODO Bit confusing UX, because all the offset changes won't show up in the
                 // undo stack. Could maybe do ConsumeState.
                 Transition::Pop
             }
+            "Create a green wave" => Transition::Replace(PickCorridor::new_state(
+                ctx,
+                app,
+                self.members.clone(),
+                None,
+            )),
             _ => unreachable!(),
         }
     }
@@ -324,3 +342,226 @@ impl SimpleState<App> for TuneRelative {
         g.redraw(&self.labels);
     }
 }
+
+struct PickCorridor {
+    members: BTreeSet<IntersectionID>,
+    first: Option<IntersectionID>,
+    labels: Drawable,
+}
+
+impl PickCorridor {
+    fn new_state(
+        ctx: &mut EventCtx,
+        app: &App,
+        members: BTreeSet<IntersectionID>,
+        first: Option<IntersectionID>,
+    ) -> Box<dyn State<App>> {
+        let mut batch = fade_irrelevant(app, &members);
+        if let Some(i) = first {
+            batch.push(
+                Color::BLUE.alpha(0.8),
+                app.primary.map.get_i(i).polygon.clone(),
+            );
+        }
+
+        let panel = Panel::new_builder(Widget::col(vec![
+            Widget::row(vec![
+                Line("Create a green wave").small_heading().into_widget(ctx),
+                ctx.style().btn_close_widget(ctx),
+            ]),
+            if first.is_none() {
+                "Select the first signal along the corridor".text_widget(ctx)
+            } else {
+                "Select the last signal along the corridor".text_widget(ctx)
+            },
+        ]))
+        .aligned(HorizontalAlignment::Center, VerticalAlignment::Top)
+        .build(ctx);
+        <dyn SimpleState<_>>::new_state(
+            panel,
+            Box::new(PickCorridor {
+                members,
+                first,
+                labels: ctx.upload(batch),
+            }),
+        )
+    }
+}
+
+impl SimpleState<App> for PickCorridor {
+    fn on_click(
+        &mut self,
+        ctx: &mut EventCtx,
+        app: &mut App,
+        x: &str,
+        _: &mut Panel,
+    ) -> Transition {
+        match x {
+            "close" => Transition::Replace(ShowAbsolute::new_state(ctx, app, self.members.clone())),
+            _ => unreachable!(),
+        }
+    }
+
+    fn on_mouseover(&mut self, ctx: &mut EventCtx, app: &mut App) {
+        app.primary.current_selection = app.mouseover_unzoomed_intersections(ctx).filter(|id| {
+            let i = id.as_intersection();
+            self.members.contains(&i) && Some(i) != self.first
+        });
+    }
+
+    fn other_event(&mut self, ctx: &mut EventCtx, app: &mut App) -> Transition {
+        ctx.canvas_movement();
+        if let Some(i) = app.click_on_intersection(ctx, "select signal") {
+            return match self.first {
+                None => Transition::Replace(PickCorridor::new_state(
+                    ctx,
+                    app,
+                    self.members.clone(),
+                    Some(i),
+                )),
+                Some(first) => Transition::Replace(ConfigureCorridor::new_state(
+                    ctx,
+                    app,
+                    self.members.clone(),
+                    first,
+                    i,
+                )),
+            };
+        }
+
+        Transition::Keep
+    }
+
+    fn draw(&self, g: &mut GfxCtx, app: &App) {
+        CommonState::draw_osd(g, app);
+
+        g.redraw(&self.labels);
+    }
+}
+
+struct ConfigureCorridor {
+    members: BTreeSet<IntersectionID>,
+    first: IntersectionID,
+    last: IntersectionID,
+    labels: Drawable,
+}
+
+impl ConfigureCorridor {
+    fn new_state(
+        ctx: &mut EventCtx,
+        app: &App,
+        members: BTreeSet<IntersectionID>,
+        first: IntersectionID,
+        last: IntersectionID,
+    ) -> Box<dyn State<App>> {
+        let map = &app.primary.map;
+        let mut batch = fade_irrelevant(app, &members);
+        batch.push(Color::BLUE.alpha(0.8), map.get_i(first).polygon.clone());
+        batch.push(Color::RED.alpha(0.8), map.get_i(last).polygon.clone());
+        if let Some((roads, _)) = map.simple_path_btwn_v2(first, last, PathConstraints::Car) {
+            for r in roads {
+                batch.push(app.cs.route, map.get_r(r).get_thick_polygon());
+            }
+        }
+
+        let cycle_length = map.get_traffic_signal(first).simple_cycle_duration();
+        let panel = Panel::new_builder(Widget::col(vec![
+            Widget::row(vec![
+                Line(format!("Green wave from {} to {}", first, last))
+                    .small_heading()
+                    .into_widget(ctx),
+                ctx.style().btn_close_widget(ctx),
+            ]),
+            Text::from(
+                "Every signal along the corridor will use the same cycle length. Offsets are \
+                 chosen so that cars driving at the speed limit arrive on a green light.",
+            )
+            .wrap_to_pct(ctx, 30)
+            .into_widget(ctx),
+            Widget::row(vec![
+                "Cycle length:".text_widget(ctx).centered_vert(),
+                Spinner::widget(
+                    ctx,
+                    "cycle length",
+                    (Duration::seconds(30.0), Duration::seconds(240.0)),
+                    cycle_length,
+                    Duration::seconds(5.0),
+                ),
+            ]),
+            Widget::row(vec![
+                "Favor the blue to red direction (%):"
+                    .text_widget(ctx)
+                    .centered_vert(),
+                Spinner::widget(ctx, "forward weight", (0, 100), 50_usize, 10),
+            ]),
+            ctx.style()
+                .btn_outline
+                .text("Apply green wave")
+                .hotkey(Key::Enter)
+                .build_def(ctx),
+        ]))
+        .aligned(HorizontalAlignment::Center, VerticalAlignment::Top)
+        .build(ctx);
+        <dyn SimpleState<_>>::new_state(
+            panel,
+            Box::new(ConfigureCorridor {
+                members,
+                first,
+                last,
+                labels: ctx.upload(batch),
+            }),
+        )
+    }
+}
+
+impl SimpleState<App> for ConfigureCorridor {
+    fn on_click(
+        &mut self,
+        ctx: &mut EventCtx,
+        app: &mut App,
+        x: &str,
+        panel: &mut Panel,
+    ) -> Transition {
+        match x {
+            "close" => Transition::Replace(ShowAbsolute::new_state(ctx, app, self.members.clone())),
+            "Apply green wave" => {
+                let cycle_length: Duration = panel.spinner("cycle length");
+                let forward_weight = panel.spinner::<usize>("forward weight") as f64 / 100.0;
+                let map = &app.primary.map;
+                let result = SignalCorridor::along_path(
+                    map,
+                    self.first,
+                    self.last,
+                    cycle_length,
+                    forward_weight,
+                )
+                .and_then(|mut corridor| {
+                    // The editor can only change the signals it's currently editing
+                    corridor.intersections.retain(|i| self.members.contains(i));
+                    corridor.optimize(map)
+                });
+                match result {
+                    Ok(timing) => {
+                        for ts in timing.signals {
+                            app.primary.map.incremental_edit_traffic_signal(ts);
+                        }
+                        Transition::Replace(ShowAbsolute::new_state(ctx, app, self.members.clone()))
+                    }
+                    Err(err) => {
+                        Transition::Push(PopupMsg::new_state(ctx, "Error", vec![err.to_string()]))
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn other_event(&mut self, ctx: &mut EventCtx, _: &mut App) -> Transition {
+        ctx.canvas_movement();
+        Transition::Keep
+    }
+
+    fn draw(&self, g: &mut GfxCtx, _: &App) {
+        g.redraw(&self.labels);
+    }
+}
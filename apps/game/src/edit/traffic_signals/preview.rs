@@ -96,7 +96,8 @@ pub fn make_previewer(
                         // TODO If there are variable stages, this could land anywhere
                         let mut step = Duration::ZERO;
                         for idx in 0..stage {
-                            step += signal.stages[idx].stage_type.simple_duration();
+                            step += signal.stages[idx].stage_type.simple_duration()
+                                + signal.stages[idx].intergreen();
                         }
                         app.primary.sim.timed_step(
                             &app.primary.map,
@@ -4,7 +4,7 @@ use abstutil::prettyprint_usize;
 use geom::{ArrowCap, Distance, Duration, PolyLine, Polygon, Tessellation, Time};
 use map_gui::options::TrafficSignalStyle;
 use map_gui::render::traffic_signal::draw_signal_stage;
-use map_model::{IntersectionControl, IntersectionID, StageType};
+use map_model::{IntersectionControl, IntersectionID, SignalControl, StageType};
 use sim::AgentType;
 use widgetry::{
     Color, DrawWithTooltips, EventCtx, FanChart, GeomBatch, Line, PlotOptions, ScatterPlot, Series,
@@ -317,13 +317,33 @@ fn traffic_signal_body(ctx: &mut EventCtx, app: &App, id: IntersectionID) -> Wid
         let mut txt = Text::new();
         txt.add_line(Line(format!("{} stages", signal.stages.len())).small_heading());
         txt.add_line(format!("Signal offset: {}", signal.offset));
-        {
-            let mut total = Duration::ZERO;
-            for s in &signal.stages {
-                total += s.stage_type.simple_duration();
-            }
-            // TODO Say "normally" or something?
-            txt.add_line(format!("One cycle lasts {}", total));
+        if signal.control != SignalControl::Cycle {
+            txt.add_line(format!("Control: {}", signal.control.describe()));
+        }
+        if let Some(max) = signal.priority.transit_max_adjustment {
+            txt.add_line(format!("Buses can shift the green by up to {}", max));
+        }
+        if signal.priority.emergency_preemption {
+            txt.add_line("Emergency vehicles pre-empt this signal");
+        }
+        if signal.priority.is_enabled() {
+            let (granted, cost) = app.primary.sim.get_analytics().signal_priority_summary(id);
+            txt.add_line(format!(
+                "Priority granted {} times so far, costing conflicting traffic about {}",
+                prettyprint_usize(granted.sum()),
+                cost
+            ));
+        }
+        // TODO Say "normally" or something?
+        txt.add_line(format!(
+            "One cycle lasts {}",
+            signal.simple_cycle_duration()
+        ));
+        if signal.total_intergreen() > Duration::ZERO {
+            txt.add_line(format!(
+                "including {} of amber and all-red",
+                signal.total_intergreen()
+            ));
         }
         rows.push(txt.into_widget(ctx));
     }
@@ -108,6 +108,7 @@ impl PickLayer {
                     btn("delay", Key::D),
                     btn("throughput", Key::T),
                     btn("traffic jams", Key::J),
+                    btn("queue lengths", Key::Q),
                     btn("cycling activity", Key::B),
                     btn("pedestrian crowding", Key::C),
                 ]),
@@ -247,6 +248,9 @@ impl State<App> for PickLayer {
                 "traffic jams" => {
                     app.primary.layer = Some(Box::new(traffic::TrafficJams::new(ctx, app)));
                 }
+                "queue lengths" => {
+                    app.primary.layer = Some(Box::new(traffic::QueueLengths::new(ctx, app, false)));
+                }
                 "transit network" => {
                     app.primary.layer = Some(Box::new(transit::TransitNetwork::new(
                         ctx, app, false, true, true,
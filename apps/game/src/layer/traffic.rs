@@ -1,4 +1,4 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use anyhow::Result;
 use maplit::btreeset;
@@ -539,6 +539,132 @@ impl Jam {
     }
 }
 
+// Shows how far back the queue on each lane reaches, or how much of the lane is occupied, as of
+// the most recent sample.
+pub struct QueueLengths {
+    time: Time,
+    occupancy: bool,
+    draw: ToggleZoomed,
+    panel: Panel,
+}
+
+impl Layer for QueueLengths {
+    fn name(&self) -> Option<&'static str> {
+        Some("queue lengths")
+    }
+    fn event(&mut self, ctx: &mut EventCtx, app: &mut App) -> Option<LayerOutcome> {
+        if app.primary.sim.time() != self.time {
+            *self = QueueLengths::new(ctx, app, self.occupancy);
+        }
+
+        match self.panel.event(ctx) {
+            Outcome::Clicked(x) => match x.as_ref() {
+                "close" => {
+                    return Some(LayerOutcome::Close);
+                }
+                _ => unreachable!(),
+            },
+            Outcome::Changed(_) => {
+                return Some(LayerOutcome::Replace(Box::new(QueueLengths::new(
+                    ctx,
+                    app,
+                    self.panel.is_checked("Show occupancy"),
+                ))));
+            }
+            _ => {}
+        }
+        None
+    }
+    fn draw(&self, g: &mut GfxCtx, _: &App) {
+        self.panel.draw(g);
+        self.draw.draw(g);
+    }
+    fn draw_minimap(&self, g: &mut GfxCtx) {
+        g.redraw(&self.draw.unzoomed);
+    }
+}
+
+impl QueueLengths {
+    pub fn new(ctx: &mut EventCtx, app: &App, occupancy: bool) -> QueueLengths {
+        let map = &app.primary.map;
+        let now = app.primary.sim.time();
+        let lane_queues = &app.primary.sim.get_analytics().lane_queues;
+
+        let mut draw = ToggleZoomed::builder();
+        draw.unzoomed
+            .push(app.cs.fade_map_dark, map.get_boundary_polygon().clone());
+        // When unzoomed, color each road by its worst lane
+        let mut worst_per_road = BTreeMap::new();
+        let mut spillback = 0;
+        for l in lane_queues.samples.keys() {
+            let sample = lane_queues.get_at(*l, now);
+            let lane = map.get_l(*l);
+            let pct = if occupancy {
+                sample.reserved_length / lane.length()
+            } else {
+                sample.queue_length / lane.length()
+            };
+            if pct == 0.0 {
+                continue;
+            }
+            if sample.queue_length > lane.length() {
+                spillback += 1;
+            }
+            let color = app.cs.good_to_bad_red.eval(pct.min(1.0));
+            draw.zoomed.push(color.alpha(0.4), lane.get_thick_polygon());
+            let worst = worst_per_road.entry(l.road).or_insert(0.0);
+            if pct > *worst {
+                *worst = pct;
+            }
+        }
+        for (r, pct) in worst_per_road {
+            draw.unzoomed.push(
+                app.cs.good_to_bad_red.eval(pct.min(1.0)),
+                map.get_r(r).get_thick_polygon(),
+            );
+        }
+
+        let panel = Panel::new_builder(Widget::col(vec![
+            header(ctx, "Queue lengths"),
+            Text::from(
+                Line(if occupancy {
+                    "How much of each lane is reserved by vehicles on it or about to enter"
+                } else {
+                    "How far back from the end of each lane stopped vehicles are queued"
+                })
+                .secondary(),
+            )
+            .wrap_to_pct(ctx, 15)
+            .into_widget(ctx),
+            Toggle::switch(ctx, "Show occupancy", None, occupancy),
+            if lane_queues.samples.is_empty() {
+                "Queues aren't being sampled. Run with --queue_sample_interval=60".text_widget(ctx)
+            } else {
+                Widget::nothing()
+            },
+            format!(
+                "{} lanes have spilled back into the previous intersection",
+                prettyprint_usize(spillback)
+            )
+            .text_widget(ctx),
+            ColorLegend::gradient(
+                ctx,
+                &app.cs.good_to_bad_red,
+                vec!["0%", "50%", "100% of the lane"],
+            ),
+        ]))
+        .aligned_pair(PANEL_PLACEMENT)
+        .build(ctx);
+
+        QueueLengths {
+            time: now,
+            occupancy,
+            draw: draw.build(ctx),
+            panel,
+        }
+    }
+}
+
 // Shows how long each agent has been waiting in one spot.
 pub struct Delay {
     time: Time,
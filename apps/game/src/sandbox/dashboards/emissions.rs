@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+use abstutil::prettyprint_usize;
+use sim::Emissions;
+use synthpop::TripMode;
+use widgetry::{
+    Color, EventCtx, GfxCtx, Line, Outcome, Panel, State, Text, TextExt, TextSpan, Widget,
+};
+
+use crate::app::{App, Transition};
+use crate::sandbox::dashboards::DashTab;
+
+pub struct EmissionsSummary {
+    panel: Panel,
+}
+
+impl EmissionsSummary {
+    pub fn new_state(ctx: &mut EventCtx, app: &App) -> Box<dyn State<App>> {
+        let now = app.primary.sim.time();
+        let after = app.primary.sim.get_analytics().total_emissions_by_time(now);
+        let before = app
+            .has_prebaked()
+            .map(|_| app.prebaked().total_emissions_by_time(now));
+
+        let mut rows = Vec::new();
+        let mut total_before = Emissions::ZERO;
+        let mut total_after = Emissions::ZERO;
+        for (vehicle_type, emissions) in &after {
+            total_after += *emissions;
+            let maybe_before = before
+                .as_ref()
+                .map(|before| before.get(vehicle_type).cloned().unwrap_or(Emissions::ZERO));
+            if let Some(x) = maybe_before {
+                total_before += x;
+            }
+            rows.push((vehicle_type.to_string(), maybe_before, *emissions));
+        }
+        rows.push((
+            "total".to_string(),
+            before.as_ref().map(|_| total_before),
+            total_after,
+        ));
+
+        let mut col = vec![
+            DashTab::Emissions.picker(ctx, app),
+            Widget::col(vec![
+                Line("Estimated tailpipe emissions so far")
+                    .small_heading()
+                    .into_widget(ctx),
+                if before.is_some() {
+                    format!(
+                        "Changes are relative to the baseline, before \"{}\"",
+                        app.primary.map.get_edits().edits_name
+                    )
+                    .text_widget(ctx)
+                } else {
+                    "No baseline results are available to compare to".text_widget(ctx)
+                },
+                emissions_table(ctx, rows),
+            ])
+            .section(ctx),
+        ];
+
+        if app.has_prebaked().is_some() {
+            let mut per_mode: BTreeMap<TripMode, (usize, Emissions, Emissions)> = BTreeMap::new();
+            for (_, before, after, mode) in app
+                .primary
+                .sim
+                .get_analytics()
+                .both_finished_trip_emissions(now, app.prebaked())
+            {
+                let entry = per_mode
+                    .entry(mode)
+                    .or_insert((0, Emissions::ZERO, Emissions::ZERO));
+                entry.0 += 1;
+                entry.1 += before;
+                entry.2 += after;
+            }
+            let rows = per_mode
+                .into_iter()
+                .filter(|(_, (_, before, after))| before.co2_grams > 0.0 || after.co2_grams > 0.0)
+                .map(|(mode, (cnt, before, after))| {
+                    (
+                        format!("{} ({} trips)", mode.ongoing_verb(), prettyprint_usize(cnt)),
+                        Some(before),
+                        after,
+                    )
+                })
+                .collect();
+            col.push(
+                Widget::col(vec![
+                    Line("Trips finished in both worlds")
+                        .small_heading()
+                        .into_widget(ctx),
+                    emissions_table(ctx, rows),
+                ])
+                .section(ctx),
+            );
+        }
+
+        Box::new(EmissionsSummary {
+            panel: Panel::new_builder(Widget::col(col))
+                .exact_size_percent(90, 90)
+                .build(ctx),
+        })
+    }
+}
+
+impl State<App> for EmissionsSummary {
+    fn event(&mut self, ctx: &mut EventCtx, app: &mut App) -> Transition {
+        match self.panel.event(ctx) {
+            Outcome::Clicked(x) => match x.as_ref() {
+                "close" => Transition::Pop,
+                _ => unreachable!(),
+            },
+            Outcome::Changed(_) => DashTab::Emissions
+                .transition(ctx, app, &self.panel)
+                .unwrap(),
+            _ => Transition::Keep,
+        }
+    }
+
+    fn draw(&self, g: &mut GfxCtx, _app: &App) {
+        self.panel.draw(g);
+    }
+}
+
+// Each row is (name, before, after)
+fn emissions_table(ctx: &EventCtx, rows: Vec<(String, Option<Emissions>, Emissions)>) -> Widget {
+    let mut columns: Vec<Vec<Widget>> = ["", "CO2 (kg)", "NOx (g)", "Fuel (liters)"]
+        .iter()
+        .map(|header| vec![Line(*header).secondary().into_widget(ctx)])
+        .collect();
+    let values = |e: Emissions| [e.co2_grams / 1000.0, e.nox_grams, e.fuel_liters];
+    for (name, before, after) in rows {
+        columns[0].push(name.text_widget(ctx));
+        let after = values(after);
+        let before = before.map(values);
+        for (idx, value) in after.into_iter().enumerate() {
+            let mut spans = vec![Line(format!("{:.1}", value))];
+            if let Some(before) = before {
+                spans.extend(cmp_less(before[idx], value));
+            }
+            columns[idx + 1].push(Text::from_all(spans).into_widget(ctx));
+        }
+    }
+    Widget::evenly_spaced_row(32, columns.into_iter().map(Widget::col).collect())
+}
+
+// Less is better
+fn cmp_less(before: f64, after: f64) -> Vec<TextSpan> {
+    if before <= 0.0 {
+        return Vec::new();
+    }
+    let pct = 100.0 * (after - before) / before;
+    if pct.abs() < 0.05 {
+        vec![Line(" (same)")]
+    } else if pct < 0.0 {
+        vec![Line(format!(" ({:.1}% less)", -pct)).fg(Color::GREEN)]
+    } else {
+        vec![Line(format!(" ({:.1}% more)", pct)).fg(Color::RED)]
+    }
+}
@@ -7,6 +7,7 @@ use crate::app::App;
 use crate::app::Transition;
 
 mod commuter;
+mod emissions;
 mod generic_trip_table;
 mod misc;
 mod mode_shift;
@@ -29,6 +30,7 @@ pub enum DashTab {
     TransitRoutes,
     CommuterPatterns,
     TrafficSignals,
+    Emissions,
     ModeShift,
 }
 
@@ -43,6 +45,7 @@ impl DashTab {
             Choice::new("Transit Routes", DashTab::TransitRoutes),
             Choice::new("Commuter Patterns", DashTab::CommuterPatterns),
             Choice::new("Traffic Signal Demand", DashTab::TrafficSignals),
+            Choice::new("Emissions", DashTab::Emissions),
             Choice::new("Mode shift (experimental)", DashTab::ModeShift),
         ];
         if app.has_prebaked().is_none() {
@@ -72,6 +75,7 @@ impl DashTab {
             DashTab::TransitRoutes => misc::TransitRoutes::new_state(ctx, app),
             DashTab::CommuterPatterns => CommuterPatterns::new_state(ctx, app),
             DashTab::TrafficSignals => TrafficSignalDemand::new_state(ctx, app),
+            DashTab::Emissions => emissions::EmissionsSummary::new_state(ctx, app),
             DashTab::ModeShift => mode_shift::ModeShift::new_state(ctx, app),
         }
     }
@@ -6,7 +6,7 @@ use abstutil::prettyprint_usize;
 use geom::{Duration, Time};
 use map_gui::tools::{checkbox_per_mode, grey_out_map, CityPicker};
 use sim::SlidingWindow;
-use synthpop::{ScenarioModifier, TripMode};
+use synthpop::{DayType, ScenarioModifier, TripMode};
 use widgetry::tools::{ChooseSomething, PopupMsg, URLManager};
 use widgetry::{
     lctrl, Choice, Color, EventCtx, GfxCtx, HorizontalAlignment, Key, Line, LinePlot, Outcome,
@@ -272,6 +272,12 @@ impl EditScenarioModifiers {
                 .text("Repeat schedule multiple days with +/- 10 minutes of noise")
                 .build_def(ctx),
         ]));
+        rows.push(
+            ctx.style()
+                .btn_outline
+                .text("Add a weekend after the last day")
+                .build_def(ctx),
+        );
         rows.push(Widget::horiz_separator(ctx, 1.0));
         rows.push(
             Widget::row(vec![
@@ -379,6 +385,19 @@ impl State<App> for EditScenarioModifiers {
                         self.modifiers.clone(),
                     ));
                 }
+                "Add a weekend after the last day" => {
+                    for day_type in [DayType::Saturday, DayType::Sunday] {
+                        self.modifiers.push(ScenarioModifier::AddDay {
+                            day_type,
+                            scenario: None,
+                        });
+                    }
+                    return Transition::Replace(EditScenarioModifiers::new_state(
+                        ctx,
+                        self.scenario_name.clone(),
+                        self.modifiers.clone(),
+                    ));
+                }
                 x => {
                     if let Some(x) = x.strip_prefix("delete modifier ") {
                         self.modifiers.remove(x.parse::<usize>().unwrap() - 1);
@@ -0,0 +1,74 @@
+//! Scale the people in a scenario up or down to match observed traffic counts, then write the
+//! calibrated scenario and a report comparing every count location.
+
+use anyhow::{bail, Result};
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+
+use abstutil::Timer;
+use geom::Time;
+use map_model::Map;
+use synthpop::{CalibrationOptions, ObservedCounts, Scenario, TrafficCounts};
+
+pub struct Args {
+    pub scenario: String,
+    pub counts: Vec<String>,
+    pub max_iterations: usize,
+    pub geh_tolerance: f64,
+    pub max_scale: f64,
+    pub rng_seed: u64,
+    pub output_name: Option<String>,
+    pub report: Option<String>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut timer = Timer::new("calibrate scenario");
+    let scenario: Scenario = abstio::read_object(args.scenario, &mut timer)?;
+    let map = Map::load_synchronously(scenario.map_name.path(), &mut timer);
+
+    let mut observed = Vec::new();
+    for arg in args.counts {
+        observed.push(parse_counts(&arg, &mut timer)?);
+    }
+    let opts = CalibrationOptions {
+        max_iterations: args.max_iterations,
+        geh_tolerance: args.geh_tolerance,
+        max_scale: args.max_scale,
+        ..Default::default()
+    };
+    let mut rng = XorShiftRng::seed_from_u64(args.rng_seed);
+    let (mut scenario, report) =
+        synthpop::calibrate(scenario, &observed, &opts, &map, &mut rng, &mut timer)?;
+
+    scenario.scenario_name = args
+        .output_name
+        .unwrap_or_else(|| format!("{}_calibrated", scenario.scenario_name));
+    scenario.save();
+    println!(
+        "Wrote {}",
+        abstio::path_scenario(&scenario.map_name, &scenario.scenario_name)
+    );
+    println!("{}", report.summary(opts.geh_tolerance));
+    if let Some(path) = args.report {
+        abstio::write_file(path.clone(), report.to_csv())?;
+        println!("Wrote {}", path);
+    }
+    Ok(())
+}
+
+/// Either just a path to `TrafficCounts`, or a path followed by a time window, like
+/// `counts.json@07:00:00-08:00:00`
+fn parse_counts(arg: &str, timer: &mut Timer) -> Result<ObservedCounts> {
+    let (path, window) = match arg.split_once('@') {
+        Some((path, window)) => {
+            let (t1, t2) = match window.split_once('-') {
+                Some(pair) => pair,
+                None => bail!("Bad time window in {}; use something like 07:00-08:00", arg),
+            };
+            (path, Some((Time::parse(t1)?, Time::parse(t2)?)))
+        }
+        None => (arg, None),
+    };
+    let counts: TrafficCounts = abstio::read_object(path.to_string(), timer)?;
+    Ok(ObservedCounts { counts, window })
+}
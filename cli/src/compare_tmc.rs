@@ -0,0 +1,61 @@
+//! Compare turning movement counts against the prebaked results of a simulation, per movement and
+//! hour.
+
+use std::fmt::Write;
+
+use anyhow::Result;
+
+use abstutil::Timer;
+use geom::{Duration, Time};
+use map_model::Map;
+use sim::{Analytics, CountedAt};
+use synthpop::TurningMovementCounts;
+
+pub struct Args {
+    pub tmc: String,
+    pub prebaked: String,
+    pub geh_threshold: f64,
+    pub report: Option<String>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut timer = Timer::new("compare turning movement counts");
+    let tmc: TurningMovementCounts = abstio::read_object(args.tmc, &mut timer)?;
+    let map = Map::load_synchronously(tmc.map.path(), &mut timer);
+    let analytics: Analytics = abstio::maybe_read_binary(args.prebaked, &mut timer)?;
+
+    // Prebaked results cover the whole day
+    let end_of_day = Time::START_OF_DAY + Duration::hours(24);
+    let validation = analytics.compare_counts(&tmc.to_time_binned_counts(), end_of_day, &map)?;
+    println!("{}", validation.summary(args.geh_threshold));
+
+    if let Some(path) = args.report {
+        let mut out = String::from(
+            "intersection,approach,turn,from_road,to_road,mode,hour,observed,simulated,geh\n",
+        );
+        for bin in &validation.bins {
+            let m = match bin.location {
+                CountedAt::Movement(m) => m,
+                CountedAt::Road(_) => unreachable!(),
+            };
+            let (approach, turn) = tmc.labels[&m];
+            writeln!(
+                out,
+                "{},{:?},{:?},{},{},{},{},{},{},{:.2}",
+                m.parent.0,
+                approach,
+                turn,
+                m.from.road.0,
+                m.to.road.0,
+                bin.mode.noun(),
+                bin.hour,
+                bin.observed,
+                bin.simulated,
+                bin.geh
+            )?;
+        }
+        abstio::write_file(path.clone(), out)?;
+        println!("Wrote {}", path);
+    }
+    Ok(())
+}
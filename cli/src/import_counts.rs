@@ -0,0 +1,67 @@
+use anyhow::Result;
+
+use abstutil::Timer;
+use geom::{Distance, Duration};
+use map_model::Map;
+use synthpop::{TimeBinnedCounts, TurningMovementCounts};
+
+pub fn run(
+    input: String,
+    map: String,
+    description: Option<String>,
+    bin_minutes: usize,
+    max_snap_dist_meters: f64,
+    tmc: bool,
+    output: String,
+) -> Result<()> {
+    let mut timer = Timer::new("import counts");
+    let map = Map::load_synchronously(map, &mut timer);
+    let description = description.unwrap_or_else(|| abstutil::basename(&input));
+    let bin_size = Duration::minutes(bin_minutes);
+    let max_snap_dist = Distance::meters(max_snap_dist_meters);
+
+    if tmc {
+        let counts = TurningMovementCounts::import_csv(
+            &map,
+            description,
+            bin_size,
+            input,
+            max_snap_dist,
+            &mut timer,
+        )?;
+        println!(
+            "Imported {} movements at {} intersections",
+            counts.labels.len(),
+            counts.intersections().len()
+        );
+        abstio::write_json(output, &counts);
+        return Ok(());
+    }
+
+    let counts = if input.ends_with(".geojson") {
+        TimeBinnedCounts::import_geojson(
+            &map,
+            description,
+            bin_size,
+            input,
+            max_snap_dist,
+            &mut timer,
+        )?
+    } else {
+        TimeBinnedCounts::import_csv(
+            &map,
+            description,
+            bin_size,
+            input,
+            max_snap_dist,
+            &mut timer,
+        )?
+    };
+    println!(
+        "Imported {} bins at {} count sites",
+        counts.counts.len(),
+        counts.sites().len()
+    );
+    abstio::write_json(output, &counts);
+    Ok(())
+}
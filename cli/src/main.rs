@@ -5,11 +5,17 @@
 extern crate log;
 
 mod augment_scenario;
+mod calibrate_scenario;
 mod clip_osm;
+mod compare_tmc;
 mod generate_houses;
+mod import_counts;
 mod import_grid2demand;
 mod import_scenario;
 mod one_step_import;
+mod optimize_signals;
+mod static_assignment;
+mod time_signals;
 
 use std::io::Write;
 
@@ -59,6 +65,13 @@ enum Command {
         /// A JSON list of modifiers to transform the scenario. These can be generated with the GUI.
         #[structopt(long, parse(try_from_str = parse_modifiers), default_value = "[]")]
         scenario_modifiers: ModifierList,
+        /// Days to add after the scenario's last one, like "saturday,sunday". Each is "weekday",
+        /// "saturday", "sunday", or "holiday". Everybody repeats their first day, turned into a
+        /// day off for weekends and holidays. To take a day's trips from another scenario for the
+        /// same map instead, use something like "saturday=weekend". Days are added before
+        /// applying `scenario_modifiers`.
+        #[structopt(long, parse(try_from_str = parse_days), default_value = "")]
+        add_days: ModifierList,
         /// Delete cancelled trips, and delete people with no remaining trips.
         #[structopt(long)]
         delete_cancelled_trips: bool,
@@ -66,6 +79,55 @@ enum Command {
         #[structopt(long, default_value = "42")]
         rng_seed: u64,
     },
+    /// Scale the people in a scenario up or down until routing their trips matches observed
+    /// traffic counts. Writes the calibrated scenario and reports the GEH statistic per count
+    /// location.
+    CalibrateScenario {
+        /// The path to a scenario file
+        #[structopt(long)]
+        scenario: String,
+        /// The path to a `TrafficCounts` file with observations. Only trips departing during a
+        /// time window can be compared by adding it, like "counts.json@07:00:00-08:00:00". Can be
+        /// repeated.
+        #[structopt(long, required = true)]
+        counts: Vec<String>,
+        /// How many rounds of scaling to do at most
+        #[structopt(long, default_value = "50")]
+        max_iterations: usize,
+        /// Stop once every count location has a GEH statistic below this
+        #[structopt(long, default_value = "5.0")]
+        geh_tolerance: f64,
+        /// Never duplicate one person more than this many times
+        #[structopt(long, default_value = "5.0")]
+        max_scale: f64,
+        /// A seed for generating random numbers
+        #[structopt(long, default_value = "42")]
+        rng_seed: u64,
+        /// The name of the calibrated scenario to write. Defaults to the input name with
+        /// "_calibrated" appended.
+        #[structopt(long)]
+        output_name: Option<String>,
+        /// The path to write a CSV comparing every count location before and after calibrating
+        #[structopt(long)]
+        report: Option<String>,
+    },
+    /// Compare turning movement counts imported with `import-counts --tmc` against the prebaked
+    /// results of a simulation, reporting the GEH statistic per movement and hour, and the R² per
+    /// hour.
+    CompareTMC {
+        /// The path to turning movement counts
+        #[structopt(long)]
+        tmc: String,
+        /// The path to prebaked results from simulating a scenario on the same map
+        #[structopt(long)]
+        prebaked: String,
+        /// Report how many movements and hours have a GEH below this
+        #[structopt(long, default_value = "5.0")]
+        geh_threshold: f64,
+        /// The path to write a CSV with every movement and hour
+        #[structopt(long)]
+        report: Option<String>,
+    },
     /// Clips an OSM file to a boundary. This is a simple Rust port of `osmium extract large_map.osm
     /// -p clipping.poly -o smaller_map.osm`.
     ClipOSM {
@@ -79,6 +141,32 @@ enum Command {
         #[structopt(long)]
         out_path: String,
     },
+    /// Import real traffic counts from a CSV or GeoJSON file, snapping each count station to the
+    /// nearest road going the same direction. See `TimeBinnedCounts::import_csv` for the format.
+    ImportCounts {
+        /// The path to a CSV or .geojson file with counts
+        #[structopt(long)]
+        input: String,
+        /// The path to the map to snap count stations to
+        #[structopt(long)]
+        map: String,
+        /// Describes the counts. Defaults to the input filename.
+        #[structopt(long)]
+        description: Option<String>,
+        /// How many minutes each count covers
+        #[structopt(long, default_value = "15")]
+        bin_minutes: usize,
+        /// Skip count stations further than this many meters from a suitable road
+        #[structopt(long, default_value = "50")]
+        max_snap_dist_meters: f64,
+        /// The input is a CSV file with turning movement counts at intersections. See
+        /// `TurningMovementCounts::import_csv` for the format.
+        #[structopt(long)]
+        tmc: bool,
+        /// The path to write the imported counts as JSON
+        #[structopt(long)]
+        output: String,
+    },
     /// Import a scenario from https://github.com/asu-trans-ai-lab/grid2demand.
     ImportGrid2Demand {
         /// The path to a grid2demand CSV file
@@ -222,6 +310,89 @@ enum Command {
         #[structopt()]
         scenario_path: String,
     },
+    /// Search for traffic signal timing that reduces delay, by repeatedly simulating a scenario.
+    /// Writes the result as map edits.
+    OptimizeSignals {
+        /// The path to a scenario file
+        #[structopt(long)]
+        scenario: String,
+        /// The path to map edits to apply first
+        #[structopt(long)]
+        edits: Option<String>,
+        /// A comma-separated list of intersection IDs to retime, like "12,13,40"
+        #[structopt(long, parse(try_from_str = parse_intersections))]
+        intersections: IntersectionList,
+        /// coordinate-descent, random-search, or webster
+        #[structopt(long, default_value = "coordinate-descent")]
+        method: optimize_signals::Method,
+        /// How many rounds of candidates to simulate
+        #[structopt(long, default_value = "10")]
+        iterations: usize,
+        /// How long to simulate each candidate, like "24:00:00"
+        #[structopt(long, parse(try_from_str = geom::Duration::parse), default_value = "24:00:00")]
+        duration: geom::Duration,
+        /// A seed for generating random numbers
+        #[structopt(long, default_value = "42")]
+        rng_seed: u64,
+        /// The path to write the resulting map edits
+        #[structopt(long)]
+        output: String,
+        /// The path to write a before/after comparison of delay per movement. If omitted, print
+        /// it instead.
+        #[structopt(long)]
+        report: Option<String>,
+    },
+    /// Estimate how many vehicles use each road with static traffic assignment (user equilibrium
+    /// with BPR volume-delay functions), much faster than simulating the scenario. Writes the
+    /// volumes as traffic counts, to compare with other counts.
+    StaticAssignment {
+        /// The path to a scenario file
+        #[structopt(long)]
+        scenario: String,
+        /// Only include trips departing at or after this time, like "07:00:00". Road capacities
+        /// cover the time until --end-time. Without this, the entire day is used.
+        #[structopt(long, parse(try_from_str = geom::Time::parse))]
+        start_time: Option<geom::Time>,
+        /// Only include trips departing before this time
+        #[structopt(long, parse(try_from_str = geom::Time::parse))]
+        end_time: Option<geom::Time>,
+        /// Stop after this many iterations
+        #[structopt(long, default_value = "20")]
+        max_iterations: usize,
+        /// Stop once the relative gap from equilibrium is below this
+        #[structopt(long, default_value = "0.01")]
+        relative_gap: f64,
+        /// The path to write volumes per road and intersection
+        #[structopt(long)]
+        output: String,
+        /// The path to write each road's volume-to-capacity ratio, as a percent
+        #[structopt(long)]
+        vc_output: Option<String>,
+    },
+    /// Retime every fixed traffic signal in a map with Webster's method, using demand from routing
+    /// the scenario's trips. Writes the result as map edits.
+    TimeSignals {
+        /// The path to a scenario file
+        #[structopt(long)]
+        scenario: String,
+        /// The path to map edits to apply first
+        #[structopt(long)]
+        edits: Option<String>,
+        /// The path to turning movement counts, imported with `import-counts --tmc`. Signals with
+        /// these counts are timed from them, instead of from the scenario.
+        #[structopt(long)]
+        tmc: Option<String>,
+        /// Only count trips departing at or after this time, like "07:00:00". This should usually
+        /// be the start of a peak period.
+        #[structopt(long, parse(try_from_str = geom::Time::parse), default_value = "07:00:00")]
+        start_time: geom::Time,
+        /// Only count trips departing before this time
+        #[structopt(long, parse(try_from_str = geom::Time::parse), default_value = "09:00:00")]
+        end_time: geom::Time,
+        /// The path to write the resulting map edits
+        #[structopt(long)]
+        output: String,
+    },
 }
 
 // See https://github.com/TeXitoi/structopt/issues/94
@@ -231,6 +402,35 @@ fn parse_modifiers(x: &str) -> Result<ModifierList> {
     abstutil::from_json(&x.to_string().into_bytes())
 }
 
+fn parse_days(x: &str) -> Result<ModifierList> {
+    let mut list = Vec::new();
+    for day in x
+        .split(',')
+        .map(|day| day.trim())
+        .filter(|day| !day.is_empty())
+    {
+        let (day_type, scenario) = match day.split_once('=') {
+            Some((day_type, scenario)) => (day_type, Some(scenario.to_string())),
+            None => (day, None),
+        };
+        list.push(synthpop::ScenarioModifier::AddDay {
+            day_type: synthpop::DayType::parse(day_type)?,
+            scenario,
+        });
+    }
+    Ok(list)
+}
+
+type IntersectionList = Vec<map_model::IntersectionID>;
+
+fn parse_intersections(x: &str) -> Result<IntersectionList> {
+    let mut list = Vec::new();
+    for id in x.split(',') {
+        list.push(map_model::IntersectionID(id.trim().parse::<usize>()?));
+    }
+    Ok(list)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cmd = Command::from_args();
@@ -257,21 +457,69 @@ async fn main() -> Result<()> {
             add_return_trips,
             add_lunch_trips,
             scenario_modifiers,
+            add_days,
             delete_cancelled_trips,
             rng_seed,
         } => augment_scenario::run(
             input_scenario,
             add_return_trips,
             add_lunch_trips,
-            scenario_modifiers,
+            add_days.into_iter().chain(scenario_modifiers).collect(),
             delete_cancelled_trips,
             rng_seed,
         ),
+        Command::CalibrateScenario {
+            scenario,
+            counts,
+            max_iterations,
+            geh_tolerance,
+            max_scale,
+            rng_seed,
+            output_name,
+            report,
+        } => calibrate_scenario::run(calibrate_scenario::Args {
+            scenario,
+            counts,
+            max_iterations,
+            geh_tolerance,
+            max_scale,
+            rng_seed,
+            output_name,
+            report,
+        })?,
+        Command::CompareTMC {
+            tmc,
+            prebaked,
+            geh_threshold,
+            report,
+        } => compare_tmc::run(compare_tmc::Args {
+            tmc,
+            prebaked,
+            geh_threshold,
+            report,
+        })?,
         Command::ClipOSM {
             pbf_path,
             clip_path,
             out_path,
         } => clip_osm::run(pbf_path, clip_path, out_path)?,
+        Command::ImportCounts {
+            input,
+            map,
+            description,
+            bin_minutes,
+            max_snap_dist_meters,
+            tmc,
+            output,
+        } => import_counts::run(
+            input,
+            map,
+            description,
+            bin_minutes,
+            max_snap_dist_meters,
+            tmc,
+            output,
+        )?,
         Command::ImportGrid2Demand { input, map } => import_grid2demand::run(input, map)?,
         Command::ImportScenario {
             input,
@@ -340,6 +588,52 @@ async fn main() -> Result<()> {
         Command::RegenerateEverythingExternally => regenerate_everything_externally()?,
         Command::Import { job } => job.run(&mut Timer::new("import one city")).await,
         Command::PrebakeScenario { scenario_path } => prebake_scenario(scenario_path),
+        Command::OptimizeSignals {
+            scenario,
+            edits,
+            intersections,
+            method,
+            iterations,
+            duration,
+            rng_seed,
+            output,
+            report,
+        } => optimize_signals::run(optimize_signals::Args {
+            scenario,
+            edits,
+            intersections,
+            method,
+            iterations,
+            duration,
+            rng_seed,
+            output,
+            report,
+        })?,
+        Command::StaticAssignment {
+            scenario,
+            start_time,
+            end_time,
+            max_iterations,
+            relative_gap,
+            output,
+            vc_output,
+        } => static_assignment::run(static_assignment::Args {
+            scenario,
+            start_time,
+            end_time,
+            max_iterations,
+            relative_gap,
+            output,
+            vc_output,
+        })?,
+        Command::TimeSignals {
+            scenario,
+            edits,
+            tmc,
+            start_time,
+            end_time,
+            output,
+        } => time_signals::run(scenario, edits, tmc, start_time, end_time, output)?,
     }
     Ok(())
 }
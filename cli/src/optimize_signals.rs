@@ -0,0 +1,477 @@
+//! Search for traffic signal timing that minimizes delay, by repeatedly simulating a scenario with
+//! different stage durations and offsets.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+
+use abstutil::{prettyprint_usize, Timer};
+use geom::Duration;
+use map_model::{
+    CompressedMovementID, ControlTrafficSignal, EditIntersectionControl, IntersectionID, Map,
+    MapEdits, MovementID, StageType,
+};
+use sim::{AgentType, AlertHandler, Sim, SimOptions};
+use synthpop::Scenario;
+
+// Coordinate descent nudges one stage duration or offset by this much at a time
+const STEP: Duration = Duration::const_seconds(5.0);
+// Random search changes stage durations by at most this much
+const MAX_RANDOM_CHANGE: f64 = 15.0;
+// How many random candidates to simulate per iteration
+const RANDOM_BATCH_SIZE: usize = 8;
+
+pub enum Method {
+    /// Repeatedly try nudging every stage duration and offset up or down, keeping the single
+    /// change that helps most
+    CoordinateDescent,
+    /// Repeatedly try a batch of random changes to all signals, keeping the best
+    RandomSearch,
+    /// Time every signal with Webster's method using the demand from the original run, then
+    /// refine with coordinate descent
+    Webster,
+}
+
+impl FromStr for Method {
+    type Err = anyhow::Error;
+
+    fn from_str(x: &str) -> Result<Method> {
+        match x {
+            "coordinate-descent" => Ok(Method::CoordinateDescent),
+            "random-search" => Ok(Method::RandomSearch),
+            "webster" => Ok(Method::Webster),
+            _ => bail!(
+                "Unknown method {}; try coordinate-descent, random-search, or webster",
+                x
+            ),
+        }
+    }
+}
+
+pub struct Args {
+    pub scenario: String,
+    pub edits: Option<String>,
+    pub intersections: Vec<IntersectionID>,
+    pub method: Method,
+    pub iterations: usize,
+    pub duration: Duration,
+    pub rng_seed: u64,
+    pub output: String,
+    pub report: Option<String>,
+}
+
+/// The results of simulating one candidate timing
+#[derive(Clone)]
+struct Evaluation {
+    total_delay: Duration,
+    /// Total delay and number of agents for each movement
+    per_movement: BTreeMap<MovementID, (Duration, usize)>,
+    /// Vehicles per hour through each movement
+    vehicle_flow: BTreeMap<MovementID, f64>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let mut timer = Timer::new("optimize traffic signals");
+    let scenario: Scenario = abstio::read_object(args.scenario.clone(), &mut timer)?;
+    let mut map = Map::load_synchronously(scenario.map_name.path(), &mut timer);
+    if let Some(path) = args.edits.clone() {
+        let edits = MapEdits::load_from_file(&map, path, &mut timer)?;
+        map.must_apply_edits(edits, &mut timer);
+        map.recalculate_pathfinding_after_edits(&mut timer);
+    }
+    if args.intersections.is_empty() {
+        bail!("Pass at least one intersection to optimize");
+    }
+    for i in &args.intersections {
+        if map.maybe_get_traffic_signal(*i).is_none() {
+            bail!("{} isn't a traffic signal", i);
+        }
+    }
+
+    let original: Vec<ControlTrafficSignal> = args
+        .intersections
+        .iter()
+        .map(|i| map.get_traffic_signal(*i).clone())
+        .collect();
+    let baseline = evaluate_all(&map, &scenario, &args, vec![original.clone()], &mut timer)
+        .pop()
+        .unwrap();
+    info!("Original timing has {} of delay", baseline.total_delay);
+
+    let (best, best_eval) = match args.method {
+        Method::CoordinateDescent => coordinate_descent(
+            &map,
+            &scenario,
+            &args,
+            original,
+            baseline.clone(),
+            &mut timer,
+        ),
+        Method::RandomSearch => random_search(
+            &map,
+            &scenario,
+            &args,
+            original,
+            baseline.clone(),
+            &mut timer,
+        ),
+        Method::Webster => {
+            let mut seed = original;
+            for ts in &mut seed {
+                let demand = baseline
+                    .vehicle_flow
+                    .iter()
+                    .filter(|(m, _)| m.parent == ts.id)
+                    .map(|(m, flow)| (*m, *flow))
+                    .collect();
+                ts.apply_webster_timing(&map, &demand)?;
+            }
+            let seed_eval = evaluate_all(&map, &scenario, &args, vec![seed.clone()], &mut timer)
+                .pop()
+                .unwrap();
+            info!("Webster's method gives {} of delay", seed_eval.total_delay);
+            coordinate_descent(&map, &scenario, &args, seed, seed_eval, &mut timer)
+        }
+    };
+
+    // Express the result as edits on top of whatever was loaded
+    let mut edits = map.get_edits().clone();
+    for ts in &best {
+        if ts != map.get_traffic_signal(ts.id) {
+            edits.commands.push(map.edit_intersection_cmd(ts.id, |new| {
+                new.control = EditIntersectionControl::TrafficSignal(ts.export(&map));
+            }));
+        }
+    }
+    if args.edits.is_none() {
+        edits.edits_name = abstutil::basename(&args.output);
+    }
+    abstio::write_json(args.output.clone(), &edits.to_permanent(&map));
+    println!("Wrote {}", args.output);
+
+    let report = make_report(&map, &baseline, &best_eval);
+    if let Some(path) = args.report {
+        abstio::write_file(path.clone(), report)?;
+        println!("Wrote {}", path);
+    } else {
+        println!("{}", report);
+    }
+    Ok(())
+}
+
+/// Simulate every candidate in parallel
+fn evaluate_all(
+    map: &Map,
+    scenario: &Scenario,
+    args: &Args,
+    candidates: Vec<Vec<ControlTrafficSignal>>,
+    timer: &mut Timer,
+) -> Vec<Evaluation> {
+    let duration = args.duration;
+    let rng_seed = args.rng_seed;
+    timer.parallelize(
+        &format!("simulate {} candidates", candidates.len()),
+        candidates,
+        |signals| evaluate(map, scenario, duration, rng_seed, signals),
+    )
+}
+
+fn evaluate(
+    map: &Map,
+    scenario: &Scenario,
+    duration: Duration,
+    rng_seed: u64,
+    signals: Vec<ControlTrafficSignal>,
+) -> Evaluation {
+    let mut map = map.clone();
+    let intersections: BTreeSet<IntersectionID> = signals.iter().map(|ts| ts.id).collect();
+    for ts in signals {
+        map.incremental_edit_traffic_signal(ts);
+    }
+
+    let mut opts = SimOptions::new("optimize_signals");
+    opts.alerts = AlertHandler::Silence;
+    let mut sim = Sim::new(&map, opts);
+    let mut rng = XorShiftRng::seed_from_u64(rng_seed);
+    sim.instantiate(scenario, &map, &mut rng, &mut Timer::throwaway());
+    sim.timed_step(&map, duration, &mut None, &mut Timer::throwaway());
+
+    let analytics = sim.get_analytics();
+    let mut total_delay = Duration::ZERO;
+    let mut per_movement = BTreeMap::new();
+    let mut vehicle_flow = BTreeMap::new();
+    let vehicles: BTreeSet<AgentType> = vec![AgentType::Car, AgentType::Bike, AgentType::Bus]
+        .into_iter()
+        .collect();
+    for i in intersections {
+        let movements: Vec<MovementID> = map.get_i(i).movements.keys().cloned().collect();
+        if let Some(list) = analytics.intersection_delays.get(&i) {
+            for (idx, _, dt, _) in list {
+                total_delay += *dt;
+                let entry = per_movement
+                    .entry(movements[*idx as usize])
+                    .or_insert((Duration::ZERO, 0));
+                entry.0 += *dt;
+                entry.1 += 1;
+            }
+        }
+        for (idx, m) in movements.into_iter().enumerate() {
+            let count = analytics.traffic_signal_thruput.total_for_with_agent_types(
+                CompressedMovementID { i, idx: idx as u8 },
+                vehicles.clone(),
+            );
+            vehicle_flow.insert(m, (count as f64) / (duration.inner_seconds() / 3600.0));
+        }
+    }
+
+    Evaluation {
+        total_delay,
+        per_movement,
+        vehicle_flow,
+    }
+}
+
+fn coordinate_descent(
+    map: &Map,
+    scenario: &Scenario,
+    args: &Args,
+    mut best: Vec<ControlTrafficSignal>,
+    mut best_eval: Evaluation,
+    timer: &mut Timer,
+) -> (Vec<ControlTrafficSignal>, Evaluation) {
+    for iteration in 0..args.iterations {
+        let mut candidates = Vec::new();
+        for (idx, ts) in best.iter().enumerate() {
+            for stage in 0..ts.stages.len() {
+                for delta in [STEP, -STEP] {
+                    let mut signals = best.clone();
+                    if change_duration(&mut signals[idx], stage, delta, map) {
+                        candidates.push(signals);
+                    }
+                }
+            }
+            let cycle = ts.simple_cycle_duration();
+            // Shifting the offset by a whole cycle or more doesn't change anything
+            if cycle <= STEP {
+                continue;
+            }
+            for delta in [STEP, cycle - STEP] {
+                let mut signals = best.clone();
+                signals[idx].offset = wrap_offset(ts.offset + delta, cycle);
+                candidates.push(signals);
+            }
+        }
+
+        let evals = evaluate_all(map, scenario, args, candidates.clone(), timer);
+        let improved = keep_best(&mut best, &mut best_eval, candidates.into_iter().zip(evals));
+        info!(
+            "After iteration {}, best timing has {} of delay",
+            iteration + 1,
+            best_eval.total_delay
+        );
+        if !improved {
+            info!("No change helps, so stopping early");
+            break;
+        }
+    }
+    (best, best_eval)
+}
+
+fn random_search(
+    map: &Map,
+    scenario: &Scenario,
+    args: &Args,
+    mut best: Vec<ControlTrafficSignal>,
+    mut best_eval: Evaluation,
+    timer: &mut Timer,
+) -> (Vec<ControlTrafficSignal>, Evaluation) {
+    let mut rng = XorShiftRng::seed_from_u64(args.rng_seed);
+    for iteration in 0..args.iterations {
+        let mut candidates = Vec::new();
+        for _ in 0..RANDOM_BATCH_SIZE {
+            let mut signals = best.clone();
+            for ts in &mut signals {
+                for stage in 0..ts.stages.len() {
+                    let delta = rng
+                        .gen_range(-MAX_RANDOM_CHANGE..=MAX_RANDOM_CHANGE)
+                        .round();
+                    change_duration(ts, stage, Duration::seconds(delta), map);
+                }
+                let cycle = ts.simple_cycle_duration();
+                if cycle > Duration::ZERO {
+                    ts.offset =
+                        Duration::seconds(rng.gen_range(0.0..cycle.inner_seconds()).round());
+                }
+            }
+            candidates.push(signals);
+        }
+
+        let evals = evaluate_all(map, scenario, args, candidates.clone(), timer);
+        keep_best(&mut best, &mut best_eval, candidates.into_iter().zip(evals));
+        info!(
+            "After iteration {}, best timing has {} of delay",
+            iteration + 1,
+            best_eval.total_delay
+        );
+    }
+    (best, best_eval)
+}
+
+/// Replace the best candidate with any that has strictly less total delay. Returns true if
+/// anything changed.
+fn keep_best<T>(
+    best: &mut T,
+    best_eval: &mut Evaluation,
+    results: impl Iterator<Item = (T, Evaluation)>,
+) -> bool {
+    let mut improved = false;
+    for (candidate, eval) in results {
+        if eval.total_delay < best_eval.total_delay {
+            *best = candidate;
+            *best_eval = eval;
+            improved = true;
+        }
+    }
+    improved
+}
+
+/// Change the duration of one stage. For variable stages, this changes the minimum. Returns false
+/// if the result isn't valid.
+fn change_duration(
+    ts: &mut ControlTrafficSignal,
+    stage: usize,
+    delta: Duration,
+    map: &Map,
+) -> bool {
+    let orig = ts.stages[stage].stage_type.clone();
+    let new_duration = orig.simple_duration() + delta;
+    if new_duration < Duration::seconds(1.0) {
+        return false;
+    }
+    ts.stages[stage].stage_type = match orig {
+        StageType::Fixed(_) => StageType::Fixed(new_duration),
+        StageType::Variable(_, delay, additional) => {
+            StageType::Variable(new_duration, delay, additional)
+        }
+    };
+    if ts.validate(map.get_i(ts.id)).is_err() {
+        ts.stages[stage].stage_type = orig;
+        return false;
+    }
+    true
+}
+
+/// The cycle must be positive
+fn wrap_offset(offset: Duration, cycle: Duration) -> Duration {
+    let secs = offset.inner_seconds().rem_euclid(cycle.inner_seconds());
+    Duration::seconds(secs)
+}
+
+/// Compare delay per movement before and after optimizing
+fn make_report(map: &Map, before: &Evaluation, after: &Evaluation) -> String {
+    let mut lines = vec![
+        format!(
+            "Total delay: {} before, {} after",
+            before.total_delay, after.total_delay
+        ),
+        String::new(),
+    ];
+    let movements: BTreeSet<MovementID> = before
+        .per_movement
+        .keys()
+        .chain(after.per_movement.keys())
+        .cloned()
+        .collect();
+    for m in movements {
+        let (delay1, count1) = before
+            .per_movement
+            .get(&m)
+            .cloned()
+            .unwrap_or((Duration::ZERO, 0));
+        let (delay2, count2) = after
+            .per_movement
+            .get(&m)
+            .cloned()
+            .unwrap_or((Duration::ZERO, 0));
+        lines.push(format!(
+            "{}: {} -> {}{}",
+            m.parent,
+            map.get_r(m.from.road).get_name(None),
+            map.get_r(m.to.road).get_name(None),
+            if m.crosswalk { " (crosswalk)" } else { "" },
+        ));
+        lines.push(format!(
+            "  before: {} agents, {} total delay",
+            prettyprint_usize(count1),
+            delay1
+        ));
+        lines.push(format!(
+            "  after: {} agents, {} total delay",
+            prettyprint_usize(count2),
+            delay2
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(delay: f64) -> Evaluation {
+        Evaluation {
+            total_delay: Duration::seconds(delay),
+            per_movement: BTreeMap::new(),
+            vehicle_flow: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_keep_best_minimizes_delay() {
+        let mut best = "original";
+        let mut best_eval = eval(100.0);
+        let candidates = vec![
+            ("worse", eval(120.0)),
+            ("better", eval(80.0)),
+            ("best", eval(60.0)),
+            ("in between", eval(70.0)),
+        ];
+        assert!(keep_best(&mut best, &mut best_eval, candidates.into_iter()));
+        assert_eq!(best, "best");
+        assert_eq!(best_eval.total_delay, Duration::seconds(60.0));
+    }
+
+    #[test]
+    fn test_keep_best_ignores_ties() {
+        let mut best = "original";
+        let mut best_eval = eval(100.0);
+        let candidates = vec![("tie", eval(100.0)), ("worse", eval(150.0))];
+        assert!(!keep_best(
+            &mut best,
+            &mut best_eval,
+            candidates.into_iter()
+        ));
+        assert_eq!(best, "original");
+    }
+
+    #[test]
+    fn test_wrap_offset() {
+        let cycle = Duration::seconds(60.0);
+        assert_eq!(
+            wrap_offset(Duration::seconds(15.0), cycle),
+            Duration::seconds(15.0)
+        );
+        assert_eq!(
+            wrap_offset(Duration::seconds(70.0), cycle),
+            Duration::seconds(10.0)
+        );
+        assert_eq!(wrap_offset(Duration::seconds(60.0), cycle), Duration::ZERO);
+        assert_eq!(
+            wrap_offset(Duration::seconds(-5.0), cycle),
+            Duration::seconds(55.0)
+        );
+    }
+}
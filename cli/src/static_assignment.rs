@@ -0,0 +1,67 @@
+//! Estimate traffic volumes for a scenario with static traffic assignment, much faster than
+//! simulating it. The results can be compared against other counts like anything else.
+
+use anyhow::{bail, Result};
+
+use abstutil::{prettyprint_usize, Timer};
+use geom::Time;
+use map_model::{AssignmentOptions, Map};
+use synthpop::Scenario;
+
+pub struct Args {
+    pub scenario: String,
+    pub start_time: Option<Time>,
+    pub end_time: Option<Time>,
+    pub max_iterations: usize,
+    pub relative_gap: f64,
+    pub output: String,
+    pub vc_output: Option<String>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let window = match (args.start_time, args.end_time) {
+        (Some(t1), Some(t2)) => {
+            if t2 <= t1 {
+                bail!("--end-time must be after --start-time");
+            }
+            Some((t1, t2))
+        }
+        (None, None) => None,
+        _ => bail!("Pass both --start-time and --end-time, or neither"),
+    };
+
+    let mut timer = Timer::new("static traffic assignment");
+    let scenario: Scenario = abstio::read_object(args.scenario, &mut timer)?;
+    let map = Map::load_synchronously(scenario.map_name.path(), &mut timer);
+    let opts = AssignmentOptions {
+        max_iterations: args.max_iterations,
+        relative_gap: args.relative_gap,
+        ..Default::default()
+    };
+    let assigned = synthpop::assign_traffic(&scenario, &map, window, &opts, &mut timer);
+
+    println!(
+        "After {} iterations, the relative gap is {:.4}. {} vehicles couldn't be routed.",
+        assigned.result.iterations,
+        assigned.result.relative_gap,
+        prettyprint_usize(assigned.result.unroutable)
+    );
+    let over_capacity = assigned
+        .volume_to_capacity
+        .per_road
+        .borrow()
+        .values()
+        .filter(|pct| **pct > 100)
+        .count();
+    println!(
+        "{} roads are over capacity",
+        prettyprint_usize(over_capacity)
+    );
+    abstio::write_json(args.output.clone(), &assigned.volumes);
+    println!("Wrote {}", args.output);
+    if let Some(path) = args.vc_output {
+        abstio::write_json(path.clone(), &assigned.volume_to_capacity);
+        println!("Wrote {}", path);
+    }
+    Ok(())
+}
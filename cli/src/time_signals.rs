@@ -0,0 +1,99 @@
+//! Retime every traffic signal in a map with Webster's method, using demand predicted by routing
+//! the trips in a scenario. Signals with turning movement counts use those instead.
+
+use anyhow::{bail, Result};
+
+use abstutil::{prettyprint_usize, Timer};
+use geom::Time;
+use map_model::{
+    demand_from_path_requests, demand_per_hour, EditIntersectionControl, Map, MapEdits,
+    PathRequest, SignalControl,
+};
+use synthpop::{Scenario, TripEndpoint, TurningMovementCounts};
+
+pub fn run(
+    scenario: String,
+    edits: Option<String>,
+    tmc: Option<String>,
+    start_time: Time,
+    end_time: Time,
+    output: String,
+) -> Result<()> {
+    if end_time <= start_time {
+        bail!("--end-time must be after --start-time");
+    }
+
+    let mut timer = Timer::new("time traffic signals from demand");
+    let scenario: Scenario = abstio::read_object(scenario, &mut timer)?;
+    let mut map = Map::load_synchronously(scenario.map_name.path(), &mut timer);
+    if let Some(path) = edits.clone() {
+        let edits = MapEdits::load_from_file(&map, path, &mut timer)?;
+        map.must_apply_edits(edits, &mut timer);
+        map.recalculate_pathfinding_after_edits(&mut timer);
+    }
+
+    // Only trips departing during the time window count
+    let requests: Vec<PathRequest> = scenario
+        .all_trips()
+        .filter(|trip| !trip.cancelled && trip.depart >= start_time && trip.depart < end_time)
+        .filter_map(|trip| TripEndpoint::path_req(trip.origin, trip.destination, trip.mode, &map))
+        .collect();
+    info!(
+        "{} trips depart between {} and {}",
+        prettyprint_usize(requests.len()),
+        start_time.ampm_tostring(),
+        end_time.ampm_tostring()
+    );
+    let mut counts =
+        demand_from_path_requests(&map, PathRequest::deduplicate(&map, requests), &mut timer);
+    if let Some(path) = tmc {
+        let tmc: TurningMovementCounts = abstio::read_object(path, &mut timer)?;
+        if &tmc.map != map.get_name() {
+            bail!("Turning movement counts are for {}", tmc.map.describe());
+        }
+        // Real counts are better than predictions, so don't mix the two at one intersection
+        let observed = tmc.intersections();
+        counts.retain(|m, _| !observed.contains(&m.parent));
+        counts.extend(tmc.vehicle_counts(start_time, end_time));
+        info!(
+            "Using turning movement counts for {} intersections",
+            prettyprint_usize(observed.len())
+        );
+    }
+    let demand = demand_per_hour(&counts, end_time - start_time);
+
+    let mut new_edits = map.get_edits().clone();
+    let mut retimed = 0;
+    for i in map.all_intersections() {
+        if !i.is_traffic_signal() {
+            continue;
+        }
+        let mut ts = map.get_traffic_signal(i.id).clone();
+        if ts.control != SignalControl::Cycle {
+            // Adaptive signals pick their own timing
+            continue;
+        }
+        if let Err(err) = ts.apply_webster_timing(&map, &demand) {
+            warn!("Couldn't retime {}: {}", i.id, err);
+            continue;
+        }
+        if &ts != map.get_traffic_signal(i.id) {
+            new_edits
+                .commands
+                .push(map.edit_intersection_cmd(i.id, |new| {
+                    new.control = EditIntersectionControl::TrafficSignal(ts.export(&map));
+                }));
+            retimed += 1;
+        }
+    }
+    if edits.is_none() {
+        new_edits.edits_name = abstutil::basename(&output);
+    }
+    abstio::write_json(output.clone(), &new_edits.to_permanent(&map));
+    println!(
+        "Retimed {} traffic signals, wrote {}",
+        prettyprint_usize(retimed),
+        output
+    );
+    Ok(())
+}
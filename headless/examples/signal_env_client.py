@@ -0,0 +1,60 @@
+#!/usr/bin/python3
+# This example trains nothing, but shows how a reinforcement learning agent
+# would drive the traffic signal environment. Every signal in the map is
+# controlled externally; every step, this controller switches to the next stage
+# once the current one has lasted long enough.
+#
+# Before running this script, start the API server:
+#
+# > cargo run --release --bin headless -- --port=1234 --alerts=silence
+#
+# You may need to install https://requests.readthedocs.io
+# Keep this script formatted with autopep8 -i
+
+import argparse
+import requests
+
+
+def main():
+    parser = argparse.ArgumentParser()
+    parser.add_argument('--api', default='http://localhost:1234')
+    parser.add_argument('--country_code', default='us')
+    parser.add_argument('--city_name', default='seattle')
+    parser.add_argument('--map_name', default='montlake')
+    parser.add_argument('--hours', type=int, default=1)
+    parser.add_argument('--min_stage_seconds', type=int, default=20)
+    args = parser.parse_args()
+
+    observations = requests.post(args.api + '/env/reset', json={
+        'scenario': 'data/system/{}/{}/scenarios/{}/weekday.bin'.format(
+            args.country_code, args.city_name, args.map_name),
+        'config': {
+            'step_duration': 5.0,
+            'end_time': args.hours * 3600.0,
+            'reward': 'Delay',
+        },
+    }).json()
+    print('Controlling {} traffic signals'.format(len(observations)))
+
+    total_reward = 0.0
+    while True:
+        actions = {}
+        for i, obs in observations.items():
+            if obs['time_in_stage'] >= args.min_stage_seconds:
+                actions[i] = 'Next'
+            else:
+                actions[i] = 'Hold'
+        resp = requests.post(args.api + '/env/step', json=actions)
+        if resp.status_code != 200:
+            raise Exception(resp.text)
+        result = resp.json()
+        observations = result['observations']
+        total_reward += result['total_reward']
+        if result['done']:
+            break
+    print('Finished at {}s with a total reward of {:.1f}'.format(
+        result['time'], total_reward))
+
+
+if __name__ == '__main__':
+    main()
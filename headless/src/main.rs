@@ -6,8 +6,26 @@
 //! 00:00:00.0
 //! > curl http://localhost:1234/sim/goto-time?t=01:01:00
 //! it's now 01:01:00.0
+//! > curl http://localhost:1234/sim/step?dt=30
+//! ... JSON observation of signals, queues, finished trips, and alerts
+//! > curl -X POST -d '{"scenario": "..."}' http://localhost:1234/env/reset
+//! ... JSON observation of every traffic signal, now controlled through /env/step
 //! > curl http://localhost:1234/data/get-road-thruput
 //! ... huge JSON blob
+//! > curl http://localhost:1234/data/get-queue-lengths?lane=1234&t1=07:00:00&t2=09:00:00
+//! ... JSON samples of the queue on one lane, if run with --queue_sample_interval=60
+//! > curl http://localhost:1234/detectors/export-csv
+//! ... CSV of counts and occupancy from every detector, every 15 minutes
+//! > curl http://localhost:1234/data/get-emissions
+//! ... JSON of estimated CO2, NOx, and fuel per vehicle type, trip, road, and intersection
+//! > curl -X POST -d '[{"start": 28800.0, "end": 30600.0, "location": {"Road": 123}, "effect": "Closure"}]' http://localhost:1234/incidents/add
+//! ... JSON list of incident IDs
+//! > curl -X POST --data-binary @counts.json http://localhost:1234/data/compare-counts
+//! ... JSON comparing real counts against the simulation, per hour, with GEH for each
+//! > curl -X POST --data-binary @tmc.json http://localhost:1234/data/compare-tmc
+//! ... the same, for turning movement counts at traffic signals
+//! > curl http://localhost:1234/data/get-daily-summaries
+//! ... JSON of finished trips and throughput for each day of a multi-day scenario
 
 #[macro_use]
 extern crate anyhow;
@@ -28,17 +46,23 @@ use abstio::MapName;
 use abstutil::{serialize_btreemap, Timer};
 use geom::{Distance, Duration, FindClosest, LonLat, Time};
 use map_model::{
-    CompressedMovementID, ControlTrafficSignal, EditIntersectionControl, IntersectionID, Map,
-    MovementID, PermanentMapEdits, RoadID, TurnID,
+    CompressedMovementID, ControlTrafficSignal, EditIntersectionControl, IntersectionID, LaneID,
+    Map, MovementID, PermanentMapEdits, RoadID, SignalCorridor, TurnID,
 };
+use signal_env::{Action, ResetArgs, SignalEnv};
 use sim::{
-    AgentID, AgentType, DelayCause, PersonID, Sim, SimFlags, SimOptions, TripID, VehicleType,
+    AgentID, AgentType, AlertLocation, DelayCause, DetectorID, DetectorSpec, Emissions, Incident,
+    IncidentID, PersonID, QueueSample, RerouteReason, Sim, SimFlags, SimOptions, TripID,
+    VehicleType,
+};
+use synthpop::{
+    ExternalPerson, Scenario, ScenarioModifier, TimeBinnedCounts, TripMode, TurningMovementCounts,
 };
-use synthpop::{ExternalPerson, Scenario, ScenarioModifier, TripMode};
 
 lazy_static::lazy_static! {
     static ref MAP: RwLock<Map> = RwLock::new(Map::blank());
     static ref SIM: RwLock<Sim> = RwLock::new(Sim::new(&Map::blank(), SimOptions::new("tmp")));
+    static ref ENV: RwLock<SignalEnv> = RwLock::new(SignalEnv::new(SimOptions::new("tmp")));
     static ref LOAD: RwLock<LoadSim> = RwLock::new({
         LoadSim {
             scenario: abstio::path_scenario(&MapName::seattle("montlake"), "weekday"),
@@ -77,6 +101,7 @@ async fn main() {
         let mut load = LOAD.write().unwrap();
         load.rng_seed = args.rng_seed;
         load.opts = args.opts;
+        *ENV.write().unwrap() = SignalEnv::new(load.opts.clone());
 
         let (map, sim) = load.setup(&mut Timer::new("setup headless"));
         *MAP.write().unwrap() = map;
@@ -112,6 +137,7 @@ async fn serve_req(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
             &mut SIM.write().unwrap(),
             &mut MAP.write().unwrap(),
             &mut LOAD.write().unwrap(),
+            &mut ENV.write().unwrap(),
         ) {
             Ok(resp) => Response::new(Body::from(resp)),
             Err(err) => {
@@ -132,6 +158,7 @@ fn handle_command(
     sim: &mut Sim,
     map: &mut Map,
     load: &mut LoadSim,
+    env: &mut SignalEnv,
 ) -> Result<String> {
     let get = |key: &str| {
         params
@@ -178,6 +205,20 @@ fn handle_command(
                 Ok(format!("it's now {}", t))
             }
         }
+        "/sim/step" => {
+            let dt = Duration::parse(get("dt")?)?;
+            if dt <= Duration::ZERO {
+                bail!("dt must be positive, not {}", dt);
+            }
+            let config: ObservationConfig = if body.is_empty() {
+                ObservationConfig::default()
+            } else {
+                abstutil::from_json(body)?
+            };
+            let t1 = sim.time();
+            sim.timed_step(map, dt, &mut None, &mut Timer::throwaway());
+            Ok(abstutil::to_json(&observe(sim, map, t1, config)?))
+        }
         "/sim/new-person" => {
             let input: ExternalPerson = abstutil::from_json(body)?;
             for trip in &input.trips {
@@ -223,6 +264,48 @@ fn handle_command(
 
             Ok(format!("{} has been updated", id))
         }
+        "/traffic-signals/optimize-corridor" => {
+            let corridor: SignalCorridor = abstutil::from_json(body)?;
+            let timing = corridor.optimize(map)?;
+            if params.get("dry_run").map(|x| x == "true").unwrap_or(false) {
+                return Ok(abstutil::to_json(&timing));
+            }
+
+            let mut edits = map.get_edits().clone();
+            edits.commands.extend(timing.make_edits(map));
+            map.must_apply_edits(edits, &mut Timer::throwaway());
+            map.recalculate_pathfinding_after_edits(&mut Timer::throwaway());
+
+            Ok(abstutil::to_json(&timing))
+        }
+        "/traffic-signals/set-stage" => {
+            let i = IntersectionID(get("id")?.parse::<usize>()?);
+            match (params.get("stage"), params.get("extend")) {
+                (Some(stage), None) => {
+                    let start = sim.set_traffic_signal_stage(map, i, stage.parse::<usize>()?)?;
+                    Ok(format!("{} will switch to stage {} at {}", i, stage, start))
+                }
+                (None, Some(dt)) => {
+                    let dt = Duration::seconds(dt.parse::<f64>()?);
+                    sim.extend_traffic_signal_stage(i, dt)?;
+                    Ok(format!("{} current stage extended by {}", i, dt))
+                }
+                _ => bail!("pass exactly one of the GET parameters stage or extend"),
+            }
+        }
+        "/traffic-signals/set-external-control" => {
+            let i = IntersectionID(get("id")?.parse::<usize>()?);
+            let enabled = get("enabled")?.parse::<bool>()?;
+            sim.set_traffic_signal_external_control(i, enabled)?;
+            if enabled {
+                Ok(format!(
+                    "{} will hold each stage until set-stage is called",
+                    i
+                ))
+            } else {
+                Ok(format!("{} resumed its normal timing", i))
+            }
+        }
         "/traffic-signals/get-delays" => {
             let i = map.get_i(IntersectionID(get("id")?.parse::<usize>()?));
             let t1 = Time::parse(get("t1")?)?;
@@ -273,47 +356,101 @@ fn handle_command(
             }
             Ok(abstutil::to_json(&thruput))
         }
+        "/traffic-signals/get-current-state" => {
+            let i = IntersectionID(get("id")?.parse::<usize>()?);
+            if map.maybe_get_traffic_signal(i).is_none() {
+                bail!("{} isn't a traffic signal", i);
+            }
+            Ok(abstutil::to_json(&get_signal_state(
+                sim,
+                i,
+                &SignalStateField::all(),
+            )))
+        }
+        "/traffic-signals/get-current-state-filtered" => {
+            let query: SignalStateQuery = abstutil::from_json(body)?;
+            let fields = query
+                .fields
+                .map(|list| list.into_iter().collect())
+                .unwrap_or_else(SignalStateField::all);
+            let mut some_state = BTreeMap::new();
+            for i in query.intersections {
+                if map.maybe_get_traffic_signal(i).is_none() {
+                    bail!("{} isn't a traffic signal", i);
+                }
+                some_state.insert(i, get_signal_state(sim, i, &fields));
+            }
+            Ok(abstutil::to_json(&some_state))
+        }
         "/traffic-signals/get-all-current-state" => {
+            let fields = SignalStateField::all();
             let mut all_state = BTreeMap::new();
             for i in map.all_intersections() {
                 if !i.is_traffic_signal() {
                     continue;
                 }
-                let (current_stage_idx, remaining_time) =
-                    sim.current_stage_and_remaining_time(i.id);
-                all_state.insert(
-                    i.id,
-                    TrafficSignalState {
-                        current_stage_idx,
-                        remaining_time,
-                        accepted: sim
-                            .get_accepted_agents(i.id)
-                            .into_iter()
-                            .map(|(a, _)| a)
-                            .collect(),
-                        waiting: sim.get_waiting_agents(i.id),
-                    },
-                );
+                all_state.insert(i.id, get_signal_state(sim, i.id, &fields));
             }
             Ok(abstutil::to_json(&all_state))
         }
+        // Detectors
+        "/detectors/add" => {
+            let specs: Vec<DetectorSpec> = abstutil::from_json(body)?;
+            let mut ids = Vec::new();
+            for spec in specs {
+                ids.push(sim.add_detector(spec, map)?);
+            }
+            Ok(abstutil::to_json(&ids))
+        }
+        "/detectors/get-all" => Ok(abstutil::to_json(&sim.get_detectors().all_detectors())),
+        "/detectors/get-actuations" => {
+            let id = get_detector(sim, get("id")?)?;
+            let t1 = Time::parse(get("t1")?)?;
+            let t2 = Time::parse(get("t2")?)?;
+            Ok(abstutil::to_json(&sim.get_detectors().get_actuations(
+                id,
+                t1,
+                t2,
+                sim.time(),
+            )))
+        }
+        "/detectors/get-counts" => {
+            let id = get_detector(sim, get("id")?)?;
+            Ok(abstutil::to_json(
+                &sim.get_detectors().get_bins(id, sim.time()),
+            ))
+        }
+        "/detectors/export-csv" => Ok(sim.get_detectors().export_csv(sim.time())),
+        // Incidents
+        "/incidents/add" => {
+            let incidents: Vec<Incident> = abstutil::from_json(body)?;
+            let mut ids = Vec::new();
+            for incident in incidents {
+                ids.push(sim.schedule_incident(incident, map)?);
+            }
+            Ok(abstutil::to_json(&ids))
+        }
+        "/incidents/get-all" => Ok(abstutil::to_json(sim.get_incidents())),
+        "/incidents/get-impacts" => {
+            let id = IncidentID(get("id")?.parse::<usize>()?);
+            if id.0 >= sim.get_incidents().len() {
+                bail!("{} doesn't exist", id);
+            }
+            Ok(abstutil::to_json(
+                &sim.get_analytics()
+                    .trips_affected_by_incident(id, sim.time()),
+            ))
+        }
         // Querying data
         "/data/get-finished-trips" => {
-            let mut trips = Vec::new();
-            for (_, id, mode, maybe_duration) in &sim.get_analytics().finished_trips {
-                let distance_crossed = if maybe_duration.is_some() {
-                    sim.finished_trip_details(*id).unwrap().2
-                } else {
-                    Distance::ZERO
-                };
-                trips.push(FinishedTrip {
-                    id: *id,
-                    person: sim.trip_to_person(*id).unwrap(),
-                    duration: *maybe_duration,
-                    distance_crossed,
-                    mode: *mode,
-                });
-            }
+            let trips: Vec<FinishedTrip> = sim
+                .get_analytics()
+                .finished_trips
+                .iter()
+                .map(|(_, id, mode, maybe_duration)| {
+                    FinishedTrip::new(sim, *id, *mode, *maybe_duration)
+                })
+                .collect();
             Ok(abstutil::to_json(&trips))
         }
         "/data/get-agent-positions" => Ok(abstutil::to_json(&AgentPositions {
@@ -340,6 +477,61 @@ fn handle_command(
                 .map(|((r, a, hr), cnt)| (*r, *a, *hr, *cnt))
                 .collect(),
         })),
+        "/data/get-queue-lengths" => {
+            let l = LaneID::decode_u32(get("lane")?.parse::<u32>()?);
+            let t1 = Time::parse(get("t1")?)?;
+            let t2 = Time::parse(get("t2")?)?;
+            let lane = map
+                .maybe_get_l(l)
+                .ok_or_else(|| anyhow!("{} doesn't exist", l))?;
+            let analytics = sim.get_analytics();
+            if analytics.lane_queues.samples.is_empty() {
+                bail!("Queues aren't being sampled. Run with --queue_sample_interval=60");
+            }
+            let (max_queue_length, spillback) = analytics.max_queue_length(l, t1, t2, map);
+            Ok(abstutil::to_json(&QueueLengths {
+                lane_length: lane.length(),
+                samples: analytics.lane_queues.get(l, t1, t2),
+                max_queue_length,
+                spillback,
+            }))
+        }
+        "/data/get-emissions" => {
+            let analytics = sim.get_analytics();
+            Ok(abstutil::to_json(&EmissionsResults {
+                per_vehicle_type: analytics.total_emissions_by_time(sim.time()),
+                per_trip: analytics.trip_emissions.clone(),
+                per_road: analytics.road_emissions.clone(),
+                per_intersection: analytics.intersection_emissions.clone(),
+            }))
+        }
+        "/data/get-reroutes" => {
+            let analytics = sim.get_analytics();
+            Ok(abstutil::to_json(&RerouteResults {
+                per_reason: analytics.count_reroutes(sim.time()).consume(),
+                diverted_from: analytics.diverted_from.borrow().clone(),
+                diverted_to: analytics.diverted_to.borrow().clone(),
+            }))
+        }
+        "/data/compare-counts" => {
+            let observed: TimeBinnedCounts = abstutil::from_json(body)?;
+            Ok(abstutil::to_json(&sim.get_analytics().compare_counts(
+                &observed,
+                sim.time(),
+                map,
+            )?))
+        }
+        "/data/compare-tmc" => {
+            let tmc: TurningMovementCounts = abstutil::from_json(body)?;
+            Ok(abstutil::to_json(&sim.get_analytics().compare_counts(
+                &tmc.to_time_binned_counts(),
+                sim.time(),
+                map,
+            )?))
+        }
+        "/data/get-daily-summaries" => Ok(abstutil::to_json(
+            &sim.get_analytics().daily_summaries(sim.time()),
+        )),
         "/data/get-blocked-by-graph" => Ok(abstutil::to_json(&BlockedByGraph {
             blocked_by: sim
                 .get_blocked_by_graph(map)
@@ -403,6 +595,22 @@ fn handle_command(
                 None => bail!("No road within {} of {}", threshold, pt),
             }
         }
+        // Training signal controllers. This runs a separate simulation from everything above.
+        "/env/reset" => {
+            let args: ResetArgs = abstutil::from_json(body)?;
+            Ok(abstutil::to_json(
+                &env.reset(args, &mut Timer::new("reset env"))?,
+            ))
+        }
+        "/env/step" => {
+            let actions: BTreeMap<IntersectionID, Action> = if body.is_empty() {
+                BTreeMap::new()
+            } else {
+                abstutil::from_json(body)?
+            };
+            Ok(abstutil::to_json(&env.step(actions)?))
+        }
+        "/env/get-observation" => Ok(abstutil::to_json(&env.observe())),
         _ => Err(anyhow!("Unknown command")),
     }
 }
@@ -418,6 +626,72 @@ struct FinishedTrip {
     mode: TripMode,
 }
 
+impl FinishedTrip {
+    fn new(sim: &Sim, id: TripID, mode: TripMode, duration: Option<Duration>) -> FinishedTrip {
+        let distance_crossed = if duration.is_some() {
+            sim.finished_trip_details(id).unwrap().2
+        } else {
+            Distance::ZERO
+        };
+        FinishedTrip {
+            id,
+            person: sim.trip_to_person(id).unwrap(),
+            duration,
+            distance_crossed,
+            mode,
+        }
+    }
+}
+
+/// What to observe after each /sim/step
+#[derive(Deserialize)]
+#[serde(default)]
+struct ObservationConfig {
+    /// Describe the state of these traffic signals. If None, describe all of them.
+    signals: Option<Vec<IntersectionID>>,
+    /// Which parts of each traffic signal's state to return. If None, return everything.
+    signal_fields: Option<Vec<SignalStateField>>,
+    /// Only report queue lengths for these lanes. If None, report every lane with a queue.
+    lanes: Option<Vec<LaneID>>,
+    include_signals: bool,
+    include_queue_lengths: bool,
+    include_finished_trips: bool,
+    /// Alerts are only collected when headless runs with --alerts=collect. Otherwise, they're
+    /// printed or dropped as usual.
+    include_alerts: bool,
+}
+
+impl Default for ObservationConfig {
+    fn default() -> ObservationConfig {
+        ObservationConfig {
+            signals: None,
+            signal_fields: None,
+            lanes: None,
+            include_signals: true,
+            include_queue_lengths: true,
+            include_finished_trips: true,
+            include_alerts: true,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Observation {
+    time: Time,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signals: Option<BTreeMap<IntersectionID, TrafficSignalState>>,
+    /// For each lane, how many vehicles are stopped, waiting to move forward or start a turn.
+    /// Lanes with nobody waiting are omitted, unless they're explicitly requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue_lengths: Option<BTreeMap<LaneID, usize>>,
+    /// Trips that finished or were cancelled during this step
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished_trips: Option<Vec<FinishedTrip>>,
+    /// Alerts raised since the last time they were collected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alerts: Option<Vec<(Time, AlertLocation, String)>>,
+}
+
 #[derive(Serialize)]
 struct Delays {
     #[serde(serialize_with = "serialize_btreemap")]
@@ -467,13 +741,90 @@ struct RoadThroughput {
     counts: Vec<(RoadID, AgentType, usize, usize)>,
 }
 
+#[derive(Serialize)]
+struct QueueLengths {
+    lane_length: Distance,
+    /// The queue at t1 and every time it changed until t2
+    samples: Vec<(Time, QueueSample)>,
+    max_queue_length: Distance,
+    /// Did the queue ever extend past the start of the lane?
+    spillback: bool,
+}
+
+#[derive(Serialize)]
+struct EmissionsResults {
+    per_vehicle_type: BTreeMap<VehicleType, Emissions>,
+    /// Buses aren't included here, because they don't belong to a trip
+    per_trip: BTreeMap<TripID, Emissions>,
+    per_road: BTreeMap<RoadID, Emissions>,
+    per_intersection: BTreeMap<IntersectionID, Emissions>,
+}
+
+#[derive(Serialize)]
+struct RerouteResults {
+    /// How many times vehicles changed their route while driving
+    per_reason: BTreeMap<RerouteReason, usize>,
+    /// How many times vehicles re-planned to stop using each road
+    diverted_from: BTreeMap<RoadID, usize>,
+    /// How many times vehicles re-planned to start using each road
+    diverted_to: BTreeMap<RoadID, usize>,
+}
+
 #[derive(Serialize)]
 struct TrafficSignalState {
-    current_stage_idx: usize,
-    remaining_time: Duration,
-    accepted: BTreeSet<AgentID>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current_stage_idx: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remaining_time: Option<Duration>,
+    /// When the current stage began. Extending a variable stage doesn't reset this.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage_start_time: Option<Time>,
+    /// How many times the current variable stage has been extended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extensions_count: Option<usize>,
+    /// If true, the signal holds the current stage until /traffic-signals/set-stage is called
+    #[serde(skip_serializing_if = "Option::is_none")]
+    externally_controlled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    accepted: Option<BTreeSet<AgentID>>,
     // Some agent has been waiting to start a turn since some time
-    waiting: Vec<(AgentID, TurnID, Time)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waiting: Option<Vec<(AgentID, TurnID, Time)>>,
+}
+
+/// Which parts of TrafficSignalState to calculate and return
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+enum SignalStateField {
+    CurrentStage,
+    RemainingTime,
+    StageStartTime,
+    ExtensionsCount,
+    ExternallyControlled,
+    Accepted,
+    Waiting,
+}
+
+impl SignalStateField {
+    fn all() -> BTreeSet<SignalStateField> {
+        vec![
+            SignalStateField::CurrentStage,
+            SignalStateField::RemainingTime,
+            SignalStateField::StageStartTime,
+            SignalStateField::ExtensionsCount,
+            SignalStateField::ExternallyControlled,
+            SignalStateField::Accepted,
+            SignalStateField::Waiting,
+        ]
+        .into_iter()
+        .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct SignalStateQuery {
+    intersections: Vec<IntersectionID>,
+    /// If this is missing, return every field
+    fields: Option<Vec<SignalStateField>>,
 }
 
 #[derive(Serialize)]
@@ -520,6 +871,112 @@ impl LoadSim {
     }
 }
 
+/// Describe what happened in the simulation since `since`.
+fn observe(
+    sim: &mut Sim,
+    map: &Map,
+    since: Time,
+    config: ObservationConfig,
+) -> Result<Observation> {
+    let signals = if config.include_signals {
+        let fields = config
+            .signal_fields
+            .map(|list| list.into_iter().collect())
+            .unwrap_or_else(SignalStateField::all);
+        let ids = config.signals.unwrap_or_else(|| {
+            map.all_intersections()
+                .iter()
+                .filter(|i| i.is_traffic_signal())
+                .map(|i| i.id)
+                .collect()
+        });
+        let mut signals = BTreeMap::new();
+        for i in ids {
+            if map.maybe_get_traffic_signal(i).is_none() {
+                bail!("{} isn't a traffic signal", i);
+            }
+            signals.insert(i, get_signal_state(sim, i, &fields));
+        }
+        Some(signals)
+    } else {
+        None
+    };
+
+    let queue_lengths = if config.include_queue_lengths {
+        let mut all = sim.get_lane_queue_lengths();
+        Some(if let Some(lanes) = config.lanes {
+            lanes
+                .into_iter()
+                .map(|l| (l, all.remove(&l).unwrap_or(0)))
+                .collect()
+        } else {
+            all
+        })
+    } else {
+        None
+    };
+
+    let finished_trips = config.include_finished_trips.then(|| {
+        sim.get_analytics()
+            .finished_trips
+            .iter()
+            .rev()
+            .take_while(|(t, _, _, _)| *t > since)
+            .map(|(_, id, mode, maybe_duration)| {
+                FinishedTrip::new(sim, *id, *mode, *maybe_duration)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect()
+    });
+
+    let alerts = config.include_alerts.then(|| sim.clear_alerts());
+
+    Ok(Observation {
+        time: sim.time(),
+        signals,
+        queue_lengths,
+        finished_trips,
+        alerts,
+    })
+}
+
+/// The caller must make sure the intersection is a traffic signal.
+fn get_signal_state(
+    sim: &Sim,
+    i: IntersectionID,
+    fields: &BTreeSet<SignalStateField>,
+) -> TrafficSignalState {
+    let (current_stage_idx, remaining_time) = sim.current_stage_and_remaining_time(i);
+    let (stage_start_time, extensions_count) = sim.current_stage_start_and_extensions(i);
+    let has = |field| fields.contains(&field);
+    TrafficSignalState {
+        current_stage_idx: has(SignalStateField::CurrentStage).then_some(current_stage_idx),
+        remaining_time: has(SignalStateField::RemainingTime).then_some(remaining_time),
+        stage_start_time: has(SignalStateField::StageStartTime).then_some(stage_start_time),
+        extensions_count: has(SignalStateField::ExtensionsCount).then_some(extensions_count),
+        externally_controlled: has(SignalStateField::ExternallyControlled)
+            .then(|| sim.is_traffic_signal_externally_controlled(i)),
+        accepted: has(SignalStateField::Accepted).then(|| {
+            sim.get_accepted_agents(i)
+                .into_iter()
+                .map(|(a, _)| a)
+                .collect()
+        }),
+        waiting: has(SignalStateField::Waiting).then(|| sim.get_waiting_agents(i)),
+    }
+}
+
+/// Parse a detector ID and make sure it exists.
+fn get_detector(sim: &Sim, id: &str) -> Result<DetectorID> {
+    let id = DetectorID(id.parse::<usize>()?);
+    if id.0 >= sim.get_detectors().all_detectors().len() {
+        bail!("{} doesn't exist", id);
+    }
+    Ok(id)
+}
+
 fn export_geometry(map: &Map, i: IntersectionID) -> geojson::GeoJson {
     let mut pairs = Vec::new();
 
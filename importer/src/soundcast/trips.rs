@@ -293,6 +293,7 @@ pub fn make_scenario(
         map_name: map.get_name().clone(),
         people,
         only_seed_buses: None,
+        day_types: Vec::new(),
     }
     .remove_weird_schedules(true)
 }
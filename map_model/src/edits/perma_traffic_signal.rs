@@ -32,6 +32,62 @@ pub struct Plan {
     pub stages: Vec<Stage>,
     /// Relative to a central clock, delay the first stage by this many seconds.
     pub offset_seconds: usize,
+    /// How the signal picks the next stage and times it. If this is missing, the stages repeat in
+    /// order.
+    #[serde(default, skip_serializing_if = "SignalControl::is_cycle")]
+    pub control: SignalControl,
+    /// How buses and emergency vehicles are favored. If this is missing, they aren't.
+    #[serde(default, skip_serializing_if = "SignalPriority::is_disabled")]
+    pub priority: SignalPriority,
+}
+
+/// How a traffic signal picks the next stage and decides how long it lasts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SignalControl {
+    /// Repeat the stages in order, timing each one according to its `stage_type`.
+    Cycle,
+    /// After the minimum, switch to the stage with the most queued vehicles upstream minus
+    /// downstream. No stage lasts longer than the maximum.
+    MaxPressure {
+        min_green_seconds: usize,
+        max_green_seconds: usize,
+    },
+    /// Repeat the stages in order, dividing the cycle length among stages each cycle based on the
+    /// demand observed during the previous one.
+    AdaptiveSplits {
+        cycle_length_seconds: usize,
+        min_green_seconds: usize,
+    },
+}
+
+impl Default for SignalControl {
+    fn default() -> SignalControl {
+        SignalControl::Cycle
+    }
+}
+
+impl SignalControl {
+    fn is_cycle(&self) -> bool {
+        *self == SignalControl::Cycle
+    }
+}
+
+/// Transit signal priority and emergency vehicle pre-emption.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SignalPriority {
+    /// When a bus approaches, extend its green or end the stage before it early, by at most this
+    /// many seconds. If this is missing, buses get no priority.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transit_max_adjustment_seconds: Option<usize>,
+    /// Immediately switch to a stage serving an approaching emergency vehicle.
+    #[serde(default)]
+    pub emergency_preemption: bool,
+}
+
+impl SignalPriority {
+    fn is_disabled(&self) -> bool {
+        *self == SignalPriority::default()
+    }
 }
 
 /// A traffic signal is in one stage at any time. The stage describes what movements are possible.
@@ -47,6 +103,44 @@ pub struct Stage {
     pub permitted_turns: BTreeSet<Turn>,
     /// The stage lasts this long before moving to the next one.
     pub stage_type: StageType,
+    /// Whether pedestrians must push a button to get a walk signal. If this is missing, the walk
+    /// signal is shown every time.
+    #[serde(default, skip_serializing_if = "PedestrianPhase::is_recall")]
+    pub pedestrian_phase: PedestrianPhase,
+    /// After the stage, its movements show amber for this many seconds before the next stage
+    /// begins.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub amber_seconds: usize,
+    /// After the amber interval, every movement is red for this many seconds before the next
+    /// stage begins.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub all_red_seconds: usize,
+}
+
+fn is_zero(x: &usize) -> bool {
+    *x == 0
+}
+
+/// How a stage serves its protected crosswalks.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PedestrianPhase {
+    /// Show the walk signal every time the stage runs.
+    Recall,
+    /// Only show the walk signal when a pedestrian has pushed the button. The walk interval lasts
+    /// at least this many seconds, followed by enough time to finish crossing.
+    PushButton { min_walk_seconds: usize },
+}
+
+impl Default for PedestrianPhase {
+    fn default() -> PedestrianPhase {
+        PedestrianPhase::Recall
+    }
+}
+
+impl PedestrianPhase {
+    fn is_recall(&self) -> bool {
+        *self == PedestrianPhase::Recall
+    }
 }
 
 /// How long a stage lasts before moving to the next one.
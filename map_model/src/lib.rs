@@ -52,6 +52,7 @@ pub use crate::edits::{
     PermanentMapEdits,
 };
 
+pub use crate::make::traffic_signals::{demand_from_path_requests, demand_per_hour};
 pub use crate::make::RawToMapOptions;
 pub use crate::objects::area::{Area, AreaID};
 pub use crate::objects::building::{Building, BuildingID, BuildingType, OffstreetParking};
@@ -63,15 +64,18 @@ pub use crate::objects::parking_lot::{ParkingLot, ParkingLotID};
 pub use crate::objects::road::{
     Crossing, DirectedRoadID, OriginalRoad, Road, RoadID, RoadSideID, SideOfRoad,
 };
+pub use crate::objects::signal_corridor::{CorridorTiming, SignalCorridor};
 pub use crate::objects::stop_signs::{ControlStopSign, RoadWithStopSign};
-pub use crate::objects::traffic_signals::{ControlTrafficSignal, Stage, StageType};
+pub use crate::objects::traffic_signals::{
+    ControlTrafficSignal, PedestrianPhase, SignalControl, SignalPriority, Stage, StageType,
+};
 pub use crate::objects::transit::{TransitRoute, TransitRouteID, TransitStop, TransitStopID};
 pub use crate::objects::turn::{Turn, TurnID, TurnPriority, TurnType};
 pub use crate::objects::zone::{AccessRestrictions, Zone};
 pub use crate::pathfind::uber_turns::{IntersectionCluster, UberTurn};
 pub use crate::pathfind::{
-    Path, PathConstraints, PathRequest, PathStep, PathStepV2, PathV2, Pathfinder, PathfinderCache,
-    PathfinderCaching, RoutingParams,
+    static_assignment, AssignmentOptions, AssignmentResult, Path, PathConstraints, PathRequest,
+    PathStep, PathStepV2, PathV2, Pathfinder, PathfinderCache, PathfinderCaching, RoutingParams,
 };
 pub use crate::traversable::{Position, Traversable, MAX_BIKE_SPEED, MAX_WALKING_SPEED};
 pub use map::turn_type_from_angles;
@@ -5,15 +5,19 @@
 //! For example, lagging_green.rs contains a one public fn:
 //!     pub fn make_traffic_signal(map: &Map, i: IntersectionID)->Option<ControlTrafficSignal>
 
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use crate::{
     ControlTrafficSignal, DrivingSide, Intersection, IntersectionCluster, IntersectionID, Map,
-    MapConfig, MovementID, RoadID, Stage, StageType, TurnPriority, TurnType,
+    MapConfig, MovementID, RoadID, SignalControl, SignalPriority, Stage, StageType, TurnPriority,
+    TurnType,
 };
 use geom::Duration;
 
 mod lagging_green;
+mod webster;
+
+pub use webster::{apply_webster_timing, demand_from_path_requests, demand_per_hour};
 
 /// Applies a bunch of heuristics to a single intersection, returning the valid results in
 /// best-first order. The signal configuration is only based on the roads connected to the
@@ -69,11 +73,29 @@ pub fn get_possible_policies(map: &Map, id: IntersectionID) -> Vec<(String, Cont
     results
 }
 
+/// Like `get_possible_policies`, but first offers a policy timed for the given demand, in vehicles
+/// per hour for each movement.
+pub fn get_possible_policies_with_demand(
+    map: &Map,
+    id: IntersectionID,
+    demand: &BTreeMap<MovementID, f64>,
+) -> Vec<(String, ControlTrafficSignal)> {
+    let mut results = get_possible_policies(map, id);
+    if let Some(ts) = webster::make_traffic_signal(map, id, demand) {
+        if ts.validate(map.get_i(id)).is_ok() {
+            results.insert(0, ("Webster's method, timed from demand".to_string(), ts));
+        }
+    }
+    results
+}
+
 fn new(id: IntersectionID) -> ControlTrafficSignal {
     ControlTrafficSignal {
         id,
         stages: Vec::new(),
         offset: Duration::ZERO,
+        control: SignalControl::Cycle,
+        priority: SignalPriority::default(),
     }
 }
 
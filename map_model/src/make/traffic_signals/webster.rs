@@ -0,0 +1,142 @@
+//! Time a traffic signal from observed or predicted demand, using Webster's method. See
+//! <https://en.wikipedia.org/wiki/Webster%27s_method> and chapter 19 of the Highway Capacity
+//! Manual.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+
+use abstutil::Timer;
+use geom::Duration;
+
+use crate::{
+    ControlTrafficSignal, IntersectionID, LaneID, Map, MovementID, PathConstraints, PathRequest,
+    PathStep, StageType,
+};
+
+/// How many vehicles per hour one lane can discharge during a green light, under ideal
+/// conditions
+pub const SATURATION_FLOW_PER_LANE: f64 = 1800.0;
+/// Start-up delay plus clearance time lost every time a stage begins. Stages with longer amber and
+/// all-red intervals lose that much instead.
+pub const LOST_TIME_PER_STAGE: Duration = Duration::const_seconds(4.0);
+const MIN_CYCLE: Duration = Duration::const_seconds(30.0);
+const MAX_CYCLE: Duration = Duration::const_seconds(180.0);
+// Past this, the formula blows up; just use the maximum cycle length
+const MAX_FLOW_RATIO: f64 = 0.9;
+
+/// Replace the timing of every stage with fixed durations from Webster's method. `demand` is
+/// vehicles per hour for each movement. Movements missing from `demand` have none. The stages
+/// themselves aren't changed.
+pub fn apply_webster_timing(
+    ts: &mut ControlTrafficSignal,
+    map: &Map,
+    demand: &BTreeMap<MovementID, f64>,
+) -> Result<()> {
+    if ts.stages.is_empty() {
+        bail!("{} has no stages", ts.id);
+    }
+    let i = map.get_i(ts.id);
+
+    // The critical flow ratio of each stage is the busiest protected movement, relative to how
+    // much that movement could handle.
+    let mut flow_ratios = Vec::new();
+    for stage in &ts.stages {
+        let mut critical: f64 = 0.0;
+        for m in &stage.protected_movements {
+            if m.crosswalk {
+                continue;
+            }
+            let lanes: BTreeSet<LaneID> = i.movements[m].members.iter().map(|t| t.src).collect();
+            let saturation_flow = SATURATION_FLOW_PER_LANE * (lanes.len() as f64);
+            let flow = demand.get(m).cloned().unwrap_or(0.0);
+            critical = critical.max(flow / saturation_flow);
+        }
+        flow_ratios.push(critical);
+    }
+
+    let lost_times: Vec<Duration> = ts
+        .stages
+        .iter()
+        .map(|s| LOST_TIME_PER_STAGE.max(s.intergreen()))
+        .collect();
+    let lost_time: Duration = lost_times.iter().cloned().sum();
+    let total_ratio: f64 = flow_ratios.iter().sum();
+    let cycle_length = if total_ratio >= MAX_FLOW_RATIO {
+        MAX_CYCLE
+    } else {
+        let optimal = (1.5 * lost_time.inner_seconds() + 5.0) / (1.0 - total_ratio);
+        Duration::seconds(optimal).max(MIN_CYCLE).min(MAX_CYCLE)
+    };
+
+    // Split the effective green time in proportion to each stage's critical flow ratio
+    let effective_green = (cycle_length - lost_time).max(Duration::ZERO);
+    for (idx, ratio) in flow_ratios.into_iter().enumerate() {
+        let share = if total_ratio > 0.0 {
+            ratio / total_ratio
+        } else {
+            1.0 / (ts.stages.len() as f64)
+        };
+        // The amber and all-red come after the displayed green
+        let green = effective_green * share + lost_times[idx] - ts.stages[idx].intergreen();
+        // Round to whole seconds, because the timing is exported that way
+        let duration =
+            Duration::seconds(green.inner_seconds().round()).max(ts.get_min_crossing_time(idx, i));
+        ts.stages[idx].stage_type = StageType::Fixed(duration);
+    }
+    Ok(())
+}
+
+/// Use the same stages as the best default policy, but time them for the given demand, in
+/// vehicles per hour for each movement.
+pub fn make_traffic_signal(
+    map: &Map,
+    id: IntersectionID,
+    demand: &BTreeMap<MovementID, f64>,
+) -> Option<ControlTrafficSignal> {
+    let mut ts = super::get_possible_policies(map, id).into_iter().next()?.1;
+    apply_webster_timing(&mut ts, map, demand).ok()?;
+    Some(ts)
+}
+
+/// Route every request, then count how many vehicles perform each movement through a traffic
+/// signal. Each request has the count it contributes -- use `PathRequest::deduplicate` to easily
+/// generate this. Pedestrians are skipped, since they don't affect vehicle timing.
+pub fn demand_from_path_requests(
+    map: &Map,
+    requests: Vec<(PathRequest, usize)>,
+    timer: &mut Timer,
+) -> BTreeMap<MovementID, usize> {
+    let paths = timer.parallelize(
+        "route trips to measure signal demand",
+        requests
+            .into_iter()
+            .filter(|(req, _)| req.constraints != PathConstraints::Pedestrian)
+            .collect(),
+        |(req, count)| map.pathfind(req).ok().map(|path| (path, count)),
+    );
+
+    let mut demand = BTreeMap::new();
+    for (path, count) in paths.into_iter().flatten() {
+        for step in path.get_steps() {
+            if let PathStep::Turn(t) | PathStep::ContraflowTurn(t) = step {
+                if let Some((id, _)) = map.get_movement_for_traffic_signal(*t) {
+                    *demand.entry(id).or_insert(0) += count;
+                }
+            }
+        }
+    }
+    demand
+}
+
+/// Express counts observed over some duration as vehicles per hour.
+pub fn demand_per_hour(
+    counts: &BTreeMap<MovementID, usize>,
+    duration: Duration,
+) -> BTreeMap<MovementID, f64> {
+    let hours = duration.inner_seconds() / 3600.0;
+    counts
+        .iter()
+        .map(|(m, count)| (*m, (*count as f64) / hours))
+        .collect()
+}
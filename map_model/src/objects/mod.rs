@@ -6,6 +6,7 @@ pub mod modal_filter;
 pub mod movement;
 pub mod parking_lot;
 pub mod road;
+pub mod signal_corridor;
 pub mod stop_signs;
 pub mod traffic_signals;
 pub mod transit;
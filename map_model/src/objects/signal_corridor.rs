@@ -0,0 +1,367 @@
+//! Coordinate a sequence of traffic signals along a route, so that vehicles driving at the speed
+//! limit arrive at each signal just as it turns green.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use geom::Duration;
+
+use crate::{
+    ControlTrafficSignal, DirectedRoadID, EditCmd, EditIntersectionControl, IntersectionID, Map,
+    PathConstraints, RoadID, SignalControl, StageType,
+};
+
+/// A sequence of traffic signals along a route, sharing one cycle length.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SignalCorridor {
+    /// The signals in order along the corridor. Vehicles must be able to drive from each one to
+    /// the next.
+    pub intersections: Vec<IntersectionID>,
+    /// Every signal will be retimed to use this cycle length.
+    pub cycle_length: Duration,
+    /// How much to favor traffic moving from the first signal to the last, between 0 and 1. 1
+    /// only creates a green wave in that direction, 0 only in the opposite direction, and 0.5
+    /// compromises equally.
+    pub forward_weight: f64,
+}
+
+/// The result of optimizing a corridor.
+#[derive(Clone, Debug, Serialize)]
+pub struct CorridorTiming {
+    /// The retimed signals, in the same order as the corridor
+    pub signals: Vec<ControlTrafficSignal>,
+    /// How long it takes to drive from the first signal to each signal at the speed limit
+    pub forward_travel_times: Vec<Duration>,
+    /// How long it takes to drive from the last signal to each signal at the speed limit, if
+    /// that's possible
+    pub backward_travel_times: Option<Vec<Duration>>,
+}
+
+impl SignalCorridor {
+    /// Create a corridor from signals along the shortest driving path between two
+    /// intersections. The endpoints don't have to be signals themselves.
+    pub fn along_path(
+        map: &Map,
+        from: IntersectionID,
+        to: IntersectionID,
+        cycle_length: Duration,
+        forward_weight: f64,
+    ) -> Result<SignalCorridor> {
+        let (_, path) = map
+            .simple_path_btwn_v2(from, to, PathConstraints::Car)
+            .ok_or_else(|| anyhow!("Can't drive from {} to {}", from, to))?;
+        let corridor = SignalCorridor {
+            intersections: path
+                .into_iter()
+                .filter(|i| map.get_i(*i).is_traffic_signal())
+                .collect(),
+            cycle_length,
+            forward_weight,
+        };
+        corridor.validate(map)?;
+        Ok(corridor)
+    }
+
+    pub fn validate(&self, map: &Map) -> Result<()> {
+        if self.intersections.len() < 2 {
+            bail!("A corridor needs at least 2 traffic signals");
+        }
+        for (idx, i) in self.intersections.iter().enumerate() {
+            if map.maybe_get_traffic_signal(*i).is_none() {
+                bail!("{} isn't a traffic signal", i);
+            }
+            if self.intersections[..idx].contains(i) {
+                bail!("{} is in the corridor twice", i);
+            }
+        }
+        if self.cycle_length <= Duration::ZERO {
+            bail!("Bad cycle length {}", self.cycle_length);
+        }
+        if !(0.0..=1.0).contains(&self.forward_weight) {
+            bail!(
+                "forward_weight must be between 0 and 1, not {}",
+                self.forward_weight
+            );
+        }
+        Ok(())
+    }
+
+    /// Retime every signal in the corridor to use the common cycle length, then pick offsets so
+    /// that a vehicle driving at the speed limit reaches each signal at the start of the stage
+    /// serving it. When both directions matter, each offset minimizes the weighted squared error
+    /// from the ideal offset for each direction. The first signal keeps its current offset.
+    pub fn optimize(&self, map: &Map) -> Result<CorridorTiming> {
+        self.validate(map)?;
+        let cycle = self.cycle_length.inner_seconds().round() as usize;
+        if cycle == 0 {
+            bail!("Bad cycle length {}", self.cycle_length);
+        }
+
+        let forward = self.leg_roads(map, false)?.unwrap();
+        let backward = self.leg_roads(map, true)?;
+
+        let mut signals = Vec::new();
+        for i in &self.intersections {
+            signals.push(retime(
+                map.get_traffic_signal(*i).clone(),
+                self.cycle_length,
+                map,
+            )?);
+        }
+
+        // When does each signal's coordinated stage begin, measured in seconds since the start
+        // of its cycle?
+        let n = self.intersections.len();
+        let mut forward_stage_starts = Vec::new();
+        let mut backward_stage_starts = Vec::new();
+        for idx in 0..n {
+            let incoming = if idx == 0 {
+                None
+            } else {
+                forward[idx - 1].last().cloned()
+            };
+            let outgoing = forward.get(idx).and_then(|roads| roads.first().cloned());
+            forward_stage_starts.push(stage_start(&signals[idx], incoming, outgoing));
+
+            if let Some(ref backward) = backward {
+                // backward[idx] leads from signal idx + 1 to signal idx
+                let incoming = backward.get(idx).and_then(|roads| roads.last().cloned());
+                let outgoing = if idx == 0 {
+                    None
+                } else {
+                    backward[idx - 1].first().cloned()
+                };
+                backward_stage_starts.push(stage_start(&signals[idx], incoming, outgoing));
+            }
+        }
+
+        let forward_travel_times = cumulative_travel_times(map, &forward);
+        let backward_travel_times = backward.as_ref().map(|legs| {
+            // Measure from the last signal
+            let mut times = cumulative_travel_times(map, legs);
+            let total = *times.last().unwrap();
+            for t in &mut times {
+                *t = total - *t;
+            }
+            times
+        });
+
+        // The first signal anchors everything else. In its own cycle, when does each coordinated
+        // stage begin, relative to a central clock?
+        let offset0 = signals[0].offset.inner_seconds();
+        let forward_green0 = forward_stage_starts[0] - offset0;
+        let backward_green0 = backward_stage_starts.first().map(|start| start - offset0);
+
+        let weight = if backward.is_some() {
+            self.forward_weight
+        } else {
+            1.0
+        };
+        for idx in 1..n {
+            // Signal idx should turn green for the forward stage when a vehicle arrives from the
+            // first signal.
+            let ideal_forward = forward_stage_starts[idx]
+                - (forward_green0 + forward_travel_times[idx].inner_seconds());
+            // In the other direction, the first signal should turn green when a vehicle arrives
+            // from signal idx.
+            let ideal_backward = match (&backward_travel_times, backward_green0) {
+                (Some(times), Some(green0)) => {
+                    backward_stage_starts[idx] - (green0 - (times[0] - times[idx]).inner_seconds())
+                }
+                _ => ideal_forward,
+            };
+
+            let mut best = (f64::MAX, 0);
+            for candidate in 0..cycle {
+                let candidate = candidate as f64;
+                let error = weight
+                    * circular_distance(candidate, ideal_forward, cycle as f64).powi(2)
+                    + (1.0 - weight)
+                        * circular_distance(candidate, ideal_backward, cycle as f64).powi(2);
+                if error < best.0 {
+                    best = (error, candidate as usize);
+                }
+            }
+            signals[idx].offset = Duration::seconds(best.1 as f64);
+        }
+
+        Ok(CorridorTiming {
+            signals,
+            forward_travel_times,
+            backward_travel_times,
+        })
+    }
+
+    /// For each consecutive pair of signals, the directed roads driven between them. If
+    /// `backward`, each leg goes from the later signal to the earlier one instead. Returns None
+    /// if driving backward isn't possible.
+    fn leg_roads(&self, map: &Map, backward: bool) -> Result<Option<Vec<Vec<DirectedRoadID>>>> {
+        let mut legs = Vec::new();
+        for pair in self.intersections.windows(2) {
+            let (from, to) = if backward {
+                (pair[1], pair[0])
+            } else {
+                (pair[0], pair[1])
+            };
+            match map.simple_path_btwn_v2(from, to, PathConstraints::Car) {
+                Some((roads, path)) => {
+                    legs.push(directed_roads(map, &roads, &path));
+                }
+                None => {
+                    if backward {
+                        return Ok(None);
+                    }
+                    bail!("Can't drive from {} to {}", from, to);
+                }
+            }
+        }
+        Ok(Some(legs))
+    }
+}
+
+impl CorridorTiming {
+    /// Express the new timing as map edits.
+    pub fn make_edits(&self, map: &Map) -> Vec<EditCmd> {
+        self.signals
+            .iter()
+            .map(|ts| {
+                map.edit_intersection_cmd(ts.id, |new| {
+                    new.control = EditIntersectionControl::TrafficSignal(ts.export(map));
+                })
+            })
+            .collect()
+    }
+}
+
+fn directed_roads(map: &Map, roads: &[RoadID], path: &[IntersectionID]) -> Vec<DirectedRoadID> {
+    roads
+        .iter()
+        .zip(path.iter())
+        .map(|(r, i)| map.get_r(*r).directed_id_from(*i))
+        .collect()
+}
+
+/// The first entry is zero, then the time to drive through each leg at the speed limit.
+fn cumulative_travel_times(map: &Map, legs: &[Vec<DirectedRoadID>]) -> Vec<Duration> {
+    let mut times = vec![Duration::ZERO];
+    let mut total = Duration::ZERO;
+    for leg in legs {
+        for dr in leg {
+            let road = map.get_r(dr.road);
+            total += road.length() / road.speed_limit;
+        }
+        times.push(total);
+    }
+    times
+}
+
+/// Scale fixed stage durations to fit the new cycle length, keeping enough time for pedestrians
+/// to cross.
+fn retime(
+    mut ts: ControlTrafficSignal,
+    cycle_length: Duration,
+    map: &Map,
+) -> Result<ControlTrafficSignal> {
+    if ts.control != SignalControl::Cycle
+        || ts
+            .stages
+            .iter()
+            .any(|s| !matches!(s.stage_type, StageType::Fixed(_)))
+    {
+        bail!(
+            "{} doesn't use fixed timing, so it can't be coordinated",
+            ts.id
+        );
+    }
+    let i = map.get_i(ts.id);
+    // Amber and all-red intervals stay the same; only the greens are scaled
+    let intergreen = ts.total_intergreen();
+    let old_green = ts.simple_cycle_duration() - intergreen;
+    let new_green = cycle_length - intergreen;
+    if new_green <= Duration::ZERO {
+        bail!(
+            "A {} cycle is too short for {}, which has {} of amber and all-red",
+            cycle_length,
+            ts.id,
+            intergreen
+        );
+    }
+    let mut durations = Vec::new();
+    for (idx, stage) in ts.stages.iter().enumerate() {
+        let scaled = stage.stage_type.simple_duration() * (new_green / old_green);
+        // Round to whole seconds, because offsets and timing are exported that way
+        durations.push(
+            Duration::seconds(scaled.inner_seconds().round())
+                .max(ts.get_min_crossing_time(idx, i))
+                .max(Duration::seconds(1.0)),
+        );
+    }
+    // Rounding might leave a few seconds over or under. Make up the difference with the longest
+    // stage.
+    let total: Duration = durations.iter().cloned().sum();
+    let longest = (0..durations.len())
+        .max_by_key(|idx| durations[*idx])
+        .unwrap();
+    let adjusted = durations[longest] + new_green - total;
+    if adjusted
+        < ts.get_min_crossing_time(longest, i)
+            .max(Duration::seconds(1.0))
+    {
+        bail!(
+            "A {} cycle is too short for {}, which needs time for pedestrians to cross",
+            cycle_length,
+            ts.id
+        );
+    }
+    durations[longest] = adjusted;
+
+    for (stage, duration) in ts.stages.iter_mut().zip(durations) {
+        stage.stage_type = StageType::Fixed(duration);
+    }
+    Ok(ts)
+}
+
+/// Find the stage protecting the movement through the corridor, and return when it starts,
+/// measured in seconds from the start of the cycle. If no stage matches, assume the first one.
+fn stage_start(
+    ts: &ControlTrafficSignal,
+    incoming: Option<DirectedRoadID>,
+    outgoing: Option<DirectedRoadID>,
+) -> f64 {
+    let matches = |from: DirectedRoadID, to: DirectedRoadID| {
+        incoming.map(|r| r == from).unwrap_or(true) && outgoing.map(|r| r == to).unwrap_or(true)
+    };
+    let idx = ts
+        .stages
+        .iter()
+        .position(|stage| {
+            stage
+                .protected_movements
+                .iter()
+                .any(|m| !m.crosswalk && matches(m.from, m.to))
+        })
+        .unwrap_or(0);
+    ts.stages[..idx]
+        .iter()
+        .map(|s| (s.stage_type.simple_duration() + s.intergreen()).inner_seconds())
+        .sum()
+}
+
+/// The distance between two points on a circle with the given circumference
+fn circular_distance(a: f64, b: f64, circumference: f64) -> f64 {
+    let diff = (a - b).rem_euclid(circumference);
+    diff.min(circumference - diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circular_distance() {
+        assert_eq!(circular_distance(10.0, 20.0, 60.0), 10.0);
+        assert_eq!(circular_distance(5.0, 55.0, 60.0), 10.0);
+        assert_eq!(circular_distance(-5.0, 55.0, 60.0), 0.0);
+        assert_eq!(circular_distance(0.0, 90.0, 60.0), 30.0);
+    }
+}
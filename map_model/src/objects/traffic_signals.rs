@@ -6,7 +6,9 @@ use serde::{Deserialize, Serialize};
 use geom::{Distance, Duration, Speed};
 
 use crate::edits::perma_traffic_signal;
-use crate::make::traffic_signals::get_possible_policies;
+use crate::make::traffic_signals::{
+    apply_webster_timing, get_possible_policies, get_possible_policies_with_demand,
+};
 use crate::{
     Intersection, IntersectionID, Map, Movement, MovementID, RoadID, TurnID, TurnPriority,
 };
@@ -23,6 +25,77 @@ pub struct ControlTrafficSignal {
     pub id: IntersectionID,
     pub stages: Vec<Stage>,
     pub offset: Duration,
+    #[serde(default)]
+    pub control: SignalControl,
+    #[serde(default)]
+    pub priority: SignalPriority,
+}
+
+/// Decides which stage a traffic signal shows next and for how long.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum SignalControl {
+    /// Repeat the stages in order, timing each one according to its `StageType`.
+    Cycle,
+    /// Once the current stage has lasted `min_green`, switch to whichever stage has the highest
+    /// pressure -- the number of vehicles queued upstream of its protected movements, minus the
+    /// number queued downstream, plus pedestrians waiting for its crosswalks. Stages can be picked
+    /// in any order, and none lasts longer than `max_green`. The offset is ignored.
+    MaxPressure {
+        min_green: Duration,
+        max_green: Duration,
+    },
+    /// Repeat the stages in order, but at the start of every cycle, divide `cycle_length` among
+    /// the stages in proportion to the demand observed for each one during the previous cycle.
+    /// This is loosely based on SCATS and SCOOT. The offset is ignored.
+    AdaptiveSplits {
+        cycle_length: Duration,
+        min_green: Duration,
+    },
+}
+
+impl Default for SignalControl {
+    fn default() -> SignalControl {
+        SignalControl::Cycle
+    }
+}
+
+impl SignalControl {
+    pub fn describe(&self) -> String {
+        match self {
+            SignalControl::Cycle => "fixed cycle".to_string(),
+            SignalControl::MaxPressure {
+                min_green,
+                max_green,
+            } => format!("max pressure, {} to {} per stage", min_green, max_green),
+            SignalControl::AdaptiveSplits {
+                cycle_length,
+                min_green,
+            } => format!(
+                "adaptive splits, {} cycle, at least {} per stage",
+                cycle_length, min_green
+            ),
+        }
+    }
+}
+
+/// How a traffic signal favors buses and emergency vehicles over everybody else.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct SignalPriority {
+    /// If set, transit signal priority is used, but only for `SignalControl::Cycle`. When a bus
+    /// approaches and its movement is protected by the current stage, the green may be extended
+    /// by up to this much, so the bus makes it through. If the bus' movement is protected by the
+    /// next stage instead, the current stage may end up to this much early, once pedestrians have
+    /// had time to cross.
+    pub transit_max_adjustment: Option<Duration>,
+    /// If true, an approaching emergency vehicle immediately gets a stage protecting its
+    /// movement, after any crosswalk, amber, and all-red time needed to clear the intersection.
+    pub emergency_preemption: bool,
+}
+
+impl SignalPriority {
+    pub fn is_enabled(&self) -> bool {
+        self.transit_max_adjustment.is_some() || self.emergency_preemption
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -32,6 +105,34 @@ pub struct Stage {
     // TODO Not renaming this, because this is going to change radically in
     // https://github.com/a-b-street/abstreet/pull/298 anyway
     pub stage_type: StageType,
+    #[serde(default)]
+    pub pedestrian_phase: PedestrianPhase,
+    /// When the signal moves to a different stage, this stage's movements first show amber for
+    /// this long, then red in every direction for `all_red`. Agents already in the intersection
+    /// may finish, but nobody new may enter. These come after the duration from `stage_type`.
+    #[serde(default)]
+    pub amber: Duration,
+    #[serde(default)]
+    pub all_red: Duration,
+}
+
+/// How a stage serves its protected crosswalks.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum PedestrianPhase {
+    /// The walk signal is shown every time the stage runs, whether or not anybody is waiting.
+    Recall,
+    /// Pedestrians must push a button to get a walk signal. Calls registered before the stage
+    /// begins are served; otherwise the crosswalks stay at don't walk for the whole stage, and a
+    /// stage with only crosswalks is skipped entirely. When the stage serves a call, the walk
+    /// interval lasts at least `min_walk`, followed by a clearance interval (flashing don't walk)
+    /// long enough to finish crossing the longest crosswalk.
+    PushButton { min_walk: Duration },
+}
+
+impl Default for PedestrianPhase {
+    fn default() -> PedestrianPhase {
+        PedestrianPhase::Recall
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -69,17 +170,35 @@ impl ControlTrafficSignal {
         get_possible_policies(map, id)
     }
 
+    /// Like `get_possible_policies`, but first offers a policy timed for the given demand, in
+    /// vehicles per hour for each movement.
+    pub fn get_possible_policies_with_demand(
+        map: &Map,
+        id: IntersectionID,
+        demand: &BTreeMap<MovementID, f64>,
+    ) -> Vec<(String, ControlTrafficSignal)> {
+        get_possible_policies_with_demand(map, id, demand)
+    }
+
+    /// Keep the same stages, but retime them using Webster's method. `demand` is vehicles per
+    /// hour for each movement.
+    pub fn apply_webster_timing(
+        &mut self,
+        map: &Map,
+        demand: &BTreeMap<MovementID, f64>,
+    ) -> Result<()> {
+        apply_webster_timing(self, map, demand)
+    }
+
+    /// The shortest a stage can last while letting pedestrians cross. For push-button stages,
+    /// this includes the minimum walk interval.
     pub fn get_min_crossing_time(&self, idx: usize, i: &Intersection) -> Duration {
-        let mut max_distance = Distance::meters(0.0);
-        for movement in &self.stages[idx].protected_movements {
-            if movement.crosswalk {
-                max_distance = max_distance.max(i.movements[movement].geom.length());
-            }
+        let stage = &self.stages[idx];
+        let clearance = stage.pedestrian_clearance(i);
+        match stage.pedestrian_phase {
+            PedestrianPhase::Recall => clearance,
+            PedestrianPhase::PushButton { min_walk } => min_walk + clearance,
         }
-        let time = max_distance / CROSSWALK_PACE;
-        assert!(time >= Duration::ZERO);
-        // Round up because it is converted to a usize elsewhere
-        Duration::seconds(time.inner_seconds().ceil())
     }
 
     pub fn validate(&self, i: &Intersection) -> Result<()> {
@@ -104,6 +223,50 @@ impl ControlTrafficSignal {
                     .collect::<Vec<_>>()
             );
         }
+        match self.control {
+            SignalControl::Cycle => {}
+            SignalControl::MaxPressure {
+                min_green,
+                max_green,
+            } => {
+                if min_green < Duration::seconds(1.0) || max_green < min_green {
+                    bail!(
+                        "Max pressure control needs 1s <= min_green <= max_green, not {} and {}",
+                        min_green,
+                        max_green
+                    );
+                }
+            }
+            SignalControl::AdaptiveSplits {
+                cycle_length,
+                min_green,
+            } => {
+                if min_green < Duration::seconds(1.0) {
+                    bail!(
+                        "Adaptive splits need min_green of at least 1s, not {}",
+                        min_green
+                    );
+                }
+                if cycle_length < min_green * (self.stages.len() as f64) + self.total_intergreen() {
+                    bail!(
+                        "A {} cycle can't give {} stages at least {} each, plus {} of amber and \
+                         all-red",
+                        cycle_length,
+                        self.stages.len(),
+                        min_green,
+                        self.total_intergreen()
+                    );
+                }
+            }
+        }
+        if let Some(max) = self.priority.transit_max_adjustment {
+            if max < Duration::seconds(1.0) {
+                bail!(
+                    "Transit signal priority needs to adjust stages by at least 1s, not {}",
+                    max
+                );
+            }
+        }
         for (stage_index, stage) in self.stages.iter().enumerate() {
             // Do any of the priority movements in one stage conflict?
             for m1 in stage.protected_movements.iter().map(|m| &i.movements[m]) {
@@ -119,6 +282,31 @@ impl ControlTrafficSignal {
                 }
             }
 
+            if stage.amber < Duration::ZERO || stage.all_red < Duration::ZERO {
+                bail!(
+                    "Stage {} has a negative amber ({}) or all-red ({}) interval",
+                    stage_index,
+                    stage.amber,
+                    stage.all_red
+                );
+            }
+
+            if let PedestrianPhase::PushButton { min_walk } = stage.pedestrian_phase {
+                if min_walk < Duration::seconds(1.0) {
+                    bail!(
+                        "Stage {} needs a minimum walk interval of at least 1s, not {}",
+                        stage_index,
+                        min_walk
+                    );
+                }
+                if !stage.protected_movements.iter().any(|m| m.crosswalk) {
+                    bail!(
+                        "Stage {} has a pedestrian push-button, but no protected crosswalks",
+                        stage_index
+                    );
+                }
+            }
+
             // Do any of the crosswalks yield?
             for m in stage.yield_movements.iter().map(|m| &i.movements[m]) {
                 // TODO Maybe make UnmarkedCrossing yield
@@ -247,7 +435,7 @@ impl ControlTrafficSignal {
             }
         }
 
-        if self.simple_cycle_duration() != major + minor {
+        if self.simple_cycle_duration() - self.total_intergreen() != major + minor {
             bail!("This intersection didn't already group major/minor roads together.");
         }
 
@@ -271,14 +459,20 @@ impl ControlTrafficSignal {
         missing
     }
 
-    /// How long a full cycle of the signal lasts, assuming no actuated timings.
+    /// How long a full cycle of the signal lasts, assuming no actuated timings. This includes
+    /// amber and all-red intervals.
     pub fn simple_cycle_duration(&self) -> Duration {
         let mut total = Duration::ZERO;
         for s in &self.stages {
-            total += s.stage_type.simple_duration();
+            total += s.stage_type.simple_duration() + s.intergreen();
         }
         total
     }
+
+    /// The total amber and all-red time over one cycle
+    pub fn total_intergreen(&self) -> Duration {
+        self.stages.iter().map(|s| s.intergreen()).sum()
+    }
 }
 
 impl Stage {
@@ -288,7 +482,37 @@ impl Stage {
             yield_movements: BTreeSet::new(),
             // TODO Set a default
             stage_type: StageType::Fixed(Duration::seconds(30.0)),
+            pedestrian_phase: PedestrianPhase::Recall,
+            amber: Duration::ZERO,
+            all_red: Duration::ZERO,
+        }
+    }
+
+    /// The amber plus all-red time after this stage ends, before the next one begins
+    pub fn intergreen(&self) -> Duration {
+        self.amber + self.all_red
+    }
+
+    /// How long the clearance interval (flashing don't walk) must last, so that somebody starting
+    /// at the end of the walk interval can finish crossing the longest protected crosswalk.
+    pub fn pedestrian_clearance(&self, i: &Intersection) -> Duration {
+        let mut max_distance = Distance::meters(0.0);
+        for movement in &self.protected_movements {
+            if movement.crosswalk {
+                max_distance = max_distance.max(i.movements[movement].geom.length());
+            }
         }
+        let time = max_distance / CROSSWALK_PACE;
+        assert!(time >= Duration::ZERO);
+        // Round up because it is converted to a usize elsewhere
+        Duration::seconds(time.inner_seconds().ceil())
+    }
+
+    /// True if this stage serves nothing besides crosswalks that need a push-button call.
+    pub fn only_push_button_crosswalks(&self) -> bool {
+        matches!(self.pedestrian_phase, PedestrianPhase::PushButton { .. })
+            && self.protected_movements.iter().all(|m| m.crosswalk)
+            && self.yield_movements.is_empty()
     }
 
     pub fn could_be_protected(&self, m1: MovementID, i: &Intersection) -> bool {
@@ -397,9 +621,45 @@ impl ControlTrafficSignal {
                                 )
                             }
                         },
+                        pedestrian_phase: match s.pedestrian_phase {
+                            PedestrianPhase::Recall => {
+                                perma_traffic_signal::PedestrianPhase::Recall
+                            }
+                            PedestrianPhase::PushButton { min_walk } => {
+                                perma_traffic_signal::PedestrianPhase::PushButton {
+                                    min_walk_seconds: min_walk.inner_seconds() as usize,
+                                }
+                            }
+                        },
+                        amber_seconds: s.amber.inner_seconds() as usize,
+                        all_red_seconds: s.all_red.inner_seconds() as usize,
                     })
                     .collect(),
                 offset_seconds: self.offset.inner_seconds() as usize,
+                control: match self.control {
+                    SignalControl::Cycle => perma_traffic_signal::SignalControl::Cycle,
+                    SignalControl::MaxPressure {
+                        min_green,
+                        max_green,
+                    } => perma_traffic_signal::SignalControl::MaxPressure {
+                        min_green_seconds: min_green.inner_seconds() as usize,
+                        max_green_seconds: max_green.inner_seconds() as usize,
+                    },
+                    SignalControl::AdaptiveSplits {
+                        cycle_length,
+                        min_green,
+                    } => perma_traffic_signal::SignalControl::AdaptiveSplits {
+                        cycle_length_seconds: cycle_length.inner_seconds() as usize,
+                        min_green_seconds: min_green.inner_seconds() as usize,
+                    },
+                },
+                priority: perma_traffic_signal::SignalPriority {
+                    transit_max_adjustment_seconds: self
+                        .priority
+                        .transit_max_adjustment
+                        .map(|d| d.inner_seconds() as usize),
+                    emergency_preemption: self.priority.emergency_preemption,
+                },
             }],
         }
     }
@@ -452,6 +712,16 @@ impl ControlTrafficSignal {
                             )
                         }
                     },
+                    pedestrian_phase: match s.pedestrian_phase {
+                        perma_traffic_signal::PedestrianPhase::Recall => PedestrianPhase::Recall,
+                        perma_traffic_signal::PedestrianPhase::PushButton { min_walk_seconds } => {
+                            PedestrianPhase::PushButton {
+                                min_walk: Duration::seconds(min_walk_seconds as f64),
+                            }
+                        }
+                    },
+                    amber: Duration::seconds(s.amber_seconds as f64),
+                    all_red: Duration::seconds(s.all_red_seconds as f64),
                 });
             } else {
                 bail!("{}", errors.join("; "));
@@ -461,6 +731,30 @@ impl ControlTrafficSignal {
             id,
             stages,
             offset: Duration::seconds(plan.offset_seconds as f64),
+            control: match plan.control {
+                perma_traffic_signal::SignalControl::Cycle => SignalControl::Cycle,
+                perma_traffic_signal::SignalControl::MaxPressure {
+                    min_green_seconds,
+                    max_green_seconds,
+                } => SignalControl::MaxPressure {
+                    min_green: Duration::seconds(min_green_seconds as f64),
+                    max_green: Duration::seconds(max_green_seconds as f64),
+                },
+                perma_traffic_signal::SignalControl::AdaptiveSplits {
+                    cycle_length_seconds,
+                    min_green_seconds,
+                } => SignalControl::AdaptiveSplits {
+                    cycle_length: Duration::seconds(cycle_length_seconds as f64),
+                    min_green: Duration::seconds(min_green_seconds as f64),
+                },
+            },
+            priority: SignalPriority {
+                transit_max_adjustment: plan
+                    .priority
+                    .transit_max_adjustment_seconds
+                    .map(|x| Duration::seconds(x as f64)),
+                emergency_preemption: plan.priority.emergency_preemption,
+            },
         };
         ts.validate(map.get_i(id))?;
         Ok(ts)
@@ -0,0 +1,416 @@
+//! Static traffic assignment finds how much traffic uses each road without simulating individual
+//! vehicles, by assuming everybody eventually picks a route that nobody could improve on alone
+//! (user equilibrium). This uses the method of successive averages (MSA): repeatedly route all
+//! demand along the currently fastest paths, then blend those volumes into the running average.
+//! Congestion slows roads down following the BPR volume-delay function.
+//!
+//! This is much faster than the full simulation, but ignores queues spilling back, signal timing,
+//! and when trips happen within the period.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, prettyprint_usize, serialize_btreemap, Timer};
+use geom::{Distance, Duration, Speed};
+
+use super::CreateEngine;
+use crate::{
+    DirectedRoadID, IntersectionID, Map, PathConstraints, PathRequest, PathStepV2, PathV2,
+    Pathfinder,
+};
+
+/// Vehicles keep at least this much room in front of them at any speed
+const VEHICLE_SPACING: Distance = Distance::const_meters(7.5);
+/// Plus how far they travel during this reaction time
+const REACTION_TIME: Duration = Duration::const_seconds(1.5);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AssignmentOptions {
+    /// Give up after this many rounds, even if the relative gap is still too big
+    pub max_iterations: usize,
+    /// Stop once the relative gap, measuring how far from equilibrium the volumes are, drops
+    /// below this
+    pub relative_gap: f64,
+    /// The BPR function's travel time at capacity is `1 + alpha` times the free-flow time
+    pub bpr_alpha: f64,
+    /// How sharply the BPR function's travel time grows past capacity
+    pub bpr_beta: f64,
+    /// How long the period covered by the demand lasts. Road capacities are scaled to this.
+    pub period: Duration,
+}
+
+impl Default for AssignmentOptions {
+    fn default() -> Self {
+        Self {
+            max_iterations: 20,
+            relative_gap: 0.01,
+            bpr_alpha: 0.15,
+            bpr_beta: 4.0,
+            period: Duration::hours(1),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AssignmentResult {
+    /// How many vehicles use each road over the whole period
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub volumes: BTreeMap<DirectedRoadID, f64>,
+    /// How many vehicles pass through each intersection over the whole period
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub intersection_volumes: BTreeMap<IntersectionID, f64>,
+    /// How many vehicles each road could handle over the whole period, from the number of lanes
+    /// and the speed limit
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub capacities: BTreeMap<DirectedRoadID, f64>,
+    pub iterations: usize,
+    pub relative_gap: f64,
+    /// Demand that couldn't be routed at all
+    pub unroutable: usize,
+}
+
+impl AssignmentResult {
+    /// The ratio of volume to capacity, or 0 for roads without any capacity
+    pub fn volume_to_capacity(&self, dr: DirectedRoadID) -> f64 {
+        let capacity = self.capacities.get(&dr).cloned().unwrap_or(0.0);
+        if capacity == 0.0 {
+            return 0.0;
+        }
+        self.volumes.get(&dr).cloned().unwrap_or(0.0) / capacity
+    }
+}
+
+/// Assign driving demand to roads. Each request has the number of vehicles making it -- use
+/// `PathRequest::deduplicate` to build this origin-destination matrix. Requests for anything
+/// besides cars are ignored.
+pub fn static_assignment(
+    map: &Map,
+    demand: Vec<(PathRequest, usize)>,
+    opts: &AssignmentOptions,
+    timer: &mut Timer,
+) -> AssignmentResult {
+    let demand: Vec<(PathRequest, usize)> = demand
+        .into_iter()
+        .filter(|(req, _)| req.constraints == PathConstraints::Car)
+        .collect();
+    let hours = opts.period / Duration::hours(1);
+
+    let mut network = Network {
+        free_flow: BTreeMap::new(),
+        capacities: BTreeMap::new(),
+    };
+    for road in map.all_roads() {
+        if road.speed_limit <= Speed::ZERO {
+            continue;
+        }
+        let headway = REACTION_TIME + VEHICLE_SPACING / road.speed_limit;
+        let per_lane = hours * (Duration::hours(1) / headway);
+        for dr in road.id.both_directions() {
+            let lanes = road
+                .lanes
+                .iter()
+                .filter(|l| l.dir == dr.dir && PathConstraints::Car.can_use(l, map))
+                .count();
+            if lanes > 0 {
+                network
+                    .free_flow
+                    .insert(dr, road.length() / road.speed_limit);
+                network.capacities.insert(dr, per_lane * (lanes as f64));
+            }
+        }
+    }
+
+    let result = successive_averages(&network, opts, |road_delays, iteration| {
+        // Start by routing everybody along free-flow paths
+        if iteration == 1 {
+            return all_or_nothing(
+                &demand,
+                |req| map.pathfind_v2(req).ok(),
+                "route at free-flow speeds",
+                timer,
+            );
+        }
+        let mut params = map.routing_params().clone();
+        params.road_delays = road_delays;
+        let pathfinder = Pathfinder::new_limited(
+            map,
+            params,
+            CreateEngine::Dijkstra,
+            vec![PathConstraints::Car],
+            timer,
+        );
+        all_or_nothing(
+            &demand,
+            |req| pathfinder.pathfind_v2(req, map),
+            &format!("route with congestion, iteration {}", iteration),
+            timer,
+        )
+    });
+    if result.unroutable > 0 {
+        warn!(
+            "{} vehicles couldn't be routed",
+            prettyprint_usize(result.unroutable)
+        );
+    }
+    result
+}
+
+/// Volumes per road and intersection, plus how many vehicles couldn't be routed
+type Volumes = (
+    BTreeMap<DirectedRoadID, f64>,
+    BTreeMap<IntersectionID, f64>,
+    usize,
+);
+
+/// The roads that demand can be assigned to
+struct Network {
+    free_flow: BTreeMap<DirectedRoadID, Duration>,
+    capacities: BTreeMap<DirectedRoadID, f64>,
+}
+
+impl Network {
+    /// Congested travel time minus the free-flow time
+    fn delay(&self, dr: &DirectedRoadID, volume: f64, opts: &AssignmentOptions) -> Duration {
+        match (self.free_flow.get(dr), self.capacities.get(dr)) {
+            (Some(t0), Some(capacity)) => bpr_delay(*t0, volume, *capacity, opts),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// How much total travel time would drop if everybody switched from the `current` volumes
+    /// to the `best` ones, routed along the fastest paths, relative to the current total
+    fn relative_gap(
+        &self,
+        current: &BTreeMap<DirectedRoadID, f64>,
+        best: &BTreeMap<DirectedRoadID, f64>,
+        opts: &AssignmentOptions,
+    ) -> f64 {
+        let mut current_total = 0.0;
+        let mut best_total = 0.0;
+        for (dr, t0) in &self.free_flow {
+            let x = current.get(dr).cloned().unwrap_or(0.0);
+            let y = best.get(dr).cloned().unwrap_or(0.0);
+            let time = (*t0 + self.delay(dr, x, opts)).inner_seconds();
+            current_total += x * time;
+            best_total += y * time;
+        }
+        if current_total > 0.0 {
+            (current_total - best_total) / current_total
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The BPR volume-delay function: how much longer than the free-flow time `t0` it takes to cross
+/// a road with some volume and capacity
+fn bpr_delay(t0: Duration, volume: f64, capacity: f64, opts: &AssignmentOptions) -> Duration {
+    t0 * (opts.bpr_alpha * (volume / capacity).powf(opts.bpr_beta))
+}
+
+/// The method of successive averages. `route` assigns all demand to the fastest paths, given the
+/// delay on each road and the iteration, starting from 1 with no delays.
+fn successive_averages<F: FnMut(BTreeMap<DirectedRoadID, Duration>, usize) -> Volumes>(
+    network: &Network,
+    opts: &AssignmentOptions,
+    mut route: F,
+) -> AssignmentResult {
+    let (volumes, intersection_volumes, unroutable) = route(BTreeMap::new(), 1);
+    let mut result = AssignmentResult {
+        volumes,
+        intersection_volumes,
+        capacities: network.capacities.clone(),
+        iterations: 1,
+        relative_gap: 1.0,
+        unroutable,
+    };
+
+    while result.iterations < opts.max_iterations {
+        let road_delays = result
+            .volumes
+            .iter()
+            .map(|(dr, volume)| (*dr, network.delay(dr, *volume, opts)))
+            .filter(|(_, delay)| *delay > Duration::ZERO)
+            .collect();
+        let (aux_volumes, aux_intersection_volumes, _) = route(road_delays, result.iterations + 1);
+
+        result.relative_gap = network.relative_gap(&result.volumes, &aux_volumes, opts);
+        info!(
+            "After {} iterations, the relative gap is {:.4}",
+            result.iterations, result.relative_gap
+        );
+        if result.relative_gap < opts.relative_gap {
+            break;
+        }
+
+        // Move the volumes part of the way towards the fastest paths
+        result.iterations += 1;
+        let step = 1.0 / (result.iterations as f64);
+        average_into(&mut result.volumes, aux_volumes, step);
+        average_into(
+            &mut result.intersection_volumes,
+            aux_intersection_volumes,
+            step,
+        );
+    }
+    result
+}
+
+/// Route all demand along the paths returned by `pathfind`, returning volumes per road and
+/// intersection, plus how many vehicles couldn't be routed.
+fn all_or_nothing<F: Fn(PathRequest) -> Option<PathV2> + Sync>(
+    demand: &[(PathRequest, usize)],
+    pathfind: F,
+    label: &str,
+    timer: &mut Timer,
+) -> Volumes {
+    let paths = timer.parallelize(label, demand.iter().collect(), |(req, count)| {
+        (pathfind(req.clone()), *count)
+    });
+
+    let mut volumes = BTreeMap::new();
+    let mut intersection_volumes = BTreeMap::new();
+    let mut unroutable = 0;
+    for (path, count) in paths {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                unroutable += count;
+                continue;
+            }
+        };
+        for step in path.get_steps() {
+            match step {
+                PathStepV2::Along(dr) | PathStepV2::Contraflow(dr) => {
+                    *volumes.entry(*dr).or_insert(0.0) += count as f64;
+                }
+                PathStepV2::Movement(m) | PathStepV2::ContraflowMovement(m) => {
+                    *intersection_volumes.entry(m.parent).or_insert(0.0) += count as f64;
+                }
+            }
+        }
+    }
+    (volumes, intersection_volumes, unroutable)
+}
+
+/// x = x + step * (y - x), for every key in either
+fn average_into<K: Ord + Copy>(x: &mut BTreeMap<K, f64>, y: BTreeMap<K, f64>, step: f64) {
+    for value in x.values_mut() {
+        *value *= 1.0 - step;
+    }
+    for (key, value) in y {
+        *x.entry(key).or_insert(0.0) += step * value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Direction, RoadID};
+
+    fn dr(road: usize) -> DirectedRoadID {
+        DirectedRoadID {
+            road: RoadID(road),
+            dir: Direction::Fwd,
+        }
+    }
+
+    #[test]
+    fn test_average_into() {
+        let mut x: BTreeMap<&str, f64> = vec![("a", 10.0), ("b", 4.0)].into_iter().collect();
+        let y: BTreeMap<&str, f64> = vec![("a", 0.0), ("c", 6.0)].into_iter().collect();
+        average_into(&mut x, y, 0.5);
+        assert_eq!(x["a"], 5.0);
+        // Keys only in one side blend with 0
+        assert_eq!(x["b"], 2.0);
+        assert_eq!(x["c"], 3.0);
+
+        // The first MSA step replaces the volumes entirely
+        let mut x: BTreeMap<&str, f64> = vec![("a", 10.0)].into_iter().collect();
+        let y: BTreeMap<&str, f64> = vec![("b", 7.0)].into_iter().collect();
+        average_into(&mut x, y, 1.0);
+        assert_eq!(x["a"], 0.0);
+        assert_eq!(x["b"], 7.0);
+    }
+
+    #[test]
+    fn test_bpr_delay() {
+        let opts = AssignmentOptions::default();
+        let t0 = Duration::seconds(100.0);
+        assert_eq!(bpr_delay(t0, 0.0, 500.0, &opts), Duration::ZERO);
+        // At capacity, alpha times longer
+        assert!((bpr_delay(t0, 500.0, 500.0, &opts).inner_seconds() - 15.0).abs() < 1e-6);
+        // Twice the capacity, 2^beta times that
+        assert!((bpr_delay(t0, 1000.0, 500.0, &opts).inner_seconds() - 240.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_two_routes_converge() {
+        // Two parallel routes with the same capacity; the second is slower at free-flow
+        let network = Network {
+            free_flow: vec![
+                (dr(1), Duration::seconds(60.0)),
+                (dr(2), Duration::seconds(90.0)),
+            ]
+            .into_iter()
+            .collect(),
+            capacities: vec![(dr(1), 1000.0), (dr(2), 1000.0)].into_iter().collect(),
+        };
+        let opts = AssignmentOptions::default();
+        let demand = 2000.0;
+        let travel_time = |road: usize, delays: &BTreeMap<DirectedRoadID, Duration>| {
+            network.free_flow[&dr(road)] + delays.get(&dr(road)).cloned().unwrap_or(Duration::ZERO)
+        };
+
+        let result = successive_averages(&network, &opts, |delays, _| {
+            // Everybody takes whichever route is fastest right now
+            let fastest = if travel_time(1, &delays) <= travel_time(2, &delays) {
+                1
+            } else {
+                2
+            };
+            (
+                vec![(dr(fastest), demand)].into_iter().collect(),
+                BTreeMap::new(),
+                0,
+            )
+        });
+
+        assert!(result.iterations > 1);
+        assert!(result.iterations < opts.max_iterations);
+        assert!(result.relative_gap < opts.relative_gap);
+        let v1 = result.volumes[&dr(1)];
+        let v2 = result.volumes[&dr(2)];
+        assert!((v1 + v2 - demand).abs() < 1e-6);
+        // The faster route carries more, but both get used
+        assert!(v1 > v2);
+        assert!(v2 > 0.0);
+        // At equilibrium, both routes take about as long
+        let t1 = network.free_flow[&dr(1)] + network.delay(&dr(1), v1, &opts);
+        let t2 = network.free_flow[&dr(2)] + network.delay(&dr(2), v2, &opts);
+        assert!((t1 / t2 - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_relative_gap() {
+        let network = Network {
+            free_flow: vec![(dr(1), Duration::seconds(60.0))].into_iter().collect(),
+            capacities: vec![(dr(1), 1000.0)].into_iter().collect(),
+        };
+        let opts = AssignmentOptions::default();
+        let volumes: BTreeMap<DirectedRoadID, f64> = vec![(dr(1), 10.0)].into_iter().collect();
+        // Nothing better to switch to
+        assert_eq!(network.relative_gap(&volumes, &volumes, &opts), 0.0);
+        assert_eq!(network.relative_gap(&BTreeMap::new(), &volumes, &opts), 0.0);
+    }
+}
@@ -1,20 +1,23 @@
 //! Everything related to pathfinding through a map for different types of agents.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use enumset::EnumSetType;
 use serde::{Deserialize, Serialize};
 
+use abstutil::{deserialize_btreemap, serialize_btreemap};
 use geom::Duration;
 
+pub use self::assignment::{static_assignment, AssignmentOptions, AssignmentResult};
 pub use self::engine::CreateEngine;
 pub use self::pathfinder::{Pathfinder, PathfinderCache, PathfinderCaching};
 pub use self::v1::{Path, PathRequest, PathStep};
 pub use self::v2::{PathStepV2, PathV2};
 pub use self::vehicles::vehicle_cost;
 pub use self::walking::WalkingNode;
-use crate::{osm, Lane, LaneID, LaneType, Map, MovementID, Road, RoadID, TurnType};
+use crate::{osm, DirectedRoadID, Lane, LaneID, LaneType, Map, MovementID, Road, RoadID, TurnType};
 
+mod assignment;
 mod engine;
 mod node_map;
 mod pathfinder;
@@ -198,6 +201,14 @@ pub struct RoutingParams {
     /// Don't allow movements between these roads at all. Only affects vehicle routing, not
     /// pedestrian.
     pub avoid_movements_between: BTreeSet<(RoadID, RoadID)>,
+
+    /// Extra time to cross some roads, like from live congestion. Only affects vehicle routing,
+    /// not pedestrian.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub road_delays: BTreeMap<DirectedRoadID, Duration>,
 }
 
 impl Default for RoutingParams {
@@ -219,6 +230,7 @@ impl Default for RoutingParams {
             avoid_roads: BTreeSet::new(),
             avoid_movements_between: BTreeSet::new(),
             only_use_roads: BTreeSet::new(),
+            road_delays: BTreeMap::new(),
         }
     }
 }
@@ -338,6 +338,12 @@ impl PathfinderCache {
         }
     }
 
+    /// Drop every cached pathfinder, when the caller knows the params they were built for won't
+    /// be used again.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
     /// New pathfinders will be created as-needed using Dijkstra's, no spammy logging
     pub fn pathfind_with_params(
         &mut self,
@@ -361,3 +367,16 @@ impl PathfinderCache {
         result
     }
 }
+
+impl Default for PathfinderCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Implemented manually, because the pathfinders are expensive to copy and can always be rebuilt.
+impl Clone for PathfinderCache {
+    fn clone(&self) -> Self {
+        Self::new()
+    }
+}
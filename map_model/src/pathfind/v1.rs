@@ -350,6 +350,39 @@ impl Path {
         }
     }
 
+    /// Replace every step from `idx` onwards with another path, which must begin with the same
+    /// step. This is used to re-plan the rest of a path partway through following it. The
+    /// original request is kept.
+    pub fn splice(&mut self, idx: usize, other: Path, map: &Map) -> Result<()> {
+        if self.currently_inside_ut.is_some() {
+            bail!("can't change a path in the middle of an uber-turn");
+        }
+        if self.steps.get(idx) != other.steps.front() {
+            bail!(
+                "replacement path starts with {:?}, not {:?}",
+                other.steps.front(),
+                self.steps.get(idx)
+            );
+        }
+        let kept: Vec<PathStep> = self.steps.iter().take(idx).cloned().collect();
+        if self.uber_turns.iter().any(|ut| {
+            kept.contains(&PathStep::Turn(ut.path[0]))
+                && !kept.contains(&PathStep::Turn(*ut.path.last().unwrap()))
+        }) {
+            bail!("can't change a path in the middle of an upcoming uber-turn");
+        }
+
+        for step in self.steps.split_off(idx) {
+            self.total_length -= self.dist_crossed_from_step(map, &step);
+        }
+        self.total_length += other.total_length;
+        self.uber_turns
+            .retain(|ut| kept.contains(&PathStep::Turn(ut.path[0])));
+        self.uber_turns.extend(other.uber_turns);
+        self.steps.extend(other.steps);
+        Ok(())
+    }
+
     pub fn current_step(&self) -> PathStep {
         self.steps[0]
     }
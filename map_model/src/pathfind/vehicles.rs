@@ -377,6 +377,9 @@ pub fn vehicle_cost(
     if map.is_unprotected_turn(dr.road, mvmnt.to.road, movement.turn_type) {
         extra += params.unprotected_turn_penalty
     }
+    if let Some(delay) = params.road_delays.get(&dr) {
+        extra += *delay;
+    }
 
     if (params.main_road_penalty - 1.0).abs() > f64::EPSILON
         && road.get_rank() != osm::RoadRank::Local
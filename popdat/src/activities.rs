@@ -3,10 +3,12 @@ use rand_xorshift::XorShiftRng;
 
 use geom::{Duration, Time};
 
+use synthpop::DayType;
+
 use crate::{Activity, CensusPerson, Config, PersonType, Schedule};
 
 impl CensusPerson {
-    pub fn generate_schedule(&self, _config: &Config, rng: &mut XorShiftRng) -> Schedule {
+    pub fn generate_schedule(&self, config: &Config, rng: &mut XorShiftRng) -> Schedule {
         // TODO How do we pick these categories based on census data?
         let person_type = if rng.gen_bool(0.5) {
             PersonType::Student
@@ -14,66 +16,142 @@ impl CensusPerson {
             PersonType::Worker
         };
 
-        // Fill out a list of activities and how long the person should do the activity before
-        // travelling to the next place.
-        let mut plan = Vec::new();
-        let start_time;
-
-        match person_type {
-            PersonType::Student => {
-                // I'm probably channeling a college student here...
-                start_time = rand_time(rng, hours(8), hours(11));
-                if rng.gen_bool(0.95) {
-                    plan.push((Activity::Breakfast, minutes(30)));
-                }
-                plan.push((Activity::School, rand_duration(rng, hours(3), hours(6))));
-                if rng.gen_bool(0.3) {
-                    plan.push((
-                        Activity::Lunch,
-                        rand_duration(rng, minutes(20), minutes(40)),
-                    ));
-                }
-                plan.push((Activity::School, rand_duration(rng, hours(2), hours(4))));
-                if rng.gen_bool(0.6) {
-                    plan.push((Activity::Entertainment, hours(2)));
-                } else {
-                    plan.push((Activity::Errands, rand_duration(rng, minutes(15), hours(1))));
+        let mut schedule = Vec::new();
+        for (day, day_type) in config.days.iter().enumerate() {
+            let (start_time, plan) = match day_type {
+                DayType::Weekday => weekday_plan(&person_type, rng),
+                DayType::Saturday | DayType::Sunday | DayType::Holiday => {
+                    day_off_plan(&person_type, *day_type, rng)
                 }
-                // The last duration doesn't matter
-                plan.push((Activity::Home, hours(8)));
+            };
+            // Staying home all day
+            if plan.is_empty() {
+                continue;
             }
-            PersonType::Worker => {
-                start_time = rand_time(rng, hours(6), hours(9));
-                if rng.gen_bool(0.8) {
-                    plan.push((Activity::Breakfast, minutes(15)));
-                }
-                plan.push((Activity::Work, rand_duration(rng, hours(4), hours(5))));
+
+            let mut now = start_time + hours(24 * day);
+            for (activity, duration) in plan {
+                schedule.push((day, now, activity));
+                // TODO We have to add in commute time here, but at this stage in the pipeline, we
+                // have no idea...
+                now += rand_duration(rng, Duration::minutes(30), Duration::hours(1));
+                now += duration;
+            }
+        }
+        Schedule {
+            activities: schedule,
+        }
+    }
+}
+
+/// Fill out a list of activities and how long the person should do the activity before travelling
+/// to the next place.
+fn weekday_plan(
+    person_type: &PersonType,
+    rng: &mut XorShiftRng,
+) -> (Time, Vec<(Activity, Duration)>) {
+    let mut plan = Vec::new();
+    let start_time;
+
+    match person_type {
+        PersonType::Student => {
+            // I'm probably channeling a college student here...
+            start_time = rand_time(rng, hours(8), hours(11));
+            if rng.gen_bool(0.95) {
+                plan.push((Activity::Breakfast, minutes(30)));
+            }
+            plan.push((Activity::School, rand_duration(rng, hours(3), hours(6))));
+            if rng.gen_bool(0.3) {
                 plan.push((
                     Activity::Lunch,
                     rand_duration(rng, minutes(20), minutes(40)),
                 ));
-                plan.push((Activity::Work, hours(4)));
-                if rng.gen_bool(0.8) {
-                    plan.push((Activity::Errands, rand_duration(rng, minutes(15), hours(1))));
-                }
-                // The last duration doesn't matter
-                plan.push((Activity::Home, hours(8)));
             }
+            plan.push((Activity::School, rand_duration(rng, hours(2), hours(4))));
+            if rng.gen_bool(0.6) {
+                plan.push((Activity::Entertainment, hours(2)));
+            } else {
+                plan.push((Activity::Errands, rand_duration(rng, minutes(15), hours(1))));
+            }
+            // The last duration doesn't matter
+            plan.push((Activity::Home, hours(8)));
         }
-
-        let mut schedule = Vec::new();
-        let mut now = start_time;
-        for (activity, duration) in plan {
-            schedule.push((now, activity));
-            // TODO We have to add in commute time here, but at this stage in the pipeline, we have
-            // no idea...
-            now += rand_duration(rng, Duration::minutes(30), Duration::hours(1));
-            now += duration;
+        PersonType::Worker => {
+            start_time = rand_time(rng, hours(6), hours(9));
+            if rng.gen_bool(0.8) {
+                plan.push((Activity::Breakfast, minutes(15)));
+            }
+            plan.push((Activity::Work, rand_duration(rng, hours(4), hours(5))));
+            plan.push((
+                Activity::Lunch,
+                rand_duration(rng, minutes(20), minutes(40)),
+            ));
+            plan.push((Activity::Work, hours(4)));
+            if rng.gen_bool(0.8) {
+                plan.push((Activity::Errands, rand_duration(rng, minutes(15), hours(1))));
+            }
+            // The last duration doesn't matter
+            plan.push((Activity::Home, hours(8)));
         }
-        Schedule {
-            activities: schedule,
+    }
+
+    (start_time, plan)
+}
+
+/// People start later on weekends and holidays, and mostly go out for meals, shopping, and fun.
+/// Some workers have a shift on Saturday. Fewer places are open on Sundays and holidays. An empty
+/// plan means staying home all day.
+fn day_off_plan(
+    person_type: &PersonType,
+    day_type: DayType,
+    rng: &mut XorShiftRng,
+) -> (Time, Vec<(Activity, Duration)>) {
+    let mut plan = Vec::new();
+    let saturday = day_type == DayType::Saturday;
+
+    if matches!(person_type, PersonType::Worker) && saturday && rng.gen_bool(0.2) {
+        let start_time = rand_time(rng, hours(7), hours(10));
+        plan.push((Activity::Work, rand_duration(rng, hours(4), hours(8))));
+        if rng.gen_bool(0.5) {
+            plan.push((Activity::Errands, rand_duration(rng, minutes(15), hours(1))));
         }
+        // The last duration doesn't matter
+        plan.push((Activity::Home, hours(8)));
+        return (start_time, plan);
+    }
+
+    if !rng.gen_bool(if saturday { 0.85 } else { 0.7 }) {
+        return (Time::START_OF_DAY, plan);
+    }
+    let start_time = match person_type {
+        PersonType::Student => rand_time(rng, hours(10), hours(13)),
+        PersonType::Worker => rand_time(rng, hours(9), hours(12)),
+    };
+    if rng.gen_bool(0.4) {
+        plan.push((
+            Activity::Breakfast,
+            rand_duration(rng, minutes(30), hours(1)),
+        ));
+    }
+    if rng.gen_bool(if saturday { 0.7 } else { 0.4 }) {
+        plan.push((Activity::Errands, rand_duration(rng, minutes(30), hours(2))));
     }
+    if rng.gen_bool(0.6) {
+        plan.push((
+            Activity::Entertainment,
+            rand_duration(rng, hours(1), hours(3)),
+        ));
+    }
+    if rng.gen_bool(0.4) {
+        plan.push((Activity::Dinner, rand_duration(rng, hours(1), hours(2))));
+    }
+    if plan.is_empty() {
+        return (start_time, plan);
+    }
+    // The last duration doesn't matter
+    plan.push((Activity::Home, hours(8)));
+
+    (start_time, plan)
 }
 
 fn rand_duration(rng: &mut XorShiftRng, low: Duration, high: Duration) -> Duration {
@@ -14,7 +14,8 @@
 //!    specific building on the map as their home, and assigning specific attributes based on the
 //!    census data's distribution.
 //! 3) For each CensusPerson, classify them into a PersonType, then generate a Schedule of
-//!    different Activities throughout the day.
+//!    different Activities throughout the day -- or several days, if weekends or holidays are
+//!    included.
 //! 4) Pick specific buildings to visit to satisfy the Schedule.
 
 #[macro_use]
@@ -27,7 +28,7 @@ use rand_xorshift::XorShiftRng;
 use abstutil::Timer;
 use geom::{Distance, Time};
 use map_model::{BuildingID, Map};
-use synthpop::Scenario;
+use synthpop::{DayType, Scenario};
 
 pub use self::distribute_people::distribute_population_to_homes;
 
@@ -63,10 +64,12 @@ pub enum PersonType {
     Worker,
 }
 
-/// A single person's daily schedule. It's assumed that someone always starts at home. And for most
-/// people, the last entry should probably be Activity::Home.
+/// A single person's schedule, covering every day in the Config. It's assumed that someone always
+/// starts at home. And for most people, the last entry should probably be Activity::Home.
 pub struct Schedule {
-    pub activities: Vec<(Time, Activity)>,
+    /// The day each activity belongs to, when it starts, and what it is. Late activities may start
+    /// after midnight and still belong to the previous day.
+    pub activities: Vec<(usize, Time, Activity)>,
 }
 
 /// Different things people might do in the day. Maybe it's more clear to call this a
@@ -90,6 +93,8 @@ pub enum Activity {
 pub struct Config {
     pub walk_for_distances_shorter_than: Distance,
     pub walk_or_bike_for_distances_shorter_than: Distance,
+    /// Generate a schedule for each of these days in a row, starting at midnight of the first.
+    pub days: Vec<DayType>,
 }
 
 impl Config {
@@ -97,6 +102,7 @@ impl Config {
         Config {
             walk_for_distances_shorter_than: Distance::miles(0.5),
             walk_or_bike_for_distances_shorter_than: Distance::miles(3.0),
+            days: vec![DayType::Weekday],
         }
     }
 }
@@ -119,6 +125,7 @@ pub fn generate_scenario(
     timer.stop("assigning people to houses");
 
     let mut scenario = Scenario::empty(map, scenario_name);
+    scenario.day_types = config.days.clone();
     timer.start("building people");
     scenario.people.extend(make_person::make_people(
         people, map, &mut timer, rng, &config,
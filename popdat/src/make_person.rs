@@ -187,12 +187,15 @@ impl PersonFactory {
         };
 
         let mut current_location = TripEndpoint::Building(person.home);
-        for (departure_time, activity) in schedule.activities {
+        for (day, departure_time, activity) in schedule.activities {
             // TODO This field isn't that important; later we could map Activity to a TripPurpose
             // better.
             let purpose = TripPurpose::Shopping;
 
-            let goto = if let Some(destination) =
+            let goto = if activity == Activity::Home {
+                // Multi-day schedules rely on people returning to the same home every night
+                TripEndpoint::Building(person.home)
+            } else if let Some(destination) =
                 self.find_building_for_activity(activity, current_location, map, rng)
             {
                 TripEndpoint::Building(destination)
@@ -203,15 +206,15 @@ impl PersonFactory {
                 // Broken map without borders. Don't crash, just skip the person
                 continue;
             };
+            // Already there, like going home when the last activity happened to be at home
+            if goto == current_location {
+                continue;
+            }
 
             let mode = pick_mode(current_location, goto, map, rng, config);
-            output.trips.push(IndividTrip::new(
-                departure_time,
-                purpose,
-                current_location,
-                goto,
-                mode,
-            ));
+            let mut trip = IndividTrip::new(departure_time, purpose, current_location, goto, mode);
+            trip.day = day;
+            output.trips.push(trip);
 
             current_location = goto;
         }
@@ -0,0 +1,334 @@
+//! An environment for training traffic signal controllers, in the style of OpenAI Gym. Each
+//! episode loads a scenario and takes over some traffic signals. Then the controller repeatedly
+//! picks an action for every signal, the simulation advances by a fixed step, and the environment
+//! reports a new observation and reward per signal.
+//!
+//! `headless` serves this environment over HTTP, under `/env/`.
+
+#[macro_use]
+extern crate anyhow;
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+
+use abstutil::Timer;
+use geom::{Duration, Time};
+use map_model::{IntersectionID, Map, PermanentMapEdits};
+use sim::{Sim, SimFlags, SimOptions};
+use synthpop::{Scenario, ScenarioModifier};
+
+pub use self::reward::{RewardFunction, RewardType};
+
+mod reward;
+
+/// A simulation where some traffic signals are controlled by an external agent, one step at a
+/// time.
+pub struct SignalEnv {
+    map: Map,
+    sim: Sim,
+    opts: SimOptions,
+    config: EnvConfig,
+    reward: Box<dyn RewardFunction>,
+    /// The signals being controlled, in a fixed order
+    controlled: Vec<IntersectionID>,
+}
+
+/// Describes how to run an episode. Any field left out of the JSON gets the default.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvConfig {
+    /// Which traffic signals to control. If empty, control every signal in the map.
+    pub intersections: Vec<IntersectionID>,
+    /// How much simulated time passes during each step
+    pub step_duration: Duration,
+    /// End the episode at this time, even if some trips haven't finished
+    pub end_time: Time,
+    pub reward: RewardType,
+}
+
+impl Default for EnvConfig {
+    fn default() -> EnvConfig {
+        EnvConfig {
+            intersections: Vec::new(),
+            step_duration: Duration::seconds(5.0),
+            end_time: Time::START_OF_DAY + Duration::hours(24),
+            reward: RewardType::Delay,
+        }
+    }
+}
+
+/// Everything needed to start a new episode.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResetArgs {
+    /// The path to a scenario file
+    pub scenario: String,
+    #[serde(default)]
+    pub modifiers: Vec<ScenarioModifier>,
+    #[serde(default)]
+    pub edits: Option<PermanentMapEdits>,
+    #[serde(default = "default_rng_seed")]
+    pub rng_seed: u64,
+    #[serde(default)]
+    pub config: EnvConfig,
+}
+
+fn default_rng_seed() -> u64 {
+    SimFlags::RNG_SEED
+}
+
+/// What to do with one traffic signal before the next step
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Action {
+    /// Stay in the current stage
+    Hold,
+    /// Advance to the next stage in the signal's cycle
+    Next,
+    /// Switch to a specific stage, zero based
+    SwitchTo(usize),
+}
+
+/// The state of one controlled traffic signal
+#[derive(Clone, Serialize)]
+pub struct IntersectionObservation {
+    /// How many agents are waiting to perform each movement, in the same order as
+    /// `Intersection::movements`
+    pub queue_lengths: Vec<usize>,
+    /// The current stage, zero based
+    pub current_stage: usize,
+    pub num_stages: usize,
+    pub time_in_stage: Duration,
+}
+
+impl IntersectionObservation {
+    /// Flattens the observation into numbers, suitable for feeding into a model. This has the
+    /// queue lengths, then a one-hot encoding of the current stage, then the seconds spent in
+    /// this stage.
+    pub fn to_vector(&self) -> Vec<f64> {
+        let mut vector: Vec<f64> = self.queue_lengths.iter().map(|x| *x as f64).collect();
+        for stage in 0..self.num_stages {
+            vector.push(if stage == self.current_stage {
+                1.0
+            } else {
+                0.0
+            });
+        }
+        vector.push(self.time_in_stage.inner_seconds());
+        vector
+    }
+}
+
+#[derive(Serialize)]
+pub struct StepResult {
+    pub time: Time,
+    pub observations: BTreeMap<IntersectionID, IntersectionObservation>,
+    pub rewards: BTreeMap<IntersectionID, f64>,
+    /// The sum over all controlled signals
+    pub total_reward: f64,
+    /// True when all trips have finished or the end time has been reached
+    pub done: bool,
+}
+
+impl SignalEnv {
+    /// Creates an environment with a blank map. Call `reset` before `step`.
+    pub fn new(opts: SimOptions) -> SignalEnv {
+        let map = Map::blank();
+        let sim = Sim::new(&map, opts.clone());
+        SignalEnv {
+            map,
+            sim,
+            opts,
+            config: EnvConfig::default(),
+            reward: RewardType::Delay.make(),
+            controlled: Vec::new(),
+        }
+    }
+
+    /// Loads the scenario, applies edits and modifiers, and puts all of the controlled signals
+    /// under external control. Returns the initial observation.
+    pub fn reset(
+        &mut self,
+        args: ResetArgs,
+        timer: &mut Timer,
+    ) -> Result<BTreeMap<IntersectionID, IntersectionObservation>> {
+        let mut scenario: Scenario = abstio::read_object(args.scenario.clone(), timer)?;
+
+        let mut map = Map::load_synchronously(scenario.map_name.path(), timer);
+        if let Some(perma) = args.edits {
+            let edits = perma.into_edits(&map)?;
+            map.must_apply_edits(edits, timer);
+            map.recalculate_pathfinding_after_edits(timer);
+        }
+
+        let mut rng = XorShiftRng::seed_from_u64(args.rng_seed);
+        for m in &args.modifiers {
+            scenario = m.apply(&map, scenario, &mut rng);
+        }
+
+        let mut sim = Sim::new(&map, self.opts.clone());
+        sim.instantiate(&scenario, &map, &mut rng, timer);
+
+        let controlled: Vec<IntersectionID> = if args.config.intersections.is_empty() {
+            map.all_intersections()
+                .iter()
+                .filter(|i| i.is_traffic_signal())
+                .map(|i| i.id)
+                .collect()
+        } else {
+            args.config.intersections.clone()
+        };
+        for i in &controlled {
+            if map.maybe_get_traffic_signal(*i).is_none() {
+                bail!("{} isn't a traffic signal", i);
+            }
+            sim.set_traffic_signal_external_control(*i, true)?;
+        }
+
+        self.map = map;
+        self.sim = sim;
+        self.reward = args.config.reward.make();
+        self.config = args.config;
+        self.controlled = controlled;
+        Ok(self.observe())
+    }
+
+    /// Replaces the reward function for the current episode. `reset` switches back to the one
+    /// specified in the config.
+    pub fn set_reward_function(&mut self, reward: Box<dyn RewardFunction>) {
+        self.reward = reward;
+    }
+
+    /// Applies actions to some of the controlled signals, then advances the simulation by one
+    /// step. Signals without an action hold their current stage.
+    pub fn step(&mut self, actions: BTreeMap<IntersectionID, Action>) -> Result<StepResult> {
+        // Validate everything before changing anything
+        for (i, action) in &actions {
+            if !self.controlled.contains(i) {
+                bail!("{} isn't controlled in this episode", i);
+            }
+            if let Action::SwitchTo(stage) = action {
+                let num_stages = self.map.get_traffic_signal(*i).stages.len();
+                if *stage >= num_stages {
+                    bail!("{} only has {} stages", i, num_stages);
+                }
+            }
+        }
+
+        for (i, action) in actions {
+            let stage = match action {
+                Action::Hold => {
+                    continue;
+                }
+                Action::Next => {
+                    let (current, _) = self.sim.current_stage_and_remaining_time(i);
+                    (current + 1) % self.map.get_traffic_signal(i).stages.len()
+                }
+                Action::SwitchTo(stage) => stage,
+            };
+            self.sim.set_traffic_signal_stage(&self.map, i, stage)?;
+        }
+
+        let since = self.sim.time();
+        self.sim.timed_step(
+            &self.map,
+            self.config.step_duration,
+            &mut None,
+            &mut Timer::throwaway(),
+        );
+
+        let mut rewards = BTreeMap::new();
+        for i in &self.controlled {
+            rewards.insert(*i, self.reward.reward(&self.map, &self.sim, *i, since));
+        }
+        Ok(StepResult {
+            time: self.sim.time(),
+            observations: self.observe(),
+            total_reward: rewards.values().sum(),
+            rewards,
+            done: self.sim.is_done() || self.sim.time() >= self.config.end_time,
+        })
+    }
+
+    /// Describes the current state of every controlled signal.
+    pub fn observe(&self) -> BTreeMap<IntersectionID, IntersectionObservation> {
+        let mut results = BTreeMap::new();
+        for i in &self.controlled {
+            let mut queued = self.sim.get_queued_per_movement(&self.map, *i);
+            let (current_stage, _) = self.sim.current_stage_and_remaining_time(*i);
+            let (stage_start, _) = self.sim.current_stage_start_and_extensions(*i);
+            results.insert(
+                *i,
+                IntersectionObservation {
+                    queue_lengths: self
+                        .map
+                        .get_i(*i)
+                        .movements
+                        .keys()
+                        .map(|m| queued.remove(m).unwrap_or(0))
+                        .collect(),
+                    current_stage,
+                    num_stages: self.map.get_traffic_signal(*i).stages.len(),
+                    time_in_stage: self.sim.time() - stage_start,
+                },
+            );
+        }
+        results
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn sim(&self) -> &Sim {
+        &self.sim
+    }
+
+    /// The signals controlled in this episode
+    pub fn controlled_intersections(&self) -> &Vec<IntersectionID> {
+        &self.controlled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_advances_by_step_duration() {
+        let mut env = SignalEnv::new(SimOptions::new("test"));
+        let result = env.step(BTreeMap::new()).unwrap();
+        assert_eq!(result.time, Time::START_OF_DAY + Duration::seconds(5.0));
+        assert!(result.observations.is_empty());
+        assert!(result.rewards.is_empty());
+        assert_eq!(result.total_reward, 0.0);
+        // There are no trips, so the episode is over
+        assert!(result.done);
+
+        let result = env.step(BTreeMap::new()).unwrap();
+        assert_eq!(result.time, Time::START_OF_DAY + Duration::seconds(10.0));
+    }
+
+    #[test]
+    fn test_step_rejects_uncontrolled_signals() {
+        let mut env = SignalEnv::new(SimOptions::new("test"));
+        let mut actions = BTreeMap::new();
+        actions.insert(IntersectionID(0), Action::Next);
+        assert!(env.step(actions).is_err());
+        // Nothing happened
+        assert_eq!(env.sim().time(), Time::START_OF_DAY);
+    }
+
+    #[test]
+    fn test_observation_to_vector() {
+        let obs = IntersectionObservation {
+            queue_lengths: vec![3, 0, 1],
+            current_stage: 1,
+            num_stages: 3,
+            time_in_stage: Duration::seconds(12.0),
+        };
+        assert_eq!(obs.to_vector(), vec![3.0, 0.0, 1.0, 0.0, 1.0, 0.0, 12.0]);
+    }
+}
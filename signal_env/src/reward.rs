@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use geom::Time;
+use map_model::{CompressedMovementID, IntersectionID, Map};
+use sim::Sim;
+
+/// Scores how well one traffic signal performed during the last step. Higher is better.
+pub trait RewardFunction: Send + Sync {
+    /// `since` is the time when the last step began.
+    fn reward(&mut self, map: &Map, sim: &Sim, i: IntersectionID, since: Time) -> f64;
+}
+
+/// The built-in reward functions
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum RewardType {
+    /// The negative total delay of agents that finished crossing during the step
+    Delay,
+    /// The number of agents that crossed during the step
+    Throughput,
+}
+
+impl RewardType {
+    pub fn make(self) -> Box<dyn RewardFunction> {
+        match self {
+            RewardType::Delay => Box::new(Delay),
+            RewardType::Throughput => Box::new(Throughput {
+                last_total: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+struct Delay;
+
+impl RewardFunction for Delay {
+    fn reward(&mut self, _: &Map, sim: &Sim, i: IntersectionID, since: Time) -> f64 {
+        let mut total = 0.0;
+        if let Some(list) = sim.get_analytics().intersection_delays.get(&i) {
+            for (_, t, dt, _) in list.iter().rev() {
+                if *t <= since {
+                    break;
+                }
+                total += dt.inner_seconds();
+            }
+        }
+        -total
+    }
+}
+
+struct Throughput {
+    // Analytics only stores running totals, so remember the previous one
+    last_total: BTreeMap<IntersectionID, usize>,
+}
+
+impl RewardFunction for Throughput {
+    fn reward(&mut self, map: &Map, sim: &Sim, i: IntersectionID, _: Time) -> f64 {
+        let thruput = &sim.get_analytics().traffic_signal_thruput;
+        let total: usize = (0..map.get_i(i).movements.len())
+            .map(|idx| thruput.total_for(CompressedMovementID { i, idx: idx as u8 }))
+            .sum();
+        let prev = self.last_total.insert(i, total).unwrap_or(0);
+        (total - prev) as f64
+    }
+}
@@ -4,14 +4,18 @@ use std::fmt::Write;
 use serde::{Deserialize, Serialize};
 
 use abstutil::Counter;
-use geom::{Duration, Pt2D, Time};
+use geom::{Distance, Duration, Pt2D, Time};
 use map_model::{
     CompressedMovementID, IntersectionID, LaneID, Map, MovementID, ParkingLotID, Path, PathRequest,
     RoadID, TransitRouteID, TransitStopID, Traversable, TurnID,
 };
-use synthpop::TripMode;
+use synthpop::{DayType, TripMode};
 
-use crate::{AgentID, AgentType, AlertLocation, CarID, Event, ParkingSpot, TripID, TripPhaseType};
+use crate::{
+    AgentID, AgentType, AlertLocation, CarID, Emissions, Event, IncidentID, IncidentImpact,
+    OvertakeType, ParkingSpot, RerouteReason, SignalPriorityType, TripID, TripPhaseType,
+    VehicleType,
+};
 
 /// As a simulation runs, different pieces emit Events. The Analytics object listens to these,
 /// organizing and storing some information from them. The UI queries Analytics to draw time-series
@@ -43,6 +47,9 @@ pub struct Analytics {
     pub started_trips: BTreeMap<TripID, Time>,
     /// Finish time, ID, mode, trip duration if successful (or None if cancelled)
     pub finished_trips: Vec<(Time, TripID, TripMode, Option<Duration>)>,
+    /// Which day of the scenario each finished or cancelled trip belongs to. Trips from the first
+    /// day are omitted.
+    pub finished_trip_days: BTreeMap<TripID, usize>,
 
     /// Record different problems that each trip encounters.
     pub problems_per_trip: BTreeMap<TripID, Vec<(Time, Problem)>>,
@@ -53,11 +60,44 @@ pub struct Analytics {
     // TODO Transit riders aren't represented here yet, just the vehicle they're riding.
     /// Only for traffic signals. The u8 is the movement index from a CompressedMovementID.
     pub intersection_delays: BTreeMap<IntersectionID, Vec<(u8, Time, Duration, AgentType)>>,
+    /// Only for crosswalks at traffic signals. When did each pedestrian start crossing, and how
+    /// long did they wait first? Pedestrians who didn't wait at all are included.
+    pub crosswalk_waits: BTreeMap<MovementID, Vec<(Time, Duration)>>,
+    /// Only for traffic signals. When did a bus or emergency vehicle get priority, how much did
+    /// the green shift, and how many agents on conflicting movements were waiting or approaching?
+    pub signal_priority: BTreeMap<IntersectionID, Vec<(Time, SignalPriorityType, Duration, usize)>>,
+    /// The queue on every lane, sampled every `SimOptions::queue_sample_interval`. Empty if that's
+    /// off.
+    pub lane_queues: TimeSeriesSamples<LaneID, QueueSample>,
+    /// Emissions from each trip so far. Buses don't belong to a trip, so they're only counted
+    /// everywhere else.
+    pub trip_emissions: BTreeMap<TripID, Emissions>,
+    /// Emissions on each road and intersection
+    pub road_emissions: BTreeMap<RoadID, Emissions>,
+    pub intersection_emissions: BTreeMap<IntersectionID, Emissions>,
+    /// Per vehicle type, emissions during each hour
+    pub hourly_emissions: BTreeMap<VehicleType, Vec<Emissions>>,
+    /// Every time a vehicle started to pass a slower one on each road: (time, type of the passing
+    /// vehicle, type of the slower vehicle, how they passed)
+    pub overtakes: BTreeMap<RoadID, Vec<(Time, VehicleType, VehicleType, OvertakeType)>>,
+    /// Per incident, every trip it affected and how. Each trip only appears once per type of
+    /// impact, with the first time it happened.
+    pub incident_impacts: BTreeMap<IncidentID, Vec<(Time, TripID, IncidentImpact)>>,
+    /// Every time a vehicle changed its route while driving, and why
+    pub reroutes: Vec<(Time, CarID, RerouteReason)>,
+    /// How many times a vehicle re-planned its route to stop using each road, or to start using
+    /// it. Together, these measure how much traffic diverted.
+    pub diverted_from: Counter<RoadID>,
+    pub diverted_to: Counter<RoadID>,
 
     /// Per parking lane or lot, when does a spot become filled (true) or free (false)
     pub parking_lane_changes: BTreeMap<LaneID, Vec<(Time, bool)>>,
     pub parking_lot_changes: BTreeMap<ParkingLotID, Vec<(Time, bool)>>,
 
+    /// What kind of day each 24 hours of the simulation covers, copied from the scenario. Days
+    /// missing from this are weekdays.
+    pub day_types: Vec<DayType>,
+
     pub(crate) alerts: Vec<(Time, AlertLocation, String)>,
 
     /// For benchmarking, we may want to disable collecting data.
@@ -163,11 +203,25 @@ impl Analytics {
             passengers_alighting: BTreeMap::new(),
             started_trips: BTreeMap::new(),
             finished_trips: Vec::new(),
+            finished_trip_days: BTreeMap::new(),
             problems_per_trip: BTreeMap::new(),
             trip_log: Vec::new(),
             intersection_delays: BTreeMap::new(),
+            crosswalk_waits: BTreeMap::new(),
+            signal_priority: BTreeMap::new(),
+            lane_queues: TimeSeriesSamples::new(),
+            trip_emissions: BTreeMap::new(),
+            road_emissions: BTreeMap::new(),
+            intersection_emissions: BTreeMap::new(),
+            hourly_emissions: BTreeMap::new(),
+            overtakes: BTreeMap::new(),
+            incident_impacts: BTreeMap::new(),
+            reroutes: Vec::new(),
+            diverted_from: Counter::new(),
+            diverted_to: Counter::new(),
             parking_lane_changes: BTreeMap::new(),
             parking_lot_changes: BTreeMap::new(),
+            day_types: Vec::new(),
             alerts: Vec::new(),
             record_anything,
         }
@@ -256,14 +310,21 @@ impl Analytics {
             trip,
             mode,
             total_time,
+            day,
             ..
         } = ev
         {
             self.finished_trips
                 .push((time, trip, mode, Some(total_time)));
-        } else if let Event::TripCancelled(id, mode) = ev {
+            if day > 0 {
+                self.finished_trip_days.insert(trip, day);
+            }
+        } else if let Event::TripCancelled(id, mode, day) = ev {
             self.started_trips.entry(id).or_insert(time);
             self.finished_trips.push((time, id, mode, None));
+            if day > 0 {
+                self.finished_trip_days.insert(id, day);
+            }
         }
 
         // Intersection delay
@@ -291,6 +352,22 @@ impl Analytics {
             }
         }
 
+        if let Event::PedestrianStartsCrosswalk(_, turn, wait) = ev {
+            if let Some((m, _)) = map.get_movement_for_traffic_signal(turn) {
+                self.crosswalk_waits
+                    .entry(m)
+                    .or_insert_with(Vec::new)
+                    .push((time, wait));
+            }
+        }
+
+        if let Event::SignalPriorityGranted(i, _, priority_type, shift, conflicting) = ev {
+            self.signal_priority
+                .entry(i)
+                .or_insert_with(Vec::new)
+                .push((time, priority_type, shift, conflicting));
+        }
+
         // Parking spot changes
         if let Event::CarReachedParkingSpot(_, spot) = ev {
             if let ParkingSpot::Onstreet(l, _) = spot {
@@ -342,12 +419,70 @@ impl Analytics {
             }
         }
 
+        // Emissions
+        if let Event::VehicleEmissions(car, maybe_trip, on, emissions) = ev {
+            if let Some(trip) = maybe_trip {
+                *self.trip_emissions.entry(trip).or_insert(Emissions::ZERO) += emissions;
+            }
+            match on {
+                Traversable::Lane(l) => {
+                    *self.road_emissions.entry(l.road).or_insert(Emissions::ZERO) += emissions;
+                }
+                Traversable::Turn(t) => {
+                    *self
+                        .intersection_emissions
+                        .entry(t.parent)
+                        .or_insert(Emissions::ZERO) += emissions;
+                }
+            }
+            let hours = self
+                .hourly_emissions
+                .entry(car.vehicle_type)
+                .or_insert_with(Vec::new);
+            let hour = time.get_hours();
+            if hours.len() <= hour {
+                hours.resize(hour + 1, Emissions::ZERO);
+            }
+            hours[hour] += emissions;
+        }
+
+        if let Event::VehicleOvertaking(car, slower, lane, overtake_type) = ev {
+            self.overtakes
+                .entry(lane.road)
+                .or_insert_with(Vec::new)
+                .push((time, car.vehicle_type, slower.vehicle_type, overtake_type));
+        }
+
+        if let Event::TripAffectedByIncident(trip, incident, impact) = ev {
+            let impacts = self
+                .incident_impacts
+                .entry(incident)
+                .or_insert_with(Vec::new);
+            if !impacts.iter().any(|(_, t, i)| *t == trip && *i == impact) {
+                impacts.push((time, trip, impact));
+            }
+        }
+
         // TODO Kinda hacky, but these all consume the event, so kinda bundle em.
         match ev {
+            Event::VehicleRerouted {
+                car,
+                reason,
+                avoided,
+                added,
+            } => {
+                self.reroutes.push((time, car, reason));
+                for r in avoided {
+                    self.diverted_from.inc(r);
+                }
+                for r in added {
+                    self.diverted_to.inc(r);
+                }
+            }
             Event::TripPhaseStarting(id, _, maybe_req, phase_type) => {
                 self.trip_log.push((time, id, maybe_req, phase_type));
             }
-            Event::TripCancelled(id, _) => {
+            Event::TripCancelled(id, _, _) => {
                 self.trip_log
                     .push((time, id, None, TripPhaseType::Cancelled));
             }
@@ -371,6 +506,32 @@ impl Analytics {
         }
     }
 
+    pub fn record_lane_queues(&mut self, time: Time, samples: Vec<(LaneID, QueueSample)>) {
+        if !self.record_anything {
+            return;
+        }
+        self.lane_queues.record_all(time, samples);
+    }
+
+    /// The longest queue on a lane between two times, and whether it ever spilled back past the
+    /// start of the lane.
+    pub fn max_queue_length(
+        &self,
+        lane: LaneID,
+        t1: Time,
+        t2: Time,
+        map: &Map,
+    ) -> (Distance, bool) {
+        let max = self
+            .lane_queues
+            .get(lane, t1, t2)
+            .into_iter()
+            .map(|(_, sample)| sample.queue_length)
+            .max()
+            .unwrap_or(Distance::ZERO);
+        (max, max > map.get_l(lane).length())
+    }
+
     pub fn record_demand(&mut self, path: &Path, map: &Map) {
         for step in path.get_steps() {
             if let Traversable::Turn(t) = step.as_traversable() {
@@ -381,6 +542,136 @@ impl Analytics {
         }
     }
 
+    /// For every vehicle movement through a traffic signal, how many vehicles performed it
+    /// recently, during the current and previous hour. Also returns how long that window is, so
+    /// the counts can be turned into the flow at the current time of day.
+    pub fn recent_vehicle_thruput(
+        &self,
+        now: Time,
+        map: &Map,
+    ) -> (BTreeMap<MovementID, usize>, Duration) {
+        let first_hour = now.get_hours().saturating_sub(1);
+        let mut counts = BTreeMap::new();
+        for ((id, agent_type, hour), cnt) in &self.traffic_signal_thruput.counts {
+            if *hour < first_hour
+                || *hour > now.get_hours()
+                || !matches!(
+                    agent_type,
+                    AgentType::Car | AgentType::Bike | AgentType::Bus
+                )
+            {
+                continue;
+            }
+            if let Some(m) = map.get_i(id.i).movements.keys().nth(id.idx as usize) {
+                *counts.entry(*m).or_insert(0) += cnt;
+            }
+        }
+        (
+            counts,
+            now - (Time::START_OF_DAY + Duration::hours(first_hour)),
+        )
+    }
+
+    /// How many times did one traffic signal grant each type of priority, and roughly how much
+    /// delay did that cost conflicting traffic? The cost is estimated by multiplying each shift in
+    /// the green by the number of conflicting agents present at the time.
+    pub fn signal_priority_summary(
+        &self,
+        i: IntersectionID,
+    ) -> (Counter<SignalPriorityType>, Duration) {
+        let mut granted = Counter::new();
+        let mut cost = Duration::ZERO;
+        for (_, priority_type, shift, conflicting) in
+            self.signal_priority.get(&i).into_iter().flatten()
+        {
+            granted.inc(*priority_type);
+            cost += *shift * (*conflicting as f64);
+        }
+        (granted, cost)
+    }
+
+    /// How many times did one type of vehicle pass another, in each way, before some time?
+    /// (passing vehicle, slower vehicle, how)
+    pub fn count_overtakes(&self, now: Time) -> Counter<(VehicleType, VehicleType, OvertakeType)> {
+        let mut cnt = Counter::new();
+        for (time, vehicle_type, slower, overtake_type) in self.overtakes.values().flatten() {
+            if *time <= now {
+                cnt.inc((*vehicle_type, *slower, *overtake_type));
+            }
+        }
+        cnt
+    }
+
+    /// How many times did vehicles change their route while driving before some time, for each
+    /// reason?
+    pub fn count_reroutes(&self, now: Time) -> Counter<RerouteReason> {
+        let mut cnt = Counter::new();
+        for (time, _, reason) in &self.reroutes {
+            if *time <= now {
+                cnt.inc(*reason);
+            }
+        }
+        cnt
+    }
+
+    /// Every trip affected by an incident before some time, and how
+    pub fn trips_affected_by_incident(
+        &self,
+        incident: IncidentID,
+        now: Time,
+    ) -> BTreeMap<TripID, BTreeSet<IncidentImpact>> {
+        let mut trips = BTreeMap::new();
+        for (time, trip, impact) in self.incident_impacts.get(&incident).into_iter().flatten() {
+            if *time <= now {
+                trips
+                    .entry(*trip)
+                    .or_insert_with(BTreeSet::new)
+                    .insert(*impact);
+            }
+        }
+        trips
+    }
+
+    /// Total emissions per vehicle type. Like `TimeSeriesCount::total_for_by_time`, this includes
+    /// the entire hour containing `now`.
+    pub fn total_emissions_by_time(&self, now: Time) -> BTreeMap<VehicleType, Emissions> {
+        let mut totals = BTreeMap::new();
+        for (vehicle_type, hours) in &self.hourly_emissions {
+            let mut total = Emissions::ZERO;
+            for emissions in hours.iter().take(now.get_hours() + 1) {
+                total += *emissions;
+            }
+            totals.insert(*vehicle_type, total);
+        }
+        totals
+    }
+
+    /// Returns pairs of emissions for trips finished in both worlds. (ID, before, after, mode)
+    pub fn both_finished_trip_emissions(
+        &self,
+        now: Time,
+        before: &Analytics,
+    ) -> Vec<(TripID, Emissions, Emissions, TripMode)> {
+        self.both_finished_trips(now, before)
+            .into_iter()
+            .map(|(id, _, _, mode)| {
+                (
+                    id,
+                    before
+                        .trip_emissions
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or(Emissions::ZERO),
+                    self.trip_emissions
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or(Emissions::ZERO),
+                    mode,
+                )
+            })
+            .collect()
+    }
+
     // TODO If these ever need to be speeded up, just cache the histogram and index in the events
     // list.
 
@@ -689,6 +980,72 @@ impl Analytics {
     }
 }
 
+/// What happened during one day of a simulation covering several.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DaySummary {
+    /// Starting from 0
+    pub day: usize,
+    pub day_type: DayType,
+    /// Per mode, how many trips finished this day, and their total duration
+    pub finished_trips: BTreeMap<TripMode, (usize, Duration)>,
+    pub cancelled_trips: usize,
+    /// How many agents crossed any road and intersection this day
+    pub road_thruput: usize,
+    pub intersection_thruput: usize,
+}
+
+impl Analytics {
+    fn day_type(&self, day: usize) -> DayType {
+        self.day_types.get(day).cloned().unwrap_or(DayType::Weekday)
+    }
+
+    /// Summarize every day that's started by `now`. Trips count towards the day of the scenario
+    /// they belong to, even if they finish after midnight.
+    pub fn daily_summaries(&self, now: Time) -> Vec<DaySummary> {
+        let mut days: Vec<DaySummary> = (0..=now.get_hours() / 24)
+            .map(|day| DaySummary {
+                day,
+                day_type: self.day_type(day),
+                finished_trips: BTreeMap::new(),
+                cancelled_trips: 0,
+                road_thruput: 0,
+                intersection_thruput: 0,
+            })
+            .collect();
+        for (t, id, mode, maybe_duration) in &self.finished_trips {
+            if *t > now {
+                break;
+            }
+            let day = self.finished_trip_days.get(id).cloned().unwrap_or(0);
+            let day = match days.get_mut(day) {
+                Some(day) => day,
+                None => continue,
+            };
+            if let Some(dt) = maybe_duration {
+                let entry = day
+                    .finished_trips
+                    .entry(*mode)
+                    .or_insert((0, Duration::ZERO));
+                entry.0 += 1;
+                entry.1 += *dt;
+            } else {
+                day.cancelled_trips += 1;
+            }
+        }
+        for ((_, _, hour), count) in &self.road_thruput.counts {
+            if let Some(day) = days.get_mut(hour / 24) {
+                day.road_thruput += count;
+            }
+        }
+        for ((_, _, hour), count) in &self.intersection_thruput.counts {
+            if let Some(day) = days.get_mut(hour / 24) {
+                day.intersection_thruput += count;
+            }
+        }
+        days
+    }
+}
+
 impl Default for Analytics {
     fn default() -> Analytics {
         Analytics::new(false)
@@ -704,6 +1061,93 @@ pub struct TripPhase {
     pub phase_type: TripPhaseType,
 }
 
+/// A snapshot of the vehicles on one lane
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueueSample {
+    /// How many vehicles are on the lane
+    pub vehicles: usize,
+    /// How much of the lane is reserved by vehicles on it or about to enter it. Divide by the
+    /// lane's length to get occupancy.
+    pub reserved_length: Distance,
+    /// From the end of the lane, how far back does the unbroken line of stopped vehicles reach? If
+    /// this exceeds the lane's length, the queue has spilled back into the previous intersection.
+    pub queue_length: Distance,
+}
+
+impl Default for QueueSample {
+    fn default() -> QueueSample {
+        QueueSample {
+            vehicles: 0,
+            reserved_length: Distance::ZERO,
+            queue_length: Distance::ZERO,
+        }
+    }
+}
+
+/// Something sampled periodically for many objects, like the queue on every lane. To save space, a
+/// sample is only stored when the value changes, so each value lasts until the next sample.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TimeSeriesSamples<X: Ord + Clone, T> {
+    pub samples: BTreeMap<X, Vec<(Time, T)>>,
+}
+
+impl<X: Ord + Clone, T: Clone + PartialEq + Default> TimeSeriesSamples<X, T> {
+    fn new() -> TimeSeriesSamples<X, T> {
+        TimeSeriesSamples {
+            samples: BTreeMap::new(),
+        }
+    }
+
+    /// Record the value of everything at one time. Anything missing from `values` has the default
+    /// value.
+    fn record_all(&mut self, time: Time, values: Vec<(X, T)>) {
+        let mut seen = BTreeSet::new();
+        for (id, value) in values {
+            seen.insert(id.clone());
+            let list = self.samples.entry(id).or_insert_with(Vec::new);
+            if list.last().map(|(_, last)| *last != value).unwrap_or(true) {
+                list.push((time, value));
+            }
+        }
+        let default = T::default();
+        for (id, list) in &mut self.samples {
+            if !seen.contains(id)
+                && list
+                    .last()
+                    .map(|(_, last)| *last != default)
+                    .unwrap_or(false)
+            {
+                list.push((time, default.clone()));
+            }
+        }
+    }
+
+    /// The value at `t1` and every change up to `t2`. The first entry is reported at `t1`, even
+    /// if it was sampled earlier. Before anything's sampled, this is empty.
+    pub fn get(&self, id: X, t1: Time, t2: Time) -> Vec<(Time, T)> {
+        let mut results = Vec::new();
+        for (t, value) in self.samples.get(&id).into_iter().flatten() {
+            if *t <= t1 {
+                results = vec![(t1, value.clone())];
+            } else if *t <= t2 {
+                results.push((*t, value.clone()));
+            } else {
+                break;
+            }
+        }
+        results
+    }
+
+    /// The most recent value sampled at or before `time`
+    pub fn get_at(&self, id: X, time: Time) -> T {
+        self.samples
+            .get(&id)
+            .and_then(|list| list.iter().rev().find(|(t, _)| *t <= time))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    }
+}
+
 /// See https://github.com/a-b-street/abstreet/issues/85
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TimeSeriesCount<X: Ord + Clone> {
@@ -743,17 +1187,15 @@ impl<X: Ord + Clone> TimeSeriesCount<X> {
         self.total_for_with_agent_types(id, AgentType::all().into_iter().collect())
     }
 
+    /// Sums over every day of the simulation
     pub fn total_for_with_agent_types(&self, id: X, agent_types: BTreeSet<AgentType>) -> usize {
         let mut cnt = 0;
         for agent_type in agent_types {
-            // TODO Hmm
-            for hour in 0..24 {
-                cnt += self
-                    .counts
-                    .get(&(id.clone(), agent_type, hour))
-                    .cloned()
-                    .unwrap_or(0);
-            }
+            cnt += self
+                .counts
+                .range((id.clone(), agent_type, 0)..=(id.clone(), agent_type, usize::MAX))
+                .map(|(_, count)| *count)
+                .sum::<usize>();
         }
         cnt
     }
@@ -893,3 +1335,55 @@ impl SlidingWindow {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_summaries_use_trip_day() {
+        let mut analytics = Analytics::new(true);
+        analytics.day_types = vec![DayType::Weekday, DayType::Saturday];
+        let hours = |h| Time::START_OF_DAY + Duration::hours(h);
+        // A late trip from the first day that finishes after midnight
+        analytics.finished_trips.push((
+            hours(25),
+            TripID(0),
+            TripMode::Drive,
+            Some(Duration::minutes(90)),
+        ));
+        analytics.finished_trips.push((
+            hours(33),
+            TripID(1),
+            TripMode::Walk,
+            Some(Duration::minutes(20)),
+        ));
+        analytics.finished_trip_days.insert(TripID(1), 1);
+        analytics
+            .finished_trips
+            .push((hours(34), TripID(2), TripMode::Bike, None));
+        analytics.finished_trip_days.insert(TripID(2), 1);
+
+        let days = analytics.daily_summaries(hours(40));
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].day_type, DayType::Weekday);
+        assert_eq!(
+            days[0].finished_trips.get(&TripMode::Drive),
+            Some(&(1, Duration::minutes(90)))
+        );
+        assert_eq!(days[0].cancelled_trips, 0);
+
+        assert_eq!(days[1].day_type, DayType::Saturday);
+        assert_eq!(days[1].finished_trips.get(&TripMode::Drive), None);
+        assert_eq!(
+            days[1].finished_trips.get(&TripMode::Walk),
+            Some(&(1, Duration::minutes(20)))
+        );
+        assert_eq!(days[1].cancelled_trips, 1);
+
+        // Trips finishing later aren't counted yet
+        let days = analytics.daily_summaries(hours(30));
+        assert_eq!(days[1].finished_trips.len(), 0);
+        assert_eq!(days[1].cancelled_trips, 0);
+    }
+}
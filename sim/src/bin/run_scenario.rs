@@ -12,6 +12,10 @@ struct Args {
     /// How many hours to simulate.
     #[structopt(long)]
     hours: usize,
+    /// Record the trajectory of every vehicle and pedestrian, sampling everybody this often (in
+    /// seconds or as hh:mm:ss). At the end, write them as CSV and SUMO FCD XML.
+    #[structopt(long, parse(try_from_str = geom::Duration::parse))]
+    record_trajectories: Option<geom::Duration>,
     #[structopt(flatten)]
     flags: sim::SimFlags,
 }
@@ -24,6 +28,9 @@ fn main() {
     let (mut map, mut sim, _) = args
         .flags
         .load_synchronously(&mut abstutil::Timer::new("setup"));
+    if let Some(interval) = args.record_trajectories {
+        sim.record_trajectories(interval);
+    }
 
     if args.interruptible {
         // Pressing ^C will savestate. This needs a more complex loop to check for the interrupt.
@@ -54,13 +61,15 @@ fn main() {
                 &mut None,
             );
             if sim.time() == goal_time {
-                return;
+                break;
             }
         }
-        println!("\n\nInterrupting at {}", sim.time());
-        sim.save();
-        for x in sim.describe_internal_stats() {
-            println!("{}", x);
+        if sim.time() != goal_time {
+            println!("\n\nInterrupting at {}", sim.time());
+            sim.save();
+            for x in sim.describe_internal_stats() {
+                println!("{}", x);
+            }
         }
     } else {
         sim.timed_step(
@@ -70,4 +79,11 @@ fn main() {
             &mut abstutil::Timer::new("run simulation"),
         );
     }
+
+    if args.record_trajectories.is_some() {
+        match sim.save_trajectories(&map) {
+            Ok((path1, path2)) => println!("Wrote trajectories to {} and {}", path1, path2),
+            Err(err) => println!("Couldn't write trajectories: {}", err),
+        }
+    }
 }
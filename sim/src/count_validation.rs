@@ -0,0 +1,231 @@
+//! Compare real traffic counts against what happened in a simulation, one time bin at a time.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use abstutil::prettyprint_usize;
+use geom::{Duration, Time};
+use map_model::{CompressedMovementID, Map, MovementID, RoadID};
+use synthpop::{geh, r_squared, CountSite, TimeBinnedCounts, TripMode};
+
+use crate::{AgentType, Analytics};
+
+/// Where observed and simulated counts are compared. `Analytics` only tracks throughput per road
+/// (in both directions) and per movement through traffic signals, so directional counts are summed
+/// per road.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CountedAt {
+    Road(RoadID),
+    Movement(MovementID),
+}
+
+/// Observed and simulated counts for one place, mode, and hour.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BinComparison {
+    pub location: CountedAt,
+    pub mode: TripMode,
+    pub hour: usize,
+    pub observed: usize,
+    pub simulated: usize,
+    pub geh: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountValidation {
+    pub description: String,
+    pub bins: Vec<BinComparison>,
+    /// Observed movement counts that can't be compared, because they're not at a traffic signal
+    pub skipped_movements: usize,
+}
+
+impl Analytics {
+    /// Compare observed counts against `road_thruput` and `traffic_signal_thruput`. Both of these
+    /// are recorded per hour, so the observed counts are merged into hourly bins first. Only hours
+    /// that're over by `now` are compared.
+    pub fn compare_counts(
+        &self,
+        observed: &TimeBinnedCounts,
+        now: Time,
+        map: &Map,
+    ) -> Result<CountValidation> {
+        if &observed.map != map.get_name() {
+            bail!(
+                "Counts are for {}, not {}",
+                observed.map.describe(),
+                map.get_name().describe()
+            );
+        }
+        let observed = observed.rebin(Duration::hours(1))?;
+
+        let mut per_bin: BTreeMap<(CountedAt, TripMode, usize), usize> = BTreeMap::new();
+        let mut skipped_movements = 0;
+        for ((site, mode, hour), count) in &observed.counts {
+            if observed.bin_start(hour + 1) > now {
+                continue;
+            }
+            let location = match site {
+                CountSite::Road(dr) => CountedAt::Road(dr.road),
+                CountSite::Movement(m) => {
+                    if !map.get_i(m.parent).is_traffic_signal() {
+                        skipped_movements += 1;
+                        continue;
+                    }
+                    CountedAt::Movement(*m)
+                }
+            };
+            *per_bin.entry((location, *mode, *hour)).or_insert(0) += count;
+        }
+
+        let mut bins = Vec::new();
+        for ((location, mode, hour), count) in per_bin {
+            let mut simulated = 0;
+            for agent_type in agent_types(mode) {
+                simulated += match location {
+                    CountedAt::Road(r) => self.road_thruput.counts.get(&(r, agent_type, hour)),
+                    CountedAt::Movement(m) => {
+                        // CompressedMovementID just indexes into the intersection's movements
+                        let idx = map.get_i(m.parent).movements.keys().position(|x| *x == m);
+                        idx.and_then(|idx| {
+                            self.traffic_signal_thruput.counts.get(&(
+                                CompressedMovementID {
+                                    i: m.parent,
+                                    idx: u8::try_from(idx).unwrap(),
+                                },
+                                agent_type,
+                                hour,
+                            ))
+                        })
+                    }
+                }
+                .cloned()
+                .unwrap_or(0);
+            }
+            bins.push(BinComparison {
+                location,
+                mode,
+                hour,
+                observed: count,
+                simulated,
+                geh: geh(simulated as f64, count as f64),
+            });
+        }
+
+        Ok(CountValidation {
+            description: observed.description,
+            bins,
+            skipped_movements,
+        })
+    }
+}
+
+impl CountValidation {
+    /// The R² between simulated and observed counts, over all bins
+    pub fn r_squared(&self) -> Option<f64> {
+        r_squared(&pairs(self.bins.iter()))
+    }
+
+    /// The R² between simulated and observed counts, separately for each hour
+    pub fn r_squared_per_hour(&self) -> BTreeMap<usize, Option<f64>> {
+        self.per_hour()
+            .into_iter()
+            .map(|(hour, bins)| (hour, r_squared(&pairs(bins.into_iter()))))
+            .collect()
+    }
+
+    /// Describe how many bins have a GEH below some threshold and the R², overall and per hour
+    pub fn summary(&self, geh_threshold: f64) -> String {
+        let mut out = format!(
+            "{}: {} of {} bins have GEH < {}, R² = {}\n",
+            self.description,
+            prettyprint_usize(count_good(self.bins.iter(), geh_threshold)),
+            prettyprint_usize(self.bins.len()),
+            geh_threshold,
+            describe_r_squared(self.r_squared())
+        );
+        let r_squared = self.r_squared_per_hour();
+        for (hour, bins) in self.per_hour() {
+            writeln!(
+                out,
+                "  {}: {} of {} bins have GEH < {}, R² = {}",
+                Time::START_OF_DAY + Duration::hours(hour),
+                prettyprint_usize(count_good(bins.iter().cloned(), geh_threshold)),
+                prettyprint_usize(bins.len()),
+                geh_threshold,
+                describe_r_squared(r_squared[&hour])
+            )
+            .unwrap();
+        }
+        if self.skipped_movements > 0 {
+            writeln!(
+                out,
+                "{} movement counts not at traffic signals were skipped",
+                prettyprint_usize(self.skipped_movements)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// One row per bin
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("location,mode,hour,observed,simulated,geh\n");
+        for bin in &self.bins {
+            let location = match bin.location {
+                CountedAt::Road(r) => format!("road {}", r.0),
+                CountedAt::Movement(m) => format!(
+                    "movement from road {} to road {} at intersection {}",
+                    m.from.road.0, m.to.road.0, m.parent.0
+                ),
+            };
+            writeln!(
+                out,
+                "{},{},{},{},{},{:.2}",
+                location,
+                bin.mode.noun(),
+                bin.hour,
+                bin.observed,
+                bin.simulated,
+                bin.geh
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    fn per_hour(&self) -> BTreeMap<usize, Vec<&BinComparison>> {
+        let mut per_hour: BTreeMap<usize, Vec<&BinComparison>> = BTreeMap::new();
+        for bin in &self.bins {
+            per_hour.entry(bin.hour).or_default().push(bin);
+        }
+        per_hour
+    }
+}
+
+fn pairs<'a, I: Iterator<Item = &'a BinComparison>>(bins: I) -> Vec<(f64, f64)> {
+    bins.map(|bin| (bin.simulated as f64, bin.observed as f64))
+        .collect()
+}
+
+fn count_good<'a, I: Iterator<Item = &'a BinComparison>>(bins: I, geh_threshold: f64) -> usize {
+    bins.filter(|bin| bin.geh < geh_threshold).count()
+}
+
+fn describe_r_squared(x: Option<f64>) -> String {
+    match x {
+        Some(x) => format!("{:.2}", x),
+        None => "n/a".to_string(),
+    }
+}
+
+/// The agents that real counts of some mode see. Stations count transit vehicles, not riders.
+fn agent_types(mode: TripMode) -> Vec<AgentType> {
+    match mode {
+        TripMode::Walk => vec![AgentType::Pedestrian],
+        TripMode::Bike => vec![AgentType::Bike],
+        TripMode::Transit => vec![AgentType::Bus, AgentType::Train],
+        TripMode::Drive => vec![AgentType::Car],
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::ops;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use geom::{Distance, Duration, Speed, Time};
+
+use crate::VehicleType;
+
+/// Tailpipe emissions and fuel consumed by some vehicles.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Emissions {
+    pub co2_grams: f64,
+    pub nox_grams: f64,
+    pub fuel_liters: f64,
+}
+
+impl Emissions {
+    pub const ZERO: Emissions = Emissions {
+        co2_grams: 0.0,
+        nox_grams: 0.0,
+        fuel_liters: 0.0,
+    };
+
+    fn new(co2_grams: f64, nox_grams: f64, fuel_liters: f64) -> Emissions {
+        Emissions {
+            co2_grams,
+            nox_grams,
+            fuel_liters,
+        }
+    }
+}
+
+impl ops::Add for Emissions {
+    type Output = Emissions;
+
+    fn add(self, other: Emissions) -> Emissions {
+        Emissions::new(
+            self.co2_grams + other.co2_grams,
+            self.nox_grams + other.nox_grams,
+            self.fuel_liters + other.fuel_liters,
+        )
+    }
+}
+
+impl ops::AddAssign for Emissions {
+    fn add_assign(&mut self, other: Emissions) {
+        *self = *self + other;
+    }
+}
+
+impl ops::Mul<f64> for Emissions {
+    type Output = Emissions;
+
+    fn mul(self, factor: f64) -> Emissions {
+        Emissions::new(
+            self.co2_grams * factor,
+            self.nox_grams * factor,
+            self.fuel_liters * factor,
+        )
+    }
+}
+
+/// Estimates the emissions of a vehicle from how far and fast it moved, how long it idled, and how
+/// much it had to accelerate. This is in the style of COPERT or HBEFA: each type of vehicle has a
+/// table of emission factors by average speed. The default model is a rough average petrol car
+/// (also used for emergency vehicles) and diesel bus. For real studies, a calibrated model can be
+/// loaded from a JSON file with `--emissions_model`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EmissionsModel {
+    /// Vehicle types not listed here, like bikes and electric trains by default, produce no
+    /// emissions.
+    pub vehicles: BTreeMap<VehicleType, EmissionFactors>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EmissionFactors {
+    /// Emitted every hour while stopped with the engine running
+    pub idle_per_hour: Emissions,
+    /// Emitted per kilometer at an average speed in km/h. Sorted by speed. Speeds in between
+    /// entries are interpolated, and speeds past either end use the nearest entry.
+    pub per_km_by_speed: Vec<(f64, Emissions)>,
+    /// Emitted for every km/h of speed gained while accelerating
+    pub per_kmh_gained: Emissions,
+}
+
+impl Default for EmissionsModel {
+    fn default() -> EmissionsModel {
+        let car = EmissionFactors {
+            idle_per_hour: Emissions::new(1400.0, 0.6, 0.6),
+            per_km_by_speed: vec![
+                (5.0, Emissions::new(400.0, 0.12, 0.167)),
+                (10.0, Emissions::new(300.0, 0.09, 0.125)),
+                (20.0, Emissions::new(220.0, 0.06, 0.092)),
+                (30.0, Emissions::new(185.0, 0.05, 0.077)),
+                (50.0, Emissions::new(155.0, 0.04, 0.065)),
+                (70.0, Emissions::new(140.0, 0.035, 0.059)),
+                (90.0, Emissions::new(140.0, 0.04, 0.059)),
+                (110.0, Emissions::new(155.0, 0.05, 0.065)),
+                (130.0, Emissions::new(180.0, 0.07, 0.075)),
+            ],
+            per_kmh_gained: Emissions::new(0.9, 0.0005, 0.00038),
+        };
+        let bus = EmissionFactors {
+            idle_per_hour: Emissions::new(4000.0, 25.0, 1.5),
+            per_km_by_speed: vec![
+                (5.0, Emissions::new(2200.0, 12.0, 0.83)),
+                (10.0, Emissions::new(1700.0, 9.5, 0.64)),
+                (20.0, Emissions::new(1300.0, 7.5, 0.49)),
+                (30.0, Emissions::new(1100.0, 6.5, 0.42)),
+                (50.0, Emissions::new(950.0, 5.5, 0.36)),
+                (70.0, Emissions::new(880.0, 5.0, 0.33)),
+                (90.0, Emissions::new(900.0, 5.0, 0.34)),
+            ],
+            per_kmh_gained: Emissions::new(8.0, 0.04, 0.003),
+        };
+
+        let mut vehicles = BTreeMap::new();
+        vehicles.insert(VehicleType::Car, car.clone());
+        vehicles.insert(VehicleType::Emergency, car);
+        vehicles.insert(VehicleType::Bus, bus);
+        EmissionsModel { vehicles }
+    }
+}
+
+impl EmissionsModel {
+    /// Make sure a model loaded from a file is usable.
+    pub fn check(&self) -> Result<()> {
+        for (vehicle_type, factors) in &self.vehicles {
+            if factors.per_km_by_speed.is_empty() {
+                bail!("No per_km_by_speed entries for {}", vehicle_type);
+            }
+            for pair in factors.per_km_by_speed.windows(2) {
+                if pair[0].0 >= pair[1].0 {
+                    bail!(
+                        "per_km_by_speed for {} isn't sorted by increasing speed",
+                        vehicle_type
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns None if this type of vehicle doesn't produce emissions.
+    pub fn estimate(
+        &self,
+        vehicle_type: VehicleType,
+        dist: Distance,
+        moving_time: Duration,
+        idle_time: Duration,
+        speed_gained: Speed,
+    ) -> Option<Emissions> {
+        let factors = self.vehicles.get(&vehicle_type)?;
+        let mut total = factors.idle_per_hour * (idle_time.inner_seconds() / 3600.0);
+        if dist > Distance::ZERO && moving_time > Duration::ZERO {
+            let speed = Speed::from_dist_time(dist, moving_time);
+            total += factors.per_km(speed) * (dist.inner_meters() / 1000.0);
+        }
+        total += factors.per_kmh_gained * to_kmh(speed_gained);
+        Some(total)
+    }
+}
+
+impl EmissionFactors {
+    fn per_km(&self, speed: Speed) -> Emissions {
+        let kmh = to_kmh(speed);
+        let table = &self.per_km_by_speed;
+        if kmh <= table[0].0 {
+            return table[0].1;
+        }
+        for pair in table.windows(2) {
+            let ((speed1, low), (speed2, high)) = (pair[0], pair[1]);
+            if kmh <= speed2 {
+                let pct = (kmh - speed1) / (speed2 - speed1);
+                return low * (1.0 - pct) + high * pct;
+            }
+        }
+        table.last().unwrap().1
+    }
+}
+
+fn to_kmh(speed: Speed) -> f64 {
+    speed.inner_meters_per_second() * 3.6
+}
+
+/// Follows one vehicle along its current lane or turn. When the vehicle leaves, its emissions
+/// there are estimated from the distance covered, the time spent moving and stopped, and how many
+/// times it had to start moving again.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct EmissionsTracker {
+    entered_at: Time,
+    start_dist: Distance,
+    // Car::total_blocked_time upon entering
+    blocked_before: Duration,
+    // Stopped for other reasons, like unparking or waiting at a bus stop
+    extra_idle: Duration,
+    // How many times the vehicle stopped and started moving again
+    restarts: usize,
+    // The average moving speed on the previous lane or turn
+    prev_speed: Speed,
+}
+
+impl EmissionsTracker {
+    /// A vehicle starts from a standstill
+    pub fn new(now: Time, start_dist: Distance) -> EmissionsTracker {
+        EmissionsTracker {
+            entered_at: now,
+            start_dist,
+            blocked_before: Duration::ZERO,
+            extra_idle: Duration::ZERO,
+            restarts: 0,
+            prev_speed: Speed::ZERO,
+        }
+    }
+
+    pub fn add_idle(&mut self, dt: Duration) {
+        self.extra_idle += dt;
+    }
+
+    /// The vehicle was stopped somewhere along the current lane or turn, and now it's moving again
+    pub fn restarted(&mut self) {
+        self.restarts += 1;
+    }
+
+    /// The vehicle was stopped at the end of the lane or turn it just left
+    pub fn entered_from_stop(&mut self) {
+        self.prev_speed = Speed::ZERO;
+    }
+
+    /// The vehicle leaves the current lane or turn at some distance along it. Returns the
+    /// emissions produced there, and starts tracking the next step.
+    pub fn leave(
+        &mut self,
+        now: Time,
+        end_dist: Distance,
+        total_blocked_time: Duration,
+        vehicle_type: VehicleType,
+        model: &EmissionsModel,
+    ) -> Option<Emissions> {
+        let dist = if end_dist > self.start_dist {
+            end_dist - self.start_dist
+        } else {
+            Distance::ZERO
+        };
+        let total_time = now - self.entered_at;
+        let idle_time =
+            (total_blocked_time - self.blocked_before + self.extra_idle).min(total_time);
+        let moving_time = total_time - idle_time;
+        let speed = if dist > Distance::ZERO && moving_time > Duration::ZERO {
+            Speed::from_dist_time(dist, moving_time)
+        } else {
+            Speed::ZERO
+        };
+        // Accelerating from the speed on the previous step, then from a standstill every time the
+        // vehicle had to stop
+        let mut speed_gained = speed * (self.restarts as f64);
+        if speed > self.prev_speed {
+            speed_gained = speed_gained + (speed - self.prev_speed);
+        }
+
+        *self = EmissionsTracker {
+            entered_at: now,
+            start_dist: Distance::ZERO,
+            blocked_before: total_blocked_time,
+            extra_idle: Duration::ZERO,
+            restarts: 0,
+            prev_speed: speed,
+        };
+
+        model.estimate(vehicle_type, dist, moving_time, idle_time, speed_gained)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_approx;
+
+    fn kmh(x: f64) -> Speed {
+        Speed::meters_per_second(x / 3.6)
+    }
+
+    fn car() -> EmissionFactors {
+        EmissionsModel::default().vehicles[&VehicleType::Car].clone()
+    }
+
+    #[test]
+    fn test_per_km_by_speed() {
+        let car = car();
+        assert_approx(car.per_km(kmh(5.0)).co2_grams, 400.0);
+        assert_approx(car.per_km(kmh(30.0)).co2_grams, 185.0);
+        // Interpolated between 30 and 50 km/h
+        assert_approx(car.per_km(kmh(40.0)).co2_grams, 170.0);
+        assert_approx(car.per_km(kmh(45.0)).nox_grams, 0.0425);
+        // Past either end, the nearest entry is used
+        assert_approx(car.per_km(Speed::ZERO).co2_grams, 400.0);
+        assert_approx(car.per_km(kmh(200.0)).co2_grams, 180.0);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let model = EmissionsModel::default();
+        // 1km at 30 km/h, an hour idling, and speeding up to 30 km/h once
+        let emissions = model
+            .estimate(
+                VehicleType::Car,
+                Distance::meters(1000.0),
+                Duration::minutes(2),
+                Duration::hours(1),
+                kmh(30.0),
+            )
+            .unwrap();
+        assert_approx(emissions.co2_grams, 185.0 + 1400.0 + 0.9 * 30.0);
+        assert_approx(emissions.fuel_liters, 0.077 + 0.6 + 0.00038 * 30.0);
+
+        // Just idling
+        let emissions = model
+            .estimate(
+                VehicleType::Car,
+                Distance::ZERO,
+                Duration::ZERO,
+                Duration::minutes(30),
+                Speed::ZERO,
+            )
+            .unwrap();
+        assert_approx(emissions.co2_grams, 700.0);
+
+        assert!(model
+            .estimate(
+                VehicleType::Bike,
+                Distance::meters(1000.0),
+                Duration::minutes(4),
+                Duration::ZERO,
+                kmh(15.0),
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_tracker_accumulates_per_trip() {
+        let model = EmissionsModel::default();
+        let start = Time::START_OF_DAY;
+        let mut tracker = EmissionsTracker::new(start, Distance::ZERO);
+
+        // 500m in a minute, blocked for 20s of it, so moving at 45 km/h
+        let first = tracker
+            .leave(
+                start + Duration::seconds(60.0),
+                Distance::meters(500.0),
+                Duration::seconds(20.0),
+                VehicleType::Car,
+                &model,
+            )
+            .unwrap();
+        let expected_first = 162.5 * 0.5 + 1400.0 * 20.0 / 3600.0 + 0.9 * 45.0;
+        assert_approx(first.co2_grams, expected_first);
+
+        // 400m at 36 km/h, slower than before, but after stopping once along the way. The blocked
+        // time from the previous step doesn't count again.
+        tracker.restarted();
+        let second = tracker
+            .leave(
+                start + Duration::seconds(100.0),
+                Distance::meters(400.0),
+                Duration::seconds(20.0),
+                VehicleType::Car,
+                &model,
+            )
+            .unwrap();
+        let expected_second = 176.0 * 0.4 + 0.9 * 36.0;
+        assert_approx(second.co2_grams, expected_second);
+
+        let mut trip = Emissions::ZERO;
+        trip += first;
+        trip += second;
+        assert_approx(trip.co2_grams, expected_first + expected_second);
+
+        // Staying put only produces idling emissions
+        let third = tracker
+            .leave(
+                start + Duration::seconds(136.0),
+                Distance::ZERO,
+                Duration::seconds(56.0),
+                VehicleType::Car,
+                &model,
+            )
+            .unwrap();
+        assert_approx(third.co2_grams, 1400.0 * 36.0 / 3600.0);
+    }
+}
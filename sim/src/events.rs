@@ -2,12 +2,15 @@ use serde::{Deserialize, Serialize};
 
 use geom::Duration;
 use map_model::{
-    BuildingID, IntersectionID, LaneID, Map, Path, PathRequest, TransitRouteID, TransitStopID,
-    Traversable, TurnID,
+    BuildingID, IntersectionID, LaneID, Map, Path, PathRequest, RoadID, TransitRouteID,
+    TransitStopID, Traversable, TurnID,
 };
 use synthpop::TripMode;
 
-use crate::{AgentID, CarID, ParkingSpot, PedestrianID, PersonID, Problem, TripID};
+use crate::{
+    AgentID, CarID, Emissions, IncidentID, IncidentImpact, ParkingSpot, PedestrianID, PersonID,
+    Problem, TripID,
+};
 
 /// As a simulation runs, different systems emit Events. This cleanly separates the internal
 /// mechanics of the simulation from consumers that just want to know what's happening.
@@ -45,18 +48,47 @@ pub enum Event {
     AgentEntersTraversable(AgentID, Option<TripID>, Traversable, Option<usize>),
     /// TripID, TurnID (Where the delay was encountered), Time spent waiting at that turn
     IntersectionDelayMeasured(TripID, TurnID, AgentID, Duration),
+    /// A pedestrian started crossing a crosswalk at a traffic signal, after waiting this long.
+    /// Unlike IntersectionDelayMeasured, this is also recorded when there was no wait.
+    PedestrianStartsCrosswalk(PedestrianID, TurnID, Duration),
+    /// A vehicle left a lane or turn, or finished its trip partway along one, producing these
+    /// emissions there. Vehicles without emission factors don't produce this.
+    VehicleEmissions(CarID, Option<TripID>, Traversable, Emissions),
+    /// The first vehicle started to pass the second, slower one on this lane.
+    VehicleOvertaking(CarID, CarID, LaneID, OvertakeType),
+    /// An incident changed the route of a trip's vehicle, blocked it, or slowed it down. This may
+    /// be emitted more than once per trip and incident.
+    TripAffectedByIncident(TripID, IncidentID, IncidentImpact),
+    /// A vehicle changed the rest of its route while driving. It no longer uses the `avoided`
+    /// roads, and uses the `added` roads instead. This always comes with a `PathAmended`.
+    VehicleRerouted {
+        car: CarID,
+        reason: RerouteReason,
+        avoided: Vec<RoadID>,
+        added: Vec<RoadID>,
+    },
 
     TripFinished {
         trip: TripID,
         mode: TripMode,
         total_time: Duration,
         blocked_time: Duration,
+        /// Which day of the scenario the trip belongs to
+        day: usize,
     },
-    TripCancelled(TripID, TripMode),
+    /// The usize is which day of the scenario the trip belongs to
+    TripCancelled(TripID, TripMode, usize),
     TripPhaseStarting(TripID, PersonID, Option<PathRequest>, TripPhaseType),
 
-    /// Just use for parking replanning. Not happy about copying the full path in here, but the way
-    /// to plumb info into Analytics is Event.
+    /// A traffic signal began a new stage, either on its own schedule or because it was told to.
+    TrafficSignalStageChanged(IntersectionID, usize),
+    /// A traffic signal changed its timing for a bus or emergency vehicle. The Duration is how
+    /// much the green shifted, and the usize counts agents on conflicting movements who were
+    /// waiting or approaching at the time.
+    SignalPriorityGranted(IntersectionID, CarID, SignalPriorityType, Duration, usize),
+
+    /// Used for parking replanning and routing around incidents. Not happy about copying the full
+    /// path in here, but the way to plumb info into Analytics is Event.
     PathAmended(Path),
 
     Alert(AlertLocation, String),
@@ -70,6 +102,36 @@ pub enum AlertLocation {
     Building(BuildingID),
 }
 
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum SignalPriorityType {
+    /// A bus' green was extended
+    TransitExtension,
+    /// The stage before a bus' green ended early
+    TransitEarlyGreen,
+    /// An emergency vehicle's green was held, or the signal switched to give it one
+    EmergencyPreemption,
+}
+
+/// Why did a vehicle change its route while driving?
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum RerouteReason {
+    /// The route went through a road or intersection closed by an incident
+    Closure,
+    /// A navigation app periodically checked for a faster route using live travel times
+    Periodic,
+    /// A navigation app looked for a faster route, because the vehicle was delayed for too long
+    Delayed,
+}
+
+/// How does a vehicle pass a slower one?
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum OvertakeType {
+    /// By changing into an adjacent lane going the same direction
+    ChangeLanes,
+    /// By briefly using an adjacent lane going the opposite direction
+    Oncoming,
+}
+
 #[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub enum TripPhaseType {
     Driving,
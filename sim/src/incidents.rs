@@ -0,0 +1,432 @@
+//! Incidents like crashes or roadwork close or slow down part of the map for a period of time,
+//! while the simulation is running. Unlike live map edits, vehicles already on a closed lane stay
+//! there and queue behind the blockage, and anybody whose route passes through a closed road or
+//! intersection tries to re-plan around it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::Time;
+use map_model::{
+    DirectedRoadID, IntersectionID, LaneID, Map, Path, PathConstraints, PathStep, RoadID,
+    RoutingParams, Traversable,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct IncidentID(pub usize);
+
+impl fmt::Display for IncidentID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Incident #{}", self.0)
+    }
+}
+
+/// Where an incident happens.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IncidentLocation {
+    Lane(LaneID),
+    /// Every lane of the road, in both directions
+    Road(RoadID),
+    Intersection(IntersectionID),
+}
+
+/// What an incident does to vehicles. Pedestrians aren't affected.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IncidentEffect {
+    /// Nothing can get through. Vehicles queue up at the end of a closed lane or road, or wait to
+    /// enter a closed intersection.
+    Closure,
+    /// Vehicles can still get through, but only at this fraction of their usual speed, so fewer
+    /// of them pass in the same amount of time. Must be between 0 and 1.
+    ReducedCapacity(f64),
+}
+
+/// Something closing or slowing down part of the map for a period of time. A JSON file with a
+/// list of these can be passed to the simulation with `--incidents`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Incident {
+    pub start: Time,
+    pub end: Time,
+    pub location: IncidentLocation,
+    pub effect: IncidentEffect,
+}
+
+/// How an incident affected a trip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum IncidentImpact {
+    /// The route was changed to avoid a closure
+    Rerouted,
+    /// Queued behind a closed lane, or waited at a closed intersection
+    Blocked,
+    /// Passed through somewhere with reduced capacity
+    SlowedDown,
+}
+
+impl Incident {
+    pub fn check(&self, map: &Map) -> Result<()> {
+        if self.start >= self.end {
+            bail!(
+                "Incident starts at {}, but ends at {}",
+                self.start,
+                self.end
+            );
+        }
+        match self.location {
+            IncidentLocation::Lane(l) => {
+                if map.maybe_get_l(l).is_none() {
+                    bail!("{} doesn't exist", l);
+                }
+                if !map.get_l(l).lane_type.is_for_moving_vehicles() {
+                    bail!("{} isn't for vehicles", l);
+                }
+            }
+            IncidentLocation::Road(r) => {
+                if map.maybe_get_r(r).is_none() {
+                    bail!("{} doesn't exist", r);
+                }
+            }
+            IncidentLocation::Intersection(i) => {
+                if map.maybe_get_i(i).is_none() {
+                    bail!("{} doesn't exist", i);
+                }
+            }
+        }
+        if let IncidentEffect::ReducedCapacity(factor) = self.effect {
+            if factor <= 0.0 || factor >= 1.0 {
+                bail!(
+                    "Reduced capacity must be between 0 and 1, not {}. Use a closure instead",
+                    factor
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The lanes that get a blockage at their end when the incident starts.
+    pub fn blocked_lanes(&self, map: &Map) -> Vec<LaneID> {
+        if self.effect != IncidentEffect::Closure {
+            return Vec::new();
+        }
+        match self.location {
+            IncidentLocation::Lane(l) => vec![l],
+            IncidentLocation::Road(r) => vehicle_lanes(map, r),
+            IncidentLocation::Intersection(_) => Vec::new(),
+        }
+    }
+
+    /// The lanes and turns that vehicles cross more slowly while the incident is active, and the
+    /// fraction of their usual speed.
+    pub fn slowdowns(&self, map: &Map) -> Vec<(Traversable, f64)> {
+        let factor = match self.effect {
+            IncidentEffect::Closure => {
+                return Vec::new();
+            }
+            IncidentEffect::ReducedCapacity(factor) => factor,
+        };
+        match self.location {
+            IncidentLocation::Lane(l) => vec![(Traversable::Lane(l), factor)],
+            IncidentLocation::Road(r) => vehicle_lanes(map, r)
+                .into_iter()
+                .map(|l| (Traversable::Lane(l), factor))
+                .collect(),
+            IncidentLocation::Intersection(i) => map
+                .get_i(i)
+                .turns
+                .iter()
+                .filter(|t| !t.between_sidewalks())
+                .map(|t| (Traversable::Turn(t.id), factor))
+                .collect(),
+        }
+    }
+
+    fn overlaps(&self, other: &Incident) -> bool {
+        if self.end <= other.start || other.end <= self.start {
+            return false;
+        }
+        match (self.location, other.location) {
+            (IncidentLocation::Lane(l1), IncidentLocation::Lane(l2)) => l1 == l2,
+            (IncidentLocation::Lane(l), IncidentLocation::Road(r))
+            | (IncidentLocation::Road(r), IncidentLocation::Lane(l)) => l.road == r,
+            (IncidentLocation::Road(r1), IncidentLocation::Road(r2)) => r1 == r2,
+            (IncidentLocation::Intersection(i1), IncidentLocation::Intersection(i2)) => i1 == i2,
+            _ => false,
+        }
+    }
+}
+
+fn vehicle_lanes(map: &Map, r: RoadID) -> Vec<LaneID> {
+    map.get_r(r)
+        .lanes
+        .iter()
+        .filter(|l| l.lane_type.is_for_moving_vehicles())
+        .map(|l| l.id)
+        .collect()
+}
+
+/// All incidents scheduled for a simulation.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct IncidentSimState {
+    incidents: Vec<Incident>,
+    /// Closures that already started, but couldn't block some of their lanes yet, because
+    /// vehicles were in the way
+    pending_lanes: BTreeMap<IncidentID, Vec<LaneID>>,
+}
+
+impl IncidentSimState {
+    pub fn new() -> IncidentSimState {
+        IncidentSimState {
+            incidents: Vec::new(),
+            pending_lanes: BTreeMap::new(),
+        }
+    }
+
+    /// Incidents in the same place can't overlap in time. Closing or slowing down different lanes
+    /// of one road at once is fine.
+    pub fn add(&mut self, incident: Incident, map: &Map) -> Result<IncidentID> {
+        incident.check(map)?;
+        if let Some(idx) = self
+            .incidents
+            .iter()
+            .position(|other| incident.overlaps(other))
+        {
+            bail!(
+                "Incident at {:?} overlaps with {}",
+                incident.location,
+                IncidentID(idx)
+            );
+        }
+        let id = IncidentID(self.incidents.len());
+        self.incidents.push(incident);
+        Ok(id)
+    }
+
+    pub fn get(&self, id: IncidentID) -> &Incident {
+        &self.incidents[id.0]
+    }
+
+    pub fn all_incidents(&self) -> &Vec<Incident> {
+        &self.incidents
+    }
+
+    pub fn set_pending_lanes(&mut self, id: IncidentID, lanes: Vec<LaneID>) {
+        if lanes.is_empty() {
+            self.pending_lanes.remove(&id);
+        } else {
+            self.pending_lanes.insert(id, lanes);
+        }
+    }
+
+    /// If a previous attempt to start this incident couldn't block all of its lanes, returns the
+    /// ones still left.
+    pub fn take_pending_lanes(&mut self, id: IncidentID) -> Option<Vec<LaneID>> {
+        self.pending_lanes.remove(&id)
+    }
+}
+
+/// Roads and intersections currently closed by incidents. Vehicles route around them. Incidents
+/// on different lanes of one road can overlap, so something only reopens after every incident
+/// closing it ends.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub(crate) struct Closures {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    roads: BTreeMap<DirectedRoadID, BTreeSet<IncidentID>>,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    intersections: BTreeMap<IntersectionID, BTreeSet<IncidentID>>,
+}
+
+impl Closures {
+    pub fn is_empty(&self) -> bool {
+        self.roads.is_empty() && self.intersections.is_empty()
+    }
+
+    pub fn start(&mut self, id: IncidentID, incident: &Incident, map: &Map) {
+        if incident.effect != IncidentEffect::Closure {
+            return;
+        }
+        match incident.location {
+            IncidentLocation::Road(r) => {
+                for dr in r.both_directions() {
+                    self.close_road(dr, id);
+                }
+            }
+            IncidentLocation::Intersection(i) => {
+                self.intersections.entry(i).or_default().insert(id);
+            }
+            // Vehicles usually just pick other lanes on the same road. If some of them have no
+            // other lane going the same way, route around that direction of the road.
+            IncidentLocation::Lane(l) => {
+                let lane = map.get_l(l);
+                let road = map.get_r(l.road);
+                let no_other_lane = [
+                    PathConstraints::Car,
+                    PathConstraints::Bike,
+                    PathConstraints::Bus,
+                ]
+                .into_iter()
+                .filter(|constraints| constraints.can_use(lane, map))
+                .any(|constraints| {
+                    !road.lanes.iter().any(|other| {
+                        other.id != l && other.dir == lane.dir && constraints.can_use(other, map)
+                    })
+                });
+                if no_other_lane {
+                    self.close_road(lane.get_directed_parent(), id);
+                }
+            }
+        }
+    }
+
+    fn close_road(&mut self, dr: DirectedRoadID, id: IncidentID) {
+        self.roads.entry(dr).or_default().insert(id);
+    }
+
+    pub fn end(&mut self, id: IncidentID) {
+        for ids in self.roads.values_mut() {
+            ids.remove(&id);
+        }
+        self.roads.retain(|_, ids| !ids.is_empty());
+        for ids in self.intersections.values_mut() {
+            ids.remove(&id);
+        }
+        self.intersections.retain(|_, ids| !ids.is_empty());
+    }
+
+    /// One of the incidents closing this direction of a road, if any.
+    fn road_closed_by(&self, dr: DirectedRoadID) -> Option<IncidentID> {
+        self.roads
+            .get(&dr)
+            .and_then(|ids| ids.iter().next())
+            .cloned()
+    }
+
+    /// Does the path use anything closed, after the first `skip` steps? Returns the first
+    /// incident in the way.
+    pub fn blocking(&self, path: &Path, skip: usize, map: &Map) -> Option<IncidentID> {
+        for step in path.get_steps().iter().skip(skip) {
+            let id = match step {
+                PathStep::Lane(l) => self.road_closed_by(map.get_l(*l).get_directed_parent()),
+                PathStep::Turn(t) => self
+                    .intersections
+                    .get(&t.parent)
+                    .and_then(|ids| ids.iter().next())
+                    .cloned(),
+                PathStep::ContraflowLane(_) | PathStep::ContraflowTurn(_) => None,
+            };
+            if id.is_some() {
+                return id;
+            }
+        }
+        None
+    }
+
+    /// Routing params avoiding everything closed.
+    pub fn routing_params(&self, map: &Map) -> RoutingParams {
+        let mut params = map.routing_params().clone();
+        for dr in self.roads.keys() {
+            let opposite = DirectedRoadID {
+                road: dr.road,
+                dir: dr.dir.opposite(),
+            };
+            if self.roads.contains_key(&opposite) {
+                params.avoid_roads.insert(dr.road);
+                continue;
+            }
+            // Only one direction is closed, so just don't let anybody enter it
+            for mvmnt in map.get_i(dr.src_i(map)).movements.keys() {
+                if mvmnt.to == *dr {
+                    params
+                        .avoid_movements_between
+                        .insert((mvmnt.from.road, mvmnt.to.road));
+                }
+            }
+        }
+        for i in self.intersections.keys() {
+            let roads = &map.get_i(*i).roads;
+            for r1 in roads {
+                for r2 in roads {
+                    params.avoid_movements_between.insert((*r1, *r2));
+                }
+            }
+        }
+        params
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map_model::Direction;
+
+    fn dr(road: usize, dir: Direction) -> DirectedRoadID {
+        DirectedRoadID {
+            road: RoadID(road),
+            dir,
+        }
+    }
+
+    #[test]
+    fn test_closures_by_direction() {
+        let mut closures = Closures::default();
+        closures.close_road(dr(1, Direction::Fwd), IncidentID(0));
+        assert_eq!(
+            closures.road_closed_by(dr(1, Direction::Fwd)),
+            Some(IncidentID(0))
+        );
+        // Traffic going the other way isn't affected
+        assert_eq!(closures.road_closed_by(dr(1, Direction::Back)), None);
+        assert_eq!(closures.road_closed_by(dr(2, Direction::Fwd)), None);
+
+        closures.close_road(dr(1, Direction::Back), IncidentID(1));
+        assert_eq!(
+            closures.road_closed_by(dr(1, Direction::Back)),
+            Some(IncidentID(1))
+        );
+        closures.end(IncidentID(0));
+        assert_eq!(closures.road_closed_by(dr(1, Direction::Fwd)), None);
+        assert_eq!(
+            closures.road_closed_by(dr(1, Direction::Back)),
+            Some(IncidentID(1))
+        );
+        closures.end(IncidentID(1));
+        assert!(closures.is_empty());
+    }
+
+    #[test]
+    fn test_overlapping_closures() {
+        // Two incidents closing different lanes going the same way
+        let mut closures = Closures::default();
+        closures.close_road(dr(1, Direction::Fwd), IncidentID(0));
+        closures.close_road(dr(1, Direction::Fwd), IncidentID(1));
+
+        // The road only reopens once both end, in either order
+        closures.end(IncidentID(0));
+        assert_eq!(
+            closures.road_closed_by(dr(1, Direction::Fwd)),
+            Some(IncidentID(1))
+        );
+        closures.end(IncidentID(1));
+        assert_eq!(closures.road_closed_by(dr(1, Direction::Fwd)), None);
+        assert!(closures.is_empty());
+
+        closures.close_road(dr(1, Direction::Fwd), IncidentID(0));
+        closures.close_road(dr(1, Direction::Fwd), IncidentID(1));
+        closures.end(IncidentID(1));
+        assert_eq!(
+            closures.road_closed_by(dr(1, Direction::Fwd)),
+            Some(IncidentID(0))
+        );
+        // Ending something that isn't closing anything doesn't matter
+        closures.end(IncidentID(5));
+        assert!(!closures.is_empty());
+    }
+}
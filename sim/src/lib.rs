@@ -34,13 +34,28 @@ pub use crate::render::{
     UnzoomedAgent,
 };
 
-pub use self::analytics::{Analytics, Problem, ProblemType, SlidingWindow, TripPhase};
+pub use self::analytics::{
+    Analytics, DaySummary, Problem, ProblemType, QueueSample, SlidingWindow, TimeSeriesSamples,
+    TripPhase,
+};
+pub use self::count_validation::{BinComparison, CountValidation, CountedAt};
+pub(crate) use self::emissions::EmissionsTracker;
+pub use self::emissions::{EmissionFactors, Emissions, EmissionsModel};
 pub(crate) use self::events::Event;
-pub use self::events::{AlertLocation, TripPhaseType};
+pub use self::events::{
+    AlertLocation, OvertakeType, RerouteReason, SignalPriorityType, TripPhaseType,
+};
+pub(crate) use self::incidents::{Closures, IncidentSimState};
+pub use self::incidents::{Incident, IncidentEffect, IncidentID, IncidentImpact, IncidentLocation};
 pub use self::make::SimFlags;
 pub(crate) use self::make::{StartTripArgs, TripSpec};
+pub use self::mechanics::{
+    Actuation, Detector, DetectorBin, DetectorID, DetectorSimState, DetectorSpec, DetectorType,
+    Kinematics, DETECTOR_BIN,
+};
 pub(crate) use self::mechanics::{
-    DrivingSimState, IntersectionSimState, ParkingSim, ParkingSimState, WalkingSimState,
+    DrivingSimState, IntersectionSimState, ParkingSim, ParkingSimState, SpeedProfile,
+    WalkingSimState,
 };
 pub(crate) use self::pandemic::PandemicModel;
 pub use self::prebake::PrebakeSummary;
@@ -51,13 +66,17 @@ pub use self::sim::{
     count_parked_cars_per_bldg, rand_dist, AgentProperties, AlertHandler, DelayCause, Sim,
     SimCallback, SimOptions,
 };
+pub(crate) use self::trajectories::TrajectoryRecorder;
 pub(crate) use self::transit::TransitSimState;
 pub use self::trips::{CommutersVehiclesCounts, Person, PersonState, TripInfo, TripResult};
 pub(crate) use self::trips::{TripLeg, TripManager};
 pub use synthpop::make::{fork_rng, BorderSpawnOverTime, ScenarioGenerator, SpawnOverTime};
 
 mod analytics;
+mod count_validation;
+mod emissions;
 mod events;
+mod incidents;
 mod make;
 mod mechanics;
 mod pandemic;
@@ -67,6 +86,9 @@ mod render;
 mod router;
 mod scheduler;
 mod sim;
+#[cfg(test)]
+mod testing;
+mod trajectories;
 mod transit;
 mod trips;
 
@@ -106,6 +128,7 @@ impl fmt::Display for CarID {
             VehicleType::Bus => write!(f, "Bus #{}", self.id),
             VehicleType::Train => write!(f, "Train #{}", self.id),
             VehicleType::Bike => write!(f, "Bike #{}", self.id),
+            VehicleType::Emergency => write!(f, "Emergency vehicle #{}", self.id),
         }
     }
 }
@@ -144,7 +167,7 @@ impl AgentID {
     pub fn to_type(self) -> AgentType {
         match self {
             AgentID::Car(c) => match c.vehicle_type {
-                VehicleType::Car => AgentType::Car,
+                VehicleType::Car | VehicleType::Emergency => AgentType::Car,
                 VehicleType::Bike => AgentType::Bike,
                 VehicleType::Bus => AgentType::Bus,
                 VehicleType::Train => AgentType::Train,
@@ -279,6 +302,8 @@ pub enum VehicleType {
     Bus,
     Train,
     Bike,
+    /// Drives like a car, but may pre-empt traffic signals
+    Emergency,
 }
 
 impl fmt::Display for VehicleType {
@@ -288,6 +313,7 @@ impl fmt::Display for VehicleType {
             VehicleType::Bus => write!(f, "bus"),
             VehicleType::Train => write!(f, "train"),
             VehicleType::Bike => write!(f, "bike"),
+            VehicleType::Emergency => write!(f, "emergency vehicle"),
         }
     }
 }
@@ -295,7 +321,7 @@ impl fmt::Display for VehicleType {
 impl VehicleType {
     pub fn to_constraints(self) -> PathConstraints {
         match self {
-            VehicleType::Car => PathConstraints::Car,
+            VehicleType::Car | VehicleType::Emergency => PathConstraints::Car,
             VehicleType::Bus => PathConstraints::Bus,
             VehicleType::Train => PathConstraints::Train,
             VehicleType::Bike => PathConstraints::Bike,
@@ -308,6 +334,7 @@ impl VehicleType {
             VehicleType::Bus => true,
             VehicleType::Train => true,
             VehicleType::Bike => false,
+            VehicleType::Emergency => false,
         }
     }
 }
@@ -319,6 +346,7 @@ pub struct Vehicle {
     pub vehicle_type: VehicleType,
     pub length: Distance,
     pub max_speed: Option<Speed>,
+    pub kinematics: Kinematics,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -326,6 +354,7 @@ pub struct VehicleSpec {
     pub vehicle_type: VehicleType,
     pub length: Distance,
     pub max_speed: Option<Speed>,
+    pub kinematics: Kinematics,
 }
 
 impl VehicleSpec {
@@ -337,6 +366,7 @@ impl VehicleSpec {
             vehicle_type: self.vehicle_type,
             length: self.length,
             max_speed: self.max_speed,
+            kinematics: self.kinematics,
         }
     }
 }
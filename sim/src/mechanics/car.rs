@@ -1,13 +1,15 @@
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use serde::{Deserialize, Serialize};
 
-use geom::{Distance, Duration, PolyLine, Time, EPSILON_DIST};
-use map_model::{Direction, LaneID, Map, Traversable};
+use geom::{Distance, Duration, PolyLine, Speed, Time, EPSILON_DIST};
+use map_model::{Direction, LaneID, Map, Traversable, TurnPriority};
 
+use crate::mechanics::driving::TIME_TO_CHANGE_LANES;
 use crate::{
-    CarID, CarStatus, DistanceInterval, DrawCarInput, Intent, ParkingSpot, PersonID, Router,
-    TimeInterval, TransitSimState, TripID, Vehicle, VehicleType,
+    CarID, CarStatus, DistanceInterval, DrawCarInput, EmissionsTracker, IncidentID, Intent,
+    ParkingSpot, PersonID, Router, SpeedProfile, TimeInterval, TransitSimState, TripID, Vehicle,
+    VehicleType,
 };
 
 /// Represents a single vehicle. Note "car" is a misnomer; it could also be a bus or bike.
@@ -21,19 +23,33 @@ pub(crate) struct Car {
     pub trip_and_person: Option<(TripID, PersonID)>,
     pub started_at: Time,
     pub total_blocked_time: Duration,
+    pub emissions: EmissionsTracker,
+    /// Only used when `SimOptions::acceleration` is enabled. How fast the vehicle was going at the
+    /// end of its last Crossing state.
+    pub last_speed: Option<Speed>,
 
     /// In reverse order -- most recently left is first. The sum length of these must be >=
     /// vehicle.length.
     pub last_steps: VecDeque<Traversable>,
 
-    /// Since lane over-taking isn't implemented yet, a vehicle tends to be stuck behind a slow
-    /// leader for a while. Avoid duplicate events.
+    /// A vehicle may be stuck behind a slow leader until there's a chance to pass them. Remember
+    /// who they wanted to overtake, to only record the problem once per leader.
     pub wants_to_overtake: BTreeSet<CarID>,
+
+    /// Only used for vehicles with a navigation app. The `total_blocked_time` when they last
+    /// re-planned their route, to notice when they've been delayed too much since then.
+    pub blocked_time_at_last_reroute: Duration,
 }
 
 impl Car {
     /// Assumes the current head of the path is the thing to cross.
-    pub fn crossing_state(&self, start_dist: Distance, start_time: Time, map: &Map) -> CarState {
+    pub fn crossing_state(
+        &self,
+        start_dist: Distance,
+        start_time: Time,
+        slowdowns: &BTreeMap<Traversable, (IncidentID, f64)>,
+        map: &Map,
+    ) -> CarState {
         let end_dist = if self.router.last_step() {
             self.router.get_end_dist()
         } else {
@@ -47,16 +63,19 @@ impl Car {
         }
 
         let dist_int = DistanceInterval::new_driving(start_dist, end_dist);
-        self.crossing_state_with_end_dist(dist_int, start_time, map)
+        self.crossing_state_with_end_dist(dist_int, start_time, slowdowns, map)
     }
 
+    /// `slowdowns` are the lanes and turns where an incident currently reduces speeds, and the
+    /// fraction of the usual speed.
     pub fn crossing_state_with_end_dist(
         &self,
         dist_int: DistanceInterval,
         start_time: Time,
+        slowdowns: &BTreeMap<Traversable, (IncidentID, f64)>,
         map: &Map,
     ) -> CarState {
-        let (speed, percent_incline) = self
+        let (mut speed, percent_incline) = self
             .router
             .get_path()
             .current_step()
@@ -65,14 +84,112 @@ impl Car {
                 self.vehicle.vehicle_type.to_constraints(),
                 map,
             );
-        let dt = (dist_int.end - dist_int.start) / speed;
+        if let Some((_, factor)) = slowdowns.get(&self.router.head()) {
+            speed = speed * *factor;
+        }
+        let dist = dist_int.end - dist_int.start;
+        let (dt, profile) = if let Some(last_speed) = self.last_speed {
+            let start_speed = self.current_speed(start_time, last_speed);
+            // Vehicles stopped at the stop bar take a moment to start moving
+            let delay = if start_speed == Speed::ZERO
+                && dist_int.start == Distance::ZERO
+                && matches!(self.router.head(), Traversable::Turn(_))
+            {
+                self.vehicle.kinematics.startup_lost_time
+            } else {
+                Duration::ZERO
+            };
+            // Brake to a stop at the end of the trip or at a stop sign. Vehicles may also need to
+            // stop at traffic signals, but that isn't known in advance.
+            let end_speed = if self.router.last_step()
+                || (dist_int.end >= self.router.head().get_polyline(map).length()
+                    && self.stop_sign_ahead(map))
+            {
+                Speed::ZERO
+            } else {
+                speed
+            };
+            let profile = SpeedProfile::new(
+                dist,
+                start_speed,
+                speed,
+                end_speed,
+                delay,
+                &self.vehicle.kinematics,
+            );
+            (profile.total_time(), Some(profile))
+        } else {
+            (dist / speed, None)
+        };
         CarState::Crossing {
             time_int: TimeInterval::new(start_time, start_time + dt),
             dist_int,
             steep_uphill: percent_incline >= 0.08,
+            profile,
         }
     }
 
+    /// Roughly how fast is the vehicle going right now?
+    pub fn estimate_speed(&self, now: Time) -> Speed {
+        self.current_speed(now, self.last_speed.unwrap_or(Speed::ZERO))
+    }
+
+    /// With acceleration enabled, remember how fast the vehicle is going right before its
+    /// Crossing state ends.
+    pub fn remember_speed(&mut self, now: Time) {
+        if let Some(speed) = self.last_speed {
+            self.last_speed = Some(self.current_speed(now, speed));
+        }
+    }
+
+    // How fast is the vehicle going right now, before changing to a new state? Some states don't
+    // say, so fall back to last_speed.
+    fn current_speed(&self, now: Time, last_speed: Speed) -> Speed {
+        match self.state {
+            CarState::Crossing {
+                ref time_int,
+                ref dist_int,
+                ref profile,
+                ..
+            } => match profile {
+                Some(profile) => profile.speed_at(now - time_int.start),
+                None => average_speed(time_int, dist_int),
+            },
+            CarState::ChangingLanes {
+                ref new_time,
+                ref new_dist,
+                ..
+            } => average_speed(new_time, new_dist),
+            CarState::Overtaking {
+                ref pass_time,
+                ref pass_dist,
+                ..
+            } => average_speed(pass_time, pass_dist),
+            CarState::Queued { blocked_since, .. }
+            | CarState::WaitingToAdvance { blocked_since } => {
+                if now > blocked_since {
+                    Speed::ZERO
+                } else {
+                    last_speed
+                }
+            }
+            CarState::Unparking { .. }
+            | CarState::Parking(_, _, _)
+            | CarState::IdlingAtStop(_, _) => Speed::ZERO,
+        }
+    }
+
+    // Does the vehicle have to stop at the end of the current lane for a stop sign?
+    fn stop_sign_ahead(&self, map: &Map) -> bool {
+        if let (Traversable::Lane(_), Traversable::Turn(t)) =
+            (self.router.head(), self.router.next())
+        {
+            return map.get_i(t.parent).is_stop_sign()
+                && map.get_stop_sign(t.parent).get_priority(t, map) == TurnPriority::Yield;
+        }
+        false
+    }
+
     pub fn get_draw_car(
         &self,
         front: Distance,
@@ -81,6 +198,16 @@ impl Car {
         transit: &TransitSimState,
     ) -> DrawCarInput {
         assert!(front >= Distance::ZERO);
+        // While overtaking, the queue reserves space in front of the vehicle being passed, but the
+        // vehicle is really still moving alongside them.
+        let front = match self.state {
+            CarState::Overtaking {
+                ref pass_time,
+                ref pass_dist,
+                ..
+            } => pass_dist.lerp(pass_time.percent_clamp_end(now)),
+            _ => front,
+        };
         // This goes from back to front
         let mut partly_on = Vec::new();
         let raw_body = if front >= self.vehicle.length {
@@ -173,6 +300,33 @@ impl Car {
                     }
                 }
             }
+            CarState::Overtaking {
+                oncoming,
+                ref pass_time,
+                ..
+            } => {
+                // Pull out into the oncoming lane, then back in, taking the same time as a
+                // lane-change for each
+                let percent_out = ((now - pass_time.start) / TIME_TO_CHANGE_LANES)
+                    .min((pass_time.end - now) / TIME_TO_CHANGE_LANES)
+                    .clamp(0.0, 1.0);
+                let current = map.get_l(self.router.head().as_lane());
+                let mut diff = (current.id.offset as isize) - (oncoming.offset as isize);
+                if current.dir == Direction::Fwd {
+                    diff *= -1;
+                }
+                let width = current.width * (diff as f64) * percent_out;
+                match raw_body.shift_right(width) {
+                    Ok(pl) => pl,
+                    Err(err) => {
+                        println!(
+                            "Body for overtaking {} at {} broken: {}",
+                            self.vehicle.id, now, err
+                        );
+                        raw_body
+                    }
+                }
+            }
             CarState::Unparking {
                 ref spot,
                 ref time_int,
@@ -276,6 +430,7 @@ impl Car {
                 CarState::WaitingToAdvance { .. } => CarStatus::Moving,
                 CarState::Crossing { .. } => CarStatus::Moving,
                 CarState::ChangingLanes { .. } => CarStatus::Moving,
+                CarState::Overtaking { .. } => CarStatus::Moving,
                 CarState::Unparking { .. } => CarStatus::Moving,
                 CarState::Parking(_, _, _) => CarStatus::Moving,
                 // Changing color for idling buses is helpful
@@ -325,6 +480,8 @@ pub(crate) enum CarState {
         time_int: TimeInterval,
         dist_int: DistanceInterval,
         steep_uphill: bool,
+        /// Only when acceleration is enabled. Otherwise, the vehicle crosses at a constant speed.
+        profile: Option<SpeedProfile>,
     },
     ChangingLanes {
         from: LaneID,
@@ -335,8 +492,17 @@ pub(crate) enum CarState {
         // How long does the lane-changing itself last? This must end before new_time_int does.
         lc_time: TimeInterval,
     },
+    /// Passing a slow leader by temporarily using an adjacent lane going the opposite direction.
+    /// In the queue, the vehicle is already in front of the vehicle being passed.
+    Overtaking {
+        oncoming: LaneID,
+        // Where the front of the vehicle is during the maneuver
+        pass_time: TimeInterval,
+        pass_dist: DistanceInterval,
+    },
     Queued {
         blocked_since: Time,
+        /// Either to change into an adjacent lane, or to overtake using an oncoming lane
         want_to_change_lanes: Option<LaneID>,
     },
     WaitingToAdvance {
@@ -361,6 +527,7 @@ impl CarState {
             CarState::WaitingToAdvance { .. } => unreachable!(),
             // Note this state lasts for lc_time, NOT for new_time.
             CarState::ChangingLanes { ref lc_time, .. } => lc_time.end,
+            CarState::Overtaking { ref pass_time, .. } => pass_time.end,
             CarState::Unparking { ref time_int, .. } => time_int.end,
             CarState::Parking(_, _, ref time_int) => time_int.end,
             CarState::IdlingAtStop(_, ref time_int) => time_int.end,
@@ -375,3 +542,11 @@ impl CarState {
         }
     }
 }
+
+fn average_speed(time_int: &TimeInterval, dist_int: &DistanceInterval) -> Speed {
+    if time_int.end > time_int.start {
+        Speed::from_dist_time(dist_int.end - dist_int.start, time_int.end - time_int.start)
+    } else {
+        Speed::ZERO
+    }
+}
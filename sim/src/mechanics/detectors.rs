@@ -0,0 +1,598 @@
+//! Virtual loop detectors, placed at a point along a lane. They record every vehicle passing over
+//! them, like the inductive loops that traffic signal controllers in the field use.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use geom::{Distance, Duration, Speed, Time};
+use map_model::{LaneID, Map, Position};
+
+use crate::CarID;
+
+/// Detectors are aggregated into bins this long for export.
+pub const DETECTOR_BIN: Duration = Duration::const_seconds(15.0 * 60.0);
+
+/// If no distance is specified, place a stop-bar detector this far back from the end of the lane.
+const STOP_BAR_SETBACK: Distance = Distance::const_meters(1.0);
+/// If no distance is specified, place an advance detector this far back from the end of the lane.
+const ADVANCE_SETBACK: Distance = Distance::const_meters(60.0);
+/// If no distance is specified, place an exit detector this far from the start of the lane.
+const EXIT_SETBACK: Distance = Distance::const_meters(5.0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct DetectorID(pub usize);
+
+impl fmt::Display for DetectorID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Detector #{}", self.0)
+    }
+}
+
+/// What a detector is used for. This only changes where it's placed by default, and whether
+/// actuated traffic signals consider it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum DetectorType {
+    /// Right before an intersection, to detect vehicles waiting for a green
+    StopBar,
+    /// Upstream of an intersection, to detect vehicles approaching it
+    Advance,
+    /// Just after an intersection, to count vehicles leaving it
+    Exit,
+}
+
+/// Describes where to place a detector. A JSON file with a list of these can be passed to the
+/// simulation with `--detectors`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DetectorSpec {
+    pub name: String,
+    pub lane: LaneID,
+    pub detector_type: DetectorType,
+    /// How far along the lane to place the detector, in meters. If this is missing, a default
+    /// depending on `detector_type` is used.
+    #[serde(default)]
+    pub dist_along: Option<Distance>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Detector {
+    pub id: DetectorID,
+    pub name: String,
+    pub detector_type: DetectorType,
+    pub pos: Position,
+}
+
+/// One vehicle passing over a detector.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Actuation {
+    pub car: CarID,
+    /// When the front of the vehicle reached the detector
+    pub on: Time,
+    /// When the back of the vehicle passed the detector
+    pub off: Time,
+    /// How fast the vehicle was moving when it reached the detector
+    pub speed: Speed,
+}
+
+/// Counts and occupancy of one detector over some period of time.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct DetectorBin {
+    pub start: Time,
+    /// How many vehicles reached the detector during the bin
+    pub count: usize,
+    /// The fraction of the bin when some vehicle was over the detector, from 0 to 1
+    pub occupancy: f64,
+    /// The average speed of the vehicles counted
+    pub mean_speed: Speed,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct DetectorState {
+    detector: Detector,
+    actuations: Vec<Actuation>,
+    // Vehicles currently over the detector, when they reached it, and how fast they were going
+    occupied: Vec<(CarID, Time, Speed)>,
+}
+
+/// Tracks every detector. The driving simulation reports where vehicles are every time one of them
+/// changes state on a lane with detectors. Between these observations, vehicles are assumed to
+/// move at a constant speed. Vehicles are only tracked while their front is on the lane, so a
+/// vehicle starting a turn ends its actuation of a stop-bar detector.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DetectorSimState {
+    detectors: Vec<DetectorState>,
+    per_lane: BTreeMap<LaneID, Vec<DetectorID>>,
+    // For each lane with detectors, where each vehicle's front and back were last seen
+    last_seen: BTreeMap<LaneID, Vec<(CarID, Time, Distance, Distance)>>,
+}
+
+impl DetectorSimState {
+    pub(crate) fn new() -> DetectorSimState {
+        DetectorSimState {
+            detectors: Vec::new(),
+            per_lane: BTreeMap::new(),
+            last_seen: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn add(&mut self, spec: DetectorSpec, map: &Map) -> Result<DetectorID> {
+        let lane = map
+            .maybe_get_l(spec.lane)
+            .ok_or_else(|| anyhow!("{} doesn't exist", spec.lane))?;
+        if !lane.lane_type.is_for_moving_vehicles() {
+            bail!("{} isn't for vehicles, so can't have a detector", spec.lane);
+        }
+        let len = lane.length();
+        let dist = spec.dist_along.unwrap_or_else(|| match spec.detector_type {
+            DetectorType::StopBar => (len - STOP_BAR_SETBACK).max(Distance::ZERO),
+            DetectorType::Advance => (len - ADVANCE_SETBACK).max(Distance::ZERO),
+            DetectorType::Exit => EXIT_SETBACK.min(len),
+        });
+        if dist < Distance::ZERO || dist > len {
+            bail!(
+                "Detector {} is {} along {}, but the lane is only {} long",
+                spec.name,
+                dist,
+                spec.lane,
+                len
+            );
+        }
+
+        let id = DetectorID(self.detectors.len());
+        self.detectors.push(DetectorState {
+            detector: Detector {
+                id,
+                name: spec.name,
+                detector_type: spec.detector_type,
+                pos: Position::new(spec.lane, dist),
+            },
+            actuations: Vec::new(),
+            occupied: Vec::new(),
+        });
+        self.per_lane
+            .entry(spec.lane)
+            .or_insert_with(Vec::new)
+            .push(id);
+        Ok(id)
+    }
+
+    pub(crate) fn has_detectors(&self, l: LaneID) -> bool {
+        self.per_lane.contains_key(&l)
+    }
+
+    /// Update detectors on a lane, given the front and back of every vehicle on it right now.
+    pub(crate) fn observe(
+        &mut self,
+        now: Time,
+        l: LaneID,
+        vehicles: Vec<(CarID, Distance, Distance)>,
+    ) {
+        let ids = match self.per_lane.get(&l) {
+            Some(ids) => ids,
+            None => {
+                return;
+            }
+        };
+        let last_seen = self.last_seen.remove(&l).unwrap_or_default();
+
+        for id in ids {
+            let state = &mut self.detectors[id.0];
+            let d = state.detector.pos.dist_along();
+
+            for (car, front, back) in &vehicles {
+                let prev = last_seen
+                    .iter()
+                    .find(|(c, _, _, _)| c == car)
+                    .map(|(_, t, f, b)| (*t, *f, *b));
+
+                if let Some(idx) = state.occupied.iter().position(|(c, _, _)| c == car) {
+                    if *back > d {
+                        let (_, on, speed) = state.occupied.remove(idx);
+                        let off = match prev {
+                            Some((t0, _, b0)) => crossed_at(t0, b0, now, *back, d),
+                            None => now,
+                        };
+                        state.actuations.push(Actuation {
+                            car: *car,
+                            on,
+                            off: off.max(on),
+                            speed,
+                        });
+                    }
+                    continue;
+                }
+
+                if *front < d {
+                    continue;
+                }
+                let (on, speed) = match prev {
+                    Some((t0, f0, _)) => {
+                        if f0 >= d {
+                            // Already passed it before
+                            continue;
+                        }
+                        let speed = if now > t0 {
+                            Speed::from_dist_time(*front - f0, now - t0)
+                        } else {
+                            Speed::ZERO
+                        };
+                        (crossed_at(t0, f0, now, *front, d), speed)
+                    }
+                    // The vehicle just appeared on the lane. Only count it if it's over the
+                    // detector.
+                    None => {
+                        if *back > d {
+                            continue;
+                        }
+                        (now, Speed::ZERO)
+                    }
+                };
+                match prev {
+                    // Did it pass entirely over the detector since last time?
+                    Some((t0, _, b0)) if *back > d => {
+                        state.actuations.push(Actuation {
+                            car: *car,
+                            on,
+                            off: crossed_at(t0, b0, now, *back, d).max(on),
+                            speed,
+                        });
+                    }
+                    _ => {
+                        state.occupied.push((*car, on, speed));
+                    }
+                }
+            }
+
+            // Anybody who left the lane isn't over the detector anymore
+            let mut idx = 0;
+            while idx < state.occupied.len() {
+                let (car, on, speed) = state.occupied[idx];
+                if vehicles.iter().any(|(c, _, _)| *c == car) {
+                    idx += 1;
+                } else {
+                    state.occupied.remove(idx);
+                    state.actuations.push(Actuation {
+                        car,
+                        on,
+                        off: now,
+                        speed,
+                    });
+                }
+            }
+        }
+
+        if !vehicles.is_empty() {
+            self.last_seen.insert(
+                l,
+                vehicles
+                    .into_iter()
+                    .map(|(car, front, back)| (car, now, front, back))
+                    .collect(),
+            );
+        }
+    }
+}
+
+// Queries
+impl DetectorSimState {
+    pub fn all_detectors(&self) -> Vec<&Detector> {
+        self.detectors.iter().map(|state| &state.detector).collect()
+    }
+
+    pub fn get_detectors_on(&self, l: LaneID) -> Vec<&Detector> {
+        self.per_lane
+            .get(&l)
+            .into_iter()
+            .flatten()
+            .map(|id| &self.detectors[id.0].detector)
+            .collect()
+    }
+
+    /// Every vehicle that reached the detector between the two times. Vehicles still over the
+    /// detector are reported with `off` set to `now`.
+    pub fn get_actuations(&self, id: DetectorID, t1: Time, t2: Time, now: Time) -> Vec<Actuation> {
+        let state = &self.detectors[id.0];
+        let mut results: Vec<Actuation> = state
+            .actuations
+            .iter()
+            .cloned()
+            .chain(state.occupied.iter().map(|(car, on, speed)| Actuation {
+                car: *car,
+                on: *on,
+                off: now,
+                speed: *speed,
+            }))
+            .filter(|a| a.on >= t1 && a.on <= t2)
+            .collect();
+        results.sort_by_key(|a| a.on);
+        results
+    }
+
+    /// Is some vehicle over the detector now, or did one leave it within the last `gap`?
+    pub fn actuated_within(&self, id: DetectorID, now: Time, gap: Duration) -> bool {
+        let state = &self.detectors[id.0];
+        !state.occupied.is_empty()
+            || state
+                .actuations
+                .last()
+                .map(|a| a.off >= now - gap)
+                .unwrap_or(false)
+    }
+
+    /// Aggregate a detector's counts, occupancy, and speed into bins of `DETECTOR_BIN`, up to
+    /// `now`.
+    pub fn get_bins(&self, id: DetectorID, now: Time) -> Vec<DetectorBin> {
+        let num_bins = ((now - Time::START_OF_DAY) / DETECTOR_BIN).ceil() as usize;
+        let mut bins: Vec<DetectorBin> = (0..num_bins)
+            .map(|idx| DetectorBin {
+                start: Time::START_OF_DAY + DETECTOR_BIN * (idx as f64),
+                count: 0,
+                occupancy: 0.0,
+                mean_speed: Speed::ZERO,
+            })
+            .collect();
+        let mut total_speed = vec![0.0; num_bins];
+
+        for a in self.get_actuations(id, Time::START_OF_DAY, now, now) {
+            let first = bin_idx(a.on);
+            if let Some(bin) = bins.get_mut(first) {
+                bin.count += 1;
+                total_speed[first] += a.speed.inner_meters_per_second();
+            }
+            for bin in bins.iter_mut().skip(first) {
+                if a.off <= bin.start {
+                    break;
+                }
+                let overlap = a.off.min(bin.start + DETECTOR_BIN) - a.on.max(bin.start);
+                if overlap > Duration::ZERO {
+                    bin.occupancy += overlap / DETECTOR_BIN;
+                }
+            }
+        }
+
+        for (bin, speed) in bins.iter_mut().zip(total_speed) {
+            bin.occupancy = bin.occupancy.min(1.0);
+            if bin.count > 0 {
+                bin.mean_speed = Speed::meters_per_second(speed / (bin.count as f64));
+            }
+        }
+        bins
+    }
+
+    /// Returns the contents of a CSV file, with counts and occupancy for every detector, binned
+    /// every 15 minutes.
+    pub fn export_csv(&self, now: Time) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "detector,name,type,lane,dist_along_meters,bin_start,count,occupancy_percent,mean_speed_mps"
+        )
+        .unwrap();
+        for state in &self.detectors {
+            let detector = &state.detector;
+            for bin in self.get_bins(detector.id, now) {
+                writeln!(
+                    out,
+                    "{},{},{:?},{},{},{},{},{:.1},{:.1}",
+                    detector.id.0,
+                    detector.name,
+                    detector.detector_type,
+                    detector.pos.lane().encode_u32(),
+                    detector.pos.dist_along().inner_meters(),
+                    bin.start,
+                    bin.count,
+                    bin.occupancy * 100.0,
+                    bin.mean_speed.inner_meters_per_second()
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+// Something moving at a constant speed was at x0 at t0 and x1 at t1. When was it at x?
+fn crossed_at(t0: Time, x0: Distance, t1: Time, x1: Distance, x: Distance) -> Time {
+    if x1 <= x0 || t1 <= t0 {
+        return t1;
+    }
+    t0 + (t1 - t0) * ((x - x0) / (x1 - x0)).max(0.0).min(1.0)
+}
+
+fn bin_idx(t: Time) -> usize {
+    ((t - Time::START_OF_DAY) / DETECTOR_BIN).floor() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use map_model::RoadID;
+
+    use super::*;
+    use crate::testing::assert_approx;
+    use crate::VehicleType;
+
+    const CAR_LENGTH: f64 = 5.0;
+
+    fn lane() -> LaneID {
+        LaneID {
+            road: RoadID(0),
+            offset: 0,
+        }
+    }
+
+    fn car(id: usize) -> CarID {
+        CarID {
+            id,
+            vehicle_type: VehicleType::Car,
+        }
+    }
+
+    fn t(seconds: f64) -> Time {
+        Time::START_OF_DAY + Duration::seconds(seconds)
+    }
+
+    fn seconds(time: Time) -> f64 {
+        (time - Time::START_OF_DAY).inner_seconds()
+    }
+
+    /// A single detector at some distance along the lane
+    fn one_detector(dist: f64) -> DetectorSimState {
+        let mut state = DetectorSimState::new();
+        state.detectors.push(DetectorState {
+            detector: Detector {
+                id: DetectorID(0),
+                name: "test".to_string(),
+                detector_type: DetectorType::StopBar,
+                pos: Position::new(lane(), Distance::meters(dist)),
+            },
+            actuations: Vec::new(),
+            occupied: Vec::new(),
+        });
+        state.per_lane.insert(lane(), vec![DetectorID(0)]);
+        state
+    }
+
+    /// Report one car with its front at some distance along the lane
+    fn observe(state: &mut DetectorSimState, time: f64, front: Option<f64>) {
+        let vehicles = front
+            .map(|front| {
+                (
+                    car(1),
+                    Distance::meters(front),
+                    Distance::meters(front - CAR_LENGTH),
+                )
+            })
+            .into_iter()
+            .collect();
+        state.observe(t(time), lane(), vehicles);
+    }
+
+    #[test]
+    fn test_crossed_at() {
+        let at = |x: f64| {
+            seconds(crossed_at(
+                t(0.0),
+                Distance::meters(0.0),
+                t(10.0),
+                Distance::meters(100.0),
+                Distance::meters(x),
+            ))
+        };
+        assert_approx(at(0.0), 0.0);
+        assert_approx(at(25.0), 2.5);
+        assert_approx(at(100.0), 10.0);
+        // Clamped to the interval
+        assert_approx(at(150.0), 10.0);
+
+        // Not moving forward, so just use the latest time
+        assert_approx(
+            seconds(crossed_at(
+                t(0.0),
+                Distance::meters(50.0),
+                t(10.0),
+                Distance::meters(50.0),
+                Distance::meters(50.0),
+            )),
+            10.0,
+        );
+    }
+
+    #[test]
+    fn test_pass_between_observations() {
+        let mut state = one_detector(50.0);
+        observe(&mut state, 0.0, Some(40.0));
+        // The whole car passed over the detector since last time
+        observe(&mut state, 10.0, Some(60.0));
+
+        let actuations = state.get_actuations(DetectorID(0), t(0.0), t(10.0), t(10.0));
+        assert_eq!(actuations.len(), 1);
+        assert_approx(seconds(actuations[0].on), 5.0);
+        assert_approx(seconds(actuations[0].off), 7.5);
+        assert_approx(actuations[0].speed.inner_meters_per_second(), 2.0);
+
+        // Seeing it again doesn't count it twice
+        observe(&mut state, 20.0, Some(80.0));
+        assert_eq!(
+            state
+                .get_actuations(DetectorID(0), t(0.0), t(20.0), t(20.0))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_stopped_over_detector() {
+        let mut state = one_detector(50.0);
+        observe(&mut state, 0.0, Some(40.0));
+        // Stopped at the stop bar with the detector underneath
+        observe(&mut state, 10.0, Some(52.0));
+        observe(&mut state, 20.0, Some(52.0));
+        assert!(state.actuated_within(DetectorID(0), t(20.0), Duration::ZERO));
+        // Still occupied, so the actuation so far ends now
+        let actuations = state.get_actuations(DetectorID(0), t(0.0), t(20.0), t(20.0));
+        assert_eq!(actuations.len(), 1);
+        assert_approx(seconds(actuations[0].on), 25.0 / 3.0);
+        assert_approx(seconds(actuations[0].off), 20.0);
+
+        // Starting a turn ends the actuation
+        observe(&mut state, 30.0, None);
+        let actuations = state.get_actuations(DetectorID(0), t(0.0), t(30.0), t(40.0));
+        assert_eq!(actuations.len(), 1);
+        assert_approx(seconds(actuations[0].off), 30.0);
+    }
+
+    #[test]
+    fn test_gap_out() {
+        let mut state = one_detector(50.0);
+        observe(&mut state, 0.0, Some(40.0));
+        observe(&mut state, 10.0, Some(60.0));
+        // The back of the car left the detector at 7.5s
+        let gap = Duration::seconds(2.0);
+        assert!(state.actuated_within(DetectorID(0), t(9.0), gap));
+        assert!(state.actuated_within(DetectorID(0), t(9.5), gap));
+        assert!(!state.actuated_within(DetectorID(0), t(10.0), gap));
+
+        // Never actuated at all
+        let state = one_detector(50.0);
+        assert!(!state.actuated_within(DetectorID(0), t(10.0), gap));
+    }
+
+    #[test]
+    fn test_bins() {
+        let mut state = one_detector(50.0);
+        state.detectors[0].actuations = vec![
+            Actuation {
+                car: car(1),
+                on: t(100.0),
+                off: t(110.0),
+                speed: Speed::meters_per_second(20.0),
+            },
+            // Straddles the first two bins
+            Actuation {
+                car: car(2),
+                on: t(840.0),
+                off: t(960.0),
+                speed: Speed::meters_per_second(10.0),
+            },
+        ];
+        let bins = state.get_bins(DetectorID(0), t(1800.0));
+        assert_eq!(bins.len(), 2);
+
+        assert_eq!(bins[0].start, t(0.0));
+        assert_eq!(bins[0].count, 2);
+        assert_approx(bins[0].occupancy, 70.0 / 900.0);
+        assert_approx(bins[0].mean_speed.inner_meters_per_second(), 15.0);
+
+        // Vehicles only count towards the bin where they arrived, but occupancy is split
+        assert_eq!(bins[1].start, t(900.0));
+        assert_eq!(bins[1].count, 0);
+        assert_approx(bins[1].occupancy, 60.0 / 900.0);
+        assert_approx(bins[1].mean_speed.inner_meters_per_second(), 0.0);
+
+        // A partial bin at the end still shows up
+        assert_eq!(state.get_bins(DetectorID(0), t(1801.0)).len(), 3);
+    }
+}
@@ -1,23 +1,37 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-use abstutil::{deserialize_hashmap, serialize_hashmap, FixedMap, IndexableKey};
-use geom::{Distance, Duration, PolyLine, Time};
-use map_model::{DrivingSide, IntersectionID, LaneID, Map, Path, PathStep, Position, Traversable};
+use abstutil::{
+    deserialize_btreemap, deserialize_hashmap, serialize_btreemap, serialize_hashmap, FixedMap,
+    IndexableKey,
+};
+use geom::{Distance, Duration, PolyLine, Speed, Time};
+use map_model::{
+    DirectedRoadID, DrivingSide, IntersectionID, LaneID, Map, MovementID, Path, PathConstraints,
+    PathStep, PathfinderCache, Position, RoadID, RoutingParams, Traversable,
+};
 
 use crate::mechanics::car::{Car, CarState};
+use crate::mechanics::detectors::{DetectorID, DetectorSimState, DetectorSpec};
 use crate::mechanics::queue::{Queue, QueueEntry, Queued};
 use crate::sim::Ctx;
 use crate::{
-    ActionAtEnd, AgentID, AgentProperties, CarID, CarStatus, Command, CreateCar, DelayCause,
-    DistanceInterval, DrawCarInput, Event, IntersectionSimState, ParkedCar, ParkingSim,
-    ParkingSpot, PersonID, Problem, SimOptions, TimeInterval, TransitSimState, TripID, TripManager,
-    UnzoomedAgent, Vehicle, VehicleType, WalkingSimState, FOLLOWING_DISTANCE, MAX_CAR_LENGTH,
+    ActionAtEnd, AgentID, AgentProperties, CarID, CarStatus, Closures, Command, CreateCar,
+    DelayCause, DistanceInterval, DrawCarInput, EmissionsModel, EmissionsTracker, Event, Incident,
+    IncidentEffect, IncidentID, IncidentImpact, IntersectionSimState, OvertakeType, ParkedCar,
+    ParkingSim, ParkingSpot, PersonID, Problem, QueueSample, RerouteReason, SimOptions,
+    TimeInterval, TransitSimState, TripID, TripManager, UnzoomedAgent, Vehicle, VehicleType,
+    WalkingSimState, FOLLOWING_DISTANCE, MAX_CAR_LENGTH,
 };
 
 const TIME_TO_WAIT_AT_BUS_STOP: Duration = Duration::const_seconds(10.0);
-const TIME_TO_CHANGE_LANES: Duration = Duration::const_seconds(1.0);
+pub(crate) const TIME_TO_CHANGE_LANES: Duration = Duration::const_seconds(1.0);
+const TIME_TO_RETRY_LANE_CHANGE: Duration = Duration::const_seconds(2.0);
+const INCIDENT_BLOCKAGE_LENGTH: Distance = Distance::const_meters(1.0);
+/// Roughly how long each vehicle stopped in a queue adds to the time to cross a road
+const QUEUE_DISCHARGE_HEADWAY: Duration = Duration::const_seconds(2.0);
 
 // TODO Do something else.
 pub const BLIND_RETRY_TO_CREEP_FORWARDS: Duration = Duration::const_seconds(0.1);
@@ -45,11 +59,36 @@ pub(crate) struct DrivingSimState {
 
     recalc_lanechanging: bool,
     handle_uber_turns: bool,
+    acceleration: bool,
+    overtake_into_oncoming: bool,
+    change_lanes_around_blockages: bool,
 
     time_to_unpark_onstreet: Duration,
     time_to_park_onstreet: Duration,
     time_to_unpark_offstreet: Duration,
     time_to_park_offstreet: Duration,
+
+    detectors: DetectorSimState,
+    emissions_model: EmissionsModel,
+
+    /// Lanes and turns where an incident makes vehicles go slower, with the fraction of their
+    /// usual speed
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    slowdowns: BTreeMap<Traversable, (IncidentID, f64)>,
+    closures: Closures,
+
+    /// What fraction of cars have a navigation app, re-planning their route using live travel
+    /// times
+    navigation_app_share: f64,
+    reroute_delay_threshold: Duration,
+    /// Used for re-planning routes while driving. This avoids incident closures and includes
+    /// delays from live congestion.
+    reroute_params: RoutingParams,
+    #[serde(skip_serializing, skip_deserializing)]
+    pathfinder_cache: PathfinderCache,
 }
 
 // Mutations
@@ -61,12 +100,26 @@ impl DrivingSimState {
             events: Vec::new(),
             recalc_lanechanging: !opts.dont_recalc_lanechanging,
             handle_uber_turns: !opts.dont_handle_uber_turns,
+            acceleration: opts.acceleration,
+            overtake_into_oncoming: opts.overtake_into_oncoming,
+            change_lanes_around_blockages: opts.change_lanes_around_blockages,
             waiting_to_spawn: BTreeMap::new(),
 
             time_to_unpark_onstreet: Duration::seconds(10.0),
             time_to_park_onstreet: Duration::seconds(15.0),
             time_to_unpark_offstreet: Duration::seconds(5.0),
             time_to_park_offstreet: Duration::seconds(5.0),
+
+            detectors: DetectorSimState::new(),
+            emissions_model: EmissionsModel::default(),
+
+            slowdowns: BTreeMap::new(),
+            closures: Closures::default(),
+
+            navigation_app_share: opts.navigation_app_share,
+            reroute_delay_threshold: opts.reroute_delay_threshold,
+            reroute_params: map.routing_params().clone(),
+            pathfinder_cache: PathfinderCache::new(),
         };
         if opts.infinite_parking {
             sim.time_to_unpark_offstreet = Duration::seconds(0.1);
@@ -189,8 +242,15 @@ impl DrivingSimState {
                 last_steps: VecDeque::new(),
                 started_at: now,
                 total_blocked_time: Duration::ZERO,
+                emissions: EmissionsTracker::new(now, start_dist),
+                last_speed: if self.acceleration {
+                    Some(Speed::ZERO)
+                } else {
+                    None
+                },
                 trip_and_person: params.trip_and_person,
                 wants_to_overtake: BTreeSet::new(),
+                blocked_time_at_last_reroute: Duration::ZERO,
             };
             let mut start_crossing = false;
             if let Some(p) = params.maybe_parked_car {
@@ -266,7 +326,7 @@ impl DrivingSimState {
                     }
                 }
 
-                car.state = car.crossing_state(start_dist, now, ctx.map);
+                car.state = car.crossing_state(start_dist, now, &self.slowdowns, ctx.map);
                 start_crossing = true;
             }
             ctx.scheduler
@@ -280,10 +340,11 @@ impl DrivingSimState {
             if start_crossing {
                 // Don't call this earlier where we set crossing_state, because we're not in the
                 // queue yet
-                self.new_crossing_state(ctx, &car);
+                self.new_crossing_state(now, ctx, &car);
             }
 
             self.cars.insert(car.vehicle.id, car);
+            self.observe_detectors(now, Traversable::Lane(first_lane));
 
             return None;
         }
@@ -325,10 +386,14 @@ impl DrivingSimState {
         transit: &mut TransitSimState,
         walking: &mut WalkingSimState,
     ) {
+        let started_on = self.cars[&id].router.head();
         let mut need_distances = {
             let car = &self.cars[&id];
             match car.state {
-                CarState::Queued { .. } => car.router.last_step(),
+                CarState::Queued {
+                    want_to_change_lanes,
+                    ..
+                } => car.router.last_step() || want_to_change_lanes.is_some(),
                 CarState::Parking(_, _, _) => true,
                 CarState::IdlingAtStop(_, _) => true,
                 _ => false,
@@ -365,9 +430,21 @@ impl DrivingSimState {
             {
                 self.cars.insert(id, car);
             } else {
+                let on = car.router.head();
+                self.emissions_produced(&mut car, on, dists[idx].front, now);
                 self.delete_car_internal(&mut car, dists, idx, now, ctx);
             }
         }
+
+        self.observe_detectors(now, started_on);
+        if let Some(on) = self
+            .cars
+            .get(&id)
+            .map(|car| car.router.head())
+            .filter(|on| *on != started_on)
+        {
+            self.observe_detectors(now, on);
+        }
     }
 
     // If this returns true, we need to immediately run update_car_with_distances. If we don't,
@@ -382,6 +459,7 @@ impl DrivingSimState {
     ) -> bool {
         match car.state {
             CarState::Crossing { .. } => {
+                car.remember_speed(now);
                 car.state = CarState::Queued {
                     blocked_since: now,
                     want_to_change_lanes: None,
@@ -391,6 +469,15 @@ impl DrivingSimState {
                     return true;
                 }
                 let queue = &self.queues[&car.router.head()];
+                if let Some((trip, _)) = car.trip_and_person {
+                    if let Some(incident) = queue.incident_ahead(car.vehicle.id) {
+                        self.events.push(Event::TripAffectedByIncident(
+                            trip,
+                            incident,
+                            IncidentImpact::Blocked,
+                        ));
+                    }
+                }
                 if queue.is_car_at_front(car.vehicle.id) {
                     // Want to re-run, but no urgency about it happening immediately.
                     car.state = CarState::WaitingToAdvance { blocked_since: now };
@@ -428,10 +515,21 @@ impl DrivingSimState {
                         };
                         return true;
                     }
+                } else if self.change_lanes_around_blockages {
+                    if let Some(target_lane) = self.pick_lane_around_blockage(car, ctx.map) {
+                        // Just like overtaking, check for a gap in the target lane with exact
+                        // positions.
+                        car.state = CarState::Queued {
+                            blocked_since: now,
+                            want_to_change_lanes: Some(target_lane),
+                        };
+                        return true;
+                    }
                 }
             }
             CarState::Unparking {
                 front,
+                time_int,
                 ref blocked_starts,
                 ..
             } => {
@@ -478,10 +576,11 @@ impl DrivingSimState {
                         &mut self.events,
                     );
                 }
-                car.state = car.crossing_state(front, now, ctx.map);
+                car.emissions.add_idle(now - time_int.start);
+                car.state = car.crossing_state(front, now, &self.slowdowns, ctx.map);
                 ctx.scheduler
                     .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
-                self.new_crossing_state(ctx, car);
+                self.new_crossing_state(now, ctx, car);
             }
             CarState::WaitingToAdvance { blocked_since } => {
                 // 'car' is the leader.
@@ -531,8 +630,44 @@ impl DrivingSimState {
                     car.trip_and_person,
                     &mut self.events,
                 );
+                if !self.closures.is_empty() {
+                    reroute_around_closures(
+                        car,
+                        &self.closures,
+                        &self.reroute_params,
+                        &mut self.pathfinder_cache,
+                        ctx.map,
+                        &mut self.events,
+                    );
+                }
+                if let Some((trip, _)) = car.trip_and_person {
+                    if let Some((incident, _)) = self.slowdowns.get(&goto) {
+                        self.events.push(Event::TripAffectedByIncident(
+                            trip,
+                            *incident,
+                            IncidentImpact::SlowedDown,
+                        ));
+                    }
+                }
                 car.total_blocked_time += now - blocked_since;
-                car.state = car.crossing_state(Distance::ZERO, now, ctx.map);
+                if has_navigation_app(car, self.navigation_app_share)
+                    && car.total_blocked_time - car.blocked_time_at_last_reroute
+                        >= self.reroute_delay_threshold
+                {
+                    reroute(
+                        car,
+                        RerouteReason::Delayed,
+                        &self.reroute_params,
+                        &mut self.pathfinder_cache,
+                        ctx.map,
+                        &mut self.events,
+                    );
+                }
+                self.emissions_produced(car, from, from.get_polyline(ctx.map).length(), now);
+                if now > blocked_since {
+                    car.emissions.entered_from_stop();
+                }
+                car.state = car.crossing_state(Distance::ZERO, now, &self.slowdowns, ctx.map);
                 ctx.scheduler
                     .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
                 self.events.push(Event::AgentEntersTraversable(
@@ -559,6 +694,7 @@ impl DrivingSimState {
                             car.vehicle.length + FOLLOWING_DISTANCE,
                         ),
                         now,
+                        &self.slowdowns,
                         ctx.map,
                     )
                     .get_end_time(),
@@ -582,6 +718,7 @@ impl DrivingSimState {
                     time_int: new_time,
                     dist_int: new_dist,
                     steep_uphill: false,
+                    profile: None,
                 };
                 ctx.scheduler
                     .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
@@ -601,6 +738,20 @@ impl DrivingSimState {
                     .unwrap()
                     .clear_dynamic_blockage(car.vehicle.id, idx);
             }
+            CarState::Overtaking {
+                oncoming,
+                pass_dist,
+                ..
+            } => {
+                // Pull back into the original lane. The car is already in the right place in the
+                // queue.
+                car.state = car.crossing_state(pass_dist.end, now, &self.slowdowns, ctx.map);
+                ctx.scheduler
+                    .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                self.new_crossing_state(now, ctx, car);
+
+                self.stop_blocking_oncoming(car, oncoming, now, ctx);
+            }
             CarState::Queued { .. } => unreachable!(),
             CarState::Parking(_, _, _) => unreachable!(),
             CarState::IdlingAtStop(_, _) => unreachable!(),
@@ -626,7 +777,8 @@ impl DrivingSimState {
             CarState::Crossing { .. }
             | CarState::Unparking { .. }
             | CarState::WaitingToAdvance { .. }
-            | CarState::ChangingLanes { .. } => unreachable!(),
+            | CarState::ChangingLanes { .. }
+            | CarState::Overtaking { .. } => unreachable!(),
             CarState::Queued {
                 blocked_since,
                 want_to_change_lanes,
@@ -634,7 +786,38 @@ impl DrivingSimState {
                 // Two totally different reasons we'll wind up here: we want to lane-change, and
                 // we're on our last step.
                 if let Some(target_lane) = want_to_change_lanes {
-                    self.try_start_lc(car, our_dist, idx, target_lane, now, ctx);
+                    let attempt = if ctx.map.get_l(target_lane).dir
+                        == ctx.map.get_l(car.router.head().as_lane()).dir
+                    {
+                        self.try_start_lc(car, dists, idx, target_lane, now, ctx)
+                    } else {
+                        self.try_start_overtaking(
+                            car,
+                            dists,
+                            idx,
+                            target_lane,
+                            blocked_since,
+                            now,
+                            ctx,
+                        )
+                    };
+                    match attempt {
+                        LaneChangeAttempt::Started => {}
+                        LaneChangeAttempt::NoGap => {
+                            // Keep looking for a gap. If the vehicle in front starts moving
+                            // first, this gets rescheduled.
+                            ctx.scheduler.update(
+                                now + TIME_TO_RETRY_LANE_CHANGE,
+                                Command::UpdateCar(car.vehicle.id),
+                            );
+                        }
+                        LaneChangeAttempt::GiveUp => {
+                            car.state = CarState::Queued {
+                                blocked_since,
+                                want_to_change_lanes: None,
+                            };
+                        }
+                    }
                     return true;
                 }
 
@@ -682,6 +865,7 @@ impl DrivingSimState {
                                 self.time_to_park_offstreet
                             }
                         };
+                        car.emissions.add_idle(delay);
                         car.state =
                             CarState::Parking(our_dist, spot, TimeInterval::new(now, now + delay));
                         // If we don't do this, then we might have another car creep up behind, see
@@ -694,10 +878,13 @@ impl DrivingSimState {
                     }
                     Some(ActionAtEnd::GotoLaneEnd) => {
                         car.total_blocked_time += now - blocked_since;
-                        car.state = car.crossing_state(our_dist, now, ctx.map);
+                        if now > blocked_since {
+                            car.emissions.restarted();
+                        }
+                        car.state = car.crossing_state(our_dist, now, &self.slowdowns, ctx.map);
                         ctx.scheduler
                             .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
-                        self.new_crossing_state(ctx, car);
+                        self.new_crossing_state(now, ctx, car);
                         true
                     }
                     Some(ActionAtEnd::StopBiking(bike_rack)) => {
@@ -772,14 +959,16 @@ impl DrivingSimState {
                 );
                 false
             }
-            CarState::IdlingAtStop(dist, _) => {
+            CarState::IdlingAtStop(dist, time_int) => {
+                car.emissions.add_idle(now - time_int.start);
+                car.emissions.restarted();
                 car.router = transit.bus_departed_from_stop(car.vehicle.id, ctx.map);
                 self.events
                     .push(Event::PathAmended(car.router.get_path().clone()));
-                car.state = car.crossing_state(dist, now, ctx.map);
+                car.state = car.crossing_state(dist, now, &self.slowdowns, ctx.map);
                 ctx.scheduler
                     .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
-                self.new_crossing_state(ctx, car);
+                self.new_crossing_state(now, ctx, car);
 
                 self.update_follower(idx, dists, now, ctx);
 
@@ -823,10 +1012,14 @@ impl DrivingSimState {
         if car.router.last_step() {
             ctx.parking.unreserve_spot(c);
         }
+        if let CarState::Overtaking { oncoming, .. } = car.state {
+            self.stop_blocking_oncoming(&car, oncoming, now, ctx);
+        }
 
         self.delete_car_internal(&mut car, dists, idx, now, ctx);
         // delete_car_internal cancels UpdateLaggyHead
         ctx.scheduler.cancel(Command::UpdateCar(c));
+        self.observe_detectors(now, car.router.head());
         car.vehicle
     }
 
@@ -900,26 +1093,31 @@ impl DrivingSimState {
 
                     // Prevent them from jumping forwards.
                     follower.total_blocked_time += now - blocked_since;
-                    follower.state = follower.crossing_state(follower_dist, now, ctx.map);
+                    if now > blocked_since {
+                        follower.emissions.restarted();
+                    }
+                    follower.state =
+                        follower.crossing_state(follower_dist, now, &self.slowdowns, ctx.map);
                     ctx.scheduler.update(
                         follower.state.get_end_time(),
                         Command::UpdateCar(follower_id),
                     );
                     let follower = &self.cars[&follower_id];
-                    self.new_crossing_state(ctx, follower);
+                    self.new_crossing_state(now, ctx, follower);
                 }
                 CarState::Crossing { .. } => {
                     // If the follower was still Crossing, they might not've been blocked by the
                     // leader yet. But recalculating their Crossing state isn't necessarily a no-op
                     // -- this could prevent them from suddenly warping past a blockage.
-                    follower.state = follower.crossing_state(follower_dist, now, ctx.map);
+                    follower.state =
+                        follower.crossing_state(follower_dist, now, &self.slowdowns, ctx.map);
                     ctx.scheduler.update(
                         follower.state.get_end_time(),
                         Command::UpdateCar(follower_id),
                     );
                     // This'll possibly update the ETA
                     let follower = &self.cars[&follower_id];
-                    self.new_crossing_state(ctx, follower);
+                    self.new_crossing_state(now, ctx, follower);
                 }
                 CarState::ChangingLanes {
                     from, to, lc_time, ..
@@ -934,6 +1132,7 @@ impl DrivingSimState {
                     let (new_time, new_dist) = match follower.crossing_state_with_end_dist(
                         DistanceInterval::new_driving(follower_dist, ctx.map.get_l(to).length()),
                         now,
+                        &self.slowdowns,
                         ctx.map,
                     ) {
                         CarState::Crossing {
@@ -950,10 +1149,12 @@ impl DrivingSimState {
                         lc_time,
                     };
                 }
-                // They weren't blocked
+                // They weren't blocked. If they're overtaking, their position in this queue is
+                // already fixed at the end of the maneuver.
                 CarState::Unparking { .. }
                 | CarState::Parking(_, _, _)
-                | CarState::IdlingAtStop(_, _) => {}
+                | CarState::IdlingAtStop(_, _)
+                | CarState::Overtaking { .. } => {}
                 CarState::WaitingToAdvance { .. } => unreachable!(),
             }
         }
@@ -1005,6 +1206,7 @@ impl DrivingSimState {
                         self.cars[&id].vehicle.length + FOLLOWING_DISTANCE,
                     ),
                     now,
+                    &self.slowdowns,
                     ctx.map,
                 )
                 .get_end_time();
@@ -1081,8 +1283,9 @@ impl DrivingSimState {
                                             self.handle_uber_turns,
                                         );
                                     }
+                                    // They might've been waiting for a gap to change lanes
                                     ctx.scheduler
-                                        .push(now, Command::UpdateCar(follower.vehicle.id));
+                                        .update(now, Command::UpdateCar(follower.vehicle.id));
                                 }
                             }
                             CarState::WaitingToAdvance { .. } => unreachable!(),
@@ -1090,6 +1293,7 @@ impl DrivingSimState {
                             // jump forwards here; the leader vanished from the end of the traversable.
                             CarState::Crossing { .. }
                             | CarState::ChangingLanes { .. }
+                            | CarState::Overtaking { .. }
                             | CarState::Unparking { .. }
                             | CarState::Parking(_, _, _)
                             | CarState::IdlingAtStop(_, _) => {}
@@ -1105,9 +1309,10 @@ impl DrivingSimState {
     }
 
     /// If the car wants to over-take somebody, what adjacent lane should they use?
-    /// - The lane must be in the same direction as the current; no support for crossing the road's
-    ///   yellow line yet.
-    /// - Prefer passing on the left (for DrivingSide::Right)
+    /// - Prefer a lane in the same direction as the current, passing on the left (for
+    ///   DrivingSide::Right)
+    /// - Otherwise, maybe briefly cross the road's yellow line into an oncoming lane. Whether
+    ///   there's a big enough gap in oncoming traffic is checked later.
     /// For now, just pick one candidate lane, even if both might be usable.
     fn pick_overtaking_lane(&self, car: &Car, map: &Map) -> Option<LaneID> {
         // Don't overtake in the middle of a turn!
@@ -1126,12 +1331,9 @@ impl DrivingSimState {
             candidates.reverse();
         }
 
+        let mut oncoming = None;
         for l in candidates {
             let target_lane = map.get_l(l);
-            // Must be the same direction -- no crossing into oncoming traffic yet
-            if current_lane.dir != target_lane.dir {
-                continue;
-            }
             // The lane types can differ, as long as the vehicle can use the target. Imagine
             // overtaking a slower cyclist in a bike lane using the rest of the road.
             if !car
@@ -1142,6 +1344,10 @@ impl DrivingSimState {
             {
                 continue;
             }
+            if current_lane.dir != target_lane.dir {
+                oncoming = oncoming.or(Some(target_lane.id));
+                continue;
+            }
             // Is this other lane compatible with the path? We won't make any attempts to return to the
             // original lane after changing.
             if !car
@@ -1153,27 +1359,33 @@ impl DrivingSimState {
             return Some(target_lane.id);
         }
 
+        // Passing in the oncoming lane doesn't change the path. Don't bother on the last step,
+        // when the vehicle is about to stop anyway.
+        if self.overtake_into_oncoming && !car.router.last_step() {
+            return oncoming;
+        }
         None
     }
 
     fn try_start_lc(
         &mut self,
         car: &mut Car,
-        front_current_queue: Distance,
+        dists: &[QueueEntry],
         idx_in_current_queue: usize,
         target_lane: LaneID,
         now: Time,
         ctx: &mut Ctx,
-    ) {
+    ) -> LaneChangeAttempt {
         // If we are a laggy head somewhere else (our back is still sticking into another lane or
         // turn), don't start lane-changing!
         if !car.last_steps.is_empty() {
-            return;
+            return LaneChangeAttempt::NoGap;
         }
+        let front_current_queue = dists[idx_in_current_queue].front;
         // If the lanes are very different lengths and we're too close to the end at the target,
         // not going to work.
         if front_current_queue >= ctx.map.get_l(target_lane).length() {
-            return;
+            return LaneChangeAttempt::GiveUp;
         }
         let current_lane = car.router.head().as_lane();
         let front_target_queue = Position::new(current_lane, front_current_queue)
@@ -1185,6 +1397,7 @@ impl DrivingSimState {
         let (new_time, new_dist) = match car.crossing_state_with_end_dist(
             DistanceInterval::new_driving(front_target_queue, ctx.map.get_l(target_lane).length()),
             now,
+            &self.slowdowns,
             ctx.map,
         ) {
             CarState::Crossing {
@@ -1197,54 +1410,577 @@ impl DrivingSimState {
         // possible in the target?
         let lc_time = TimeInterval::new(now, now + TIME_TO_CHANGE_LANES);
         if lc_time.end >= new_time.end {
-            return;
+            return LaneChangeAttempt::GiveUp;
+        }
+
+        // Is it worth it? There should be more room to move ahead in the target lane.
+        let target_queue = &self.queues[&Traversable::Lane(target_lane)];
+        let target_dists = target_queue.get_car_positions(now, &self.cars, &self.queues);
+        let room_here = room_ahead(
+            &self.queues[&car.router.head()],
+            dists,
+            front_current_queue,
+            &self.cars,
+        );
+        let room_there = room_ahead(target_queue, &target_dists, front_target_queue, &self.cars);
+        if room_there < room_here + car.vehicle.length + FOLLOWING_DISTANCE {
+            return LaneChangeAttempt::GiveUp;
         }
 
         // Is there room for us to sliiiide on over into that lane's DMs?
-        if let Some(idx_in_target_queue) = self.queues[&Traversable::Lane(target_lane)]
-            .get_idx_to_insert_car(
-                front_target_queue,
-                car.vehicle.length,
+        let idx_in_target_queue = match target_queue.get_idx_to_insert_car(
+            front_target_queue,
+            car.vehicle.length,
+            now,
+            &self.cars,
+            &self.queues,
+        ) {
+            Some(idx) => idx,
+            None => {
+                return LaneChangeAttempt::NoGap;
+            }
+        };
+        // Gap acceptance: whoever would be behind us in the target lane shouldn't have to slam on
+        // the brakes. Leave them room to keep going at their current speed while we change lanes.
+        if let Some(QueueEntry {
+            member: Queued::Vehicle(follower),
+            front,
+            ..
+        }) = target_dists.get(idx_in_target_queue)
+        {
+            let their_speed = self.cars[follower].estimate_speed(now);
+            if *front + their_speed * TIME_TO_CHANGE_LANES + FOLLOWING_DISTANCE
+                > front_target_queue - car.vehicle.length
+            {
+                return LaneChangeAttempt::NoGap;
+            }
+        }
+
+        // TODO Can downgrade this to an alert or debug once active work has settled down
+        if false {
+            info!(
+                "{} is starting to change lanes from {} to {}",
+                car.vehicle.id,
+                car.router.head(),
+                target_lane
+            );
+        }
+
+        // Lane-changing to get around something stopped doesn't count as overtaking
+        if let Some(slower) = self.queues[&car.router.head()]
+            .get_leader(car.vehicle.id)
+            .filter(|slower| car.wants_to_overtake.contains(slower))
+        {
+            self.events.push(Event::VehicleOvertaking(
+                car.vehicle.id,
+                slower,
+                current_lane,
+                OvertakeType::ChangeLanes,
+            ));
+        }
+
+        // Exit the old queue (leaving a dynamic blockage in place)
+        self.queues
+            .get_mut(&car.router.head())
+            .unwrap()
+            .replace_car_with_dynamic_blockage(car, idx_in_current_queue);
+
+        // Change the path
+        car.router.confirm_lanechange(target_lane, ctx.map);
+
+        // Insert into the new queue
+        self.queues
+            .get_mut(&car.router.head())
+            .unwrap()
+            .insert_car_at_idx(idx_in_target_queue, car);
+
+        // Put into the new state
+        car.state = CarState::ChangingLanes {
+            from: current_lane,
+            to: target_lane,
+            new_time,
+            new_dist,
+            lc_time,
+        };
+        ctx.scheduler
+            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+        LaneChangeAttempt::Started
+    }
+
+    /// If the car is stuck behind something that won't move for a while, is there an adjacent lane
+    /// going the same direction that'd be better? Ordinary queues, like the line at a red light,
+    /// don't count. The lane must be compatible with the rest of the path. Whether there's a gap
+    /// to move into and more room ahead is checked later with exact positions.
+    fn pick_lane_around_blockage(&self, car: &Car, map: &Map) -> Option<LaneID> {
+        let current_lane = map.get_l(car.router.head().maybe_lane()?);
+        let queue = &self.queues[&car.router.head()];
+        let stopped_in_lane = |id: &CarID| {
+            matches!(
+                self.cars[id].state,
+                CarState::Parking(..) | CarState::Unparking { .. } | CarState::IdlingAtStop(..)
+            )
+        };
+        let behind_stopped_leader = matches!(
+            queue.get_leader(car.vehicle.id),
+            Some(leader) if stopped_in_lane(&leader)
+        );
+        let blocked = behind_stopped_leader
+            || match queue.head_of_line(car.vehicle.id) {
+                Some(Queued::StaticBlockage { .. } | Queued::IncidentBlockage { .. }) => true,
+                // Somebody changing lanes will be out of the way soon
+                Some(Queued::DynamicBlockage { .. }) => false,
+                // The front of the line is usually just waiting at the intersection
+                Some(Queued::Vehicle(id)) => stopped_in_lane(id),
+                None => false,
+            };
+        if !blocked {
+            return None;
+        }
+
+        let road = map.get_parent(current_lane.id);
+        let idx = current_lane.id.offset;
+        let constraints = car.vehicle.vehicle_type.to_constraints();
+        let mut candidates = Vec::new();
+        if idx != 0 {
+            candidates.push(road.lanes[idx - 1].id);
+        }
+        if idx != road.lanes.len() - 1 {
+            candidates.push(road.lanes[idx + 1].id);
+        }
+        candidates
+            .into_iter()
+            .filter(|l| {
+                let lane = map.get_l(*l);
+                lane.dir == current_lane.dir
+                    && constraints.can_use(lane, map)
+                    && car.router.can_lanechange(current_lane.id, *l, map)
+                    && !self.queues[&Traversable::Lane(*l)].is_closed()
+            })
+            .min_by_key(|l| self.queues[&Traversable::Lane(*l)].target_lane_penalty())
+    }
+
+    /// Try to pass the vehicle in front by briefly using an oncoming lane. This only happens if
+    /// there's room in front of the slower vehicle before the end of the lane, and nobody in the
+    /// oncoming lane would reach us before the maneuver is done.
+    fn try_start_overtaking(
+        &mut self,
+        car: &mut Car,
+        dists: &[QueueEntry],
+        idx: usize,
+        oncoming: LaneID,
+        blocked_since: Time,
+        now: Time,
+        ctx: &mut Ctx,
+    ) -> LaneChangeAttempt {
+        // Like lane-changing, don't start while our back is still in the previous turn
+        if !car.last_steps.is_empty() {
+            return LaneChangeAttempt::NoGap;
+        }
+        if idx == 0 {
+            return LaneChangeAttempt::GiveUp;
+        }
+        let slower = match dists[idx - 1].member {
+            Queued::Vehicle(c) => c,
+            _ => return LaneChangeAttempt::GiveUp,
+        };
+        let their_speed = {
+            let slower = &self.cars[&slower];
+            // If they're stopped, they're probably stuck behind somebody else too
+            if !matches!(slower.state, CarState::Crossing { .. }) {
+                return LaneChangeAttempt::NoGap;
+            }
+            slower.router.get_path().current_step().max_speed_along(
+                slower.vehicle.max_speed,
+                slower.vehicle.vehicle_type.to_constraints(),
+                ctx.map,
+            )
+        };
+        let our_speed = car.router.get_path().current_step().max_speed_along(
+            car.vehicle.max_speed,
+            car.vehicle.vehicle_type.to_constraints(),
+            ctx.map,
+        );
+        if our_speed <= their_speed {
+            return LaneChangeAttempt::GiveUp;
+        }
+
+        // Keep going until there's room for our back in front of them, assuming they keep going
+        // as fast as possible
+        let our_dist = dists[idx].front;
+        let gain = dists[idx - 1].front - our_dist + car.vehicle.length + FOLLOWING_DISTANCE;
+        let pass_duration = (gain / (our_speed - their_speed)).max(TIME_TO_CHANGE_LANES * 2.0);
+        let pass_end = our_dist + our_speed * pass_duration;
+
+        // Is there room to pull back in before whatever's in front of them, and before the end of
+        // the lane?
+        let queue = &self.queues[&car.router.head()];
+        let limit = if idx >= 2 {
+            dists[idx - 2].back - FOLLOWING_DISTANCE
+        } else if let Some(c) = queue.laggy_head {
+            // Assume the worst case about the laggy head, like get_idx_to_insert_car
+            queue.geom_len - self.cars[&c].vehicle.length - FOLLOWING_DISTANCE
+        } else {
+            queue.geom_len
+        };
+        if pass_end > queue.geom_len {
+            return LaneChangeAttempt::GiveUp;
+        }
+        if pass_end > limit {
+            return LaneChangeAttempt::NoGap;
+        }
+
+        // Block the part of the oncoming lane that we'll use during the maneuver
+        let current_lane = car.router.head().as_lane();
+        let block_front = Position::new(
+            current_lane,
+            (our_dist - car.vehicle.length).max(Distance::ZERO),
+        )
+        .equiv_pos(oncoming, ctx.map)
+        .dist_along();
+        let block_back = Position::new(current_lane, pass_end)
+            .equiv_pos(oncoming, ctx.map)
+            .dist_along();
+        if block_back < FOLLOWING_DISTANCE || block_front <= block_back {
+            return LaneChangeAttempt::GiveUp;
+        }
+        // Calculating positions on another queue may recurse to this one, so temporarily make the
+        // car visible. See the Unparking case in update_car_without_distances.
+        self.cars.insert(car.vehicle.id, car.clone());
+        let oncoming_dists = self.queues[&Traversable::Lane(oncoming)].get_car_positions(
+            now,
+            &self.cars,
+            &self.queues,
+        );
+        let oncoming_idx = self.queues[&Traversable::Lane(oncoming)].get_idx_to_insert_car(
+            block_front,
+            block_front - block_back,
+            now,
+            &self.cars,
+            &self.queues,
+        );
+        self.cars.remove(&car.vehicle.id);
+        let oncoming_idx = match oncoming_idx {
+            Some(i) => i,
+            None => return LaneChangeAttempt::NoGap,
+        };
+        // Is anybody approaching who could reach us before we pull back in? Also watch for anybody
+        // about to enter the oncoming lane.
+        let approach = ctx.map.get_parent(oncoming).speed_limit * pass_duration;
+        if let Some(entry) = oncoming_dists.get(oncoming_idx) {
+            if entry.front + approach + FOLLOWING_DISTANCE > block_back {
+                return LaneChangeAttempt::NoGap;
+            }
+        }
+        if approach + FOLLOWING_DISTANCE > block_back
+            && ctx.map.get_turns_to_lane(oncoming).into_iter().any(|t| {
+                self.queues
+                    .get(&Traversable::Turn(t.id))
+                    .map(|q| !q.get_active_cars().is_empty() || q.laggy_head.is_some())
+                    .unwrap_or(false)
+            })
+        {
+            return LaneChangeAttempt::NoGap;
+        }
+
+        self.events.push(Event::VehicleOvertaking(
+            car.vehicle.id,
+            slower,
+            current_lane,
+            OvertakeType::Oncoming,
+        ));
+        car.total_blocked_time += now - blocked_since;
+        if now > blocked_since {
+            car.emissions.restarted();
+        }
+
+        self.queues
+            .get_mut(&Traversable::Lane(oncoming))
+            .unwrap()
+            .add_static_blockage(car.vehicle.id, block_front, block_back, oncoming_idx);
+        self.queues
+            .get_mut(&car.router.head())
+            .unwrap()
+            .overtake_leader(car.vehicle.id, idx);
+        car.state = CarState::Overtaking {
+            oncoming,
+            pass_time: TimeInterval::new(now, now + pass_duration),
+            pass_dist: DistanceInterval::new_driving(our_dist, pass_end),
+        };
+        ctx.scheduler
+            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+
+        // Whoever was behind us can follow the slower vehicle now
+        self.update_follower(idx, dists, now, ctx);
+        LaneChangeAttempt::Started
+    }
+
+    /// A vehicle finished overtaking (or vanished in the middle of it), so let oncoming traffic
+    /// continue.
+    fn stop_blocking_oncoming(&mut self, car: &Car, oncoming: LaneID, now: Time, ctx: &mut Ctx) {
+        // Similar to the Unparking case in update_car_without_distances, we calculate distances
+        // in this OTHER queue.
+        self.cars.insert(car.vehicle.id, car.clone());
+        let dists = self.queues[&Traversable::Lane(oncoming)].get_car_positions(
+            now,
+            &self.cars,
+            &self.queues,
+        );
+        self.cars.remove(&car.vehicle.id);
+
+        let idx = dists.iter().position(|entry| matches!(entry.member, Queued::StaticBlockage { cause, ..} if cause == car.vehicle.id)).unwrap();
+        self.update_follower(idx, &dists, now, ctx);
+
+        self.queues
+            .get_mut(&Traversable::Lane(oncoming))
+            .unwrap()
+            .clear_static_blockage(car.vehicle.id, idx);
+    }
+
+    pub fn add_detector(&mut self, spec: DetectorSpec, map: &Map) -> Result<DetectorID> {
+        self.detectors.add(spec, map)
+    }
+
+    pub fn set_emissions_model(&mut self, model: EmissionsModel) {
+        self.emissions_model = model;
+    }
+
+    /// Start slowing down vehicles for an incident, and re-route anybody headed through something
+    /// it closes. Blocking lanes happens separately.
+    pub fn start_incident(
+        &mut self,
+        id: IncidentID,
+        incident: &Incident,
+        now: Time,
+        ctx: &mut Ctx,
+    ) {
+        for (on, factor) in incident.slowdowns(ctx.map) {
+            self.slowdowns.insert(on, (id, factor));
+        }
+
+        if incident.effect != IncidentEffect::Closure {
+            return;
+        }
+        self.closures.start(id, incident, ctx.map);
+        let road_delays = self.reroute_params.road_delays.clone();
+        self.update_reroute_params(road_delays, ctx.map);
+        if !self.closures.is_empty() {
+            self.reroute_active_cars(RerouteReason::Closure, now, ctx);
+        }
+    }
+
+    /// Place an incident's blockage at the end of some lanes. Everything already on the lane
+    /// behind the blockage stays stuck there. Returns the lanes that couldn't be blocked yet,
+    /// because vehicles are in the way.
+    pub fn block_lanes(&mut self, id: IncidentID, lanes: Vec<LaneID>, now: Time) -> Vec<LaneID> {
+        let mut pending = Vec::new();
+        for l in lanes {
+            let on = Traversable::Lane(l);
+            let (idx, front) = match self.queues[&on].find_room_for_incident_blockage(
+                INCIDENT_BLOCKAGE_LENGTH,
                 now,
                 &self.cars,
                 &self.queues,
-            )
-        {
-            // TODO Can downgrade this to an alert or debug once active work has settled down
-            if false {
-                info!(
-                    "{} is starting to change lanes from {} to {}",
-                    car.vehicle.id,
-                    car.router.head(),
-                    target_lane
-                );
+            ) {
+                Some(pair) => pair,
+                None => {
+                    pending.push(l);
+                    continue;
+                }
+            };
+            let queue = self.queues.get_mut(&on).unwrap();
+            queue.add_incident_blockage(id, front, INCIDENT_BLOCKAGE_LENGTH, idx);
+            for c in queue.get_active_cars() {
+                if queue.incident_ahead(c) == Some(id) {
+                    if let Some((trip, _)) = self.cars[&c].trip_and_person {
+                        self.events.push(Event::TripAffectedByIncident(
+                            trip,
+                            id,
+                            IncidentImpact::Blocked,
+                        ));
+                    }
+                }
             }
+        }
+        pending
+    }
 
-            // Exit the old queue (leaving a dynamic blockage in place)
-            self.queues
-                .get_mut(&car.router.head())
-                .unwrap()
-                .replace_car_with_dynamic_blockage(car, idx_in_current_queue);
+    /// Remove everything an incident did. Vehicles stuck behind a blockage start moving again.
+    pub fn end_incident(&mut self, id: IncidentID, incident: &Incident, now: Time, ctx: &mut Ctx) {
+        self.slowdowns.retain(|_, (x, _)| *x != id);
+        if incident.effect == IncidentEffect::Closure {
+            self.closures.end(id);
+            let road_delays = self.reroute_params.road_delays.clone();
+            self.update_reroute_params(road_delays, ctx.map);
+        }
 
-            // Change the path
-            car.router.confirm_lanechange(target_lane, ctx.map);
+        for l in incident.blocked_lanes(ctx.map) {
+            let on = Traversable::Lane(l);
+            let dists = self.queues[&on].get_car_positions(now, &self.cars, &self.queues);
+            // The blockage might never have been placed
+            if let Some(idx) = dists.iter().position(
+                |entry| matches!(entry.member, Queued::IncidentBlockage { id: x, .. } if x == id),
+            ) {
+                self.update_follower(idx, &dists, now, ctx);
+                self.queues
+                    .get_mut(&on)
+                    .unwrap()
+                    .clear_incident_blockage(id, idx);
+            }
+        }
+    }
 
-            // Insert into the new queue
-            self.queues
-                .get_mut(&car.router.head())
-                .unwrap()
-                .insert_car_at_idx(idx_in_target_queue, car);
+    /// A vehicle is leaving a lane or turn, or finishing its trip somewhere along it.
+    fn emissions_produced(
+        &mut self,
+        car: &mut Car,
+        on: Traversable,
+        end_dist: Distance,
+        now: Time,
+    ) {
+        if let Some(emissions) = car.emissions.leave(
+            now,
+            end_dist,
+            car.total_blocked_time,
+            car.vehicle.vehicle_type,
+            &self.emissions_model,
+        ) {
+            self.events.push(Event::VehicleEmissions(
+                car.vehicle.id,
+                car.trip_and_person.map(|(t, _)| t),
+                on,
+                emissions,
+            ));
+        }
+    }
 
-            // Put into the new state
-            car.state = CarState::ChangingLanes {
-                from: current_lane,
-                to: target_lane,
-                new_time,
-                new_dist,
-                lc_time,
+    /// Estimate live travel times from the vehicles currently stopped in queues, then let
+    /// vehicles with a navigation app re-plan using them.
+    pub fn live_reroute(&mut self, now: Time, ctx: &mut Ctx) {
+        let road_delays = self.live_road_delays(ctx.map);
+        self.update_reroute_params(road_delays, ctx.map);
+        self.reroute_active_cars(RerouteReason::Periodic, now, ctx);
+    }
+
+    /// How much longer than usual does it take to cross each road right now? Vehicles stopped on
+    /// a road each delay anybody behind them a bit, split among the lanes they can use.
+    fn live_road_delays(&self, map: &Map) -> BTreeMap<DirectedRoadID, Duration> {
+        // (stopped vehicles, lanes)
+        let mut per_road: BTreeMap<DirectedRoadID, (usize, usize)> = BTreeMap::new();
+        for queue in self.queues.values() {
+            let lane = match queue.id {
+                Traversable::Lane(l) => map.get_l(l),
+                Traversable::Turn(_) => {
+                    continue;
+                }
             };
-            ctx.scheduler
-                .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+            if !PathConstraints::Car.can_use(lane, map) {
+                continue;
+            }
+            let stopped = queue
+                .get_active_cars()
+                .into_iter()
+                .filter(|c| {
+                    matches!(
+                        self.cars[c].state,
+                        CarState::Queued { .. } | CarState::WaitingToAdvance { .. }
+                    )
+                })
+                .count();
+            let entry = per_road.entry(lane.get_directed_parent()).or_insert((0, 0));
+            entry.0 += stopped;
+            entry.1 += 1;
+        }
+        per_road
+            .into_iter()
+            .filter(|(_, (stopped, _))| *stopped > 0)
+            .map(|(dr, (stopped, lanes))| {
+                (
+                    dr,
+                    QUEUE_DISCHARGE_HEADWAY * (stopped as f64 / lanes as f64),
+                )
+            })
+            .collect()
+    }
+
+    fn update_reroute_params(
+        &mut self,
+        road_delays: BTreeMap<DirectedRoadID, Duration>,
+        map: &Map,
+    ) {
+        self.reroute_params = self.closures.routing_params(map);
+        self.reroute_params.road_delays = road_delays;
+        // Nothing will route with the old params again
+        self.pathfinder_cache.clear();
+    }
+
+    /// Let active vehicles re-plan the rest of their route. For closures, everybody whose route
+    /// goes through one tries. Otherwise, only vehicles with a navigation app do.
+    fn reroute_active_cars(&mut self, reason: RerouteReason, now: Time, ctx: &mut Ctx) {
+        let ids: Vec<CarID> = self.cars.values().map(|car| car.vehicle.id).collect();
+        for car_id in ids {
+            let car = self.cars.get_mut(&car_id).unwrap();
+            if reason != RerouteReason::Closure
+                && !has_navigation_app(car, self.navigation_app_share)
+            {
+                continue;
+            }
+            let waiting_for_turn = match car.state {
+                CarState::Crossing { .. } | CarState::Queued { .. } => None,
+                CarState::WaitingToAdvance { .. } => Some(car.router.next()),
+                // Not a good time to change the route
+                CarState::ChangingLanes { .. }
+                | CarState::Overtaking { .. }
+                | CarState::Unparking { .. }
+                | CarState::Parking(_, _, _)
+                | CarState::IdlingAtStop(_, _) => {
+                    continue;
+                }
+            };
+            let changed = if reason == RerouteReason::Closure {
+                reroute_around_closures(
+                    car,
+                    &self.closures,
+                    &self.reroute_params,
+                    &mut self.pathfinder_cache,
+                    ctx.map,
+                    &mut self.events,
+                )
+            } else {
+                reroute(
+                    car,
+                    reason,
+                    &self.reroute_params,
+                    &mut self.pathfinder_cache,
+                    ctx.map,
+                    &mut self.events,
+                )
+            };
+            // They might be waiting to start a turn that's no longer part of the route, so cancel
+            // that request and try again
+            if let Some(old_turn) = waiting_for_turn {
+                if changed && car.router.next() != old_turn {
+                    ctx.intersections
+                        .cancel_request(AgentID::Car(car_id), old_turn.as_turn());
+                    ctx.scheduler.update(now, Command::UpdateCar(car_id));
+                }
+            }
+        }
+    }
+
+    /// If there are detectors on a lane, tell them where every vehicle on it is now.
+    fn observe_detectors(&mut self, now: Time, on: Traversable) {
+        if let Traversable::Lane(l) = on {
+            if self.detectors.has_detectors(l) {
+                let vehicles = self.queues[&on]
+                    .get_car_positions(now, &self.cars, &self.queues)
+                    .into_iter()
+                    .filter_map(|entry| match entry.member {
+                        Queued::Vehicle(c) => Some((c, entry.front, entry.back)),
+                        _ => None,
+                    })
+                    .collect();
+                self.detectors.observe(now, l, vehicles);
+            }
         }
     }
 
@@ -1291,13 +2027,16 @@ impl DrivingSimState {
         }
     }
 
-    fn new_crossing_state(&self, ctx: &mut Ctx, car: &Car) {
+    fn new_crossing_state(&self, now: Time, ctx: &mut Ctx, car: &Car) {
         if self.queues[&car.router.head()].is_car_at_front(car.vehicle.id) {
             if let Some(Traversable::Turn(turn)) = car.router.maybe_next() {
                 ctx.intersections.approaching_leader(
+                    now,
                     AgentID::Car(car.vehicle.id),
                     turn,
                     car.state.get_end_time(),
+                    ctx.map,
+                    ctx.scheduler,
                 );
             }
         }
@@ -1427,6 +2166,7 @@ impl DrivingSimState {
                             None
                         }
                     }
+                    Queued::IncidentBlockage { .. } => None,
                     Queued::DynamicBlockage { cause, vehicle_len } => {
                         if false {
                             Some(DrawCarInput {
@@ -1544,6 +2284,15 @@ impl DrivingSimState {
         car.vehicle.owner
     }
 
+    /// For traffic signals that react to queued vehicles
+    pub fn get_queues(&self) -> &HashMap<Traversable, Queue> {
+        &self.queues
+    }
+
+    pub fn get_detectors(&self) -> &DetectorSimState {
+        &self.detectors
+    }
+
     pub fn target_lane_penalty(&self, l: LaneID) -> (usize, usize) {
         self.queues[&Traversable::Lane(l)].target_lane_penalty()
     }
@@ -1601,6 +2350,112 @@ impl DrivingSimState {
         }
     }
 
+    /// For every lane, count how many vehicles are stopped, waiting to move forward or start a
+    /// turn. Lanes without anybody waiting are omitted.
+    pub fn get_lane_queue_lengths(&self) -> BTreeMap<LaneID, usize> {
+        let mut results = BTreeMap::new();
+        for queue in self.queues.values() {
+            if let Traversable::Lane(l) = queue.id {
+                let waiting = queue
+                    .get_active_cars()
+                    .into_iter()
+                    .filter(|c| {
+                        matches!(
+                            self.cars[c].state,
+                            CarState::Queued { .. } | CarState::WaitingToAdvance { .. }
+                        )
+                    })
+                    .count();
+                if waiting > 0 {
+                    results.insert(l, waiting);
+                }
+            }
+        }
+        results
+    }
+
+    /// Summarize the queue on every lane that has any vehicles on it or headed to it.
+    pub fn sample_lane_queues(&self, now: Time) -> Vec<(LaneID, QueueSample)> {
+        let mut samples = Vec::new();
+        for queue in self.queues.values() {
+            let l = match queue.id {
+                Traversable::Lane(l) => l,
+                Traversable::Turn(_) => {
+                    continue;
+                }
+            };
+            let positions = queue.get_car_positions(now, &self.cars, &self.queues);
+            if positions.is_empty() && queue.reserved_length == Distance::ZERO {
+                continue;
+            }
+            let vehicles = positions
+                .iter()
+                .filter(|entry| matches!(entry.member, Queued::Vehicle(_)))
+                .count();
+            // Starting from the end of the lane, follow the unbroken line of stopped vehicles.
+            // Positions are ordered with the farthest along first.
+            let mut queue_length = Distance::ZERO;
+            for entry in &positions {
+                match entry.member {
+                    Queued::Vehicle(c)
+                        if matches!(
+                            self.cars[&c].state,
+                            CarState::Queued { .. } | CarState::WaitingToAdvance { .. }
+                        ) =>
+                    {
+                        // The back may be negative, if the vehicle hasn't completely left the
+                        // previous intersection
+                        queue_length = queue.geom_len - entry.back;
+                    }
+                    _ => {
+                        break;
+                    }
+                }
+            }
+            samples.push((
+                l,
+                QueueSample {
+                    vehicles,
+                    reserved_length: queue.reserved_length,
+                    queue_length,
+                },
+            ));
+        }
+        samples
+    }
+
+    /// Count the vehicles stopped on lanes leading to a traffic signal, grouped by the movement
+    /// they'll make through it.
+    pub fn count_queued_per_movement(
+        &self,
+        map: &Map,
+        i: IntersectionID,
+        counts: &mut BTreeMap<MovementID, usize>,
+    ) {
+        for l in &map.get_i(i).incoming_lanes {
+            let queue = match self.queues.get(&Traversable::Lane(*l)) {
+                Some(q) => q,
+                None => continue,
+            };
+            for c in queue.get_active_cars() {
+                let car = &self.cars[&c];
+                if !matches!(
+                    car.state,
+                    CarState::Queued { .. } | CarState::WaitingToAdvance { .. }
+                ) {
+                    continue;
+                }
+                if let Some(Traversable::Turn(t)) = car.router.maybe_next() {
+                    if t.parent == i {
+                        if let Some((m, _)) = map.get_movement_for_traffic_signal(t) {
+                            *counts.entry(m).or_insert(0) += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     pub fn debug_queue_lengths(&self, l: LaneID) -> Option<(Distance, Distance)> {
         let queue = self.queues.get(&Traversable::Lane(l))?;
         Some((queue.reserved_length, queue.geom_len))
@@ -1701,3 +2556,125 @@ impl IndexableKey for CarID {
         self.id
     }
 }
+
+/// The result of trying to start changing lanes
+enum LaneChangeAttempt {
+    Started,
+    /// There's not a big enough gap in the target lane right now, or something in the way might
+    /// move soon
+    NoGap,
+    /// Changing lanes won't help or isn't possible here
+    GiveUp,
+}
+
+/// If the rest of a vehicle's route passes through a closed road or intersection, try to find
+/// another way. Returns true if the route changed.
+fn reroute_around_closures(
+    car: &mut Car,
+    closures: &Closures,
+    params: &RoutingParams,
+    cache: &mut PathfinderCache,
+    map: &Map,
+    events: &mut Vec<Event>,
+) -> bool {
+    // Whatever's on the current lane or just after the current turn is unavoidable
+    let skip = match car.router.head() {
+        Traversable::Lane(_) => 1,
+        Traversable::Turn(_) => 2,
+    };
+    let incident = match closures.blocking(car.router.get_path(), skip, map) {
+        Some(id) => id,
+        None => {
+            return false;
+        }
+    };
+    if !reroute(car, RerouteReason::Closure, params, cache, map, events) {
+        // Maybe there's no way around, so they'll wait for the incident to end
+        return false;
+    }
+    if let Some((trip, _)) = car.trip_and_person {
+        events.push(Event::TripAffectedByIncident(
+            trip,
+            incident,
+            IncidentImpact::Rerouted,
+        ));
+    }
+    true
+}
+
+/// Re-plan the rest of a vehicle's route. Returns true if the route changed.
+fn reroute(
+    car: &mut Car,
+    reason: RerouteReason,
+    params: &RoutingParams,
+    cache: &mut PathfinderCache,
+    map: &Map,
+    events: &mut Vec<Event>,
+) -> bool {
+    // Transit follows a fixed route
+    if car.vehicle.vehicle_type.is_transit() {
+        return false;
+    }
+    car.blocked_time_at_last_reroute = car.total_blocked_time;
+    let old_roads = path_roads(car.router.get_path());
+    match car.router.replan(params, cache, map) {
+        Ok(true) => {
+            let new_roads = path_roads(car.router.get_path());
+            events.push(Event::PathAmended(car.router.get_path().clone()));
+            events.push(Event::VehicleRerouted {
+                car: car.vehicle.id,
+                reason,
+                avoided: old_roads.difference(&new_roads).cloned().collect(),
+                added: new_roads.difference(&old_roads).cloned().collect(),
+            });
+            true
+        }
+        Ok(false) => false,
+        Err(err) => {
+            debug!(
+                "{} couldn't re-plan for {:?}: {}",
+                car.vehicle.id, reason, err
+            );
+            false
+        }
+    }
+}
+
+fn path_roads(path: &Path) -> BTreeSet<RoadID> {
+    path.get_steps()
+        .iter()
+        .filter_map(|step| match step {
+            PathStep::Lane(l) | PathStep::ContraflowLane(l) => Some(l.road),
+            PathStep::Turn(_) | PathStep::ContraflowTurn(_) => None,
+        })
+        .collect()
+}
+
+/// Deterministically pick roughly `share` of all cars to have a navigation app, without needing
+/// an RNG.
+fn has_navigation_app(car: &Car, share: f64) -> bool {
+    if share <= 0.0 || car.vehicle.vehicle_type != VehicleType::Car {
+        return false;
+    }
+    // Fibonacci hashing spreads out consecutive IDs
+    let hash = (car.vehicle.id.id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 11;
+    (hash as f64) / ((1_u64 << 53) as f64) < share
+}
+
+/// How far could a vehicle at some position in a queue move before running into whatever's in
+/// front of it? The positions must come from the same queue.
+fn room_ahead(
+    queue: &Queue,
+    dists: &[QueueEntry],
+    front: Distance,
+    cars: &FixedMap<CarID, Car>,
+) -> Distance {
+    let limit = match dists.iter().rev().find(|entry| entry.front > front) {
+        Some(entry) => entry.back - FOLLOWING_DISTANCE,
+        None => match queue.laggy_head {
+            Some(c) => queue.geom_len - cars[&c].vehicle.length - FOLLOWING_DISTANCE,
+            None => queue.geom_len,
+        },
+    };
+    (limit - front).max(Distance::ZERO)
+}
@@ -1,22 +1,30 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_btreemap, prettyprint_usize, serialize_btreemap, FixedMap};
 use geom::{Duration, Time};
 use map_model::{
-    ControlStopSign, ControlTrafficSignal, Intersection, IntersectionID, LaneID, Map, StageType,
-    Traversable, TurnID, TurnPriority, TurnType, UberTurn,
+    ControlStopSign, ControlTrafficSignal, Intersection, IntersectionID, LaneID, Map, MovementID,
+    PedestrianPhase, SignalControl, Stage, StageType, Traversable, TurnID, TurnPriority, TurnType,
+    UberTurn,
 };
 
 use crate::mechanics::car::{Car, CarState};
-use crate::mechanics::Queue;
+use crate::mechanics::{DetectorSimState, DetectorType, Queue};
 use crate::{
-    AgentID, AlertLocation, CarID, Command, DelayCause, Event, Scheduler, SimOptions, Speed,
+    AgentID, AlertLocation, CarID, Command, DelayCause, Event, IncidentID, IncidentImpact,
+    Scheduler, SignalPriorityType, SimOptions, Speed, VehicleType,
 };
 
 const WAIT_AT_STOP_SIGN: Duration = Duration::const_seconds(0.5);
 const WAIT_BEFORE_YIELD_AT_TRAFFIC_SIGNAL: Duration = Duration::const_seconds(0.2);
+// When a max pressure signal keeps its current stage, check again this often
+const MAX_PRESSURE_RECHECK: Duration = Duration::const_seconds(2.0);
+// After a bus or emergency vehicle reaches a traffic signal, assume it needs this long to get
+// through
+const PRIORITY_CROSSING_TIME: Duration = Duration::const_seconds(5.0);
 
 /// Manages conflicts at intersections. When an agent has reached the end of a lane, they call
 /// maybe_start_turn to make a Request. Based on the intersection type (stop sign, traffic signal,
@@ -38,6 +46,8 @@ pub(crate) struct IntersectionSimState {
     // structure.
     blocked_by: BTreeSet<(CarID, CarID)>,
     events: Vec<Event>,
+    // Vehicles can't start any turns through these until the incident ends
+    closed: BTreeMap<IntersectionID, IncidentID>,
 
     // Count how many calls to maybe_start_turn there are aside from the initial call. Break down
     // failures by those not allowed by the current intersection state vs those blocked by a
@@ -85,10 +95,33 @@ struct State {
 struct SignalState {
     // The current stage of the signal, zero based
     current_stage: usize,
+    // When the current stage began. Extending a variable stage doesn't change this.
+    stage_started_at: Time,
     // The time when the signal is checked for advancing
     stage_ends_at: Time,
     // The number of times a variable signal has been extended during the current stage.
     extensions_count: usize,
+    // If true, never advance to the next stage automatically; wait for a command through
+    // set_signal_stage. stage_ends_at is then the earliest time the stage may end.
+    external_control: bool,
+    // A stage change that's waiting for pedestrians to finish crossing, or for the current stage's
+    // amber and all-red intervals
+    pending_stage: Option<usize>,
+    // Only for SignalControl::AdaptiveSplits. How long each stage lasts during this cycle, and
+    // how many agents wanted each stage's protected movements so far.
+    splits: Vec<Duration>,
+    demand: Vec<usize>,
+    // Crosswalks where a pedestrian has pushed the button, but hasn't been served yet
+    ped_calls: BTreeSet<MovementID>,
+    // Is the current stage showing a walk signal for its protected crosswalks?
+    walk_shown: bool,
+    // If set, the green for the current stage ends at this time. Its amber and all-red intervals
+    // follow, until pending_stage begins at stage_ends_at.
+    intergreen_starts_at: Option<Time>,
+    // How much transit signal priority has lengthened or shortened the current stage so far
+    transit_adjustment: Duration,
+    // The last vehicle given priority, so it's only counted once
+    priority_granted_to: Option<CarID>,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone, Debug)]
@@ -109,6 +142,7 @@ impl IntersectionSimState {
             disable_turn_conflicts: opts.disable_turn_conflicts,
             blocked_by: BTreeSet::new(),
             events: Vec::new(),
+            closed: BTreeMap::new(),
 
             total_repeat_requests: 0,
             not_allowed_requests: 0,
@@ -291,27 +325,27 @@ impl IntersectionSimState {
         id: IntersectionID,
         map: &Map,
         scheduler: &mut Scheduler,
+        queues: &HashMap<Traversable, Queue>,
+        detectors: &DetectorSimState,
     ) {
         let i = map.get_i(id);
 
         // trivial function that advances the signal stage and returns duration
         fn advance(
+            now: Time,
             signal_state: &mut SignalState,
             signal: &ControlTrafficSignal,
             i: &Intersection,
             allow_crosswalk_skip: bool,
         ) -> Duration {
-            signal_state.current_stage = (signal_state.current_stage + 1) % signal.stages.len();
-            let stage = &signal.stages[signal_state.current_stage];
-            // only skip for variable all-walk crosswalk
-            if let StageType::Variable(_, _, _) = stage.stage_type {
-                if allow_crosswalk_skip && stage.max_crosswalk_time(i).is_some() {
-                    // we can skip this stage, as its all walk and we're allowed to skip (no
-                    // pedestrian waiting).
-                    signal_state.current_stage =
-                        (signal_state.current_stage + 1) % signal.stages.len();
-                }
-            }
+            signal_state.current_stage = next_cycle_stage(
+                signal,
+                i,
+                signal_state.current_stage,
+                &signal_state.ped_calls,
+                allow_crosswalk_skip,
+            );
+            signal_state.stage_started_at = now;
             signal.stages[signal_state.current_stage]
                 .stage_type
                 .simple_duration()
@@ -328,16 +362,108 @@ impl IntersectionSimState {
         let duration: Duration;
         // Switch to a new stage?
         assert_eq!(now, signal_state.stage_ends_at);
+        // A stage change was requested earlier, but had to wait for a crosswalk to clear
+        if let Some(next) = signal_state.pending_stage.take() {
+            self.switch_stage(now, id, next, map, scheduler);
+            return;
+        }
+        // Hold the current stage until somebody asks for a different one
+        if signal_state.external_control {
+            return;
+        }
+        let prev_stage = signal_state.current_stage;
+        let prev_started_at = signal_state.stage_started_at;
         let old_stage = &signal.stages[signal_state.current_stage];
-        match old_stage.stage_type {
-            StageType::Fixed(_) => {
-                duration = advance(signal_state, signal, i, !ped_waiting);
+        match (&signal.control, &old_stage.stage_type) {
+            (
+                SignalControl::MaxPressure {
+                    min_green,
+                    max_green,
+                },
+                _,
+            ) => {
+                let current = signal_state.current_stage;
+                let elapsed = now - signal_state.stage_started_at;
+                let pressures = stage_pressures(signal, i, &state.waiting, queues, map);
+                // Consider the current stage first, so it wins ties. Once it's lasted too long,
+                // don't consider it at all.
+                let mut candidates = Vec::new();
+                if elapsed < *max_green || signal.stages.len() == 1 {
+                    candidates.push(current);
+                }
+                candidates.extend((0..signal.stages.len()).filter(|idx| *idx != current));
+                let mut best = candidates[0];
+                for idx in candidates {
+                    if pressures[idx] > pressures[best] {
+                        best = idx;
+                    }
+                }
+
+                if best == current {
+                    signal_state.extensions_count += 1;
+                    duration = if signal.stages.len() == 1 {
+                        MAX_PRESSURE_RECHECK
+                    } else {
+                        MAX_PRESSURE_RECHECK.min(*max_green - elapsed)
+                    };
+                } else {
+                    signal_state.current_stage = best;
+                    signal_state.stage_started_at = now;
+                    signal_state.extensions_count = 0;
+                    duration = (*min_green).max(signal.get_min_crossing_time(best, i));
+                }
             }
-            StageType::Variable(min, delay, additional) => {
+            (
+                SignalControl::AdaptiveSplits {
+                    cycle_length,
+                    min_green,
+                },
+                _,
+            ) => {
+                // Anybody still waiting for a protected movement didn't get served this cycle,
+                // so count them as demand too.
+                signal_state.demand[signal_state.current_stage] += state
+                    .waiting
+                    .keys()
+                    .filter(|req| {
+                        old_stage.get_priority_of_turn(req.turn, i) == TurnPriority::Protected
+                    })
+                    .count();
+                let old_idx = signal_state.current_stage;
+                signal_state.current_stage =
+                    next_stage(signal, signal_state.current_stage, &signal_state.ped_calls);
+                signal_state.stage_started_at = now;
+                // Did the cycle wrap around?
+                if signal_state.current_stage <= old_idx {
+                    let weights: Vec<f64> = signal_state.demand.iter().map(|x| *x as f64).collect();
+                    signal_state.splits =
+                        adaptive_splits(signal, i, &weights, *cycle_length, *min_green);
+                    signal_state.demand = vec![0; signal.stages.len()];
+                }
+                duration = signal_state.splits[signal_state.current_stage];
+            }
+            (SignalControl::Cycle, StageType::Fixed(_)) => {
+                duration = advance(now, signal_state, signal, i, !ped_waiting);
+            }
+            (SignalControl::Cycle, StageType::Variable(min, delay, additional)) => {
                 // test if anyone is waiting in current stage, and if so, extend the signal cycle.
                 // Filter out pedestrians, as they've had their chance and the delay
                 // could be short enough to keep them on the curb.
-                let delay = std::cmp::max(Duration::const_seconds(1.0), delay);
+                let delay = std::cmp::max(Duration::const_seconds(1.0), *delay);
+                // Detectors on the approaches measure demand directly: keep extending until
+                // there's a gap of at least delay between vehicles. Without them, look for
+                // anybody waiting for a protected movement.
+                let has_demand = detected_demand(old_stage, i, map, detectors, now, delay)
+                    .unwrap_or_else(|| {
+                        state.waiting.keys().any(|req| {
+                            if let AgentID::Pedestrian(_) = req.agent {
+                                return false;
+                            }
+                            // Should we only allow protected to extend or any not banned?
+                            // currently only the protected demand control extended.
+                            old_stage.get_priority_of_turn(req.turn, i) == TurnPriority::Protected
+                        })
+                    });
                 // Only extend for the fixed additional time
                 if signal_state.extensions_count as f64 * delay.inner_seconds()
                     >= additional.inner_seconds()
@@ -349,18 +475,11 @@ impl IntersectionSimState {
                             min, delay, additional, signal_state.extensions_count
                         ),
                     ));
-                    duration = advance(signal_state, signal, i, !ped_waiting);
+                    duration = advance(now, signal_state, signal, i, !ped_waiting);
                     signal_state.extensions_count = 0;
-                } else if state.waiting.keys().all(|req| {
-                    if let AgentID::Pedestrian(_) = req.agent {
-                        return true;
-                    }
-                    // Should we only allow protected to extend or any not banned?
-                    // currently only the protected demand control extended.
-                    old_stage.get_priority_of_turn(req.turn, i) != TurnPriority::Protected
-                }) {
+                } else if !has_demand {
                     signal_state.extensions_count = 0;
-                    duration = advance(signal_state, signal, i, !ped_waiting);
+                    duration = advance(now, signal_state, signal, i, !ped_waiting);
                 } else {
                     signal_state.extensions_count += 1;
                     duration = delay;
@@ -375,11 +494,218 @@ impl IntersectionSimState {
             }
         }
 
+        // Before moving to a different stage, show amber and all-red. The new stage, already
+        // picked above with any skipping applied, begins afterwards through pending_stage.
+        if signal_state.stage_started_at == now
+            && signal_state.current_stage != prev_stage
+            && old_stage.intergreen() > Duration::ZERO
+        {
+            signal_state.pending_stage = Some(signal_state.current_stage);
+            signal_state.current_stage = prev_stage;
+            signal_state.stage_started_at = prev_started_at;
+            signal_state.intergreen_starts_at = Some(now);
+            signal_state.stage_ends_at = now + old_stage.intergreen();
+            scheduler.push(signal_state.stage_ends_at, Command::UpdateIntersection(id));
+            return;
+        }
+
+        if signal_state.stage_started_at == now {
+            signal_state.start_stage(&signal.stages[signal_state.current_stage]);
+            self.events.push(Event::TrafficSignalStageChanged(
+                id,
+                signal_state.current_stage,
+            ));
+        }
         signal_state.stage_ends_at = now + duration;
         scheduler.push(signal_state.stage_ends_at, Command::UpdateIntersection(id));
         self.wakeup_waiting(now, id, scheduler, map);
     }
 
+    /// Immediately switch a traffic signal to a different stage. If pedestrians haven't had enough
+    /// time to cross during the current stage, the switch is delayed. The current stage's amber
+    /// and all-red intervals also happen first. Returns the time when the new stage will begin.
+    pub fn set_signal_stage(
+        &mut self,
+        now: Time,
+        id: IntersectionID,
+        stage: usize,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) -> Result<Time> {
+        let signal = map
+            .maybe_get_traffic_signal(id)
+            .ok_or_else(|| anyhow!("{} isn't a traffic signal", id))?;
+        if stage >= signal.stages.len() {
+            bail!("{} only has {} stages", id, signal.stages.len());
+        }
+        let signal_state = self.state.get_mut(&id).unwrap().signal.as_mut().unwrap();
+        if signal_state.intergreen_starts_at.is_some() {
+            // Already clearing the intersection; just change what comes next
+            signal_state.pending_stage = Some(stage);
+            return Ok(signal_state.stage_ends_at);
+        }
+        let earliest = signal_state.stage_started_at
+            + signal.get_min_crossing_time(signal_state.current_stage, map.get_i(id));
+        let intergreen = signal.stages[signal_state.current_stage].intergreen();
+        if intergreen > Duration::ZERO {
+            let green_ends = now.max(earliest);
+            signal_state.pending_stage = Some(stage);
+            signal_state.intergreen_starts_at = Some(green_ends);
+            signal_state.stage_ends_at = green_ends + intergreen;
+            scheduler.update(signal_state.stage_ends_at, Command::UpdateIntersection(id));
+            Ok(signal_state.stage_ends_at)
+        } else if now >= earliest {
+            self.switch_stage(now, id, stage, map, scheduler);
+            Ok(now)
+        } else {
+            signal_state.pending_stage = Some(stage);
+            signal_state.stage_ends_at = earliest;
+            scheduler.update(earliest, Command::UpdateIntersection(id));
+            Ok(earliest)
+        }
+    }
+
+    /// Make the current stage of a traffic signal last longer. For externally controlled signals,
+    /// this delays any pending stage change.
+    pub fn extend_signal_stage(
+        &mut self,
+        now: Time,
+        id: IntersectionID,
+        dt: Duration,
+        scheduler: &mut Scheduler,
+    ) -> Result<()> {
+        if dt <= Duration::ZERO {
+            bail!("Can't extend a stage by {}", dt);
+        }
+        let signal_state = self
+            .state
+            .get_mut(&id)
+            .and_then(|state| state.signal.as_mut())
+            .ok_or_else(|| anyhow!("{} isn't a traffic signal", id))?;
+        signal_state.stage_ends_at = signal_state.stage_ends_at.max(now) + dt;
+        // If the amber hasn't started yet, push it back too
+        if let Some(t) = signal_state.intergreen_starts_at {
+            if t > now {
+                signal_state.intergreen_starts_at = Some(t + dt);
+            }
+        }
+        scheduler.update(signal_state.stage_ends_at, Command::UpdateIntersection(id));
+        Ok(())
+    }
+
+    /// Decide if a traffic signal should advance stages on its own, or hold each stage until
+    /// set_signal_stage is called.
+    pub fn set_signal_external_control(
+        &mut self,
+        now: Time,
+        id: IntersectionID,
+        enabled: bool,
+        scheduler: &mut Scheduler,
+    ) -> Result<()> {
+        let signal_state = self
+            .state
+            .get_mut(&id)
+            .and_then(|state| state.signal.as_mut())
+            .ok_or_else(|| anyhow!("{} isn't a traffic signal", id))?;
+        signal_state.external_control = enabled;
+        if !enabled && signal_state.pending_stage.is_none() && signal_state.stage_ends_at <= now {
+            // The stage might've been held indefinitely, so resume the normal cycle right away
+            signal_state.stage_ends_at = now;
+            scheduler.update(now, Command::UpdateIntersection(id));
+        }
+        Ok(())
+    }
+
+    fn switch_stage(
+        &mut self,
+        now: Time,
+        id: IntersectionID,
+        stage: usize,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let signal = map.get_traffic_signal(id);
+        let signal_state = self.state.get_mut(&id).unwrap().signal.as_mut().unwrap();
+        signal_state.current_stage = stage;
+        signal_state.stage_started_at = now;
+        signal_state.extensions_count = 0;
+        signal_state.pending_stage = None;
+        signal_state.intergreen_starts_at = None;
+        let min_crossing_time = signal.get_min_crossing_time(stage, map.get_i(id));
+        let duration = if signal_state.external_control {
+            // The stage will be held indefinitely, but first give pedestrians time to cross
+            min_crossing_time
+        } else {
+            match signal.control {
+                SignalControl::Cycle => signal.stages[stage].stage_type.simple_duration(),
+                SignalControl::MaxPressure { min_green, .. } => min_green.max(min_crossing_time),
+                SignalControl::AdaptiveSplits { .. } => signal_state.splits[stage],
+            }
+        };
+        signal_state.stage_ends_at = now + duration;
+        signal_state.start_stage(&signal.stages[stage]);
+        scheduler.update(signal_state.stage_ends_at, Command::UpdateIntersection(id));
+        self.events
+            .push(Event::TrafficSignalStageChanged(id, stage));
+        self.wakeup_waiting(now, id, scheduler, map);
+    }
+
+    /// Stop vehicles from starting any turns through an intersection, until the incident ends.
+    /// Vehicles already in the intersection finish their turn.
+    pub fn close(&mut self, i: IntersectionID, incident: IncidentID) {
+        self.closed.insert(i, incident);
+    }
+
+    pub fn reopen(
+        &mut self,
+        now: Time,
+        i: IntersectionID,
+        incident: IncidentID,
+        scheduler: &mut Scheduler,
+        map: &Map,
+    ) {
+        if self.closed.get(&i) == Some(&incident) {
+            self.closed.remove(&i);
+            self.wakeup_waiting(now, i, scheduler, map);
+        }
+    }
+
+    /// A pedestrian arrived at the start of a turn. If it's a crosswalk with a push-button that
+    /// isn't currently showing a walk signal, register a call, so a later stage serves it.
+    pub fn register_pedestrian_call(&mut self, now: Time, turn: TurnID, map: &Map) {
+        let m = match map.get_movement_for_traffic_signal(turn) {
+            Some((m, _)) if m.crosswalk => m,
+            _ => {
+                return;
+            }
+        };
+        let signal = map.get_traffic_signal(turn.parent);
+        if !signal.stages.iter().any(|stage| {
+            matches!(stage.pedestrian_phase, PedestrianPhase::PushButton { .. })
+                && stage.protected_movements.contains(&m)
+        }) {
+            return;
+        }
+        let signal_state = match self
+            .state
+            .get_mut(&turn.parent)
+            .and_then(|state| state.signal.as_mut())
+        {
+            Some(signal_state) => signal_state,
+            None => {
+                return;
+            }
+        };
+        let stage = &signal.stages[signal_state.current_stage];
+        if stage.protected_movements.contains(&m)
+            && signal_state.walk_allowed(now, stage, map.get_i(turn.parent))
+        {
+            // They can go right away
+            return;
+        }
+        signal_state.ped_calls.insert(m);
+    }
+
     /// For cars: The head car calls this when they're at the end of the lane WaitingToAdvance. If
     /// this returns true, then the head car MUST actually start this turn.
     /// For peds: Likewise -- only called when the ped is at the start of the turn. They must
@@ -440,6 +766,26 @@ impl IntersectionSimState {
         let shared_sidewalk_corner =
             map.get_t(req.turn).turn_type == TurnType::SharedSidewalkCorner;
 
+        // Pedestrians aren't affected by incidents
+        let closed_by = match agent {
+            AgentID::Car(_) => self.closed.get(&turn.parent).cloned(),
+            _ => None,
+        };
+        if let Some(incident) = closed_by {
+            if let Some((trip, _)) = maybe_cars_and_queues
+                .as_ref()
+                .and_then(|(car, _, _)| car.trip_and_person)
+            {
+                if !repeat_request {
+                    self.events.push(Event::TripAffectedByIncident(
+                        trip,
+                        incident,
+                        IncidentImpact::Blocked,
+                    ));
+                }
+            }
+        }
+
         let readonly_pair = maybe_cars_and_queues.as_ref().map(|(_, c, q)| (*c, &**q));
         let started_uber_turn = |state: &Self, car: &Car| {
             state.handle_uber_turns && car.router.get_path().currently_inside_ut().is_some()
@@ -448,6 +794,9 @@ impl IntersectionSimState {
         let allowed = if shared_sidewalk_corner {
             // SharedSidewalkCorner doesn't conflict with anything -- fastpath!
             true
+        } else if closed_by.is_some() {
+            // Nobody gets through until the incident ends
+            false
         } else if !self.handle_accepted_conflicts(&req, map, readonly_pair, Some((now, scheduler)))
         {
             // It's never OK to perform a conflicting turn
@@ -585,6 +934,21 @@ impl IntersectionSimState {
         // for stop signs too.
         let state = self.state.get_mut(&turn.parent).unwrap();
         state.waiting.remove(&req).unwrap();
+        if let Some(signal_state) = state.signal.as_mut() {
+            if let Some(signal) = map.maybe_get_traffic_signal(turn.parent) {
+                if signal.control != SignalControl::Cycle
+                    && map
+                        .get_movement_for_traffic_signal(turn)
+                        .map(|(m, _)| {
+                            signal.stages[signal_state.current_stage].get_priority_of_movement(m)
+                                == TurnPriority::Protected
+                        })
+                        .unwrap_or(false)
+                {
+                    signal_state.demand[signal_state.current_stage] += 1;
+                }
+            }
+        }
         state.accepted.insert(req);
         if self.break_turn_conflict_cycles {
             if let AgentID::Car(car) = agent {
@@ -619,6 +983,29 @@ impl IntersectionSimState {
                             state.id
                         );
                     }
+                    if signal_state
+                        .pending_stage
+                        .map(|stage| stage >= ts.stages.len())
+                        .unwrap_or(false)
+                    {
+                        signal_state.pending_stage = Some(0);
+                    }
+                    if signal_state.pending_stage.is_none() {
+                        signal_state.intergreen_starts_at = None;
+                    }
+                    if ts.stages[signal_state.current_stage].pedestrian_phase
+                        == PedestrianPhase::Recall
+                    {
+                        signal_state.walk_shown = true;
+                    }
+                    // The number of stages or the control mode may have changed
+                    let splits = initial_splits(ts, map.get_i(state.id));
+                    if signal_state.demand.len() != ts.stages.len()
+                        || signal_state.splits.len() != splits.len()
+                    {
+                        signal_state.demand = vec![0; ts.stages.len()];
+                        signal_state.splits = splits;
+                    }
                 }
                 (Some(_), None) => {
                     state.signal = Some(SignalState::new(state.id, now, map, scheduler));
@@ -672,13 +1059,193 @@ impl IntersectionSimState {
     // This is "best effort". If we get something wrong, somebody might start a turn and cut off an
     // approaching vehicle.
     // And it's idempotent -- can call to update an ETA.
-    pub fn approaching_leader(&mut self, agent: AgentID, turn: TurnID, eta: Time) {
+    pub fn approaching_leader(
+        &mut self,
+        now: Time,
+        agent: AgentID,
+        turn: TurnID,
+        eta: Time,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
         let state = self.state.get_mut(&turn.parent).unwrap();
         // If there was a previous entry here for turn.src, then this leader is spawning in front
         // of the previous leader on a driveway
         state
             .leader_eta
             .insert(turn.src, (Request { agent, turn }, eta));
+
+        if let AgentID::Car(car) = agent {
+            match car.vehicle_type {
+                VehicleType::Bus => {
+                    self.transit_signal_priority(now, car, turn, eta, map, scheduler);
+                }
+                VehicleType::Emergency => {
+                    self.emergency_preemption(now, car, turn, eta, map, scheduler);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// A bus is approaching a traffic signal. If its movement is protected during the current
+    /// stage, extend the green so it makes it through. If the next stage protects it, end the
+    /// current stage early.
+    fn transit_signal_priority(
+        &mut self,
+        now: Time,
+        car: CarID,
+        turn: TurnID,
+        eta: Time,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let signal = match map.maybe_get_traffic_signal(turn.parent) {
+            Some(signal) => signal,
+            None => {
+                return;
+            }
+        };
+        let max_adjustment = match signal.priority.transit_max_adjustment {
+            Some(max) if signal.control == SignalControl::Cycle => max,
+            _ => {
+                return;
+            }
+        };
+        let i = map.get_i(turn.parent);
+        let state = self.state.get_mut(&turn.parent).unwrap();
+        let signal_state = state.signal.as_mut().unwrap();
+        // Don't interfere with external control or a stage change that's already happening
+        if signal_state.external_control
+            || signal_state.pending_stage.is_some()
+            || signal_state.priority_granted_to == Some(car)
+        {
+            return;
+        }
+        let budget = max_adjustment - signal_state.transit_adjustment;
+        if budget <= Duration::ZERO {
+            return;
+        }
+
+        let current = signal_state.current_stage;
+        let (priority_type, shift, serving_stage) =
+            if signal.stages[current].get_priority_of_turn(turn, i) == TurnPriority::Protected {
+                let needed = eta + PRIORITY_CROSSING_TIME - signal_state.stage_ends_at;
+                // If the bus won't make it anyway, don't hold up everybody else
+                if needed <= Duration::ZERO || needed > budget {
+                    return;
+                }
+                signal_state.stage_ends_at += needed;
+                (SignalPriorityType::TransitExtension, needed, current)
+            } else {
+                let next = next_stage(signal, current, &signal_state.ped_calls);
+                if signal.stages[next].get_priority_of_turn(turn, i) != TurnPriority::Protected {
+                    return;
+                }
+                let new_end = now
+                    .max(signal_state.stage_started_at + signal.get_min_crossing_time(current, i))
+                    .max(signal_state.stage_ends_at - budget);
+                if new_end >= signal_state.stage_ends_at {
+                    return;
+                }
+                let shortened = signal_state.stage_ends_at - new_end;
+                signal_state.stage_ends_at = new_end;
+                (SignalPriorityType::TransitEarlyGreen, shortened, next)
+            };
+        signal_state.transit_adjustment += shift;
+        signal_state.priority_granted_to = Some(car);
+        scheduler.update(
+            signal_state.stage_ends_at,
+            Command::UpdateIntersection(turn.parent),
+        );
+        let conflicting = count_conflicting(state, &signal.stages[serving_stage], i);
+        self.events.push(Event::SignalPriorityGranted(
+            turn.parent,
+            car,
+            priority_type,
+            shift,
+            conflicting,
+        ));
+    }
+
+    /// An emergency vehicle is approaching a traffic signal. Hold the current stage if it
+    /// protects the vehicle's movement, or otherwise switch to the first stage that does.
+    fn emergency_preemption(
+        &mut self,
+        now: Time,
+        car: CarID,
+        turn: TurnID,
+        eta: Time,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let signal = match map.maybe_get_traffic_signal(turn.parent) {
+            Some(signal) if signal.priority.emergency_preemption => signal,
+            _ => {
+                return;
+            }
+        };
+        let i = map.get_i(turn.parent);
+        let state = self.state.get_mut(&turn.parent).unwrap();
+        let signal_state = state.signal.as_mut().unwrap();
+        if signal_state.external_control {
+            return;
+        }
+        let protects = |idx: usize| {
+            signal.stages[idx].get_priority_of_turn(turn, i) == TurnPriority::Protected
+        };
+        let current = signal_state.current_stage;
+        let hold_until = eta + PRIORITY_CROSSING_TIME;
+
+        let (shift, target) = if protects(current) && !signal_state.in_intergreen(now) {
+            // Cancel any stage change that hasn't started yet, and keep the green until the
+            // vehicle makes it through
+            let old_end = signal_state.green_ends_at();
+            signal_state.pending_stage = None;
+            signal_state.intergreen_starts_at = None;
+            signal_state.stage_ends_at = old_end.max(hold_until);
+            scheduler.update(
+                signal_state.stage_ends_at,
+                Command::UpdateIntersection(turn.parent),
+            );
+            (signal_state.stage_ends_at - old_end, current)
+        } else if signal_state.pending_stage.map(protects).unwrap_or(false) {
+            // The right stage is already coming
+            return;
+        } else {
+            let target = match (0..signal.stages.len()).find(|idx| protects(*idx)) {
+                Some(idx) => idx,
+                None => {
+                    // No stage protects this movement, so there's nothing to do
+                    return;
+                }
+            };
+            let old_end = signal_state.green_ends_at();
+            let starts_at = match self.set_signal_stage(now, turn.parent, target, map, scheduler) {
+                Ok(t) => t,
+                Err(err) => {
+                    warn!("Couldn't pre-empt {} for {}: {}", turn.parent, car, err);
+                    return;
+                }
+            };
+            // How much green did the stages before the emergency vehicle's lose?
+            (old_end.max(starts_at) - starts_at, target)
+        };
+
+        let state = self.state.get_mut(&turn.parent).unwrap();
+        let signal_state = state.signal.as_mut().unwrap();
+        if signal_state.priority_granted_to == Some(car) {
+            return;
+        }
+        signal_state.priority_granted_to = Some(car);
+        let conflicting = count_conflicting(state, &signal.stages[target], i);
+        self.events.push(Event::SignalPriorityGranted(
+            turn.parent,
+            car,
+            SignalPriorityType::EmergencyPreemption,
+            shift,
+            conflicting,
+        ));
     }
 }
 
@@ -747,6 +1314,10 @@ impl IntersectionSimState {
     ) -> (usize, Duration) {
         let state = &self.state[&i].signal.as_ref().unwrap();
         if now > state.stage_ends_at {
+            if state.external_control {
+                // Holding the stage until told otherwise
+                return (state.current_stage, Duration::ZERO);
+            }
             panic!(
                 "At {}, but {} should have advanced its stage at {}",
                 now, i, state.stage_ends_at
@@ -755,6 +1326,21 @@ impl IntersectionSimState {
         (state.current_stage, state.stage_ends_at - now)
     }
 
+    /// Returns (when the current stage started, how many times the current variable stage has been
+    /// extended).
+    pub fn current_stage_start_and_extensions(&self, i: IntersectionID) -> (Time, usize) {
+        let state = &self.state[&i].signal.as_ref().unwrap();
+        (state.stage_started_at, state.extensions_count)
+    }
+
+    pub fn is_signal_externally_controlled(&self, i: IntersectionID) -> bool {
+        self.state[&i]
+            .signal
+            .as_ref()
+            .map(|state| state.external_control)
+            .unwrap_or(false)
+    }
+
     pub fn describe_stats(&self) -> Vec<String> {
         vec![
             "intersection stats".to_string(),
@@ -930,7 +1516,16 @@ impl IntersectionSimState {
         let signal_state = state.signal.as_ref().unwrap();
         let stage = &signal.stages[signal_state.current_stage];
         let full_stage_duration = stage.stage_type.simple_duration();
-        let remaining_stage_time = signal_state.stage_ends_at - now;
+        // During amber and all-red, agents already in the intersection may finish, but nobody new
+        // may enter
+        if signal_state.in_intergreen(now) {
+            return false;
+        }
+        let remaining_stage_time = signal_state.green_ends_at() - now;
+        // An externally controlled signal with no stage change requested could stay in this stage
+        // indefinitely
+        let stage_ends_soon =
+            !signal_state.external_control || signal_state.pending_stage.is_some();
         let (our_time, _) = state.waiting[req];
 
         // Can't go at all this stage.
@@ -939,6 +1534,15 @@ impl IntersectionSimState {
             return false;
         }
 
+        // Pedestrians can only start crossing during the walk interval
+        if let AgentID::Pedestrian(_) = req.agent {
+            if our_priority == TurnPriority::Protected
+                && !signal_state.walk_allowed(now, stage, map.get_i(state.id))
+            {
+                return false;
+            }
+        }
+
         if our_priority == TurnPriority::Yield
             && now < our_time + WAIT_BEFORE_YIELD_AT_TRAFFIC_SIGNAL
         {
@@ -965,7 +1569,7 @@ impl IntersectionSimState {
         // turn. Don't start the turn if we won't finish by the time the light changes. If we get
         // it wrong, that's fine -- block the box a bit.
         let time_to_cross = turn.geom.length() / speed;
-        if time_to_cross > remaining_stage_time {
+        if stage_ends_soon && time_to_cross > remaining_stage_time {
             // Signals enforce a minimum crosswalk time, but some pedestrians are configured to
             // walk very slowly. In that case, allow them to go anyway and wind up in the crosswalk
             // during a red. This matches reality reasonably.
@@ -1107,17 +1711,47 @@ impl SignalState {
     fn new(id: IntersectionID, now: Time, map: &Map, scheduler: &mut Scheduler) -> SignalState {
         let mut state = SignalState {
             current_stage: 0,
+            stage_started_at: now,
             stage_ends_at: now,
             extensions_count: 0,
+            external_control: false,
+            pending_stage: None,
+            splits: Vec::new(),
+            demand: Vec::new(),
+            ped_calls: BTreeSet::new(),
+            walk_shown: false,
+            intergreen_starts_at: None,
+            transit_adjustment: Duration::ZERO,
+            priority_granted_to: None,
         };
 
         let signal = map.get_traffic_signal(id);
+        let i = map.get_i(id);
+        state.splits = initial_splits(signal, i);
+        state.demand = vec![0; signal.stages.len()];
+        match signal.control {
+            SignalControl::Cycle => {}
+            SignalControl::MaxPressure { min_green, .. } => {
+                state.stage_ends_at = now + min_green.max(signal.get_min_crossing_time(0, i));
+                state.start_stage(&signal.stages[0]);
+                scheduler.push(state.stage_ends_at, Command::UpdateIntersection(id));
+                return state;
+            }
+            SignalControl::AdaptiveSplits { .. } => {
+                state.stage_ends_at = now + state.splits[0];
+                state.start_stage(&signal.stages[0]);
+                scheduler.push(state.stage_ends_at, Command::UpdateIntersection(id));
+                return state;
+            }
+        }
+
         // What stage are we starting with?
         let mut offset = (now - Time::START_OF_DAY) + signal.offset;
         loop {
-            let dt = signal.stages[state.current_stage]
+            let green = signal.stages[state.current_stage]
                 .stage_type
                 .simple_duration();
+            let dt = green + signal.stages[state.current_stage].intergreen();
             if offset >= dt {
                 offset -= dt;
                 state.current_stage += 1;
@@ -1125,13 +1759,262 @@ impl SignalState {
                     state.current_stage = 0;
                 }
             } else {
+                state.stage_started_at = now.clamped_sub(offset);
                 state.stage_ends_at = now + dt - offset;
+                if offset >= green {
+                    // Starting in the middle of amber or all-red
+                    state.intergreen_starts_at = Some(now.clamped_sub(offset - green));
+                    // Nobody's waiting to cross yet
+                    state.pending_stage = Some(next_cycle_stage(
+                        signal,
+                        i,
+                        state.current_stage,
+                        &state.ped_calls,
+                        true,
+                    ));
+                }
                 break;
             }
         }
+        state.start_stage(&signal.stages[state.current_stage]);
         scheduler.push(state.stage_ends_at, Command::UpdateIntersection(id));
         state
     }
+
+    /// Called when a new stage begins. Push-button crosswalks only get a walk signal if somebody
+    /// called for one, and that call is then served.
+    fn start_stage(&mut self, stage: &Stage) {
+        self.transit_adjustment = Duration::ZERO;
+        match stage.pedestrian_phase {
+            PedestrianPhase::Recall => {
+                self.walk_shown = true;
+            }
+            PedestrianPhase::PushButton { .. } => {
+                let before = self.ped_calls.len();
+                self.ped_calls
+                    .retain(|m| !stage.protected_movements.contains(m));
+                self.walk_shown = self.ped_calls.len() != before;
+            }
+        }
+    }
+
+    /// Is the current stage showing amber or all-red?
+    fn in_intergreen(&self, now: Time) -> bool {
+        self.intergreen_starts_at.map(|t| now >= t).unwrap_or(false)
+    }
+
+    /// When the current stage's green ends. Amber and all-red might follow.
+    fn green_ends_at(&self) -> Time {
+        self.intergreen_starts_at.unwrap_or(self.stage_ends_at)
+    }
+
+    /// Can pedestrians start crossing the current stage's protected crosswalks right now?
+    fn walk_allowed(&self, now: Time, stage: &Stage, i: &Intersection) -> bool {
+        if !self.walk_shown || self.in_intergreen(now) {
+            return false;
+        }
+        match stage.pedestrian_phase {
+            // Pedestrians decide for themselves if they have enough time to cross
+            PedestrianPhase::Recall => true,
+            // Nobody new may start during the clearance interval. An externally controlled
+            // signal might hold the stage indefinitely, though.
+            PedestrianPhase::PushButton { .. } => {
+                (self.external_control && self.pending_stage.is_none())
+                    || now + stage.pedestrian_clearance(i) <= self.green_ends_at()
+            }
+        }
+    }
+}
+
+/// How many agents waiting at or approaching the intersection have a movement that isn't allowed
+/// during this stage?
+fn count_conflicting(state: &State, stage: &Stage, i: &Intersection) -> usize {
+    state
+        .waiting
+        .keys()
+        .map(|req| req.turn)
+        .chain(state.leader_eta.values().map(|(req, _)| req.turn))
+        .filter(|t| stage.get_priority_of_turn(*t, i) == TurnPriority::Banned)
+        .count()
+}
+
+/// If there are stop-bar or advance detectors on the lanes leading to the stage's protected
+/// movements, has a vehicle been over one of them within `gap`? None if there are no such
+/// detectors.
+fn detected_demand(
+    stage: &Stage,
+    i: &Intersection,
+    map: &Map,
+    detectors: &DetectorSimState,
+    now: Time,
+    gap: Duration,
+) -> Option<bool> {
+    let mut any_detectors = false;
+    for l in &i.incoming_lanes {
+        if !map
+            .get_turns_from_lane(*l)
+            .into_iter()
+            .any(|t| stage.get_priority_of_turn(t.id, i) == TurnPriority::Protected)
+        {
+            continue;
+        }
+        for detector in detectors.get_detectors_on(*l) {
+            if detector.detector_type == DetectorType::Exit {
+                continue;
+            }
+            any_detectors = true;
+            if detectors.actuated_within(detector.id, now, gap) {
+                return Some(true);
+            }
+        }
+    }
+    if any_detectors {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// The stage after `current` in the cycle, skipping push-button stages with only crosswalks that
+/// nobody has called for.
+fn next_stage(
+    signal: &ControlTrafficSignal,
+    current: usize,
+    ped_calls: &BTreeSet<MovementID>,
+) -> usize {
+    let mut idx = current;
+    for _ in 0..signal.stages.len() {
+        idx = (idx + 1) % signal.stages.len();
+        let stage = &signal.stages[idx];
+        if !stage.only_push_button_crosswalks()
+            || stage
+                .protected_movements
+                .iter()
+                .any(|m| ped_calls.contains(m))
+        {
+            return idx;
+        }
+    }
+    // Nothing has been called at all
+    (current + 1) % signal.stages.len()
+}
+
+/// The stage after `current` for SignalControl::Cycle. Like `next_stage`, but if
+/// `allow_crosswalk_skip` is set because no pedestrians are waiting, a variable all-walk stage is
+/// skipped too.
+fn next_cycle_stage(
+    signal: &ControlTrafficSignal,
+    i: &Intersection,
+    current: usize,
+    ped_calls: &BTreeSet<MovementID>,
+    allow_crosswalk_skip: bool,
+) -> usize {
+    let idx = next_stage(signal, current, ped_calls);
+    let stage = &signal.stages[idx];
+    // only skip for variable all-walk crosswalk
+    if let StageType::Variable(_, _, _) = stage.stage_type {
+        if allow_crosswalk_skip && stage.max_crosswalk_time(i).is_some() {
+            // we can skip this stage, as its all walk and we're allowed to skip (no pedestrian
+            // waiting).
+            return next_stage(signal, idx, ped_calls);
+        }
+    }
+    idx
+}
+
+/// For every stage, the number of vehicles queued upstream of its protected movements, minus the
+/// number queued downstream, plus the number of pedestrians waiting for its crosswalks.
+fn stage_pressures(
+    signal: &ControlTrafficSignal,
+    i: &Intersection,
+    waiting: &BTreeMap<Request, (Time, bool)>,
+    queues: &HashMap<Traversable, Queue>,
+    map: &Map,
+) -> Vec<isize> {
+    let mut peds_waiting: BTreeMap<MovementID, isize> = BTreeMap::new();
+    for req in waiting.keys() {
+        if let AgentID::Pedestrian(_) = req.agent {
+            if let Some((m, _)) = map.get_movement_for_traffic_signal(req.turn) {
+                *peds_waiting.entry(m).or_insert(0) += 1;
+            }
+        }
+    }
+    let occupancy = |l: LaneID| {
+        queues
+            .get(&Traversable::Lane(l))
+            .map(|q| q.get_active_cars().len() as isize)
+            .unwrap_or(0)
+    };
+
+    signal
+        .stages
+        .iter()
+        .map(|stage| {
+            let mut pressure = 0;
+            for m in &stage.protected_movements {
+                if m.crosswalk {
+                    pressure += peds_waiting.get(m).cloned().unwrap_or(0);
+                    continue;
+                }
+                let movement = &i.movements[m];
+                let upstream: BTreeSet<LaneID> = movement.members.iter().map(|t| t.src).collect();
+                let downstream: BTreeSet<LaneID> = movement.members.iter().map(|t| t.dst).collect();
+                pressure += upstream.into_iter().map(occupancy).sum::<isize>();
+                pressure -= downstream.into_iter().map(occupancy).sum::<isize>();
+            }
+            pressure
+        })
+        .collect()
+}
+
+/// Before any demand has been observed, divide the cycle in proportion to each stage's configured
+/// duration.
+fn initial_splits(signal: &ControlTrafficSignal, i: &Intersection) -> Vec<Duration> {
+    if let SignalControl::AdaptiveSplits {
+        cycle_length,
+        min_green,
+    } = signal.control
+    {
+        let weights: Vec<f64> = signal
+            .stages
+            .iter()
+            .map(|s| s.stage_type.simple_duration().inner_seconds())
+            .collect();
+        adaptive_splits(signal, i, &weights, cycle_length, min_green)
+    } else {
+        Vec::new()
+    }
+}
+
+/// Every stage gets its minimum time, then the rest of the cycle (minus amber and all-red) is
+/// divided up in proportion to the weights. If all weights are zero, the rest is divided evenly.
+fn adaptive_splits(
+    signal: &ControlTrafficSignal,
+    i: &Intersection,
+    weights: &[f64],
+    cycle_length: Duration,
+    min_green: Duration,
+) -> Vec<Duration> {
+    let minimums: Vec<Duration> = (0..signal.stages.len())
+        .map(|idx| min_green.max(signal.get_min_crossing_time(idx, i)))
+        .collect();
+    let spare =
+        (cycle_length - signal.total_intergreen() - minimums.iter().cloned().sum::<Duration>())
+            .max(Duration::ZERO);
+    let total: f64 = weights.iter().sum();
+    minimums
+        .into_iter()
+        .enumerate()
+        .map(|(idx, min)| {
+            let share = if total > 0.0 {
+                weights[idx] / total
+            } else {
+                1.0 / (signal.stages.len() as f64)
+            };
+            // Round to whole seconds, since that's how timing is usually configured
+            min + Duration::seconds((spare * share).inner_seconds().round())
+        })
+        .collect()
 }
 
 fn allow_block_the_box(i: &Intersection) -> bool {
@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+
+use geom::{Distance, Duration, Speed};
+
+use crate::VehicleType;
+
+/// How quickly a vehicle can speed up and slow down. This is only used when
+/// `SimOptions::acceleration` is enabled; otherwise vehicles change speed instantly.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Kinematics {
+    /// In meters per second squared
+    pub max_accel: f64,
+    /// In meters per second squared. This is positive.
+    pub max_decel: f64,
+    /// After stopping at the stop bar of an intersection, how long it takes to start moving again
+    pub startup_lost_time: Duration,
+}
+
+impl Kinematics {
+    pub fn default_for(vehicle_type: VehicleType) -> Kinematics {
+        let (max_accel, max_decel, startup_lost_time) = match vehicle_type {
+            VehicleType::Car => (2.5, 4.5, 1.0),
+            VehicleType::Emergency => (3.0, 5.0, 0.5),
+            VehicleType::Bus => (1.2, 3.5, 1.5),
+            VehicleType::Train => (1.0, 1.3, 2.0),
+            VehicleType::Bike => (1.0, 3.0, 1.0),
+        };
+        Kinematics {
+            max_accel,
+            max_decel,
+            startup_lost_time: Duration::seconds(startup_lost_time),
+        }
+    }
+}
+
+/// How a vehicle's speed changes while crossing one lane or turn. After an optional delay, the
+/// vehicle accelerates from its starting speed to a peak speed, cruises, then brakes to its ending
+/// speed. All speeds are in meters per second, times in seconds, and distances in meters.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SpeedProfile {
+    delay: f64,
+    start_speed: f64,
+    peak_speed: f64,
+    end_speed: f64,
+    // These are durations of each phase
+    first_change: f64,
+    cruise: f64,
+    second_change: f64,
+    total_dist: f64,
+}
+
+impl SpeedProfile {
+    /// Plans to cross some distance, starting and ending at the given speeds, without exceeding
+    /// `max_speed`. If the vehicle can't reach `end_speed` over this distance, it ends as close
+    /// to it as possible.
+    pub fn new(
+        dist: Distance,
+        start_speed: Speed,
+        max_speed: Speed,
+        end_speed: Speed,
+        delay: Duration,
+        kinematics: &Kinematics,
+    ) -> SpeedProfile {
+        let d = dist.inner_meters();
+        let a = kinematics.max_accel;
+        let b = kinematics.max_decel;
+        let v_max = max_speed.inner_meters_per_second();
+        // If the vehicle is coming from a faster road, just slow down immediately.
+        let v0 = start_speed.inner_meters_per_second().min(v_max);
+        let mut v1 = end_speed.inner_meters_per_second().min(v_max);
+        if v1 > v0 {
+            v1 = v1.min((v0 * v0 + 2.0 * a * d).sqrt());
+        } else {
+            v1 = v1.max((v0 * v0 - 2.0 * b * d).max(0.0).sqrt());
+        }
+
+        // The fastest speed that still leaves room to reach v1 by the end
+        let peak = ((2.0 * a * b * d + b * v0 * v0 + a * v1 * v1) / (a + b))
+            .sqrt()
+            .min(v_max)
+            .max(v0.max(v1));
+        let first_change = (peak - v0) / a;
+        let second_change = (peak - v1) / b;
+        let first_dist = (peak * peak - v0 * v0) / (2.0 * a);
+        let second_dist = (peak * peak - v1 * v1) / (2.0 * b);
+        let cruise = if peak > 0.0 {
+            (d - first_dist - second_dist).max(0.0) / peak
+        } else {
+            0.0
+        };
+
+        SpeedProfile {
+            delay: delay.inner_seconds(),
+            start_speed: v0,
+            peak_speed: peak,
+            end_speed: v1,
+            first_change,
+            cruise,
+            second_change,
+            total_dist: d,
+        }
+    }
+
+    pub fn total_time(&self) -> Duration {
+        Duration::seconds(self.delay + self.first_change + self.cruise + self.second_change)
+    }
+
+    pub fn end_speed(&self) -> Speed {
+        Speed::meters_per_second(self.end_speed)
+    }
+
+    /// How fast is the vehicle going some amount of time after starting?
+    pub fn speed_at(&self, elapsed: Duration) -> Speed {
+        let mut t = elapsed.inner_seconds() - self.delay;
+        if t <= 0.0 {
+            return Speed::ZERO;
+        }
+        if t < self.first_change {
+            return Speed::meters_per_second(
+                self.start_speed + (self.peak_speed - self.start_speed) * t / self.first_change,
+            );
+        }
+        t -= self.first_change;
+        if t < self.cruise {
+            return Speed::meters_per_second(self.peak_speed);
+        }
+        t -= self.cruise;
+        if t < self.second_change {
+            return Speed::meters_per_second(
+                self.peak_speed + (self.end_speed - self.peak_speed) * t / self.second_change,
+            );
+        }
+        self.end_speed()
+    }
+
+    /// What percent of the total distance has the vehicle covered some amount of time after
+    /// starting?
+    pub fn percent_dist(&self, elapsed: Duration) -> f64 {
+        if self.total_dist == 0.0 || elapsed >= self.total_time() {
+            return 1.0;
+        }
+        let mut t = elapsed.inner_seconds() - self.delay;
+        if t <= 0.0 {
+            return 0.0;
+        }
+        // Integrate the speed over each phase, where it changes linearly
+        let mut dist = 0.0;
+        let dt = t.min(self.first_change);
+        if dt > 0.0 {
+            let v =
+                self.start_speed + (self.peak_speed - self.start_speed) * dt / self.first_change;
+            dist += (self.start_speed + v) / 2.0 * dt;
+        }
+        t -= self.first_change;
+        if t > 0.0 {
+            dist += self.peak_speed * t.min(self.cruise);
+            t -= self.cruise;
+        }
+        if t > 0.0 {
+            let dt = t.min(self.second_change);
+            let v = self.peak_speed + (self.end_speed - self.peak_speed) * dt / self.second_change;
+            dist += (self.peak_speed + v) / 2.0 * dt;
+        }
+        (dist / self.total_dist).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_approx;
+
+    fn profile(dist: f64, start: f64, max: f64, end: f64, delay: f64) -> SpeedProfile {
+        SpeedProfile::new(
+            Distance::meters(dist),
+            Speed::meters_per_second(start),
+            Speed::meters_per_second(max),
+            Speed::meters_per_second(end),
+            Duration::seconds(delay),
+            &Kinematics::default_for(VehicleType::Car),
+        )
+    }
+
+    #[test]
+    fn test_accelerate_cruise_brake() {
+        // Cars accelerate at 2.5 m/s² and brake at 4.5 m/s². Reaching 10 m/s takes 4s and 20m,
+        // stopping takes 2.22s and 11.11m, leaving 68.89m to cruise.
+        let p = profile(100.0, 0.0, 10.0, 0.0, 0.0);
+        assert_approx(p.total_time().inner_seconds(), 4.0 + 6.8889 + 2.2222);
+        assert_approx(p.speed_at(Duration::ZERO).inner_meters_per_second(), 0.0);
+        assert_approx(
+            p.speed_at(Duration::seconds(2.0)).inner_meters_per_second(),
+            5.0,
+        );
+        assert_approx(
+            p.speed_at(Duration::seconds(8.0)).inner_meters_per_second(),
+            10.0,
+        );
+        assert_approx(p.end_speed().inner_meters_per_second(), 0.0);
+        assert_approx(p.percent_dist(Duration::seconds(4.0)), 0.2);
+        assert_approx(p.percent_dist(p.total_time()), 1.0);
+    }
+
+    #[test]
+    fn test_too_short_to_reach_max_speed() {
+        let p = profile(10.0, 0.0, 30.0, 0.0, 0.0);
+        assert_approx(p.peak_speed, (225.0_f64 / 7.0).sqrt());
+        assert_approx(p.cruise, 0.0);
+
+        let mut last = 0.0;
+        for i in 0..=10 {
+            let pct = p.percent_dist(p.total_time() * (i as f64 / 10.0));
+            assert!(pct >= last);
+            last = pct;
+        }
+        assert_approx(last, 1.0);
+    }
+
+    #[test]
+    fn test_unreachable_end_speed() {
+        // Accelerating the whole 10m only reaches sqrt(2 * 2.5 * 10) m/s
+        let p = profile(10.0, 0.0, 30.0, 20.0, 0.0);
+        assert_approx(p.end_speed().inner_meters_per_second(), 50.0_f64.sqrt());
+        assert_approx(p.second_change, 0.0);
+    }
+
+    #[test]
+    fn test_startup_delay() {
+        let p = profile(100.0, 0.0, 10.0, 0.0, 1.0);
+        assert_approx(
+            p.speed_at(Duration::seconds(0.5)).inner_meters_per_second(),
+            0.0,
+        );
+        assert_approx(p.percent_dist(Duration::seconds(0.5)), 0.0);
+        assert_approx(
+            p.speed_at(Duration::seconds(3.0)).inner_meters_per_second(),
+            5.0,
+        );
+        assert_approx(p.total_time().inner_seconds(), 1.0 + 4.0 + 6.8889 + 2.2222);
+    }
+}
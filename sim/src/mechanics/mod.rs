@@ -1,12 +1,20 @@
+pub use self::detectors::{
+    Actuation, Detector, DetectorBin, DetectorID, DetectorSimState, DetectorSpec, DetectorType,
+    DETECTOR_BIN,
+};
 pub(crate) use self::driving::DrivingSimState;
 pub(crate) use self::intersection::IntersectionSimState;
+pub use self::kinematics::Kinematics;
+pub(crate) use self::kinematics::SpeedProfile;
 pub(crate) use self::parking::{ParkingSim, ParkingSimState};
 pub(crate) use self::queue::Queue;
 pub(crate) use self::walking::WalkingSimState;
 
 mod car;
+mod detectors;
 mod driving;
 mod intersection;
+mod kinematics;
 mod parking;
 mod queue;
 mod walking;
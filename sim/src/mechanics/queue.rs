@@ -7,7 +7,7 @@ use geom::{Distance, Time};
 use map_model::{Map, Position, Traversable};
 
 use crate::mechanics::car::{Car, CarState};
-use crate::{CarID, VehicleType, FOLLOWING_DISTANCE};
+use crate::{CarID, IncidentID, VehicleType, FOLLOWING_DISTANCE};
 
 /// A Queue of vehicles on a single lane or turn. This is where
 /// https://a-b-street.github.io/docs/tech/trafficsim/discrete_event.html#exact-positions is
@@ -25,6 +25,9 @@ use crate::{CarID, VehicleType, FOLLOWING_DISTANCE};
 ///   position of the blockage in this queue is unknown (it depends on the target queue). The
 ///   blockage just occupies the length of the vehicle and keeps following whatever's in front of
 ///   it.
+/// - an "incident blockage" closes the lane during an incident. It occupies a fixed interval of
+///   distance near the end of the queue, and everything behind it stays stuck until the incident
+///   is over.
 /// - "active cars" are the main members of the queue -- everything except for laggy heads and
 ///   blockages.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -62,6 +65,12 @@ pub enum Queued {
         cause: CarID,
         vehicle_len: Distance,
     },
+    /// Something occupying a fixed interval of distance on the queue, until an incident ends
+    IncidentBlockage {
+        id: IncidentID,
+        front: Distance,
+        back: Distance,
+    },
 }
 
 /// The exact position of something in a `Queue` at some time
@@ -231,11 +240,16 @@ impl Queue {
                         CarState::Crossing {
                             ref time_int,
                             ref dist_int,
+                            ref profile,
                             ..
                         } => {
                             // TODO Why percent_clamp_end? We process car updates in any order, so we might
                             // calculate this before moving this car from Crossing to another state.
-                            dist_int.lerp(time_int.percent_clamp_end(now)).min(bound)
+                            let percent = match profile {
+                                Some(profile) => profile.percent_dist(now - time_int.start),
+                                None => time_int.percent_clamp_end(now),
+                            };
+                            dist_int.lerp(percent).min(bound)
                         }
                         CarState::ChangingLanes {
                             ref new_time,
@@ -245,6 +259,10 @@ impl Queue {
                             // Same as the Crossing logic
                             new_dist.lerp(new_time.percent_clamp_end(now)).min(bound)
                         }
+                        // The vehicle is really in the oncoming lane, alongside whoever they're
+                        // passing. Reserve the space they'll occupy after the maneuver, so that
+                        // the vehicle being passed doesn't catch up to them.
+                        CarState::Overtaking { ref pass_dist, .. } => pass_dist.end.min(bound),
                         CarState::Unparking { front, .. } => front,
                         CarState::Parking(front, _, _) => front,
                         CarState::IdlingAtStop(front, _) => front,
@@ -255,7 +273,8 @@ impl Queue {
                         back: front - car.vehicle.length,
                     }
                 }
-                Queued::StaticBlockage { front, back, .. } => QueueEntry {
+                Queued::StaticBlockage { front, back, .. }
+                | Queued::IncidentBlockage { front, back, .. } => QueueEntry {
                     member: queued,
                     front,
                     back,
@@ -290,6 +309,7 @@ impl Queue {
             Queued::Vehicle(car) => Some((car, previous.front)),
             Queued::StaticBlockage { .. } => None,
             Queued::DynamicBlockage { .. } => None,
+            Queued::IncidentBlockage { .. } => None,
         }
     }
 
@@ -452,7 +472,9 @@ impl Queue {
                     }
                     leader = Some(*car);
                 }
-                Queued::StaticBlockage { .. } | Queued::DynamicBlockage { .. } => {
+                Queued::StaticBlockage { .. }
+                | Queued::DynamicBlockage { .. }
+                | Queued::IncidentBlockage { .. } => {
                     leader = None;
                 }
             }
@@ -460,6 +482,30 @@ impl Queue {
         None
     }
 
+    /// Whatever's at the front of the line of vehicles directly ahead of this car: either a
+    /// blockage, or the vehicle at the front of the queue. None if the car itself is at the front.
+    pub fn head_of_line(&self, id: CarID) -> Option<&Queued> {
+        let mut head = None;
+        for queued in &self.members {
+            match queued {
+                Queued::Vehicle(car) => {
+                    if *car == id {
+                        return head;
+                    }
+                    if head.is_none() {
+                        head = Some(queued);
+                    }
+                }
+                Queued::StaticBlockage { .. }
+                | Queued::DynamicBlockage { .. }
+                | Queued::IncidentBlockage { .. } => {
+                    head = Some(queued);
+                }
+            }
+        }
+        None
+    }
+
     /// Record that a car is blocking a static portion of the queue (from front to back). Must use
     /// the index from can_block_from_driveway.
     pub fn add_static_blockage(
@@ -503,6 +549,15 @@ impl Queue {
         // We don't need to touch reserved_length -- it's still vehicle_len + FOLLOWING_DISTANCE
     }
 
+    /// Record that a car is starting to pass the vehicle immediately in front of it, using an
+    /// oncoming lane.
+    pub fn overtake_leader(&mut self, car: CarID, idx: usize) {
+        assert_eq!(self.members[idx], Queued::Vehicle(car));
+        assert!(matches!(self.members[idx - 1], Queued::Vehicle(_)));
+        self.members.swap(idx - 1, idx);
+        // The reserved_length doesn't change
+    }
+
     /// Record that a car is no longer blocking a dynamic portion of the queue.
     pub fn clear_dynamic_blockage(&mut self, caused_by: CarID, idx: usize) {
         let blockage = self.members.remove(idx).unwrap();
@@ -515,6 +570,97 @@ impl Queue {
         }
     }
 
+    /// Find where an incident blockage of some length can go, as close to the end of the queue as
+    /// possible without overlapping anything already there. Returns the index and the front of
+    /// the blockage.
+    pub fn find_room_for_incident_blockage(
+        &self,
+        len: Distance,
+        now: Time,
+        cars: &FixedMap<CarID, Car>,
+        queues: &HashMap<Traversable, Queue>,
+    ) -> Option<(usize, Distance)> {
+        let dists = self.get_car_positions(now, cars, queues);
+        for idx in 0..=dists.len() {
+            let front = if idx == 0 {
+                // Like get_idx_to_insert_car, assume the worst case for the laggy head
+                match self.laggy_head {
+                    Some(c) => self.geom_len - cars[&c].vehicle.length - FOLLOWING_DISTANCE,
+                    None => self.geom_len,
+                }
+            } else {
+                dists[idx - 1].back - FOLLOWING_DISTANCE
+            };
+            let back = front - len;
+            if back < FOLLOWING_DISTANCE {
+                return None;
+            }
+            if idx == dists.len() || back - FOLLOWING_DISTANCE >= dists[idx].front {
+                return Some((idx, front));
+            }
+        }
+        None
+    }
+
+    /// Record that an incident is blocking a static portion of the queue. Must use the result of
+    /// find_room_for_incident_blockage.
+    pub fn add_incident_blockage(
+        &mut self,
+        id: IncidentID,
+        front: Distance,
+        len: Distance,
+        idx: usize,
+    ) {
+        self.members.insert(
+            idx,
+            Queued::IncidentBlockage {
+                id,
+                front,
+                back: front - len,
+            },
+        );
+        self.reserved_length += len + FOLLOWING_DISTANCE;
+    }
+
+    /// Record that an incident is no longer blocking the queue.
+    pub fn clear_incident_blockage(&mut self, id: IncidentID, idx: usize) {
+        match self.members.remove(idx).unwrap() {
+            Queued::IncidentBlockage {
+                id: blocked_by,
+                front,
+                back,
+            } => {
+                assert_eq!(id, blocked_by);
+                self.reserved_length -= front - back + FOLLOWING_DISTANCE;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Is an incident closing this queue?
+    pub fn is_closed(&self) -> bool {
+        self.members
+            .iter()
+            .any(|x| matches!(x, Queued::IncidentBlockage { .. }))
+    }
+
+    /// If this car is somewhere behind an incident blockage, return the incident.
+    pub fn incident_ahead(&self, car: CarID) -> Option<IncidentID> {
+        let mut incident = None;
+        for queued in &self.members {
+            match queued {
+                Queued::Vehicle(c) if *c == car => {
+                    return incident;
+                }
+                Queued::IncidentBlockage { id, .. } => {
+                    incident = Some(*id);
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
     /// True if a static blockage can be inserted into the queue without anything already there
     /// intersecting it. Returns the index if so. The position represents the front of the
     /// blockage.
@@ -540,6 +686,7 @@ impl Queue {
                 Queued::Vehicle(c) => Some(*c),
                 Queued::StaticBlockage { .. } => None,
                 Queued::DynamicBlockage { .. } => None,
+                Queued::IncidentBlockage { .. } => None,
             })
             .collect()
     }
@@ -599,6 +746,16 @@ fn dump_cars(dists: &[QueueEntry], cars: &FixedMap<CarID, Car>, id: Traversable,
                         new_dist.start, new_dist.end, new_time.start, new_time.end
                     );
                 }
+                CarState::Overtaking {
+                    ref pass_time,
+                    ref pass_dist,
+                    ..
+                } => {
+                    println!(
+                        "  Overtaking {} .. {} during {} .. {}",
+                        pass_dist.start, pass_dist.end, pass_time.start, pass_time.end
+                    );
+                }
                 CarState::Queued { .. } => {
                     println!("  Queued currently");
                 }
@@ -621,6 +778,9 @@ fn dump_cars(dists: &[QueueEntry], cars: &FixedMap<CarID, Car>, id: Traversable,
             Queued::DynamicBlockage { cause, vehicle_len } => {
                 println!("  Dynamic blockage of length {} by {}", vehicle_len, cause);
             }
+            Queued::IncidentBlockage { id, .. } => {
+                println!("  Blockage by {}", id);
+            }
         }
     }
     println!();
@@ -233,8 +233,14 @@ impl WalkingSimState {
                         );
                     }
 
+                    // Push the button for the next crosswalk, if needed
+                    if let PathStep::Turn(t) | PathStep::ContraflowTurn(t) = ped.path.next_step() {
+                        ctx.intersections.register_pedestrian_call(now, t, ctx.map);
+                    }
+
                     let dist = dist_int.end;
                     if ped.maybe_transition(
+                        now,
                         now,
                         ctx.map,
                         ctx.intersections,
@@ -253,6 +259,7 @@ impl WalkingSimState {
             PedState::WaitingToTurn(_, blocked_since) => {
                 if ped.maybe_transition(
                     now,
+                    blocked_since,
                     ctx.map,
                     ctx.intersections,
                     &mut self.peds_per_traversable,
@@ -872,10 +879,12 @@ impl Pedestrian {
         }
     }
 
-    // True if we successfully continued to the next step of our path
+    // True if we successfully continued to the next step of our path. `waiting_since` is when the
+    // pedestrian reached the end of the current step.
     fn maybe_transition(
         &mut self,
         now: Time,
+        waiting_since: Time,
         map: &Map,
         intersections: &mut IntersectionSimState,
         peds_per_traversable: &mut MultiMap<Traversable, PedestrianID>,
@@ -898,6 +907,15 @@ impl Pedestrian {
             ) {
                 return false;
             }
+            if map.get_t(t).turn_type.pedestrian_crossing()
+                && map.get_i(t.parent).is_traffic_signal()
+            {
+                events.push(Event::PedestrianStartsCrosswalk(
+                    self.id,
+                    t,
+                    now - waiting_since,
+                ));
+            }
         }
 
         peds_per_traversable.remove(self.path.current_step().as_traversable(), self.id);
@@ -110,6 +110,7 @@ impl TrafficRecorder {
                 })
                 .collect::<Vec<_>>(),
             only_seed_buses: None,
+            day_types: Vec::new(),
         }
         .save();
     }
@@ -3,12 +3,13 @@
 
 use std::collections::HashMap;
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use geom::Distance;
 use map_model::{
     BuildingID, IntersectionID, LaneID, Map, Path, PathConstraints, PathRequest, PathStep,
-    Position, Traversable, Turn, TurnID,
+    PathfinderCache, Position, RoutingParams, Traversable, Turn, TurnID,
 };
 
 use crate::mechanics::Queue;
@@ -355,10 +356,13 @@ impl Router {
 
             let compute_cost = |turn1: &Turn, lane: LaneID| {
                 let (lt, lc, mut slow_lane) = turn1.penalty(constraints, map);
-                let (vehicles, mut bike) = queues[&Traversable::Lane(lane)].target_lane_penalty();
+                let queue = &queues[&Traversable::Lane(lane)];
+                let (vehicles, mut bike) = queue.target_lane_penalty();
+                let closed = usize::from(queue.is_closed());
 
                 // The magic happens here. We have different penalties:
                 //
+                // 0) Is the lane closed by an incident? Always avoid it if possible.
                 // 1) Are we headed towards a general purpose lane instead of a dedicated bike/bus
                 //    lane?
                 // 2) Are there any bikes in the target lane? This ONLY matters if we're a car. If
@@ -378,7 +382,7 @@ impl Router {
                     slow_lane = 0;
                 }
 
-                (lt, bike, slow_lane, vehicles + lc)
+                (closed, lt, bike, slow_lane, vehicles + lc)
             };
 
             // Look for other candidates, and assign a cost to each.
@@ -483,6 +487,49 @@ impl Router {
         self.path.modify_step(1, PathStep::Turn(turn), map);
     }
 
+    /// Re-plan the rest of the path with different routing params, keeping the same destination.
+    /// The current lane (or if the vehicle is in the middle of a turn, the lane after it) stays
+    /// the same. Returns true if the path changed.
+    pub fn replan(
+        &mut self,
+        params: &RoutingParams,
+        cache: &mut PathfinderCache,
+        map: &Map,
+    ) -> Result<bool> {
+        if self.is_parking() {
+            bail!("{} is already looking for parking", self.owner);
+        }
+        let steps = self.path.get_steps();
+        let idx = match steps[0] {
+            PathStep::Lane(_) => 0,
+            _ => 1,
+        };
+        if idx + 1 >= steps.len() {
+            return Ok(false);
+        }
+        let start_lane = steps[idx].as_lane();
+        let end_lane = self.path.last_step().as_lane();
+        let end = if end_lane == self.path.get_req().end.lane() {
+            self.path.get_req().end
+        } else {
+            Position::new(end_lane, map.get_l(end_lane).length())
+        };
+        let req = PathRequest::vehicle(
+            Position::new(start_lane, map.get_l(start_lane).length()),
+            end,
+            self.owner.vehicle_type.to_constraints(),
+        );
+        let new_path = cache
+            .pathfind_with_params(map, req.clone(), params.clone())
+            .ok_or_else(|| anyhow!("can't fulfill {}", req))?
+            .into_v1(map)?;
+        if new_path.get_steps().iter().eq(steps.iter().skip(idx)) {
+            return Ok(false);
+        }
+        self.path.splice(idx, new_path, map)?;
+        Ok(true)
+    }
+
     pub fn is_parking(&self) -> bool {
         match self.goal {
             Goal::ParkNearBuilding {
@@ -8,7 +8,8 @@ use geom::{Duration, Histogram, Time};
 use map_model::{IntersectionID, TransitRouteID};
 
 use crate::{
-    pandemic, AgentID, CarID, CreateCar, CreatePedestrian, PedestrianID, StartTripArgs, TripID,
+    pandemic, AgentID, CarID, CreateCar, CreatePedestrian, IncidentID, PedestrianID, StartTripArgs,
+    TripID,
 };
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -26,6 +27,16 @@ pub(crate) enum Command {
     Pandemic(pandemic::Cmd),
     /// The Time is redundant, just used to dedupe commands
     StartBus(TransitRouteID, Time),
+    /// Record the queue on every lane for Analytics, then repeat after this long
+    SampleQueues(Duration),
+    /// Record the position of every agent for the TrajectoryRecorder, then repeat after this long
+    SampleTrajectories(Duration),
+    /// Also retried when vehicles are in the way of blocking some lanes
+    StartIncident(IncidentID),
+    EndIncident(IncidentID),
+    /// Refresh live travel times and let vehicles with a navigation app re-plan, then repeat
+    /// after this long
+    LiveReroute(Duration),
 }
 
 impl Command {
@@ -49,6 +60,11 @@ impl Command {
             Command::Callback(_) => CommandType::Callback,
             Command::Pandemic(ref p) => CommandType::Pandemic(p.clone()),
             Command::StartBus(r, t) => CommandType::StartBus(*r, *t),
+            Command::SampleQueues(_) => CommandType::SampleQueues,
+            Command::SampleTrajectories(_) => CommandType::SampleTrajectories,
+            Command::StartIncident(id) => CommandType::StartIncident(*id),
+            Command::EndIncident(id) => CommandType::EndIncident(*id),
+            Command::LiveReroute(_) => CommandType::LiveReroute,
         }
     }
 
@@ -64,6 +80,10 @@ impl Command {
             Command::Callback(_) => SimpleCommandType::Callback,
             Command::Pandemic(_) => SimpleCommandType::Pandemic,
             Command::StartBus(_, _) => SimpleCommandType::StartBus,
+            Command::SampleQueues(_) => SimpleCommandType::SampleQueues,
+            Command::SampleTrajectories(_) => SimpleCommandType::SampleTrajectories,
+            Command::StartIncident(_) | Command::EndIncident(_) => SimpleCommandType::Incident,
+            Command::LiveReroute(_) => SimpleCommandType::LiveReroute,
         }
     }
 }
@@ -80,6 +100,11 @@ enum CommandType {
     Callback,
     Pandemic(pandemic::Cmd),
     StartBus(TransitRouteID, Time),
+    SampleQueues,
+    SampleTrajectories,
+    StartIncident(IncidentID),
+    EndIncident(IncidentID),
+    LiveReroute,
 }
 
 /// A more compressed form of CommandType, just used for keeping stats on event processing.
@@ -93,6 +118,10 @@ enum SimpleCommandType {
     Callback,
     Pandemic,
     StartBus,
+    SampleQueues,
+    SampleTrajectories,
+    Incident,
+    LiveReroute,
 }
 
 /// The priority queue driving the discrete event simulation. Different pieces of the simulation
@@ -22,11 +22,13 @@ pub use self::queries::{AgentProperties, DelayCause};
 // TODO Super weird for both of these to wind up here
 pub use self::scenario::{count_parked_cars_per_bldg, rand_dist};
 use crate::{
-    AgentID, AlertLocation, Analytics, CarID, Command, CreateCar, DrivingSimState, Event,
-    IntersectionSimState, PandemicModel, ParkedCar, ParkingSim, ParkingSimState, ParkingSpot,
-    Person, PersonID, Router, Scheduler, SidewalkPOI, SidewalkSpot, StartTripArgs, TrafficRecorder,
-    TransitSimState, TripID, TripInfo, TripManager, TripPhaseType, Vehicle, VehicleSpec,
-    VehicleType, WalkingSimState, BUS_LENGTH, LIGHT_RAIL_LENGTH, MIN_CAR_LENGTH,
+    AgentID, AlertLocation, Analytics, CarID, Command, CreateCar, DetectorID, DetectorSpec,
+    DrivingSimState, EmissionsModel, Event, Incident, IncidentEffect, IncidentID, IncidentLocation,
+    IncidentSimState, IntersectionSimState, Kinematics, PandemicModel, ParkedCar, ParkingSim,
+    ParkingSimState, ParkingSpot, Person, PersonID, Router, Scheduler, SidewalkPOI, SidewalkSpot,
+    StartTripArgs, TrafficRecorder, TrajectoryRecorder, TransitSimState, TripID, TripInfo,
+    TripManager, TripPhaseType, Vehicle, VehicleSpec, VehicleType, WalkingSimState, BUS_LENGTH,
+    LIGHT_RAIL_LENGTH, MIN_CAR_LENGTH,
 };
 
 mod queries;
@@ -34,6 +36,7 @@ mod scenario;
 
 // TODO Do something else.
 const BLIND_RETRY_TO_SPAWN: Duration = Duration::const_seconds(5.0);
+const BLIND_RETRY_TO_BLOCK_LANE: Duration = Duration::const_seconds(1.0);
 
 /// The Sim ties together all the pieces of the simulation. Its main property is the current time.
 #[derive(Serialize, Deserialize, Clone)]
@@ -44,6 +47,7 @@ pub struct Sim {
     intersections: IntersectionSimState,
     transit: TransitSimState,
     trips: TripManager,
+    incidents: IncidentSimState,
     #[serde(skip_serializing, skip_deserializing)]
     pandemic: Option<PandemicModel>,
     scheduler: Scheduler,
@@ -62,6 +66,8 @@ pub struct Sim {
     // This is created interactively, and there's no reason to preserve one for savestates.
     #[serde(skip_serializing, skip_deserializing)]
     recorder: Option<TrafficRecorder>,
+    #[serde(skip_serializing, skip_deserializing)]
+    trajectories: Option<TrajectoryRecorder>,
 
     #[serde(skip_serializing, skip_deserializing)]
     alerts: AlertHandler,
@@ -127,6 +133,49 @@ pub struct SimOptions {
     /// quickly.
     #[structopt(long)]
     pub skip_analytics: bool,
+    /// How often to record the queue on every lane for analytics, in seconds or as hh:mm:ss. This
+    /// grows the analytics a lot, so it's off (0) by default.
+    #[structopt(long, parse(try_from_str = Duration::parse), default_value = "0")]
+    pub queue_sample_interval: Duration,
+    /// Place virtual loop detectors from this JSON file, containing a list of `DetectorSpec`s.
+    #[structopt(long)]
+    pub detectors: Option<String>,
+    /// Estimate vehicle emissions using this JSON file, containing an `EmissionsModel`. If this
+    /// isn't specified, a default model is used.
+    #[structopt(long)]
+    pub emissions_model: Option<String>,
+    /// Model vehicles speeding up and slowing down according to their `Kinematics`, instead of
+    /// instantly changing speed. Vehicles starting from the stop bar of an intersection also have
+    /// some startup lost time.
+    #[structopt(long)]
+    pub acceleration: bool,
+    /// Let a vehicle stuck behind a slower one on a road without another lane in the same
+    /// direction pass by briefly using an oncoming lane, when there's a big enough gap in oncoming
+    /// traffic.
+    #[structopt(long)]
+    pub overtake_into_oncoming: bool,
+    /// Let a vehicle stuck behind something stopped in the middle of a road, like a bus at a stop
+    /// or an incident, change to another lane going the same direction, when there's a big enough
+    /// gap there.
+    #[structopt(long)]
+    pub change_lanes_around_blockages: bool,
+    /// Schedule incidents closing or slowing down lanes, roads, and intersections from this JSON
+    /// file, containing a list of `Incident`s.
+    #[structopt(long)]
+    pub incidents: Option<String>,
+    /// What fraction of cars have a navigation app, between 0 and 1. These drivers re-plan the
+    /// rest of their route while driving, using live travel times from the current queues on
+    /// every road. By default, nobody does.
+    #[structopt(long, default_value = "0")]
+    pub navigation_app_share: f64,
+    /// How often drivers with a navigation app re-plan, in seconds or as hh:mm:ss. Live travel
+    /// times are also refreshed this often.
+    #[structopt(long, parse(try_from_str = Duration::parse), default_value = "300")]
+    pub reroute_interval: Duration,
+    /// Drivers with a navigation app also re-plan at the next intersection, once they've been
+    /// delayed this long since the last time. In seconds or as hh:mm:ss.
+    #[structopt(long, parse(try_from_str = Duration::parse), default_value = "120")]
+    pub reroute_delay_threshold: Duration,
 }
 
 impl SimOptions {
@@ -143,6 +192,16 @@ impl SimOptions {
             infinite_parking: false,
             disable_turn_conflicts: false,
             skip_analytics: false,
+            queue_sample_interval: Duration::ZERO,
+            detectors: None,
+            emissions_model: None,
+            acceleration: false,
+            overtake_into_oncoming: false,
+            change_lanes_around_blockages: false,
+            incidents: None,
+            navigation_app_share: 0.0,
+            reroute_interval: Duration::minutes(5),
+            reroute_delay_threshold: Duration::minutes(2),
         }
     }
 }
@@ -166,6 +225,8 @@ pub enum AlertHandler {
     Block,
     /// Don't do anything
     Silence,
+    /// Keep the alert until clear_alerts() is called, without printing or pausing
+    Collect,
 }
 
 impl Default for AlertHandler {
@@ -179,7 +240,8 @@ fn parse_alert_handler(x: &str) -> Result<AlertHandler> {
         "print" => Ok(AlertHandler::Print),
         "block" => Ok(AlertHandler::Block),
         "silence" => Ok(AlertHandler::Silence),
-        _ => bail!("Bad --alerts={}. Must be print|block|silence", x),
+        "collect" => Ok(AlertHandler::Collect),
+        _ => bail!("Bad --alerts={}. Must be print|block|silence|collect", x),
     }
 }
 
@@ -206,13 +268,67 @@ impl Sim {
             opts.allow_block_the_box = true;
         }
 
+        if !opts.skip_analytics && opts.queue_sample_interval > Duration::ZERO {
+            scheduler.push(
+                Time::START_OF_DAY + opts.queue_sample_interval,
+                Command::SampleQueues(opts.queue_sample_interval),
+            );
+        }
+
+        if !(0.0..=1.0).contains(&opts.navigation_app_share) {
+            panic!(
+                "--navigation_app_share={} must be between 0 and 1",
+                opts.navigation_app_share
+            );
+        }
+        if opts.navigation_app_share > 0.0 && opts.reroute_interval > Duration::ZERO {
+            scheduler.push(
+                Time::START_OF_DAY + opts.reroute_interval,
+                Command::LiveReroute(opts.reroute_interval),
+            );
+        }
+
+        let mut driving = DrivingSimState::new(map, &opts);
+        if let Some(ref path) = opts.detectors {
+            let specs: Vec<DetectorSpec> = abstio::read_json(path.clone(), &mut timer);
+            for spec in specs {
+                if let Err(err) = driving.add_detector(spec, map) {
+                    panic!("Bad detector in {}: {}", path, err);
+                }
+            }
+        }
+        if let Some(ref path) = opts.emissions_model {
+            let model: EmissionsModel = abstio::read_json(path.clone(), &mut timer);
+            if let Err(err) = model.check() {
+                panic!("Bad emissions model in {}: {}", path, err);
+            }
+            driving.set_emissions_model(model);
+        }
+        let mut incidents = IncidentSimState::new();
+        if let Some(ref path) = opts.incidents {
+            let list: Vec<Incident> = abstio::read_json(path.clone(), &mut timer);
+            for incident in list {
+                let (start, end) = (incident.start, incident.end);
+                match incidents.add(incident, map) {
+                    Ok(id) => {
+                        scheduler.push(start, Command::StartIncident(id));
+                        scheduler.push(end, Command::EndIncident(id));
+                    }
+                    Err(err) => {
+                        panic!("Bad incident in {}: {}", path, err);
+                    }
+                }
+            }
+        }
+
         Sim {
-            driving: DrivingSimState::new(map, &opts),
+            driving,
             parking: ParkingSimState::new(map, opts.infinite_parking, &mut timer),
             walking: WalkingSimState::new(),
             intersections: IntersectionSimState::new(map, &mut scheduler, &opts),
             transit: TransitSimState::new(map),
             trips: TripManager::new(),
+            incidents,
             pandemic: opts.enable_pandemic_model.map(PandemicModel::new),
             scheduler,
             time: Time::START_OF_DAY,
@@ -226,6 +342,7 @@ impl Sim {
 
             analytics: Analytics::new(!opts.skip_analytics),
             recorder: None,
+            trajectories: None,
         }
     }
 
@@ -287,6 +404,7 @@ impl Sim {
             vehicle_type: VehicleType::Car,
             length: MIN_CAR_LENGTH,
             max_speed: None,
+            kinematics: Kinematics::default_for(VehicleType::Car),
         };
         let driving_lane = map.find_driving_lane_near_building(b);
 
@@ -349,6 +467,7 @@ impl Sim {
             vehicle_type,
             length,
             max_speed: None,
+            kinematics: Kinematics::default_for(vehicle_type),
         }
         .make(
             CarID {
@@ -529,10 +648,10 @@ impl Sim {
                                 trip,
                                 person,
                                 Some(req),
-                                if id.vehicle_type == VehicleType::Car {
-                                    TripPhaseType::Driving
-                                } else {
+                                if id.vehicle_type == VehicleType::Bike {
                                     TripPhaseType::Biking
+                                } else {
+                                    TripPhaseType::Driving
                                 },
                             ));
                         }
@@ -615,8 +734,14 @@ impl Sim {
                 );
             }
             Command::UpdateIntersection(i) => {
-                self.intersections
-                    .update_intersection(self.time, i, map, &mut self.scheduler);
+                self.intersections.update_intersection(
+                    self.time,
+                    i,
+                    map,
+                    &mut self.scheduler,
+                    self.driving.get_queues(),
+                    self.driving.get_detectors(),
+                );
             }
             Command::Callback(frequency) => {
                 self.scheduler
@@ -634,6 +759,62 @@ impl Sim {
             Command::StartBus(r, _) => {
                 self.start_bus(map.get_tr(r), map);
             }
+            Command::SampleQueues(frequency) => {
+                self.scheduler
+                    .push(self.time + frequency, Command::SampleQueues(frequency));
+                let samples = self.driving.sample_lane_queues(self.time);
+                self.analytics.record_lane_queues(self.time, samples);
+            }
+            Command::SampleTrajectories(frequency) => {
+                // Savestates don't include the recorder, so stop sampling after loading one
+                if self.trajectories.is_some() {
+                    self.scheduler.push(
+                        self.time + frequency,
+                        Command::SampleTrajectories(frequency),
+                    );
+                    self.sample_all_trajectories(map);
+                }
+            }
+            Command::StartIncident(id) => {
+                let incident = self.incidents.get(id).clone();
+                let lanes = if let Some(lanes) = self.incidents.take_pending_lanes(id) {
+                    // Retrying lanes that vehicles were in the way of last time
+                    lanes
+                } else {
+                    if let (IncidentLocation::Intersection(i), IncidentEffect::Closure) =
+                        (incident.location, incident.effect)
+                    {
+                        ctx.intersections.close(i, id);
+                    }
+                    self.driving
+                        .start_incident(id, &incident, self.time, &mut ctx);
+                    incident.blocked_lanes(map)
+                };
+                let pending = self.driving.block_lanes(id, lanes, self.time);
+                if !pending.is_empty() {
+                    ctx.scheduler.push(
+                        self.time + BLIND_RETRY_TO_BLOCK_LANE,
+                        Command::StartIncident(id),
+                    );
+                }
+                self.incidents.set_pending_lanes(id, pending);
+            }
+            Command::LiveReroute(frequency) => {
+                ctx.scheduler
+                    .push(self.time + frequency, Command::LiveReroute(frequency));
+                self.driving.live_reroute(self.time, &mut ctx);
+            }
+            Command::EndIncident(id) => {
+                let incident = self.incidents.get(id).clone();
+                ctx.scheduler.cancel(Command::StartIncident(id));
+                self.incidents.set_pending_lanes(id, Vec::new());
+                if let IncidentLocation::Intersection(i) = incident.location {
+                    ctx.intersections
+                        .reopen(self.time, i, id, ctx.scheduler, map);
+                }
+                self.driving
+                    .end_incident(id, &incident, self.time, &mut ctx);
+            }
         }
 
         // Record events at precisely the time they occur.
@@ -649,7 +830,13 @@ impl Sim {
         events.extend(self.walking.collect_events());
         events.extend(self.intersections.collect_events());
         events.extend(self.parking.collect_events());
+        let mut entered = Vec::new();
         for ev in events {
+            if let Event::AgentEntersTraversable(agent, _, _, _) = ev {
+                if self.trajectories.is_some() {
+                    entered.push(agent);
+                }
+            }
             if let Some(ref mut m) = self.pandemic {
                 m.handle_event(self.time, &ev, &mut self.scheduler);
             }
@@ -659,6 +846,9 @@ impl Sim {
 
             self.analytics.event(ev, self.time, map);
         }
+        for agent in entered {
+            self.sample_trajectory(agent, map);
+        }
     }
 
     pub fn timed_step(
@@ -693,6 +883,7 @@ impl Sim {
                     AlertHandler::Silence => {
                         self.analytics.alerts.clear();
                     }
+                    AlertHandler::Collect => {}
                 }
             }
             if Duration::realtime_elapsed(last_update) >= Duration::seconds(1.0) {
@@ -747,6 +938,7 @@ impl Sim {
                     AlertHandler::Silence => {
                         self.analytics.alerts.clear();
                     }
+                    AlertHandler::Collect => {}
                 }
             }
         }
@@ -959,6 +1151,65 @@ impl Sim {
     }
 }
 
+// Controlling traffic signals
+impl Sim {
+    /// Immediately switch a traffic signal to a different stage, unless pedestrians still need
+    /// time to cross. Returns the time when the new stage will begin.
+    pub fn set_traffic_signal_stage(
+        &mut self,
+        map: &Map,
+        i: IntersectionID,
+        stage: usize,
+    ) -> Result<Time> {
+        let start =
+            self.intersections
+                .set_signal_stage(self.time, i, stage, map, &mut self.scheduler)?;
+        self.dispatch_events(Vec::new(), map);
+        Ok(start)
+    }
+
+    /// Make the current stage of a traffic signal last longer.
+    pub fn extend_traffic_signal_stage(&mut self, i: IntersectionID, dt: Duration) -> Result<()> {
+        self.intersections
+            .extend_signal_stage(self.time, i, dt, &mut self.scheduler)
+    }
+
+    /// An externally controlled traffic signal holds each stage indefinitely, only advancing when
+    /// `set_traffic_signal_stage` is called. This is useful for plugging in other signal
+    /// controllers. Turning this off resumes the signal's normal timing.
+    pub fn set_traffic_signal_external_control(
+        &mut self,
+        i: IntersectionID,
+        enabled: bool,
+    ) -> Result<()> {
+        self.intersections
+            .set_signal_external_control(self.time, i, enabled, &mut self.scheduler)
+    }
+
+    /// Place a virtual loop detector. It records vehicles passing over it from now on, and
+    /// actuated traffic signals use stop-bar and advance detectors to measure demand.
+    pub fn add_detector(&mut self, spec: DetectorSpec, map: &Map) -> Result<DetectorID> {
+        self.driving.add_detector(spec, map)
+    }
+
+    /// Schedule an incident to start and end later. It can't start in the past or overlap another
+    /// incident in the same place.
+    pub fn schedule_incident(&mut self, incident: Incident, map: &Map) -> Result<IncidentID> {
+        if incident.start < self.time {
+            bail!(
+                "It's already {}, so an incident can't start at {}",
+                self.time,
+                incident.start
+            );
+        }
+        let (start, end) = (incident.start, incident.end);
+        let id = self.incidents.add(incident, map)?;
+        self.scheduler.push(start, Command::StartIncident(id));
+        self.scheduler.push(end, Command::EndIncident(id));
+        Ok(id)
+    }
+}
+
 // Invasive debugging
 impl Sim {
     pub fn delete_car(&mut self, id: CarID, map: &Map) {
@@ -1023,6 +1274,111 @@ impl Sim {
     pub fn save_recorded_traffic(&mut self, map: &Map) {
         self.recorder.take().unwrap().save(map);
     }
+
+    /// Start recording the trajectory of every vehicle and pedestrian. Agents are sampled when
+    /// they enter a lane or turn, and everybody is sampled every `interval`.
+    pub fn record_trajectories(&mut self, interval: Duration) {
+        assert!(self.trajectories.is_none());
+        assert!(interval > Duration::ZERO);
+        self.trajectories = Some(TrajectoryRecorder::new(interval));
+        self.scheduler
+            .push(self.time + interval, Command::SampleTrajectories(interval));
+    }
+
+    pub fn num_trajectory_points(&self) -> Option<usize> {
+        Some(self.trajectories.as_ref()?.num_points())
+    }
+
+    /// Stop recording trajectories, and write them as CSV and SUMO FCD XML. Returns the paths of
+    /// both files.
+    pub fn save_trajectories(&mut self, map: &Map) -> Result<(String, String)> {
+        let recorder = self.trajectories.take().unwrap();
+        self.scheduler
+            .cancel(Command::SampleTrajectories(recorder.interval));
+
+        let path1 = abstio::write_file(
+            format!(
+                "trajectories_{}_{}.csv",
+                map.get_name().as_filename(),
+                self.time.as_filename()
+            ),
+            recorder.export_csv(),
+        )?;
+        let path2 = abstio::write_file(
+            format!(
+                "fcd_{}_{}.xml",
+                map.get_name().as_filename(),
+                self.time.as_filename()
+            ),
+            recorder.export_sumo_fcd(),
+        )?;
+        Ok((path1, path2))
+    }
+
+    fn sample_trajectory(&mut self, agent: AgentID, map: &Map) {
+        let (pt, facing, on) = match agent {
+            AgentID::Car(c) => {
+                match self
+                    .driving
+                    .get_single_draw_car(c, self.time, map, &self.transit)
+                {
+                    Some(draw) => (draw.body.last_pt(), draw.body.last_line().angle(), draw.on),
+                    None => {
+                        return;
+                    }
+                }
+            }
+            AgentID::Pedestrian(p) => match self.walking.get_draw_ped(p, self.time, map) {
+                Some(draw) => (draw.pos, draw.facing, draw.on),
+                None => {
+                    return;
+                }
+            },
+            AgentID::BusPassenger(_, _) => {
+                return;
+            }
+        };
+        let dist_crossed = self.agent_properties(map, agent).dist_crossed;
+        self.trajectories.as_mut().unwrap().record(
+            self.time,
+            agent,
+            pt,
+            facing,
+            on,
+            dist_crossed,
+            map,
+        );
+    }
+
+    fn sample_all_trajectories(&mut self, map: &Map) {
+        let mut samples = Vec::new();
+        for draw in self
+            .driving
+            .get_all_draw_cars(self.time, map, &self.transit)
+        {
+            samples.push((
+                AgentID::Car(draw.id),
+                draw.body.last_pt(),
+                draw.body.last_line().angle(),
+                draw.on,
+            ));
+        }
+        for draw in self.walking.get_all_draw_peds(self.time, map) {
+            samples.push((AgentID::Pedestrian(draw.id), draw.pos, draw.facing, draw.on));
+        }
+        for (agent, pt, facing, on) in samples {
+            let dist_crossed = self.agent_properties(map, agent).dist_crossed;
+            self.trajectories.as_mut().unwrap().record(
+                self.time,
+                agent,
+                pt,
+                facing,
+                on,
+                dist_crossed,
+                map,
+            );
+        }
+    }
 }
 
 // Managing highlighted people
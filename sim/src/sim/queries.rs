@@ -7,16 +7,17 @@ use std::collections::{BTreeMap, BTreeSet};
 use abstutil::Counter;
 use geom::{Distance, Duration, PolyLine, Pt2D, Time};
 use map_model::{
-    BuildingID, IntersectionID, Lane, LaneID, Map, Path, Position, RoadID, TransitRouteID,
-    TransitStopID, Traversable, TurnID,
+    BuildingID, IntersectionID, Lane, LaneID, Map, MovementID, Path, Position, RoadID,
+    TransitRouteID, TransitStopID, Traversable, TurnID,
 };
 use synthpop::{OrigPersonID, Scenario, TripMode};
 
 use crate::analytics::SlidingWindow;
 use crate::{
-    AgentID, AgentType, Analytics, CarID, CommutersVehiclesCounts, DrawCarInput, DrawPedCrowdInput,
-    DrawPedestrianInput, PandemicModel, ParkedCar, ParkingSim, PedestrianID, Person, PersonID,
-    PersonState, Sim, TripEndpoint, TripID, TripInfo, TripResult, UnzoomedAgent, VehicleType,
+    AgentID, AgentType, Analytics, CarID, CommutersVehiclesCounts, DetectorSimState, DrawCarInput,
+    DrawPedCrowdInput, DrawPedestrianInput, Incident, PandemicModel, ParkedCar, ParkingSim,
+    PedestrianID, Person, PersonID, PersonState, Sim, TripEndpoint, TripID, TripInfo, TripResult,
+    UnzoomedAgent, VehicleType,
 };
 
 // TODO Many of these just delegate to an inner piece. This is unorganized and hard to maintain.
@@ -204,6 +205,7 @@ impl Sim {
             VehicleType::Bike,
             VehicleType::Bus,
             VehicleType::Train,
+            VehicleType::Emergency,
         ] {
             let id = CarID {
                 id: idx,
@@ -214,13 +216,15 @@ impl Sim {
             }
         }
 
-        let id = CarID {
-            id: idx,
-            vehicle_type: VehicleType::Car,
-        };
-        // Only cars can be parked.
-        if self.parking.lookup_parked_car(id).is_some() {
-            return Some(id);
+        // Only cars and emergency vehicles can be parked.
+        for vehicle_type in [VehicleType::Car, VehicleType::Emergency] {
+            let id = CarID {
+                id: idx,
+                vehicle_type,
+            };
+            if self.parking.lookup_parked_car(id).is_some() {
+                return Some(id);
+            }
         }
 
         None
@@ -283,6 +287,32 @@ impl Sim {
         self.intersections.get_waiting_agents(id)
     }
 
+    /// For every lane, count how many vehicles are stopped, waiting to move forward or start a
+    /// turn. Lanes without anybody waiting are omitted.
+    pub fn get_lane_queue_lengths(&self) -> BTreeMap<LaneID, usize> {
+        self.driving.get_lane_queue_lengths()
+    }
+
+    /// For one traffic signal, count how many agents are waiting to perform each movement. For
+    /// vehicles, this includes everybody stopped in line, not just the first one at the
+    /// intersection. Movements with nobody waiting are omitted.
+    pub fn get_queued_per_movement(
+        &self,
+        map: &Map,
+        i: IntersectionID,
+    ) -> BTreeMap<MovementID, usize> {
+        let mut counts = BTreeMap::new();
+        self.driving.count_queued_per_movement(map, i, &mut counts);
+        for (agent, turn, _) in self.intersections.get_waiting_agents(i) {
+            if agent.is_pedestrian() {
+                if let Some((m, _)) = map.get_movement_for_traffic_signal(turn) {
+                    *counts.entry(m).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+
     /// For every agent that's currently not moving, figure out how long they've been waiting and
     /// why they're blocked.
     pub fn get_blocked_by_graph(&self, map: &Map) -> BTreeMap<AgentID, (Duration, DelayCause)> {
@@ -313,6 +343,15 @@ impl Sim {
         &self.analytics
     }
 
+    pub fn get_detectors(&self) -> &DetectorSimState {
+        self.driving.get_detectors()
+    }
+
+    /// Every incident scheduled so far, in order of `IncidentID`
+    pub fn get_incidents(&self) -> &Vec<Incident> {
+        self.incidents.all_incidents()
+    }
+
     /// For intersections with an agent waiting beyond some threshold, return when they started
     /// waiting. Sorted by earliest waiting (likely the root cause of gridlock).
     pub fn delayed_intersections(&self, threshold: Duration) -> Vec<(IntersectionID, Time)> {
@@ -343,6 +382,16 @@ impl Sim {
             .current_stage_and_remaining_time(self.time, i)
     }
 
+    /// Returns (when the current stage started, how many times the current variable stage has been
+    /// extended).
+    pub fn current_stage_start_and_extensions(&self, i: IntersectionID) -> (Time, usize) {
+        self.intersections.current_stage_start_and_extensions(i)
+    }
+
+    pub fn is_traffic_signal_externally_controlled(&self, i: IntersectionID) -> bool {
+        self.intersections.is_signal_externally_controlled(i)
+    }
+
     // TODO This is an awkward copy of raw_throughput
     // TODO And it does NOT count buses/trains spawning
     pub fn all_arrivals_at_border(
@@ -8,11 +8,11 @@ use abstutil::{prettyprint_usize, Counter, Timer};
 use geom::{Distance, Speed};
 use map_model::{BuildingID, Map, OffstreetParking, RoadID};
 use synthpop::make::fork_rng;
-use synthpop::{PersonSpec, Scenario, TripEndpoint, TripMode};
+use synthpop::{PersonSpec, Scenario, TripEndpoint, TripMode, TripPurpose};
 
 use crate::{
-    ParkingSpot, Sim, StartTripArgs, TripInfo, Vehicle, VehicleSpec, VehicleType, BIKE_LENGTH,
-    MAX_CAR_LENGTH, MIN_CAR_LENGTH,
+    Kinematics, ParkingSpot, Sim, StartTripArgs, TripInfo, Vehicle, VehicleSpec, VehicleType,
+    BIKE_LENGTH, MAX_CAR_LENGTH, MIN_CAR_LENGTH,
 };
 
 impl Sim {
@@ -40,6 +40,9 @@ impl Sim {
         self.set_run_name(scenario.scenario_name.clone());
 
         timer.start(format!("Instantiating {}", scenario.scenario_name));
+        if !scenario.day_types.is_empty() {
+            self.analytics.day_types = scenario.day_types.clone();
+        }
 
         if let Some(ref routes) = scenario.only_seed_buses {
             for route in map.all_transit_routes() {
@@ -85,6 +88,7 @@ impl Sim {
                         } else {
                             None
                         },
+                        day: trip.day,
                     },
                     StartTripArgs {
                         retry_if_no_room,
@@ -135,18 +139,33 @@ fn get_vehicles(
                     TripEndpoint::Building(b) => Some(b),
                     _ => None,
                 };
+                let vehicle_type = if matches!(trip.purpose, TripPurpose::Emergency) {
+                    VehicleType::Emergency
+                } else {
+                    VehicleType::Car
+                };
 
-                // Any available cars in the right spot?
+                // Any available cars of the right type in the right spot?
                 let idx = if let Some(idx) = car_locations
                     .iter()
-                    .find(|(_, parked_at)| *parked_at == need_parked_at)
+                    .find(|(idx, parked_at)| {
+                        *parked_at == need_parked_at
+                            && vehicle_specs[*idx].vehicle_type == vehicle_type
+                    })
                     .map(|(idx, _)| *idx)
                 {
                     idx
                 } else {
                     // Need a new car, starting in the right spot
                     let idx = vehicle_specs.len();
-                    vehicle_specs.push(rand_car(rng));
+                    let mut spec = rand_car(rng);
+                    if vehicle_type == VehicleType::Emergency {
+                        // As long as possible, but still fitting in a parking spot
+                        spec.vehicle_type = VehicleType::Emergency;
+                        spec.length = MAX_CAR_LENGTH;
+                        spec.kinematics = Kinematics::default_for(VehicleType::Emergency);
+                    }
+                    vehicle_specs.push(spec);
                     if let Some(b) = need_parked_at {
                         cars_initially_parked_at.push((idx, b));
                     }
@@ -194,6 +213,7 @@ fn rand_car(rng: &mut XorShiftRng) -> VehicleSpec {
         vehicle_type: VehicleType::Car,
         length,
         max_speed: None,
+        kinematics: Kinematics::default_for(VehicleType::Car),
     }
 }
 
@@ -207,6 +227,7 @@ fn rand_bike(rng: &mut XorShiftRng) -> VehicleSpec {
         vehicle_type: VehicleType::Bike,
         length: BIKE_LENGTH,
         max_speed,
+        kinematics: Kinematics::default_for(VehicleType::Bike),
     }
 }
 
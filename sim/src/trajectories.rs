@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use geom::{Angle, Distance, Duration, LonLat, Pt2D, Speed, Time};
+use map_model::{Map, Traversable};
+
+use crate::AgentID;
+
+/// Records the trajectory of every vehicle and pedestrian, like floating car data (FCD) from
+/// probe vehicles. Agents are sampled every time they enter a lane or turn, and everybody is also
+/// sampled at a fixed interval. The result can be written as CSV or SUMO's FCD XML format.
+#[derive(Clone)]
+pub(crate) struct TrajectoryRecorder {
+    pub interval: Duration,
+    points: Vec<TrajectoryPoint>,
+    // When each agent was last sampled and how far along their trip they were then, to calculate
+    // speed
+    last_sample: BTreeMap<AgentID, (Time, Distance)>,
+}
+
+#[derive(Clone)]
+struct TrajectoryPoint {
+    time: Time,
+    agent: AgentID,
+    pos: LonLat,
+    // Degrees clockwise from north
+    heading: f64,
+    // The average speed since the previous sample of this agent
+    speed: Speed,
+    on: Traversable,
+    dist_along: Distance,
+    // Along the current leg of the agent's trip
+    dist_crossed: Distance,
+}
+
+impl TrajectoryRecorder {
+    pub fn new(interval: Duration) -> TrajectoryRecorder {
+        TrajectoryRecorder {
+            interval,
+            points: Vec::new(),
+            last_sample: BTreeMap::new(),
+        }
+    }
+
+    /// Record an agent at the front of their body, facing some direction, and with some distance
+    /// crossed so far.
+    pub fn record(
+        &mut self,
+        time: Time,
+        agent: AgentID,
+        pt: Pt2D,
+        facing: Angle,
+        on: Traversable,
+        dist_crossed: Distance,
+        map: &Map,
+    ) {
+        let speed = match self.last_sample.insert(agent, (time, dist_crossed)) {
+            Some((prev_time, prev_dist)) if time > prev_time && dist_crossed > prev_dist => {
+                Speed::from_dist_time(dist_crossed - prev_dist, time - prev_time)
+            }
+            _ => Speed::ZERO,
+        };
+        let dist_along = on
+            .get_polyline(map)
+            .dist_along_of_point(pt)
+            .map(|(dist, _)| dist)
+            .unwrap_or(Distance::ZERO);
+        self.points.push(TrajectoryPoint {
+            time,
+            agent,
+            pos: pt.to_gps(map.get_gps_bounds()),
+            // Angles in map-space have y pointing down, so 0 degrees is east and 90 is south
+            heading: (facing.normalized_degrees() + 90.0) % 360.0,
+            speed,
+            on,
+            dist_along,
+            dist_crossed,
+        });
+    }
+
+    pub fn num_points(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Returns the contents of a CSV file
+    pub fn export_csv(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "time_seconds,agent_type,agent_id,longitude,latitude,heading_degrees,speed_mps,on,dist_along_meters,dist_crossed_meters").unwrap();
+        for pt in &self.points {
+            let (agent_type, id) = describe_agent(pt.agent);
+            writeln!(
+                out,
+                "{:.1},{},{},{:.7},{:.7},{:.1},{:.2},{},{:.2},{:.2}",
+                pt.time.inner_seconds(),
+                agent_type,
+                id,
+                pt.pos.x(),
+                pt.pos.y(),
+                pt.heading,
+                pt.speed.inner_meters_per_second(),
+                describe_traversable(pt.on),
+                pt.dist_along.inner_meters(),
+                pt.dist_crossed.inner_meters()
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Returns the contents of an XML file in the format of SUMO's `--fcd-output` with
+    /// `--fcd-output.geo`, so positions are longitude and latitude. Lanes are used for SUMO's
+    /// edges; see `describe_traversable` for the IDs.
+    pub fn export_sumo_fcd(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+        writeln!(out, "<fcd-export>").unwrap();
+        let mut current_time = None;
+        for pt in &self.points {
+            if current_time != Some(pt.time) {
+                if current_time.is_some() {
+                    writeln!(out, "    </timestep>").unwrap();
+                }
+                writeln!(
+                    out,
+                    r#"    <timestep time="{:.2}">"#,
+                    pt.time.inner_seconds()
+                )
+                .unwrap();
+                current_time = Some(pt.time);
+            }
+            let (agent_type, id) = describe_agent(pt.agent);
+            let on = describe_traversable(pt.on);
+            if let AgentID::Pedestrian(_) = pt.agent {
+                writeln!(
+                    out,
+                    r#"        <person id="{}" x="{:.7}" y="{:.7}" angle="{:.2}" speed="{:.2}" pos="{:.2}" edge="{}" slope="0.00"/>"#,
+                    id,
+                    pt.pos.x(),
+                    pt.pos.y(),
+                    pt.heading,
+                    pt.speed.inner_meters_per_second(),
+                    pt.dist_along.inner_meters(),
+                    on
+                )
+                .unwrap();
+            } else {
+                writeln!(
+                    out,
+                    r#"        <vehicle id="{}" x="{:.7}" y="{:.7}" angle="{:.2}" type="{}" speed="{:.2}" pos="{:.2}" lane="{}" slope="0.00"/>"#,
+                    id,
+                    pt.pos.x(),
+                    pt.pos.y(),
+                    pt.heading,
+                    agent_type,
+                    pt.speed.inner_meters_per_second(),
+                    pt.dist_along.inner_meters(),
+                    on
+                )
+                .unwrap();
+            }
+        }
+        if current_time.is_some() {
+            writeln!(out, "    </timestep>").unwrap();
+        }
+        writeln!(out, "</fcd-export>").unwrap();
+        out
+    }
+}
+
+// The type of agent and a numeric ID, unique per type
+fn describe_agent(agent: AgentID) -> (String, usize) {
+    match agent {
+        AgentID::Car(c) => (c.vehicle_type.to_string(), c.id),
+        AgentID::Pedestrian(p) => ("pedestrian".to_string(), p.0),
+        AgentID::BusPassenger(_, _) => unreachable!(),
+    }
+}
+
+// Lanes are identified by their encoded LaneID, and turns by the intersection, then the encoded
+// source and destination lanes.
+fn describe_traversable(on: Traversable) -> String {
+    match on {
+        Traversable::Lane(l) => l.encode_u32().to_string(),
+        Traversable::Turn(t) => format!(
+            "{}_{}_{}",
+            t.parent.0,
+            t.src.encode_u32(),
+            t.dst.encode_u32()
+        ),
+    }
+}
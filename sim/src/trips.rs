@@ -905,6 +905,7 @@ impl TripManager {
             mode: trip.info.mode,
             total_time: now - trip.info.departure,
             blocked_time: trip.total_blocked_time,
+            day: trip.info.day,
         });
 
         let person = trip.person;
@@ -968,7 +969,7 @@ impl TripManager {
         self.unfinished_trips -= 1;
         trip.info.cancellation_reason = Some(reason);
         self.events
-            .push(Event::TripCancelled(trip.id, trip.info.mode));
+            .push(Event::TripCancelled(trip.id, trip.info.mode, trip.info.day));
     }
 
     /// Cancel a trip after it's started. The person will be magically warped to their destination,
@@ -985,7 +986,7 @@ impl TripManager {
         self.unfinished_trips -= 1;
         trip.info.cancellation_reason = Some(reason);
         self.events
-            .push(Event::TripCancelled(trip.id, trip.info.mode));
+            .push(Event::TripCancelled(trip.id, trip.info.mode, trip.info.day));
         let person = trip.person;
 
         // Maintain consistentency for anyone listening to events
@@ -1008,7 +1009,10 @@ impl TripManager {
 
         // Don't forget the car!
         if let Some(vehicle) = abandoned_vehicle {
-            if vehicle.vehicle_type == VehicleType::Car {
+            if matches!(
+                vehicle.vehicle_type,
+                VehicleType::Car | VehicleType::Emergency
+            ) {
                 // First remove the parked car, if needed. Maybe the trip was cancelled while the
                 // car was parked in the starting building.
                 if let Some(parked_car) = ctx.parking.lookup_parked_car(vehicle.id).cloned() {
@@ -1169,7 +1173,7 @@ impl TripManager {
         for a in self.active_trip_mode.keys() {
             match a {
                 AgentID::Car(c) => match c.vehicle_type {
-                    VehicleType::Car => {
+                    VehicleType::Car | VehicleType::Emergency => {
                         cnt.sov_drivers += 1;
                     }
                     VehicleType::Bike => {
@@ -1184,7 +1188,9 @@ impl TripManager {
                     VehicleType::Train => {
                         cnt.train_riders += 1;
                     }
-                    VehicleType::Car | VehicleType::Bike => unreachable!(),
+                    VehicleType::Car | VehicleType::Bike | VehicleType::Emergency => {
+                        unreachable!()
+                    }
                 },
                 // These're counted separately
                 AgentID::Pedestrian(_) => {}
@@ -1330,6 +1336,8 @@ pub struct TripInfo {
     /// Did a ScenarioModifier apply to this?
     pub modified: bool,
     pub cancellation_reason: Option<String>,
+    /// Which day of the scenario this trip belongs to, starting from 0
+    pub day: usize,
 }
 
 impl Trip {
@@ -0,0 +1,94 @@
+//! Estimate traffic volumes from a scenario with static traffic assignment, instead of running
+//! the full simulation.
+
+use abstutil::{Counter, Timer};
+use geom::{Duration, Time};
+use map_model::{static_assignment, AssignmentOptions, AssignmentResult, Map, PathRequest};
+
+use crate::{Scenario, TrafficCounts, TripEndpoint, TripMode};
+
+pub struct AssignedTraffic {
+    /// How many vehicles use each road and intersection
+    pub volumes: TrafficCounts,
+    /// For each road, the bigger volume-to-capacity ratio of both directions, as a percent
+    pub volume_to_capacity: TrafficCounts,
+    pub result: AssignmentResult,
+}
+
+/// Group the driving trips in a scenario into an origin-destination matrix, then assign them to
+/// roads. If a time window is specified, only trips departing during it are included, and road
+/// capacities cover that long. Otherwise, every trip counts, and capacities cover a full day.
+/// This overrides `opts.period`.
+pub fn assign_traffic(
+    scenario: &Scenario,
+    map: &Map,
+    window: Option<(Time, Time)>,
+    opts: &AssignmentOptions,
+    timer: &mut Timer,
+) -> AssignedTraffic {
+    let requests: Vec<PathRequest> = scenario
+        .all_trips()
+        .filter(|trip| !trip.cancelled && trip.mode == TripMode::Drive)
+        .filter(|trip| match window {
+            Some((t1, t2)) => trip.depart >= t1 && trip.depart < t2,
+            None => true,
+        })
+        .filter_map(|trip| TripEndpoint::path_req(trip.origin, trip.destination, trip.mode, map))
+        .collect();
+    let mut opts = opts.clone();
+    opts.period = match window {
+        Some((t1, t2)) => t2 - t1,
+        None => Duration::hours(24),
+    };
+    let result = static_assignment(map, PathRequest::deduplicate(map, requests), &opts, timer);
+
+    let period = match window {
+        Some((t1, t2)) => format!("from {} to {}", t1.ampm_tostring(), t2.ampm_tostring()),
+        None => "all day".to_string(),
+    };
+    let mut volumes = TrafficCounts {
+        map: map.get_name().clone(),
+        description: format!("static assignment of {} {}", scenario.scenario_name, period),
+        per_road: Counter::new(),
+        per_intersection: Counter::new(),
+    };
+    let mut volume_to_capacity = TrafficCounts {
+        map: map.get_name().clone(),
+        description: format!(
+            "volume-to-capacity percent from static assignment of {} {}",
+            scenario.scenario_name, period
+        ),
+        per_road: Counter::new(),
+        per_intersection: Counter::new(),
+    };
+    // Like from_path_requests, explicitly start with 0 everywhere
+    for r in map.all_roads() {
+        volumes.per_road.add(r.id, 0);
+        volume_to_capacity.per_road.add(r.id, 0);
+    }
+    for i in map.all_intersections() {
+        volumes.per_intersection.add(i.id, 0);
+    }
+    for (dr, volume) in &result.volumes {
+        volumes.per_road.add(dr.road, volume.round() as usize);
+    }
+    for (i, volume) in &result.intersection_volumes {
+        volumes.per_intersection.add(*i, volume.round() as usize);
+    }
+    for r in map.all_roads() {
+        let ratio =
+            r.id.both_directions()
+                .into_iter()
+                .map(|dr| result.volume_to_capacity(dr))
+                .fold(0.0, f64::max);
+        volume_to_capacity
+            .per_road
+            .add(r.id, (100.0 * ratio).round() as usize);
+    }
+
+    AssignedTraffic {
+        volumes,
+        volume_to_capacity,
+        result,
+    }
+}
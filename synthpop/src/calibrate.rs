@@ -0,0 +1,398 @@
+//! Calibrate a scenario's travel demand against observed traffic counts. This is a simple form of
+//! origin-destination matrix estimation (ODME): every trip is routed once, then people are
+//! iteratively scaled up or down until the counts from routing match the observations. Finally,
+//! people are removed or duplicated to match their scaled weight.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use anyhow::Result;
+use rand::Rng;
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+
+use abstutil::{prettyprint_usize, Timer};
+use geom::{Duration, Time};
+use map_model::{IntersectionID, Map, RoadID};
+
+use crate::counts::{geh, locations_along_path};
+use crate::{PersonSpec, Scenario, TrafficCounts, TripEndpoint, TripMode};
+
+/// Real observations to calibrate against.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ObservedCounts {
+    pub counts: TrafficCounts,
+    /// If present, only trips departing during this time are compared against these counts, and
+    /// the GEH statistic uses flows per hour. Otherwise, all trips are compared against the total
+    /// counts.
+    pub window: Option<(Time, Time)>,
+}
+
+#[derive(Clone)]
+pub struct CalibrationOptions {
+    /// Only trips using these modes contribute to counts
+    pub modes: BTreeSet<TripMode>,
+    pub max_iterations: usize,
+    /// Stop early once every count location has a GEH statistic below this
+    pub geh_tolerance: f64,
+    /// Never duplicate one person more than this many times
+    pub max_scale: f64,
+    /// In each iteration, a person's weight can change by at most this factor
+    pub max_step: f64,
+}
+
+impl Default for CalibrationOptions {
+    fn default() -> Self {
+        Self {
+            modes: vec![TripMode::Drive].into_iter().collect(),
+            max_iterations: 50,
+            geh_tolerance: 5.0,
+            max_scale: 5.0,
+            max_step: 2.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CountLocation {
+    Road(RoadID),
+    Intersection(IntersectionID),
+}
+
+/// How well the scenario matches one observed count, before and after calibrating.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CountComparison {
+    /// The `description` of the observed counts
+    pub source: String,
+    pub window: Option<(Time, Time)>,
+    pub location: CountLocation,
+    pub observed: usize,
+    pub before: usize,
+    pub after: usize,
+    pub geh_before: f64,
+    pub geh_after: f64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CalibrationReport {
+    pub iterations: usize,
+    pub people_before: usize,
+    pub people_after: usize,
+    pub comparisons: Vec<CountComparison>,
+}
+
+impl CalibrationReport {
+    /// Summarize how many count locations are a good match
+    pub fn summary(&self, geh_tolerance: f64) -> String {
+        let good_before = self
+            .comparisons
+            .iter()
+            .filter(|c| c.geh_before < geh_tolerance)
+            .count();
+        let good_after = self
+            .comparisons
+            .iter()
+            .filter(|c| c.geh_after < geh_tolerance)
+            .count();
+        format!(
+            "After {} iterations, {} people became {}. {} of {} counts have GEH < {} (before: {})",
+            self.iterations,
+            prettyprint_usize(self.people_before),
+            prettyprint_usize(self.people_after),
+            prettyprint_usize(good_after),
+            prettyprint_usize(self.comparisons.len()),
+            geh_tolerance,
+            prettyprint_usize(good_before)
+        )
+    }
+
+    /// One row per count location
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from(
+            "source,window_start,window_end,location,observed,before,after,geh_before,geh_after\n",
+        );
+        for c in &self.comparisons {
+            let (start, end) = match c.window {
+                Some((t1, t2)) => (t1.to_string(), t2.to_string()),
+                None => (String::new(), String::new()),
+            };
+            let location = match c.location {
+                CountLocation::Road(r) => format!("road {}", r.0),
+                CountLocation::Intersection(i) => format!("intersection {}", i.0),
+            };
+            writeln!(
+                out,
+                "\"{}\",{},{},{},{},{},{},{:.2},{:.2}",
+                c.source.replace('"', "\"\""),
+                start,
+                end,
+                location,
+                c.observed,
+                c.before,
+                c.after,
+                c.geh_before,
+                c.geh_after
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// Scale the people in a scenario up or down until routing their trips produces counts close to
+/// the observations. People whose trips don't pass any count location are left alone. Returns the
+/// calibrated scenario, with the same name.
+pub fn calibrate(
+    scenario: Scenario,
+    observed: &[ObservedCounts],
+    opts: &CalibrationOptions,
+    map: &Map,
+    rng: &mut XorShiftRng,
+    timer: &mut Timer,
+) -> Result<(Scenario, CalibrationReport)> {
+    if observed.is_empty() {
+        bail!("No observed counts to calibrate against");
+    }
+    // Every (observation, location) pair we have a count for
+    let mut locations: Vec<(usize, CountLocation, f64)> = Vec::new();
+    let mut location_idx: BTreeMap<(usize, CountLocation), usize> = BTreeMap::new();
+    for (idx, obs) in observed.iter().enumerate() {
+        if &obs.counts.map != map.get_name() {
+            bail!(
+                "Counts \"{}\" are for {}, not {}",
+                obs.counts.description,
+                obs.counts.map.describe(),
+                map.get_name().describe()
+            );
+        }
+        if let Some((t1, t2)) = obs.window {
+            if t2 <= t1 {
+                bail!(
+                    "Counts \"{}\" have a window from {} to {}",
+                    obs.counts.description,
+                    t1,
+                    t2
+                );
+            }
+        }
+        for (r, cnt) in obs.counts.per_road.borrow() {
+            location_idx.insert((idx, CountLocation::Road(*r)), locations.len());
+            locations.push((idx, CountLocation::Road(*r), *cnt as f64));
+        }
+        for (i, cnt) in obs.counts.per_intersection.borrow() {
+            location_idx.insert((idx, CountLocation::Intersection(*i)), locations.len());
+            locations.push((idx, CountLocation::Intersection(*i), *cnt as f64));
+        }
+    }
+
+    // Route every trip once. For each person, which count locations do they pass, and how many
+    // times?
+    let hits_per_person: Vec<Vec<(usize, f64)>> =
+        timer.parallelize("route trips", scenario.people.iter().collect(), |person| {
+            let mut hits: BTreeMap<usize, f64> = BTreeMap::new();
+            for trip in &person.trips {
+                if trip.cancelled || !opts.modes.contains(&trip.mode) {
+                    continue;
+                }
+                let path =
+                    match TripEndpoint::path_req(trip.origin, trip.destination, trip.mode, map)
+                        .and_then(|req| map.pathfind_v2(req).ok())
+                    {
+                        Some(path) => path,
+                        None => {
+                            continue;
+                        }
+                    };
+                let (roads, intersections) = locations_along_path(&path, map);
+                for (obs_idx, obs) in observed.iter().enumerate() {
+                    if let Some((t1, t2)) = obs.window {
+                        if trip.depart < t1 || trip.depart >= t2 {
+                            continue;
+                        }
+                    }
+                    for loc in roads.iter().map(|r| CountLocation::Road(*r)).chain(
+                        intersections
+                            .iter()
+                            .map(|i| CountLocation::Intersection(*i)),
+                    ) {
+                        if let Some(idx) = location_idx.get(&(obs_idx, loc)) {
+                            *hits.entry(*idx).or_insert(0.0) += 1.0;
+                        }
+                    }
+                }
+            }
+            hits.into_iter().collect()
+        });
+
+    // GEH is meant for hourly flows
+    let per_hour: Vec<f64> = locations
+        .iter()
+        .map(|(obs_idx, _, _)| match observed[*obs_idx].window {
+            Some((t1, t2)) => 1.0 / ((t2 - t1) / Duration::hours(1)),
+            None => 1.0,
+        })
+        .collect();
+    let count = |weights: &[f64]| -> Vec<f64> {
+        let mut modelled = vec![0.0; locations.len()];
+        for (hits, weight) in hits_per_person.iter().zip(weights) {
+            for (idx, n) in hits {
+                modelled[*idx] += weight * n;
+            }
+        }
+        modelled
+    };
+    let geh_per_location = |modelled: &[f64]| -> Vec<f64> {
+        modelled
+            .iter()
+            .zip(locations.iter())
+            .zip(per_hour.iter())
+            .map(|((m, (_, _, o)), scale)| geh(m * scale, o * scale))
+            .collect()
+    };
+
+    let observed_counts: Vec<f64> = locations.iter().map(|(_, _, o)| *o).collect();
+    let mut weights = vec![1.0; scenario.people.len()];
+    let before = count(&weights);
+    let mut iterations = 0;
+    timer.start("scale people");
+    while iterations < opts.max_iterations {
+        let modelled = count(&weights);
+        if geh_per_location(&modelled)
+            .into_iter()
+            .all(|x| x < opts.geh_tolerance)
+        {
+            break;
+        }
+        iterations += 1;
+
+        for (hits, weight) in hits_per_person.iter().zip(weights.iter_mut()) {
+            *weight = scale_weight(*weight, hits, &modelled, &observed_counts, opts);
+        }
+    }
+    timer.stop("scale people");
+
+    // People are indivisible, so randomly round the weights
+    let people_before = scenario.people.len();
+    let mut people: Vec<PersonSpec> = Vec::new();
+    let mut final_weights = Vec::new();
+    for (person, weight) in scenario.people.iter().zip(weights) {
+        let mut copies = weight.floor() as usize;
+        if rng.gen_bool(weight.fract()) {
+            copies += 1;
+        }
+        for _ in 0..copies {
+            people.push(person.clone());
+        }
+        final_weights.push(copies as f64);
+    }
+    let after = count(&final_weights);
+
+    let geh_before = geh_per_location(&before);
+    let geh_after = geh_per_location(&after);
+    let mut comparisons = Vec::new();
+    for (idx, (obs_idx, location, observed_count)) in locations.into_iter().enumerate() {
+        comparisons.push(CountComparison {
+            source: observed[obs_idx].counts.description.clone(),
+            window: observed[obs_idx].window,
+            location,
+            observed: observed_count as usize,
+            before: before[idx].round() as usize,
+            after: after[idx].round() as usize,
+            geh_before: geh_before[idx],
+            geh_after: geh_after[idx],
+        });
+    }
+    let report = CalibrationReport {
+        iterations,
+        people_before,
+        people_after: people.len(),
+        comparisons,
+    };
+
+    let mut scenario = scenario;
+    scenario.people = people;
+    Ok((scenario, report))
+}
+
+/// Scale one person's weight by the geometric mean of the ratio of observed to modelled counts,
+/// for everywhere they pass. `hits` are (location index, times passed).
+fn scale_weight(
+    weight: f64,
+    hits: &[(usize, f64)],
+    modelled: &[f64],
+    observed: &[f64],
+    opts: &CalibrationOptions,
+) -> f64 {
+    let mut sum_log_ratio = 0.0;
+    let mut total = 0.0;
+    for (idx, n) in hits {
+        // Nobody's passing there. Scaling won't help.
+        if modelled[*idx] == 0.0 {
+            continue;
+        }
+        let ratio = (observed[*idx] / modelled[*idx])
+            .max(1.0 / opts.max_step)
+            .min(opts.max_step);
+        sum_log_ratio += n * ratio.ln();
+        total += n;
+    }
+    if total > 0.0 {
+        (weight * (sum_log_ratio / total).exp()).min(opts.max_scale)
+    } else {
+        weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::assert_approx;
+
+    #[test]
+    fn test_scale_weight() {
+        let opts = CalibrationOptions::default();
+        let modelled = vec![10.0, 10.0, 0.0];
+        let observed = vec![15.0, 10.0 / 1.5, 100.0];
+
+        // Passing one location
+        assert_approx(
+            scale_weight(1.0, &[(0, 1.0)], &modelled, &observed, &opts),
+            1.5,
+        );
+        // The geometric mean of 1.5 and 1 / 1.5 leaves the weight alone
+        assert_approx(
+            scale_weight(1.0, &[(0, 1.0), (1, 1.0)], &modelled, &observed, &opts),
+            1.0,
+        );
+        // Passing a location more often counts more
+        assert_approx(
+            scale_weight(1.0, &[(0, 3.0), (1, 1.0)], &modelled, &observed, &opts),
+            1.5_f64.sqrt(),
+        );
+        // Locations nobody passes in the model are ignored
+        assert_approx(
+            scale_weight(2.0, &[(2, 1.0)], &modelled, &observed, &opts),
+            2.0,
+        );
+        assert_approx(scale_weight(2.0, &[], &modelled, &observed, &opts), 2.0);
+    }
+
+    #[test]
+    fn test_scale_weight_limits() {
+        let opts = CalibrationOptions::default();
+        // Each step can change the weight by at most max_step
+        assert_approx(
+            scale_weight(1.0, &[(0, 1.0)], &[1.0], &[100.0], &opts),
+            opts.max_step,
+        );
+        assert_approx(
+            scale_weight(1.0, &[(0, 1.0)], &[100.0], &[1.0], &opts),
+            1.0 / opts.max_step,
+        );
+        // And the weight never exceeds max_scale
+        assert_approx(
+            scale_weight(4.0, &[(0, 1.0)], &[1.0], &[100.0], &opts),
+            opts.max_scale,
+        );
+    }
+}
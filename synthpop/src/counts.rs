@@ -1,9 +1,17 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
 use abstio::MapName;
-use abstutil::{prettyprint_usize, Counter, Timer};
-use geom::Distance;
-use map_model::{IntersectionID, Map, PathRequest, PathStepV2, PathV2, Pathfinder, RoadID};
+use abstutil::{deserialize_btreemap, prettyprint_usize, serialize_btreemap, Counter, Timer};
+use geom::{Distance, Duration, Time};
+use map_model::{
+    DirectedRoadID, IntersectionID, Map, MovementID, PathRequest, PathStepV2, PathV2, Pathfinder,
+    RoadID,
+};
+
+use crate::TripMode;
 
 /// This represents the number of vehicles (or trips, or something else) crossing roads and
 /// intersections over some span of time. The data could represent real observations or something
@@ -77,30 +85,12 @@ impl TrafficCounts {
     }
 
     pub fn update_with_path(&mut self, path: PathV2, count: usize, map: &Map) {
-        for step in path.get_steps() {
-            match step {
-                PathStepV2::Along(dr) | PathStepV2::Contraflow(dr) => {
-                    self.per_road.add(dr.road, count);
-                }
-                PathStepV2::Movement(m) | PathStepV2::ContraflowMovement(m) => {
-                    self.per_intersection.add(m.parent, count);
-                }
-            }
+        let (roads, intersections) = locations_along_path(&path, map);
+        for r in roads {
+            self.per_road.add(r, count);
         }
-
-        // If we're starting or ending at a border, count it
-        let req = path.get_req();
-        if req.start.dist_along() == Distance::ZERO {
-            // TODO src_i and dst_i may not work for pedestrians on contraflow sidewalks
-            let i = map.get_l(req.start.lane()).src_i;
-            if map.get_i(i).is_border() {
-                self.per_intersection.add(i, count);
-            }
-        } else {
-            let i = map.get_l(req.end.lane()).dst_i;
-            if map.get_i(i).is_border() {
-                self.per_intersection.add(i, count);
-            }
+        for i in intersections {
+            self.per_intersection.add(i, count);
         }
     }
 
@@ -137,3 +127,269 @@ impl TrafficCounts {
         println!("RMSE = {:.2}", (sum / n as f64).sqrt());
     }
 }
+
+/// Where a detailed count was taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CountSite {
+    /// Traffic along a road in one direction
+    Road(DirectedRoadID),
+    /// Traffic making one movement through an intersection
+    Movement(MovementID),
+}
+
+/// Counts split by direction, movement, mode, and time of day, the way real count stations
+/// usually report them. Unlike `TrafficCounts`, nothing is squeezed into the description.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TimeBinnedCounts {
+    pub map: MapName,
+    pub description: String,
+    /// Every bin has this length, starting from midnight.
+    pub bin_size: Duration,
+    /// (site, mode, bin) -> count. Bin `n` covers `[n * bin_size, (n + 1) * bin_size)`.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub counts: BTreeMap<(CountSite, TripMode, usize), usize>,
+}
+
+impl TimeBinnedCounts {
+    pub fn new(map: &Map, description: String, bin_size: Duration) -> Result<Self> {
+        if bin_size <= Duration::ZERO {
+            bail!("Time bins must have a positive length, not {}", bin_size);
+        }
+        Ok(Self {
+            map: map.get_name().clone(),
+            description,
+            bin_size,
+            counts: BTreeMap::new(),
+        })
+    }
+
+    /// Which bin a time falls in
+    pub fn bin(&self, time: Time) -> usize {
+        ((time - Time::START_OF_DAY) / self.bin_size).floor() as usize
+    }
+
+    pub fn bin_start(&self, bin: usize) -> Time {
+        Time::START_OF_DAY + self.bin_size * (bin as f64)
+    }
+
+    pub fn add(&mut self, site: CountSite, mode: TripMode, time: Time, count: usize) {
+        let bin = self.bin(time);
+        *self.counts.entry((site, mode, bin)).or_insert(0) += count;
+    }
+
+    /// All of the places with any count
+    pub fn sites(&self) -> BTreeSet<CountSite> {
+        self.counts.keys().map(|(site, _, _)| *site).collect()
+    }
+
+    /// Merge bins together, so each new bin has the specified length. Fails if the new size isn't
+    /// a multiple of the current one.
+    pub fn rebin(&self, bin_size: Duration) -> Result<Self> {
+        let factor = bin_size / self.bin_size;
+        if factor < 1.0 || (factor - factor.round()).abs() > 1e-6 {
+            bail!(
+                "Can't turn {} bins into {} bins; the new size must be a multiple",
+                self.bin_size,
+                bin_size
+            );
+        }
+        let factor = factor.round() as usize;
+        let mut counts = BTreeMap::new();
+        for ((site, mode, bin), count) in &self.counts {
+            *counts.entry((*site, *mode, bin / factor)).or_insert(0) += count;
+        }
+        Ok(Self {
+            map: self.map.clone(),
+            description: self.description.clone(),
+            bin_size,
+            counts,
+        })
+    }
+
+    /// Collapse direction, movements, and time bins into total counts per road and intersection,
+    /// only for some modes. If a time window is specified, only bins starting in it are used.
+    pub fn to_traffic_counts(
+        &self,
+        modes: &BTreeSet<TripMode>,
+        window: Option<(Time, Time)>,
+    ) -> TrafficCounts {
+        let mut counts = TrafficCounts {
+            map: self.map.clone(),
+            description: self.description.clone(),
+            per_road: Counter::new(),
+            per_intersection: Counter::new(),
+        };
+        for ((site, mode, bin), count) in &self.counts {
+            if !modes.contains(mode) {
+                continue;
+            }
+            if let Some((t1, t2)) = window {
+                let start = self.bin_start(*bin);
+                if start < t1 || start >= t2 {
+                    continue;
+                }
+            }
+            match site {
+                CountSite::Road(dr) => {
+                    counts.per_road.add(dr.road, *count);
+                }
+                CountSite::Movement(m) => {
+                    counts.per_intersection.add(m.parent, *count);
+                }
+            }
+        }
+        counts
+    }
+}
+
+/// The roads and intersections a path crosses, in order, possibly with repeats. If the path starts
+/// or ends at a border, that intersection is included.
+pub(crate) fn locations_along_path(path: &PathV2, map: &Map) -> (Vec<RoadID>, Vec<IntersectionID>) {
+    let mut roads = Vec::new();
+    let mut intersections = Vec::new();
+    for step in path.get_steps() {
+        match step {
+            PathStepV2::Along(dr) | PathStepV2::Contraflow(dr) => {
+                roads.push(dr.road);
+            }
+            PathStepV2::Movement(m) | PathStepV2::ContraflowMovement(m) => {
+                intersections.push(m.parent);
+            }
+        }
+    }
+
+    // If we're starting or ending at a border, count it
+    let req = path.get_req();
+    if req.start.dist_along() == Distance::ZERO {
+        // TODO src_i and dst_i may not work for pedestrians on contraflow sidewalks
+        let i = map.get_l(req.start.lane()).src_i;
+        if map.get_i(i).is_border() {
+            intersections.push(i);
+        }
+    } else {
+        let i = map.get_l(req.end.lane()).dst_i;
+        if map.get_i(i).is_border() {
+            intersections.push(i);
+        }
+    }
+    (roads, intersections)
+}
+
+/// The GEH statistic compares a modelled and observed hourly flow. It's commonly used to calibrate
+/// traffic models, because it tolerates bigger relative differences for small flows. A GEH under 5
+/// is usually considered a good match.
+pub fn geh(modelled: f64, observed: f64) -> f64 {
+    if modelled + observed == 0.0 {
+        return 0.0;
+    }
+    (2.0 * (modelled - observed).powi(2) / (modelled + observed)).sqrt()
+}
+
+/// The coefficient of determination between modelled and observed counts, calculated as the square
+/// of their correlation. 1 means the model explains all variation in the observations. Returns
+/// `None` without at least two pairs, or if either side is constant.
+pub fn r_squared(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 2 {
+        return None;
+    }
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let mut cov = 0.0;
+    let mut var_x = 0.0;
+    let mut var_y = 0.0;
+    for (x, y) in pairs {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+    }
+    if var_x == 0.0 || var_y == 0.0 {
+        return None;
+    }
+    Some(cov.powi(2) / (var_x * var_y))
+}
+
+#[cfg(test)]
+mod tests {
+    use map_model::Direction;
+
+    use super::*;
+    use crate::testing::assert_approx;
+
+    #[test]
+    fn test_geh() {
+        assert_approx(geh(0.0, 0.0), 0.0);
+        assert_approx(geh(100.0, 100.0), 0.0);
+        assert_approx(geh(150.0, 100.0), 20.0_f64.sqrt());
+        assert_approx(geh(100.0, 150.0), 20.0_f64.sqrt());
+        // The same relative difference is more significant for bigger flows
+        assert!(geh(1500.0, 1000.0) > geh(150.0, 100.0));
+    }
+
+    #[test]
+    fn test_r_squared() {
+        assert_eq!(r_squared(&[]), None);
+        assert_eq!(r_squared(&[(1.0, 2.0)]), None);
+        // Constant on one side
+        assert_eq!(r_squared(&[(1.0, 2.0), (1.0, 3.0)]), None);
+        // Any linear relationship explains everything, even a negative one
+        assert_approx(
+            r_squared(&[(1.0, 3.0), (2.0, 5.0), (3.0, 7.0)]).unwrap(),
+            1.0,
+        );
+        assert_approx(
+            r_squared(&[(1.0, 3.0), (2.0, 2.0), (3.0, 1.0)]).unwrap(),
+            1.0,
+        );
+        // The correlation here is 0.8
+        assert_approx(
+            r_squared(&[(0.0, 0.0), (1.0, 2.0), (2.0, 1.0), (3.0, 3.0)]).unwrap(),
+            0.64,
+        );
+    }
+
+    #[test]
+    fn test_rebin() {
+        let site = CountSite::Road(DirectedRoadID {
+            road: RoadID(1),
+            dir: Direction::Fwd,
+        });
+        let mut counts = TimeBinnedCounts {
+            map: MapName::seattle("montlake"),
+            description: "test".to_string(),
+            bin_size: Duration::minutes(15),
+            counts: BTreeMap::new(),
+        };
+        // 07:00, 07:45, and 08:15
+        for (bin, count) in [(28, 10), (31, 20), (33, 5)] {
+            counts.counts.insert((site, TripMode::Drive, bin), count);
+        }
+        counts.counts.insert((site, TripMode::Bike, 28), 3);
+
+        let hourly = counts.rebin(Duration::hours(1)).unwrap();
+        assert_eq!(hourly.bin_size, Duration::hours(1));
+        assert_eq!(
+            hourly.counts,
+            vec![
+                ((site, TripMode::Bike, 7), 3),
+                ((site, TripMode::Drive, 7), 30),
+                ((site, TripMode::Drive, 8), 5),
+            ]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>()
+        );
+        assert_eq!(hourly.bin_start(8), Time::START_OF_DAY + Duration::hours(8));
+
+        // Rebinning to the same size doesn't change anything
+        assert_eq!(
+            counts.rebin(Duration::minutes(15)).unwrap().counts,
+            counts.counts
+        );
+        // Bins can only merge, and only evenly
+        assert!(counts.rebin(Duration::minutes(5)).is_err());
+        assert!(counts.rebin(Duration::minutes(20)).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! Import counts from real count stations. Each station is a point with a compass direction, which
+//! gets snapped to the nearest road going roughly that way.
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use abstutil::{prettyprint_usize, Timer};
+use geom::{Angle, Distance, Duration, FindClosest, LonLat, Pt2D, Time};
+use map_model::{DirectedRoadID, Direction, Map, RoadID};
+
+use crate::{CountSite, TimeBinnedCounts, TripMode};
+
+/// One row of input: the count for one station, direction, mode, and time bin.
+struct StationCount {
+    name: String,
+    pt: LonLat,
+    direction: String,
+    mode: String,
+    start_time: String,
+    count: usize,
+}
+
+impl TimeBinnedCounts {
+    /// Import a CSV file with the columns `latitude`, `longitude`, `direction`, `mode`,
+    /// `start_time`, `count`, and optionally `station` for a name.
+    ///
+    /// - `direction` is a compass direction like "N", "SW", or "eastbound", or a bearing in
+    ///   degrees clockwise from north.
+    /// - `mode` is one of "car", "bike", "pedestrian", or "bus".
+    /// - `start_time` is when the time bin starts, like "07:15" or "07:15:00". Every row covers
+    ///   `bin_size`.
+    ///
+    /// Stations further than `max_snap_dist` from a suitable road are skipped with a warning.
+    pub fn import_csv(
+        map: &Map,
+        description: String,
+        bin_size: Duration,
+        path: String,
+        max_snap_dist: Distance,
+        timer: &mut Timer,
+    ) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Record {
+            #[serde(default)]
+            station: String,
+            latitude: f64,
+            longitude: f64,
+            direction: String,
+            mode: String,
+            start_time: String,
+            count: usize,
+        }
+
+        let mut rows = Vec::new();
+        for rec in csv::Reader::from_reader(&abstio::slurp_file(&path)?[..]).deserialize() {
+            let rec: Record = rec?;
+            rows.push(StationCount {
+                name: rec.station,
+                pt: LonLat::new(rec.longitude, rec.latitude),
+                direction: rec.direction,
+                mode: rec.mode,
+                start_time: rec.start_time,
+                count: rec.count,
+            });
+        }
+        Self::import_stations(map, description, bin_size, rows, max_snap_dist, timer)
+    }
+
+    /// Import a GeoJSON file with one point per station, direction, mode, and time bin. Each
+    /// feature has the same properties as the columns for `import_csv`.
+    pub fn import_geojson(
+        map: &Map,
+        description: String,
+        bin_size: Duration,
+        path: String,
+        max_snap_dist: Distance,
+        timer: &mut Timer,
+    ) -> Result<Self> {
+        let raw = String::from_utf8(abstio::slurp_file(&path)?)?;
+        let features = match raw.parse::<geojson::GeoJson>()? {
+            geojson::GeoJson::Feature(feature) => vec![feature],
+            geojson::GeoJson::FeatureCollection(collection) => collection.features,
+            x => bail!("Unexpected geojson: {:?}", x),
+        };
+
+        let mut rows = Vec::new();
+        for feature in features {
+            let pt = match feature.geometry.as_ref().map(|g| &g.value) {
+                Some(geojson::Value::Point(pt)) => LonLat::new(pt[0], pt[1]),
+                _ => bail!("Count stations must be points, not {:?}", feature.geometry),
+            };
+            // Accept numbers or strings for everything
+            let property = |key: &str| -> Result<String> {
+                match feature.property(key) {
+                    Some(geojson::JsonValue::String(x)) => Ok(x.clone()),
+                    Some(geojson::JsonValue::Number(x)) => Ok(x.to_string()),
+                    _ => bail!("Count station is missing {}: {:?}", key, feature.properties),
+                }
+            };
+            rows.push(StationCount {
+                name: property("station").unwrap_or_default(),
+                pt,
+                direction: property("direction")?,
+                mode: property("mode")?,
+                start_time: property("start_time")?,
+                count: property("count")?.parse::<usize>()?,
+            });
+        }
+        Self::import_stations(map, description, bin_size, rows, max_snap_dist, timer)
+    }
+
+    fn import_stations(
+        map: &Map,
+        description: String,
+        bin_size: Duration,
+        rows: Vec<StationCount>,
+        max_snap_dist: Distance,
+        timer: &mut Timer,
+    ) -> Result<Self> {
+        let mut counts = Self::new(map, description, bin_size)?;
+        let mut closest_per_mode: BTreeMap<TripMode, FindClosest<RoadID>> = BTreeMap::new();
+        // The same station shows up in many rows, so only snap it once
+        let mut snapped: BTreeMap<(LonLat, String, TripMode), Option<DirectedRoadID>> =
+            BTreeMap::new();
+        let mut skipped = 0;
+
+        timer.start_iter("snap count stations", rows.len());
+        for row in rows {
+            timer.next();
+            let mode = parse_mode(&row.mode)?;
+            let bearing = parse_bearing(&row.direction)?;
+            let start_time = Time::parse(&row.start_time)?;
+            if counts.bin_start(counts.bin(start_time)) != start_time {
+                bail!(
+                    "Count at {} starts at {}, which isn't the start of a {} bin",
+                    row.name,
+                    start_time,
+                    bin_size
+                );
+            }
+
+            let closest = closest_per_mode
+                .entry(mode)
+                .or_insert_with(|| build_closest(map, mode));
+            let pt = row.pt.to_pt(map.get_gps_bounds());
+            let key = (row.pt, row.direction.clone(), mode);
+            let dr = *snapped
+                .entry(key)
+                .or_insert_with(|| snap(map, closest, pt, bearing, mode, max_snap_dist));
+            match dr {
+                Some(dr) => {
+                    counts.add(CountSite::Road(dr), mode, start_time, row.count);
+                }
+                None => {
+                    warn!(
+                        "Couldn't snap count station {} at {:?} going {} to any road",
+                        row.name, row.pt, row.direction
+                    );
+                    skipped += 1;
+                }
+            }
+        }
+        if counts.counts.is_empty() {
+            bail!(
+                "None of the count stations are near roads in {}",
+                map.get_name().describe()
+            );
+        }
+        if skipped > 0 {
+            warn!(
+                "Skipped {} counts that couldn't be snapped to a road",
+                prettyprint_usize(skipped)
+            );
+        }
+        Ok(counts)
+    }
+}
+
+fn build_closest(map: &Map, mode: TripMode) -> FindClosest<RoadID> {
+    let constraints = mode.to_constraints();
+    let mut closest = FindClosest::new();
+    for r in map.all_roads() {
+        if constraints.can_use_road(r, map) {
+            closest.add(r.id, r.center_pts.points());
+        }
+    }
+    closest
+}
+
+/// Find the closest road that's roughly parallel to the station's direction, and pick the
+/// direction matching it.
+fn snap(
+    map: &Map,
+    closest: &FindClosest<RoadID>,
+    pt: Pt2D,
+    bearing: f64,
+    mode: TripMode,
+    max_snap_dist: Distance,
+) -> Option<DirectedRoadID> {
+    // Compass bearings start north and go clockwise. Angles start east, and also go clockwise,
+    // since the Y axis points down.
+    let station_angle = Angle::degrees(bearing - 90.0);
+    let constraints = mode.to_constraints();
+
+    let mut candidates = closest.all_close_pts(pt, max_snap_dist);
+    candidates.sort_by_key(|(_, _, dist)| *dist);
+    for (r, snapped_pt, _) in candidates {
+        let road = map.get_r(r);
+        let road_angle = match road.center_pts.dist_along_of_point(snapped_pt) {
+            Some((_, angle)) => angle,
+            None => {
+                continue;
+            }
+        };
+        if !road_angle.approx_parallel(station_angle, 45.0) {
+            continue;
+        }
+        let dir = if road_angle.approx_eq(station_angle, 90.0) {
+            Direction::Fwd
+        } else {
+            Direction::Back
+        };
+        // Sidewalks can be used either way, but a one-way road can't be
+        if mode != TripMode::Walk
+            && !road
+                .lanes
+                .iter()
+                .any(|l| l.dir == dir && constraints.can_use(l, map))
+        {
+            continue;
+        }
+        return Some(DirectedRoadID { road: r, dir });
+    }
+    None
+}
+
+pub(crate) fn parse_mode(x: &str) -> Result<TripMode> {
+    match x.to_lowercase().as_str() {
+        "car" | "drive" | "vehicle" => Ok(TripMode::Drive),
+        "bike" | "bicycle" | "cyclist" => Ok(TripMode::Bike),
+        "pedestrian" | "walk" => Ok(TripMode::Walk),
+        "bus" | "transit" => Ok(TripMode::Transit),
+        _ => bail!("Unknown mode {}", x),
+    }
+}
+
+/// Returns degrees clockwise from north
+fn parse_bearing(x: &str) -> Result<f64> {
+    if let Ok(degrees) = x.parse::<f64>() {
+        return Ok(degrees);
+    }
+    let lower = x.to_lowercase();
+    let compass = lower
+        .strip_suffix("bound")
+        .or_else(|| lower.strip_suffix('b'))
+        .unwrap_or(&lower);
+    Ok(match compass {
+        "n" | "north" => 0.0,
+        "ne" | "northeast" => 45.0,
+        "e" | "east" => 90.0,
+        "se" | "southeast" => 135.0,
+        "s" | "south" => 180.0,
+        "sw" | "southwest" => 225.0,
+        "w" | "west" => 270.0,
+        "nw" | "northwest" => 315.0,
+        _ => bail!("Unknown direction {}", x),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bearing() {
+        assert_eq!(parse_bearing("N").unwrap(), 0.0);
+        assert_eq!(parse_bearing("north").unwrap(), 0.0);
+        assert_eq!(parse_bearing("SW").unwrap(), 225.0);
+        assert_eq!(parse_bearing("EB").unwrap(), 90.0);
+        assert_eq!(parse_bearing("Westbound").unwrap(), 270.0);
+        assert_eq!(parse_bearing("northeastbound").unwrap(), 45.0);
+        assert_eq!(parse_bearing("123.5").unwrap(), 123.5);
+        assert!(parse_bearing("up").is_err());
+        assert!(parse_bearing("").is_err());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("Car").unwrap(), TripMode::Drive);
+        assert_eq!(parse_mode("bicycle").unwrap(), TripMode::Bike);
+        assert_eq!(parse_mode("pedestrian").unwrap(), TripMode::Walk);
+        assert_eq!(parse_mode("bus").unwrap(), TripMode::Transit);
+        assert!(parse_mode("scooter").is_err());
+    }
+}
@@ -15,20 +15,32 @@ use serde::{Deserialize, Serialize};
 use abstutil::{deserialize_usize, serialize_usize};
 use map_model::PathConstraints;
 
+pub use self::assignment::{assign_traffic, AssignedTraffic};
 pub use self::borders::{MapBorder, MapBorders};
-pub use self::counts::TrafficCounts;
+pub use self::calibrate::{
+    calibrate, CalibrationOptions, CalibrationReport, CountComparison, CountLocation,
+    ObservedCounts,
+};
+pub use self::counts::{geh, r_squared, CountSite, TimeBinnedCounts, TrafficCounts};
 pub use self::endpoint::TripEndpoint;
 pub use self::external::{ExternalPerson, ExternalTrip, ExternalTripEndpoint};
 pub use self::modifier::ScenarioModifier;
-pub use self::scenario::{IndividTrip, PersonSpec, Scenario, TripPurpose};
+pub use self::scenario::{DayType, IndividTrip, PersonSpec, Scenario, TripPurpose};
+pub use self::tmc::{Approach, TurnDirection, TurningMovementCounts};
 
+mod assignment;
 mod borders;
+mod calibrate;
 mod counts;
 mod endpoint;
 mod external;
+mod import_counts;
 pub mod make;
 mod modifier;
 mod scenario;
+#[cfg(test)]
+mod testing;
+mod tmc;
 
 /// How does a trip primarily happen?
 ///
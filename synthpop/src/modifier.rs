@@ -6,11 +6,11 @@ use rand::Rng;
 use rand_xorshift::XorShiftRng;
 use serde::{Deserialize, Serialize};
 
-use abstutil::Timer;
+use abstutil::{prettyprint_usize, Timer};
 use geom::{Duration, Time};
 use map_model::Map;
 
-use crate::{Scenario, TripMode};
+use crate::{DayType, IndividTrip, PersonSpec, Scenario, TripEndpoint, TripMode, TripPurpose};
 
 /// Transforms an existing Scenario before instantiating it.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
@@ -29,6 +29,13 @@ pub enum ScenarioModifier {
     },
     /// Scenario name
     AddExtraTrips(String),
+    /// Append another day after the last one. If a scenario name is given, its trips happen on
+    /// the new day. Otherwise, everybody's first day is reused, turned into a day off for
+    /// weekends and holidays.
+    AddDay {
+        day_type: DayType,
+        scenario: Option<String>,
+    },
 }
 
 impl ScenarioModifier {
@@ -96,6 +103,9 @@ impl ScenarioModifier {
                 }
                 s
             }
+            ScenarioModifier::AddDay { day_type, scenario } => {
+                add_day(map, s, *day_type, scenario.as_ref(), rng)
+            }
         }
     }
 
@@ -124,6 +134,10 @@ impl ScenarioModifier {
                 to_mode.map(|m| m.verb())
             ),
             ScenarioModifier::AddExtraTrips(name) => format!("Add extra trips from {}", name),
+            ScenarioModifier::AddDay { day_type, scenario } => match scenario {
+                Some(name) => format!("add a {} with trips from {}", day_type, name),
+                None => format!("add a {}", day_type),
+            },
         }
     }
 }
@@ -146,10 +160,11 @@ fn repeat_days(
     for person in &mut s.people {
         let mut trips = Vec::new();
         let mut offset = Duration::ZERO;
-        for _ in 0..days {
+        for day in 0..days {
             for trip in &person.trips {
                 let mut new = trip.clone();
                 new.depart += offset;
+                new.day = day;
                 if let Some(noise_v) = noise {
                     // + or - noise_v
                     let noise_rnd = Duration::seconds(
@@ -164,5 +179,397 @@ fn repeat_days(
         }
         person.trips = trips;
     }
+    let first = s.day_types.first().cloned().unwrap_or(DayType::Weekday);
+    s.day_types = vec![first; days];
+    s
+}
+
+fn add_day(
+    map: &Map,
+    mut s: Scenario,
+    day_type: DayType,
+    scenario: Option<&String>,
+    rng: &mut XorShiftRng,
+) -> Scenario {
+    if let Some(name) = scenario {
+        let other: Scenario = abstio::must_read_object(
+            abstio::path_scenario(map.get_name(), name),
+            &mut Timer::throwaway(),
+        );
+        add_day_from(&mut s, day_type, other);
+    } else {
+        add_day_from_first(&mut s, day_type, rng);
+    }
+    s.scenario_name = format!("{} (plus a {})", s.scenario_name, day_type);
     s
 }
+
+/// Start a new day, returning its index. Earlier days without a type were weekdays.
+fn start_day(s: &mut Scenario, day_type: DayType) -> usize {
+    if s.day_types.is_empty() {
+        s.day_types = vec![DayType::Weekday; s.num_days()];
+    }
+    s.day_types.push(day_type);
+    s.day_types.len() - 1
+}
+
+/// Everybody in the other scenario takes their trips on the new day. If the other scenario covers
+/// several days, the rest of them follow, keeping their own types.
+fn add_day_from(s: &mut Scenario, day_type: DayType, other: Scenario) {
+    let day = start_day(s, day_type);
+    for extra in 1..other.num_days() {
+        s.day_types.push(
+            other
+                .day_types
+                .get(extra)
+                .cloned()
+                .unwrap_or(DayType::Weekday),
+        );
+    }
+    let offset = Duration::hours(24) * (day as f64);
+    // Scenarios generated from the same population list people in the same order, so try to keep
+    // following the same person. When that doesn't work, they're somebody new.
+    let num_people = s.people.len();
+    for (idx, mut p) in other.people.into_iter().enumerate() {
+        for trip in &mut p.trips {
+            trip.depart += offset;
+            trip.day += day;
+            trip.modified = true;
+        }
+        if idx < num_people && continues(&s.people[idx], &p.trips) {
+            s.people[idx].trips.extend(p.trips);
+        } else {
+            s.people.push(p);
+        }
+    }
+}
+
+/// Everybody repeats their first day on the new day, turned into a day off if needed.
+fn add_day_from_first(s: &mut Scenario, day_type: DayType, rng: &mut XorShiftRng) {
+    let day = start_day(s, day_type);
+    let offset = Duration::hours(24) * (day as f64);
+    let mut skipped = 0;
+    for person in &mut s.people {
+        let first_day: Vec<IndividTrip> = person
+            .trips
+            .iter()
+            .filter(|trip| trip.day == 0)
+            .cloned()
+            .collect();
+        let mut trips = if day_type.is_day_off() {
+            day_off(first_day, day_type, rng)
+        } else {
+            first_day
+        };
+        for trip in &mut trips {
+            trip.depart += offset;
+            trip.day = day;
+            trip.modified = true;
+        }
+        // If somebody doesn't end the day where they started, they can't repeat it
+        if continues(person, &trips) {
+            person.trips.extend(trips);
+        } else {
+            skipped += 1;
+        }
+    }
+    if skipped > 0 {
+        warn!(
+            "{} people don't end their first day where they start, so they stay put on the {}",
+            prettyprint_usize(skipped),
+            day_type
+        );
+    }
+}
+
+/// Can this person take these trips after their existing ones?
+fn continues(person: &PersonSpec, trips: &[IndividTrip]) -> bool {
+    let (last, next) = match (person.trips.last(), trips.first()) {
+        (Some(last), Some(next)) => (last, next),
+        // Nothing to add, or nothing to continue from
+        (_, None) | (None, _) => {
+            return true;
+        }
+    };
+    if last.depart >= next.depart {
+        return false;
+    }
+    last.destination == next.origin
+        || (matches!(last.destination, TripEndpoint::Border(_))
+            && matches!(next.origin, TripEndpoint::Border(_)))
+}
+
+/// Turn a weekday into a day off. Commuters usually stay home or make one round-trip somewhere
+/// else they'd go, and a few still work on Saturdays. Everybody else keeps their trips, but starts
+/// a bit later. Fewer people go out on Sundays and holidays.
+fn day_off(trips: Vec<IndividTrip>, day_type: DayType, rng: &mut XorShiftRng) -> Vec<IndividTrip> {
+    if trips.is_empty() {
+        return trips;
+    }
+    let commuter = trips
+        .iter()
+        .any(|trip| matches!(trip.purpose, TripPurpose::Work | TripPurpose::School));
+    let go_out = if day_type == DayType::Saturday {
+        0.8
+    } else {
+        0.6
+    };
+
+    if !commuter {
+        if !rng.gen_bool(go_out) {
+            return Vec::new();
+        }
+        let delay = Duration::seconds(rng.gen_range(0.0..Duration::hours(2).inner_seconds()));
+        return trips
+            .into_iter()
+            .map(|mut trip| {
+                trip.depart += delay;
+                trip
+            })
+            .collect();
+    }
+
+    if day_type == DayType::Saturday && rng.gen_bool(0.2) {
+        return trips;
+    }
+    let home = trips[0].origin;
+    // People commuting in from outside the map don't come in on their day off
+    if matches!(home, TripEndpoint::Border(_)) || !rng.gen_bool(go_out) {
+        return Vec::new();
+    }
+    let destination = match trips
+        .iter()
+        .filter(|trip| {
+            !matches!(
+                trip.purpose,
+                TripPurpose::Work | TripPurpose::School | TripPurpose::Home
+            ) && trip.destination != home
+        })
+        .map(|trip| trip.destination)
+        .next()
+    {
+        Some(destination) => destination,
+        // Nowhere else to go
+        None => {
+            return Vec::new();
+        }
+    };
+    let mode = trips[0].mode;
+    let depart = Time::START_OF_DAY
+        + Duration::seconds(
+            rng.gen_range(Duration::hours(10).inner_seconds()..Duration::hours(14).inner_seconds()),
+        );
+    let stay = Duration::seconds(
+        rng.gen_range(Duration::hours(1).inner_seconds()..Duration::hours(4).inner_seconds()),
+    );
+    vec![
+        IndividTrip::new(depart, TripPurpose::Recreation, home, destination, mode),
+        IndividTrip::new(depart + stay, TripPurpose::Home, destination, home, mode),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use abstio::MapName;
+    use map_model::{BuildingID, IntersectionID};
+    use rand::SeedableRng;
+
+    fn trip(
+        depart: Time,
+        purpose: TripPurpose,
+        from: TripEndpoint,
+        to: TripEndpoint,
+    ) -> IndividTrip {
+        IndividTrip::new(depart, purpose, from, to, TripMode::Walk)
+    }
+
+    fn bldg(id: usize) -> TripEndpoint {
+        TripEndpoint::Building(BuildingID(id))
+    }
+
+    fn border(id: usize) -> TripEndpoint {
+        TripEndpoint::Border(IntersectionID(id))
+    }
+
+    fn at(hours: usize, minutes: usize) -> Time {
+        Time::START_OF_DAY + Duration::hours(hours) + Duration::minutes(minutes)
+    }
+
+    /// Somebody going out late in the evening and coming home after midnight
+    fn night_owl() -> Scenario {
+        Scenario {
+            scenario_name: "test".to_string(),
+            map_name: MapName::seattle("montlake"),
+            people: vec![PersonSpec {
+                orig_id: None,
+                trips: vec![
+                    trip(at(23, 0), TripPurpose::Social, bldg(1), bldg(2)),
+                    trip(at(24, 30), TripPurpose::Home, bldg(2), bldg(1)),
+                ],
+            }],
+            only_seed_buses: None,
+            day_types: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_continues() {
+        let person = PersonSpec {
+            orig_id: None,
+            trips: vec![trip(at(8, 0), TripPurpose::Work, bldg(1), bldg(2))],
+        };
+        assert!(continues(&person, &[]));
+        assert!(continues(
+            &person,
+            &[trip(at(17, 0), TripPurpose::Home, bldg(2), bldg(1))]
+        ));
+        // Starting somewhere else
+        assert!(!continues(
+            &person,
+            &[trip(at(17, 0), TripPurpose::Home, bldg(3), bldg(1))]
+        ));
+        // Starting before the last trip
+        assert!(!continues(
+            &person,
+            &[trip(at(7, 0), TripPurpose::Home, bldg(2), bldg(1))]
+        ));
+
+        let nobody = PersonSpec {
+            orig_id: None,
+            trips: Vec::new(),
+        };
+        assert!(continues(
+            &nobody,
+            &[trip(at(7, 0), TripPurpose::Home, bldg(2), bldg(1))]
+        ));
+
+        // Leaving and re-entering through different borders is fine
+        let commuter = PersonSpec {
+            orig_id: None,
+            trips: vec![trip(at(17, 0), TripPurpose::Home, bldg(1), border(5))],
+        };
+        assert!(continues(
+            &commuter,
+            &[trip(at(32, 0), TripPurpose::Work, border(6), bldg(1))]
+        ));
+    }
+
+    #[test]
+    fn test_add_day_late_evening() {
+        let mut rng = XorShiftRng::seed_from_u64(42);
+        let mut s = night_owl();
+        add_day_from_first(&mut s, DayType::Weekday, &mut rng);
+        add_day_from_first(&mut s, DayType::Weekday, &mut rng);
+
+        assert_eq!(s.num_days(), 3);
+        assert_eq!(s.day_types, vec![DayType::Weekday; 3]);
+        let trips: Vec<(usize, Time)> = s.people[0]
+            .trips
+            .iter()
+            .map(|trip| (trip.day, trip.depart))
+            .collect();
+        // The trip after midnight stays with the evening it belongs to
+        assert_eq!(
+            trips,
+            vec![
+                (0, at(23, 0)),
+                (0, at(24, 30)),
+                (1, at(47, 0)),
+                (1, at(48, 30)),
+                (2, at(71, 0)),
+                (2, at(72, 30)),
+            ]
+        );
+        assert!(s.people[0].check_schedule().is_ok());
+    }
+
+    #[test]
+    fn test_add_weekend() {
+        let mut rng = XorShiftRng::seed_from_u64(42);
+        let mut s = night_owl();
+        add_day_from_first(&mut s, DayType::Saturday, &mut rng);
+        add_day_from_first(&mut s, DayType::Sunday, &mut rng);
+
+        assert_eq!(
+            s.day_types,
+            vec![DayType::Weekday, DayType::Saturday, DayType::Sunday]
+        );
+        assert_eq!(s.num_days(), 3);
+        let person = &s.people[0];
+        assert!(person.check_schedule().is_ok());
+        for trip in &person.trips {
+            let start = Time::START_OF_DAY + Duration::hours(24 * trip.day);
+            assert!(trip.depart >= start);
+            assert!(trip.day == 0 || trip.modified);
+        }
+    }
+
+    #[test]
+    fn test_add_day_from_other_scenario() {
+        let mut s = night_owl();
+        let other = Scenario {
+            scenario_name: "sunday".to_string(),
+            map_name: MapName::seattle("montlake"),
+            people: vec![
+                PersonSpec {
+                    orig_id: None,
+                    trips: vec![
+                        trip(at(11, 0), TripPurpose::Meal, bldg(1), bldg(3)),
+                        trip(at(13, 0), TripPurpose::Home, bldg(3), bldg(1)),
+                    ],
+                },
+                PersonSpec {
+                    orig_id: None,
+                    trips: vec![trip(at(9, 0), TripPurpose::Shopping, bldg(4), bldg(5))],
+                },
+            ],
+            only_seed_buses: None,
+            day_types: Vec::new(),
+        };
+        add_day_from(&mut s, DayType::Sunday, other);
+
+        assert_eq!(s.day_types, vec![DayType::Weekday, DayType::Sunday]);
+        // The first person continues from the night before; the second is somebody new
+        assert_eq!(s.people.len(), 2);
+        assert_eq!(s.people[0].trips.len(), 4);
+        assert_eq!(s.people[0].trips[2].day, 1);
+        assert_eq!(s.people[0].trips[2].depart, at(35, 0));
+        assert!(s.people[0].check_schedule().is_ok());
+        assert_eq!(s.people[1].trips[0].day, 1);
+        assert_eq!(s.people[1].trips[0].depart, at(33, 0));
+    }
+
+    #[test]
+    fn test_add_day_from_multi_day_scenario() {
+        let mut s = night_owl();
+        let mut next_day = trip(at(37, 0), TripPurpose::Home, bldg(3), bldg(1));
+        next_day.day = 1;
+        let other = Scenario {
+            scenario_name: "long weekend".to_string(),
+            map_name: MapName::seattle("montlake"),
+            people: vec![PersonSpec {
+                orig_id: None,
+                trips: vec![
+                    trip(at(11, 0), TripPurpose::Meal, bldg(1), bldg(3)),
+                    next_day,
+                ],
+            }],
+            only_seed_buses: None,
+            day_types: vec![DayType::Saturday, DayType::Holiday],
+        };
+        add_day_from(&mut s, DayType::Sunday, other);
+
+        // The first new day takes the requested type, and the rest keep theirs
+        assert_eq!(
+            s.day_types,
+            vec![DayType::Weekday, DayType::Sunday, DayType::Holiday]
+        );
+        assert_eq!(s.num_days(), 3);
+        let trips = &s.people[0].trips;
+        assert_eq!(trips.len(), 4);
+        assert_eq!(trips[3].day, 2);
+        assert_eq!(trips[3].depart, at(61, 0));
+        assert!(s.people[0].check_schedule().is_ok());
+    }
+}
@@ -20,6 +20,18 @@ pub struct Scenario {
     pub people: Vec<PersonSpec>,
     /// None means seed all buses. Otherwise the route name must be present here.
     pub only_seed_buses: Option<BTreeSet<String>>,
+    /// What kind of day each 24 hours of the scenario covers, starting at midnight. Days missing
+    /// from this are weekdays.
+    pub day_types: Vec<DayType>,
+}
+
+/// People travel differently on weekends and holidays than on weekdays.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DayType {
+    Weekday,
+    Saturday,
+    Sunday,
+    Holiday,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -42,6 +54,9 @@ pub struct IndividTrip {
     pub cancelled: bool,
     /// Did a ScenarioModifier affect this?
     pub modified: bool,
+    /// Which day of the scenario's schedule this trip belongs to, starting from 0. Late trips may
+    /// depart after midnight and still belong to the previous day.
+    pub day: usize,
 }
 
 impl IndividTrip {
@@ -60,6 +75,7 @@ impl IndividTrip {
             purpose,
             cancelled: false,
             modified: false,
+            day: 0,
         }
     }
 }
@@ -78,6 +94,9 @@ pub enum TripPurpose {
     Recreation,
     Medical,
     ParkAndRideTransfer,
+    /// Responding to an emergency. Driving trips use an emergency vehicle, which may pre-empt
+    /// traffic signals.
+    Emergency,
 }
 
 impl fmt::Display for TripPurpose {
@@ -98,6 +117,42 @@ impl fmt::Display for TripPurpose {
                 TripPurpose::Recreation => "recreation",
                 TripPurpose::Medical => "medical",
                 TripPurpose::ParkAndRideTransfer => "park-and-ride transfer",
+                TripPurpose::Emergency => "emergency",
+            }
+        )
+    }
+}
+
+impl DayType {
+    /// Most people don't go to work or school on these days
+    pub fn is_day_off(self) -> bool {
+        self != DayType::Weekday
+    }
+
+    pub fn parse(x: &str) -> Result<DayType> {
+        match x.to_lowercase().as_str() {
+            "weekday" => Ok(DayType::Weekday),
+            "saturday" => Ok(DayType::Saturday),
+            "sunday" => Ok(DayType::Sunday),
+            "holiday" => Ok(DayType::Holiday),
+            _ => bail!(
+                "Unknown day type {}; use weekday, saturday, sunday, or holiday",
+                x
+            ),
+        }
+    }
+}
+
+impl fmt::Display for DayType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DayType::Weekday => "weekday",
+                DayType::Saturday => "saturday",
+                DayType::Sunday => "sunday",
+                DayType::Holiday => "holiday",
             }
         )
     }
@@ -117,9 +172,19 @@ impl Scenario {
             map_name: map.get_name().clone(),
             people: Vec::new(),
             only_seed_buses: Some(BTreeSet::new()),
+            day_types: Vec::new(),
         }
     }
 
+    /// How many days the scenario covers. Scenarios without `day_types` cover however many days
+    /// their trips belong to.
+    pub fn num_days(&self) -> usize {
+        if !self.day_types.is_empty() {
+            return self.day_types.len();
+        }
+        self.all_trips().map(|trip| trip.day + 1).max().unwrap_or(1)
+    }
+
     pub fn remove_weird_schedules(mut self, verbose: bool) -> Scenario {
         let orig = self.people.len();
         self.people.retain(|person| match person.check_schedule() {
@@ -169,6 +234,14 @@ impl PersonSpec {
         }
 
         for pair in self.trips.windows(2) {
+            if pair[0].day > pair[1].day {
+                bail!(
+                    "Person ({:?}) has a trip on day {} after one on day {}",
+                    self.orig_id,
+                    pair[1].day,
+                    pair[0].day
+                );
+            }
             if pair[0].depart >= pair[1].depart {
                 bail!(
                     "Person ({:?}) starts two trips in the wrong order: {} then {}",
@@ -207,3 +280,86 @@ impl PersonSpec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geom::Duration;
+    use map_model::BuildingID;
+
+    fn trip(day: usize, depart_hours: usize, from: usize, to: usize) -> IndividTrip {
+        let mut trip = IndividTrip::new(
+            Time::START_OF_DAY + Duration::hours(depart_hours),
+            TripPurpose::Social,
+            TripEndpoint::Building(BuildingID(from)),
+            TripEndpoint::Building(BuildingID(to)),
+            TripMode::Walk,
+        );
+        trip.day = day;
+        trip
+    }
+
+    fn scenario(trips: Vec<IndividTrip>) -> Scenario {
+        Scenario {
+            scenario_name: "test".to_string(),
+            map_name: MapName::seattle("montlake"),
+            people: vec![PersonSpec {
+                orig_id: None,
+                trips,
+            }],
+            only_seed_buses: None,
+            day_types: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_day_type() {
+        assert_eq!(DayType::parse("weekday").unwrap(), DayType::Weekday);
+        assert_eq!(DayType::parse("Saturday").unwrap(), DayType::Saturday);
+        assert_eq!(DayType::parse("SUNDAY").unwrap(), DayType::Sunday);
+        assert_eq!(DayType::parse("holiday").unwrap(), DayType::Holiday);
+        assert!(DayType::parse("friday").is_err());
+        assert!(DayType::parse("").is_err());
+
+        for day_type in [
+            DayType::Weekday,
+            DayType::Saturday,
+            DayType::Sunday,
+            DayType::Holiday,
+        ] {
+            assert_eq!(DayType::parse(&day_type.to_string()).unwrap(), day_type);
+        }
+    }
+
+    #[test]
+    fn test_num_days() {
+        assert_eq!(scenario(Vec::new()).num_days(), 1);
+
+        // A trip departing after midnight still belongs to the first day
+        let s = scenario(vec![trip(0, 23, 1, 2), trip(0, 25, 2, 1)]);
+        assert_eq!(s.num_days(), 1);
+
+        let s = scenario(vec![trip(0, 8, 1, 2), trip(1, 32, 2, 1)]);
+        assert_eq!(s.num_days(), 2);
+
+        // day_types wins, even if the last days have no trips
+        let mut s = scenario(vec![trip(0, 8, 1, 2)]);
+        s.day_types = vec![DayType::Weekday, DayType::Saturday, DayType::Sunday];
+        assert_eq!(s.num_days(), 3);
+    }
+
+    #[test]
+    fn test_check_schedule_day_order() {
+        let person = PersonSpec {
+            orig_id: None,
+            trips: vec![trip(0, 8, 1, 2), trip(1, 32, 2, 1)],
+        };
+        assert!(person.check_schedule().is_ok());
+
+        let person = PersonSpec {
+            orig_id: None,
+            trips: vec![trip(1, 8, 1, 2), trip(0, 32, 2, 1)],
+        };
+        assert!(person.check_schedule().is_err());
+    }
+}
@@ -0,0 +1,11 @@
+//! Helpers shared by unit tests
+
+/// Panic unless a number is within 1e-6 of what's expected.
+pub fn assert_approx(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < 1e-6,
+        "{} isn't close to {}",
+        actual,
+        expected
+    );
+}
@@ -0,0 +1,536 @@
+//! Turning movement counts (TMCs) are counted in the field at intersections, usually in 15 minute
+//! bins. Each count is labelled by the approach vehicles come from and whether they turn left,
+//! go straight, or turn right. These labels are matched to movements using the bearing of each
+//! road leaving the intersection.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use abstio::MapName;
+use abstutil::{deserialize_btreemap, prettyprint_usize, serialize_btreemap, Timer};
+use geom::{Distance, Duration, FindClosest, LonLat, Time};
+use map_model::{IntersectionID, Map, MovementID, RoadID};
+
+use crate::import_counts::parse_mode;
+use crate::{CountSite, TimeBinnedCounts, TripMode};
+
+/// Which leg of an intersection vehicles arrive from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Approach {
+    North,
+    East,
+    South,
+    West,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TurnDirection {
+    Left,
+    Through,
+    Right,
+    UTurn,
+}
+
+impl Approach {
+    /// The compass bearing from the center of the intersection towards this leg
+    fn bearing(self) -> f64 {
+        match self {
+            Approach::North => 0.0,
+            Approach::East => 90.0,
+            Approach::South => 180.0,
+            Approach::West => 270.0,
+        }
+    }
+}
+
+/// Turning movement counts for some intersections, split by mode and time of day.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TurningMovementCounts {
+    pub map: MapName,
+    pub description: String,
+    /// Every bin has this length, starting from midnight.
+    pub bin_size: Duration,
+    /// How the original data labelled each movement
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub labels: BTreeMap<MovementID, (Approach, TurnDirection)>,
+    /// (movement, mode, bin) -> count. Bin `n` covers `[n * bin_size, (n + 1) * bin_size)`.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub counts: BTreeMap<(MovementID, TripMode, usize), usize>,
+}
+
+impl TurningMovementCounts {
+    /// Import a CSV file with one row per intersection, mode, and time bin. The columns are
+    /// `latitude` and `longitude` of the intersection, `start_time` of the bin (like "07:15"),
+    /// optionally `mode` (defaulting to "car") and `intersection` for a name, and then one column
+    /// per movement, labelled by approach and turn.
+    ///
+    /// The approach is either the leg vehicles come from ("N", "E", "S", "W") or the direction
+    /// they're travelling ("NB", "EB", "SB", "WB"). So "NL" and "SBL" both mean vehicles coming from
+    /// the north and turning left. The turn is "L", "T", "R", or "U".
+    ///
+    /// Intersections further than `max_snap_dist` from the point are skipped with a warning, as
+    /// are labels that don't match any movement and other columns, like totals or notes.
+    pub fn import_csv(
+        map: &Map,
+        description: String,
+        bin_size: Duration,
+        path: String,
+        max_snap_dist: Distance,
+        timer: &mut Timer,
+    ) -> Result<Self> {
+        let mut tmc = Self {
+            map: map.get_name().clone(),
+            description,
+            bin_size,
+            labels: BTreeMap::new(),
+            counts: BTreeMap::new(),
+        };
+        // Reuse the binning logic
+        let bins = TimeBinnedCounts::new(map, String::new(), bin_size)?;
+
+        let mut reader = csv::Reader::from_reader(&abstio::slurp_file(&path)?[..]);
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| headers.iter().position(|x| x == name);
+        let lat_col = column("latitude").ok_or_else(|| anyhow!("Missing latitude column"))?;
+        let lon_col = column("longitude").ok_or_else(|| anyhow!("Missing longitude column"))?;
+        let time_col = column("start_time").ok_or_else(|| anyhow!("Missing start_time column"))?;
+        let mode_col = column("mode");
+        let name_col = column("intersection");
+        let label_cols = label_columns(
+            &headers,
+            &[
+                Some(lat_col),
+                Some(lon_col),
+                Some(time_col),
+                mode_col,
+                name_col,
+            ],
+        );
+
+        let mut closest = FindClosest::new();
+        for i in map.all_intersections() {
+            closest.add_polygon(i.id, &i.polygon);
+        }
+        let mut snapped: BTreeMap<LonLat, Option<IntersectionID>> = BTreeMap::new();
+        let mut matched: BTreeMap<(IntersectionID, Approach, TurnDirection), Option<MovementID>> =
+            BTreeMap::new();
+        let mut skipped = 0;
+
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        timer.start_iter("match turning movement counts", records.len());
+        for rec in records {
+            timer.next();
+            let name = name_col.and_then(|idx| rec.get(idx)).unwrap_or("");
+            let pt = LonLat::new(rec[lon_col].parse::<f64>()?, rec[lat_col].parse::<f64>()?);
+            let start_time = Time::parse(&rec[time_col])?;
+            if bins.bin_start(bins.bin(start_time)) != start_time {
+                bail!(
+                    "Count at {} starts at {}, which isn't the start of a {} bin",
+                    name,
+                    start_time,
+                    bin_size
+                );
+            }
+            let mode = match mode_col.map(|idx| &rec[idx]) {
+                Some("") | None => TripMode::Drive,
+                Some(x) => parse_mode(x)?,
+            };
+
+            let i = match *snapped.entry(pt).or_insert_with(|| {
+                closest
+                    .closest_pt(pt.to_pt(map.get_gps_bounds()), max_snap_dist)
+                    .map(|(i, _)| i)
+            }) {
+                Some(i) => i,
+                None => {
+                    warn!("Couldn't snap intersection {} at {:?} to the map", name, pt);
+                    skipped += 1;
+                    continue;
+                }
+            };
+
+            for (idx, (approach, turn)) in &label_cols {
+                let value = rec[*idx].trim();
+                if value.is_empty() {
+                    continue;
+                }
+                let count = value.parse::<usize>()?;
+                let movement = *matched
+                    .entry((i, *approach, *turn))
+                    .or_insert_with(|| match_movement(map, i, *approach, *turn));
+                match movement {
+                    Some(m) => {
+                        tmc.labels.insert(m, (*approach, *turn));
+                        *tmc.counts
+                            .entry((m, mode, bins.bin(start_time)))
+                            .or_insert(0) += count;
+                    }
+                    None => {
+                        // Legs that don't exist often just have 0 in the data
+                        if count > 0 {
+                            warn!(
+                                "No movement at {} ({}) matches {:?} {:?}",
+                                i, name, approach, turn
+                            );
+                            skipped += 1;
+                        }
+                    }
+                }
+            }
+        }
+        if tmc.counts.is_empty() {
+            bail!(
+                "None of the turning movement counts match {}",
+                map.get_name().describe()
+            );
+        }
+        if skipped > 0 {
+            warn!(
+                "Skipped {} counts that couldn't be matched to the map",
+                prettyprint_usize(skipped)
+            );
+        }
+        Ok(tmc)
+    }
+
+    pub fn intersections(&self) -> BTreeSet<IntersectionID> {
+        self.labels.keys().map(|m| m.parent).collect()
+    }
+
+    /// Express these counts generally, to compare them against other data.
+    pub fn to_time_binned_counts(&self) -> TimeBinnedCounts {
+        TimeBinnedCounts {
+            map: self.map.clone(),
+            description: self.description.clone(),
+            bin_size: self.bin_size,
+            counts: self
+                .counts
+                .iter()
+                .map(|((m, mode, bin), count)| ((CountSite::Movement(*m), *mode, *bin), *count))
+                .collect(),
+        }
+    }
+
+    /// How many vehicles perform each movement in bins starting during a time window. Pedestrians
+    /// are excluded.
+    pub fn vehicle_counts(&self, start: Time, end: Time) -> BTreeMap<MovementID, usize> {
+        let mut counts = BTreeMap::new();
+        for ((m, mode, bin), count) in &self.counts {
+            let bin_start = Time::START_OF_DAY + self.bin_size * (*bin as f64);
+            if *mode == TripMode::Walk || bin_start < start || bin_start >= end {
+                continue;
+            }
+            *counts.entry(*m).or_insert(0) += count;
+        }
+        counts
+    }
+}
+
+/// Find the columns labelled with a movement, skipping the `known` columns. Anything else is
+/// ignored with a warning.
+fn label_columns(
+    headers: &csv::StringRecord,
+    known: &[Option<usize>],
+) -> Vec<(usize, (Approach, TurnDirection))> {
+    let mut label_cols = Vec::new();
+    for (idx, header) in headers.iter().enumerate() {
+        if known.contains(&Some(idx)) {
+            continue;
+        }
+        match parse_label(header) {
+            Some(label) => label_cols.push((idx, label)),
+            None => warn!("Skipping unknown column {}", header),
+        }
+    }
+    label_cols
+}
+
+fn parse_label(x: &str) -> Option<(Approach, TurnDirection)> {
+    let label = x.to_uppercase().replace(['_', ' '], "");
+    if !label.is_ascii() {
+        return None;
+    }
+    let (approach, turn) = label.split_at(label.len().checked_sub(1)?);
+    let turn = match turn {
+        "L" => TurnDirection::Left,
+        "T" => TurnDirection::Through,
+        "R" => TurnDirection::Right,
+        "U" => TurnDirection::UTurn,
+        _ => {
+            return None;
+        }
+    };
+    let approach = match approach {
+        "N" | "SB" => Approach::North,
+        "E" | "WB" => Approach::East,
+        "S" | "NB" => Approach::South,
+        "W" | "EB" => Approach::West,
+        _ => {
+            return None;
+        }
+    };
+    Some((approach, turn))
+}
+
+/// Find the movement from the leg closest to the approach, towards the leg closest to the turn.
+fn match_movement(
+    map: &Map,
+    i: IntersectionID,
+    approach: Approach,
+    turn: TurnDirection,
+) -> Option<MovementID> {
+    let legs: BTreeMap<RoadID, f64> = map
+        .get_i(i)
+        .roads
+        .iter()
+        .map(|r| (*r, leg_bearing(map, i, *r)))
+        .collect();
+    pick_movement(&legs, map.get_i(i).movements.keys(), approach, turn)
+}
+
+/// Given the compass bearing of each leg of an intersection, pick the movement matching an
+/// approach and turn.
+fn pick_movement<'a, I: Iterator<Item = &'a MovementID>>(
+    legs: &BTreeMap<RoadID, f64>,
+    movements: I,
+    approach: Approach,
+    turn: TurnDirection,
+) -> Option<MovementID> {
+    // Don't match a leg more than this far off from the expected bearing
+    const MAX_DIFF: f64 = 60.0;
+
+    let (from, from_bearing) = legs
+        .iter()
+        .map(|(r, bearing)| (*r, *bearing, bearing_diff(*bearing, approach.bearing())))
+        .filter(|(_, _, diff)| *diff <= MAX_DIFF)
+        .min_by(|a, b| a.2.partial_cmp(&b.2).unwrap())
+        .map(|(r, bearing, _)| (r, bearing))?;
+
+    // Vehicles coming from the north leg head south, so turning left takes them east
+    let heading = from_bearing + 180.0;
+    let target = match turn {
+        TurnDirection::Left => heading - 90.0,
+        TurnDirection::Through => heading,
+        TurnDirection::Right => heading + 90.0,
+        TurnDirection::UTurn => from_bearing,
+    };
+    movements
+        .filter(|m| !m.crosswalk && m.from.road == from)
+        .filter(|m| (turn == TurnDirection::UTurn) == (m.to.road == from))
+        .filter_map(|m| Some((*m, bearing_diff(*legs.get(&m.to.road)?, target))))
+        .filter(|(_, diff)| *diff <= MAX_DIFF)
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map(|(m, _)| m)
+}
+
+/// The compass bearing from the intersection towards a road, looking a little bit along the road
+/// to smooth out curves right at the intersection.
+fn leg_bearing(map: &Map, i: IntersectionID, r: RoadID) -> f64 {
+    let road = map.get_r(r);
+    let pl = if road.src_i == i {
+        road.center_pts.clone()
+    } else {
+        road.center_pts.reversed()
+    };
+    let dist = pl.length().min(Distance::meters(20.0));
+    let pt = pl
+        .dist_along(dist)
+        .map(|(pt, _)| pt)
+        .unwrap_or_else(|_| pl.last_pt());
+    // Angles start east and go clockwise, since the Y axis points down. Compass bearings start
+    // north.
+    (map.get_i(i)
+        .polygon
+        .center()
+        .angle_to(pt)
+        .normalized_degrees()
+        + 90.0)
+        .rem_euclid(360.0)
+}
+
+/// The absolute difference between two bearings in degrees, from 0 to 180
+fn bearing_diff(a: f64, b: f64) -> f64 {
+    let diff = (a - b).rem_euclid(360.0);
+    diff.min(360.0 - diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use map_model::{DirectedRoadID, Direction};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(
+            parse_label("NL"),
+            Some((Approach::North, TurnDirection::Left))
+        );
+        // Travelling southbound means coming from the north
+        assert_eq!(
+            parse_label("SBL"),
+            Some((Approach::North, TurnDirection::Left))
+        );
+        assert_eq!(
+            parse_label("eb_t"),
+            Some((Approach::West, TurnDirection::Through))
+        );
+        assert_eq!(
+            parse_label("W R"),
+            Some((Approach::West, TurnDirection::Right))
+        );
+        assert_eq!(
+            parse_label("NBU"),
+            Some((Approach::South, TurnDirection::UTurn))
+        );
+        assert_eq!(parse_label(""), None);
+        assert_eq!(parse_label("L"), None);
+        assert_eq!(parse_label("NX"), None);
+        assert_eq!(parse_label("NEL"), None);
+        assert_eq!(parse_label("NÉL"), None);
+    }
+
+    #[test]
+    fn test_label_columns_skip_unknown() {
+        let headers = csv::StringRecord::from(vec![
+            "latitude",
+            "longitude",
+            "start_time",
+            "NL",
+            "total",
+            "SBT",
+            "notes",
+        ]);
+        assert_eq!(
+            label_columns(&headers, &[Some(0), Some(1), Some(2), None, None]),
+            vec![
+                (3, (Approach::North, TurnDirection::Left)),
+                (5, (Approach::North, TurnDirection::Through)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bearing_diff() {
+        assert_eq!(bearing_diff(10.0, 20.0), 10.0);
+        assert_eq!(bearing_diff(350.0, 10.0), 20.0);
+        assert_eq!(bearing_diff(10.0, 350.0), 20.0);
+        assert_eq!(bearing_diff(0.0, 180.0), 180.0);
+        assert_eq!(bearing_diff(-90.0, 270.0), 0.0);
+        assert_eq!(bearing_diff(450.0, 90.0), 0.0);
+    }
+
+    /// A four-way intersection with roads 0 to 3 going north, east, south, and west, slightly
+    /// askew. Every road connects to every other, both ways.
+    fn four_way() -> (BTreeMap<RoadID, f64>, Vec<MovementID>) {
+        let legs: BTreeMap<RoadID, f64> = vec![
+            (RoadID(0), 5.0),
+            (RoadID(1), 95.0),
+            (RoadID(2), 185.0),
+            (RoadID(3), 275.0),
+        ]
+        .into_iter()
+        .collect();
+        let parent = IntersectionID(0);
+        let dr = |r| DirectedRoadID {
+            road: RoadID(r),
+            dir: Direction::Fwd,
+        };
+        let mut movements = Vec::new();
+        for from in 0..4 {
+            for to in 0..4 {
+                movements.push(MovementID {
+                    from: dr(from),
+                    to: dr(to),
+                    parent,
+                    crosswalk: false,
+                });
+            }
+        }
+        // Crosswalks are never matched
+        movements.push(MovementID {
+            from: dr(0),
+            to: dr(1),
+            parent,
+            crosswalk: true,
+        });
+        (legs, movements)
+    }
+
+    fn pick(
+        legs: &BTreeMap<RoadID, f64>,
+        movements: &[MovementID],
+        approach: Approach,
+        turn: TurnDirection,
+    ) -> Option<(usize, usize)> {
+        pick_movement(legs, movements.iter(), approach, turn).map(|m| {
+            assert!(!m.crosswalk);
+            (m.from.road.0, m.to.road.0)
+        })
+    }
+
+    #[test]
+    fn test_pick_movement() {
+        let (legs, movements) = four_way();
+        // Coming from the north and turning left means heading east
+        assert_eq!(
+            pick(&legs, &movements, Approach::North, TurnDirection::Left),
+            Some((0, 1))
+        );
+        assert_eq!(
+            pick(&legs, &movements, Approach::North, TurnDirection::Through),
+            Some((0, 2))
+        );
+        assert_eq!(
+            pick(&legs, &movements, Approach::North, TurnDirection::Right),
+            Some((0, 3))
+        );
+        assert_eq!(
+            pick(&legs, &movements, Approach::North, TurnDirection::UTurn),
+            Some((0, 0))
+        );
+        assert_eq!(
+            pick(&legs, &movements, Approach::East, TurnDirection::Left),
+            Some((1, 2))
+        );
+        assert_eq!(
+            pick(&legs, &movements, Approach::West, TurnDirection::Right),
+            Some((3, 2))
+        );
+    }
+
+    #[test]
+    fn test_pick_movement_missing_leg() {
+        // A T intersection without a west leg
+        let (mut legs, movements) = four_way();
+        legs.remove(&RoadID(3));
+        let movements: Vec<MovementID> = movements
+            .into_iter()
+            .filter(|m| m.from.road != RoadID(3) && m.to.road != RoadID(3))
+            .collect();
+        assert_eq!(
+            pick(&legs, &movements, Approach::West, TurnDirection::Through),
+            None
+        );
+        assert_eq!(
+            pick(&legs, &movements, Approach::North, TurnDirection::Right),
+            None
+        );
+        assert_eq!(
+            pick(&legs, &movements, Approach::South, TurnDirection::Left),
+            None
+        );
+        assert_eq!(
+            pick(&legs, &movements, Approach::South, TurnDirection::Right),
+            Some((2, 1))
+        );
+    }
+}